@@ -0,0 +1,574 @@
+//! UDP echo servers using the Linux epoll facility to multiplex reads and
+//! writes on one or more sockets (see `--sockets`).  Each socket is
+//! registered with its index as the epoll event data.
+//!
+//! `EpollLevel` uses level-triggered events, which makes it functionally
+//! similar to programs using epoll's predecessors select() and poll().
+//!
+//! `EpollEdge` uses edge-triggered events, which can theoretically provide
+//! better performance than level-triggering by reducing the overhead
+//! related to selection.  Handlers are expected to perform I/O until an
+//! EWOULDBLOCK is indicated, since no further event will arrive until the
+//! readiness changes again.  Left unchecked, a busy socket can then starve
+//! all the others (the edge-triggered starvation problem), so each socket
+//! is limited to `--budget` receives and sends per turn.  A socket which
+//! uses up its budget is put on a `ReadyList` and serviced again, in
+//! round-robin order with the other ready sockets, without waiting for a
+//! new edge.  Listen on several sockets (`--sockets`) to see the effect.
+//!
+//! Given a `--batch` above 1, `EpollEdge` moves up to that many datagrams
+//! per recvmmsg() or sendmmsg() call instead of one per recvfrom() or
+//! sendto(), using buffers allocated once and shared by the sockets (see
+//! the `mmsg` module).  Each datagram still counts against the budget.
+//!
+//! Both backends account for their epoll, receive and send calls in a
+//! `Syscalls` ledger (see the `syscalls` module), and report them with the
+//! sockets' statistics on exit, so that the calls per datagram of level
+//! and edge triggering (and of batching) can be compared.  Given a
+//! `--syscall-interval`, they also report whenever a timerfd registered
+//! with epoll like the sockets expires (see `TimerFd` in the `timer`
+//! module), busy or not.
+//!
+//! Signals are blocked, and accepted through a signalfd registered with
+//! epoll like the sockets (see `SignalFd` in the `signal` module).
+//!
+//! Given `--unix`, either backend serves a Unix domain datagram socket
+//! instead.  nix's socket addresses cover both families, so nothing else
+//! changes.
+
+use std::cmp;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use nix::sys::epoll::*;
+use nix::sys::socket::*;
+use backend::EchoBackend;
+use config::{Config, MAX_EVENTS};
+use echo::{EchoCore, Received};
+use message::Message;
+use mmsg::{RecvBatch, SendBatch};
+use net::{bind_sockets, io_error, local_addr, recv_from, send_to};
+use ready::ReadyList;
+use shutdown::Drain;
+use signal::{self, SignalFd};
+use syscalls::{Syscall, Syscalls};
+use timer::TimerFd;
+
+/// The event data of the signalfd.  Sockets are identified by their index.
+pub const SIGNAL: u64 = u64::MAX;
+
+/// The event data of the timerfd which times the system call reports.
+pub const REPORT_TIMER: u64 = u64::MAX - 1;
+
+/// The level-triggered epoll backend.
+pub struct EpollLevel;
+
+/// The edge-triggered epoll backend.
+pub struct EpollEdge;
+
+impl EchoBackend for EpollLevel {
+    fn name(&self) -> &'static str {
+        "epoll-level"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        let epoll_fd = epoll_create1(EpollCreateFlags::empty()).map_err(io_error)?;
+
+        // Signals make the signalfd readable.
+        let mut signals = register_signals(epoll_fd)?;
+        let mut config = config.clone();
+        let mut drain = config.drain();
+        let mut syscalls = Syscalls::new();
+        let report_timer = register_report_timer(epoll_fd, &config)?;
+
+        // Open the configured number of UDP sockets in non-blocking mode, and bind them to
+        // consecutive ports on each configured address (by default IPv4 localhost, port 2000), or
+        // the Unix domain socket, whose file is removed once `_socket_file` is dropped.  Each is
+        // registered with its index as the epoll event data, so events can be traced back to
+        // their socket.
+        let (socket_fds, _socket_file) = bind_sockets(&config, SockType::Datagram)?;
+        let mut sockets = vec![];
+        for (index, &socket_fd) in socket_fds.iter().enumerate() {
+            println!("listening on {}", local_addr(socket_fd).map_err(io_error)?);
+            let mut core: EchoCore<SockAddr> = config.echo_core();
+            let interest = core.poll_interest().unwrap();
+            let mut event = EpollEvent::new(interest.epoll_flags(), index as u64);
+            syscalls
+                .call(Syscall::EpollCtl, || {
+                    epoll_ctl(epoll_fd, EpollOp::EpollCtlAdd, socket_fd, &mut event)
+                })
+                .map_err(io_error)?;
+            sockets.push(EchoSocket { socket_fd, core });
+        }
+
+        let mut inbuf = config.buffer();
+        let mut current_events = [EpollEvent::empty(); MAX_EVENTS];
+
+        loop {
+            // Set up events for each socket whose echo core's interest has changed.  (A core only
+            // asks to write when there are outgoing packets to send.)
+            for (index, socket) in sockets.iter_mut().enumerate() {
+                if let Some(interest) = socket.core.poll_interest() {
+                    let mut event = EpollEvent::new(interest.epoll_flags(), index as u64);
+                    let socket_fd = socket.socket_fd;
+                    syscalls
+                        .call(Syscall::EpollCtl, || {
+                            epoll_ctl(epoll_fd, EpollOp::EpollCtlMod, socket_fd, &mut event)
+                        })
+                        .map_err(io_error)?;
+                }
+            }
+
+            // Select via epoll.  While shutting down, only wait until the drain deadline.
+            let timeout = epoll_timeout(&drain, -1);
+            let num_events = match syscalls.call(Syscall::EpollWait, || {
+                epoll_wait(epoll_fd, &mut current_events, timeout)
+            }) {
+                Ok(num_events) => num_events,
+                Err(nix::Error::Sys(nix::errno::EINTR)) => 0,
+                Err(e) => return Err(io_error(e)),
+            };
+
+            // Process events
+            let mut signalled = false;
+            for event in &current_events[0..num_events] {
+                if event.data() == SIGNAL {
+                    for signal in signals.signals() {
+                        let cores = sockets.iter_mut().map(|socket| &mut socket.core);
+                        signalled |= signal::handle(signal, &mut config, &mut drain, cores);
+                    }
+                    continue;
+                }
+                if event.data() == REPORT_TIMER {
+                    if let Some(ref timer) = report_timer {
+                        syscalls.report_expired(timer, received(&sockets))?;
+                    }
+                    continue;
+                }
+                let EchoSocket {
+                    socket_fd,
+                    ref mut core,
+                } = sockets[event.data() as usize];
+                if event.events().contains(EPOLLIN) {
+                    // Read from the socket.
+                    core.readable();
+                    let (nbytes, addr) = syscalls
+                        .call(Syscall::RecvFrom, || recv_from(socket_fd, &mut inbuf))
+                        .map_err(io_error)?;
+                    println!("recv {} bytes from {}.", nbytes, addr);
+
+                    // Echo by handing the message to the echo core, which queues it for sending.
+                    match core.received(Message::new(inbuf[0..nbytes].to_vec(), addr)) {
+                        Received::Queued(pending) => println!("total pending writes: {}", pending),
+                        overflow => println!("{} ({})", overflow, core.stats()),
+                    }
+                }
+                if event.events().contains(EPOLLOUT) {
+                    // Write to the socket.
+                    core.writable();
+                    let message = core.poll_send().unwrap();
+                    match syscalls.call(Syscall::SendTo, || {
+                        send_to(socket_fd, &message.buffer, &message.addr)
+                    }) {
+                        Ok(nbytes) => {
+                            core.sent();
+                            println!("sent {} bytes to {}.", nbytes, message.addr);
+                        }
+                        Err(nix::Error::Sys(errno)) if errno == nix::errno::EWOULDBLOCK => {
+                            core.send_would_block(message);
+                        }
+                        Err(e) => {
+                            // E.g. a Unix domain peer which has gone away.
+                            println!("failed to send to {}: {}", message.addr, e);
+                            core.send_failed();
+                        }
+                    }
+                }
+            }
+
+            if drain.check(sockets.iter_mut().map(|socket| &mut socket.core), signalled) {
+                report(&sockets, &mut syscalls);
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl EchoBackend for EpollEdge {
+    fn name(&self) -> &'static str {
+        "epoll-edge"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        let epoll_fd = epoll_create1(EpollCreateFlags::empty()).map_err(io_error)?;
+
+        // Signals make the signalfd readable.
+        let mut signals = register_signals(epoll_fd)?;
+        let mut config = config.clone();
+        let mut drain = config.drain();
+        let mut syscalls = Syscalls::new();
+        let report_timer = register_report_timer(epoll_fd, &config)?;
+
+        // Open the configured number of UDP sockets in non-blocking mode, and bind them to
+        // consecutive ports on each configured address (by default IPv4 localhost, port 2000), or
+        // the Unix domain socket, whose file is removed once `_socket_file` is dropped.  Each is
+        // registered with its index as the epoll event data, so events can be traced back to
+        // their socket.
+        let (socket_fds, _socket_file) = bind_sockets(&config, SockType::Datagram)?;
+        let mut sockets = vec![];
+        for (index, &socket_fd) in socket_fds.iter().enumerate() {
+            println!("listening on {}", local_addr(socket_fd).map_err(io_error)?);
+            let mut core: EchoCore<SockAddr> = config.echo_core();
+            let interest = core.poll_interest().unwrap();
+            let mut event = EpollEvent::new(interest.epoll_flags() | EPOLLET, index as u64);
+            syscalls
+                .call(Syscall::EpollCtl, || {
+                    epoll_ctl(epoll_fd, EpollOp::EpollCtlAdd, socket_fd, &mut event)
+                })
+                .map_err(io_error)?;
+            sockets.push(EchoSocket { socket_fd, core });
+        }
+
+        let mut inbuf = config.buffer();
+        let mut current_events = [EpollEvent::empty(); MAX_EVENTS];
+
+        // The buffers and headers of the batched path, allocated once for all the sockets.
+        let mut batch = if config.batch > 1 {
+            Some(Batch::new(config.batch, config.max_message_size))
+        } else {
+            None
+        };
+
+        // Sockets which may be able to make progress without a new edge.  We haven't seen
+        // WouldBlock on any socket yet, so they all start out here.
+        let mut ready = ReadyList::new(sockets.len());
+        for index in 0..sockets.len() {
+            ready.push(index);
+        }
+
+        let mut signalled = false;
+        loop {
+            // Give each socket on the ready list one turn, in round-robin order.  A socket which
+            // uses up its budget goes to the back of the list instead of monopolizing the loop.
+            for _ in 0..ready.len() {
+                let index = ready.pop().unwrap();
+                let socket = &mut sockets[index];
+                let budget = config.budget_limit();
+                let exhausted = match batch {
+                    Some(ref mut batch) => socket.service_batched(batch, budget, &mut syscalls)?,
+                    None => socket.service(&mut inbuf, budget, &mut syscalls)?,
+                };
+                if exhausted {
+                    println!("socket #{} used its budget; deferring.", index);
+                    ready.push(index);
+                }
+                if let Some(interest) = socket.core.poll_interest() {
+                    let mut event = EpollEvent::new(interest.epoll_flags() | EPOLLET, index as u64);
+                    let socket_fd = socket.socket_fd;
+                    syscalls
+                        .call(Syscall::EpollCtl, || {
+                            epoll_ctl(epoll_fd, EpollOp::EpollCtlMod, socket_fd, &mut event)
+                        })
+                        .map_err(io_error)?;
+                }
+            }
+
+            if drain.check(sockets.iter_mut().map(|socket| &mut socket.core), signalled) {
+                report(&sockets, &mut syscalls);
+                return Ok(());
+            }
+
+            // Collect new edges.  Only block if no socket has work left over from its last turn,
+            // and while shutting down, only until the drain deadline.
+            let timeout = epoll_timeout(&drain, if ready.is_empty() { -1 } else { 0 });
+            println!("before wait");
+            let num_events = match syscalls.call(Syscall::EpollWait, || {
+                epoll_wait(epoll_fd, &mut current_events, timeout)
+            }) {
+                Ok(num_events) => num_events,
+                Err(nix::Error::Sys(nix::errno::EINTR)) => 0,
+                Err(e) => return Err(io_error(e)),
+            };
+            println!("after wait");
+
+            // Process events
+            for event in &current_events[0..num_events] {
+                if event.data() == SIGNAL {
+                    for signal in signals.signals() {
+                        let cores = sockets.iter_mut().map(|socket| &mut socket.core);
+                        signalled |= signal::handle(signal, &mut config, &mut drain, cores);
+                    }
+                    continue;
+                }
+                if event.data() == REPORT_TIMER {
+                    if let Some(ref timer) = report_timer {
+                        syscalls.report_expired(timer, received(&sockets))?;
+                    }
+                    continue;
+                }
+                let index = event.data() as usize;
+                let core = &mut sockets[index].core;
+                if event.events().contains(EPOLLIN) {
+                    core.readable();
+                }
+                if event.events().contains(EPOLLOUT) {
+                    core.writable();
+                }
+                ready.push(index);
+            }
+        }
+    }
+}
+
+/// Block the signals, and register a signalfd which accepts them with `epoll_fd`
+/// (level-triggered).
+pub fn register_signals(epoll_fd: RawFd) -> io::Result<SignalFd> {
+    let signals = SignalFd::new()?;
+    let mut event = EpollEvent::new(EPOLLIN, SIGNAL);
+    epoll_ctl(epoll_fd, EpollOp::EpollCtlAdd, signals.as_raw_fd(), &mut event)
+        .map_err(io_error)?;
+    Ok(signals)
+}
+
+/// Given a `--syscall-interval`, register the report timer with
+/// `epoll_fd` (level-triggered).
+pub fn register_report_timer(epoll_fd: RawFd, config: &Config) -> io::Result<Option<TimerFd>> {
+    let timer = config.report_timer()?;
+    if let Some(ref timer) = timer {
+        let mut event = EpollEvent::new(EPOLLIN, REPORT_TIMER);
+        epoll_ctl(epoll_fd, EpollOp::EpollCtlAdd, timer.as_raw_fd(), &mut event)
+            .map_err(io_error)?;
+    }
+    Ok(timer)
+}
+
+/// The epoll_wait() timeout in milliseconds: `timeout`, or less if the
+/// drain deadline is sooner.
+pub fn epoll_timeout(drain: &Drain, timeout: isize) -> isize {
+    match drain.wait() {
+        Some(wait) => {
+            // Round up, so as not to spin in the last millisecond before the deadline.
+            let wait = wait.as_micros().div_ceil(1000) as isize;
+            if timeout < 0 {
+                wait
+            } else {
+                timeout.min(wait)
+            }
+        }
+        None => timeout,
+    }
+}
+
+/// Print the final statistics of each socket, and the system calls they
+/// took.
+fn report(sockets: &[EchoSocket], syscalls: &mut Syscalls) {
+    for (index, socket) in sockets.iter().enumerate() {
+        println!("socket #{}: {}", index, socket.core.stats());
+    }
+    syscalls.report(received(sockets));
+}
+
+// The datagrams received by all the sockets.
+fn received(sockets: &[EchoSocket]) -> u64 {
+    sockets.iter().map(|socket| socket.core.stats().received).sum()
+}
+
+/// The buffers and headers of the batched path, and the echoes taken from
+/// a core for the send in progress.
+struct Batch {
+    recv: RecvBatch,
+    send: SendBatch,
+    outgoing: Vec<Message<SockAddr>>,
+}
+
+impl Batch {
+    fn new(batch: usize, max_message_size: usize) -> Batch {
+        Batch {
+            recv: RecvBatch::new(batch, max_message_size),
+            send: SendBatch::new(batch),
+            outgoing: Vec::with_capacity(batch),
+        }
+    }
+}
+
+/// A socket with its own echo core.
+struct EchoSocket {
+    socket_fd: RawFd,
+    core: EchoCore<SockAddr>,
+}
+
+impl EchoSocket {
+    // Receive and send until both would block, or until `budget` operations have been performed.
+    // Returns true if the budget ran out while the socket could still make progress.
+    fn service(
+        &mut self,
+        inbuf: &mut [u8],
+        budget: usize,
+        syscalls: &mut Syscalls,
+    ) -> io::Result<bool> {
+        let socket_fd = self.socket_fd;
+        let core = &mut self.core;
+        let mut operations = 0;
+        loop {
+            // Either read or write can set this to false to try the other again.
+            let mut blocking = true;
+
+            // Try to read
+            if core.wants_read() && operations < budget {
+                match syscalls.call(Syscall::RecvFrom, || recv_from(socket_fd, inbuf)) {
+                    Ok((nbytes, addr)) => {
+                        println!("recv {} bytes from {}.", nbytes, addr);
+                        // Since we are edge-polling, the echo core will have us at least try to
+                        // write, and we only poll for writability if the write returns
+                        // EWOULDBLOCK.
+                        match core.received(Message::new(inbuf[0..nbytes].to_vec(), addr)) {
+                            Received::Queued(pending) => {
+                                println!("total pending writes: {}", pending)
+                            }
+                            overflow => println!("{} ({})", overflow, core.stats()),
+                        }
+                        operations += 1;
+                        blocking = false;
+                    }
+                    Err(nix::Error::Sys(errno)) if errno == nix::errno::EWOULDBLOCK => {
+                        core.read_would_block();
+                    }
+                    Err(e) => return Err(io_error(e)),
+                };
+            }
+
+            // Try to write
+            if operations < budget {
+                if let Some(message) = core.poll_send() {
+                    match syscalls.call(Syscall::SendTo, || {
+                        send_to(socket_fd, &message.buffer, &message.addr)
+                    }) {
+                        Ok(nbytes) => {
+                            println!("sent {} bytes to {}.", nbytes, message.addr);
+                            core.sent();
+                            operations += 1;
+                            blocking = false;
+                        }
+                        Err(nix::Error::Sys(errno)) if errno == nix::errno::EWOULDBLOCK => {
+                            // Return outgoing message to the front of the buffer
+                            core.send_would_block(message);
+                        }
+                        Err(e) => {
+                            // E.g. a Unix domain peer which has gone away.
+                            println!("failed to send to {}: {}", message.addr, e);
+                            core.send_failed();
+                            operations += 1;
+                            blocking = false;
+                        }
+                    }
+                }
+            }
+
+            if operations >= budget {
+                return Ok(core.wants_read() || core.wants_write());
+            }
+            if blocking {
+                return Ok(false);
+            }
+        }
+    }
+
+    // Like `service()`, but receive and send up to a batch of datagrams per call.  Each datagram
+    // counts as an operation against `budget`.
+    fn service_batched(
+        &mut self,
+        batch: &mut Batch,
+        budget: usize,
+        syscalls: &mut Syscalls,
+    ) -> io::Result<bool> {
+        let socket_fd = self.socket_fd;
+        let core = &mut self.core;
+        let mut operations = 0;
+        loop {
+            // Either read or write can set this to false to try the other again.
+            let mut blocking = true;
+
+            // Try to read a batch
+            if core.wants_read() && operations < budget {
+                let (recv, max) = (&mut batch.recv, budget - operations);
+                match syscalls.call(Syscall::RecvMmsg, || recv.recv(socket_fd, max, 0)) {
+                    Ok(count) => {
+                        for index in 0..count {
+                            let datagram = batch.recv.datagram(index);
+                            let addr: SockAddr = batch.recv.addr(index)?;
+                            println!("recv {} bytes from {}.", datagram.len(), addr);
+                            match core.received(Message::new(datagram.to_vec(), addr)) {
+                                Received::Queued(pending) => {
+                                    println!("total pending writes: {}", pending)
+                                }
+                                overflow => println!("{} ({})", overflow, core.stats()),
+                            }
+                        }
+                        operations += count;
+                        blocking = false;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        core.read_would_block();
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            // Try to write a batch of the pending echoes, in the order the core hands them out.
+            if operations < budget {
+                let max = cmp::min(batch.send.size(), budget - operations);
+                while batch.outgoing.len() < max {
+                    match core.poll_send() {
+                        Some(message) => batch.outgoing.push(message),
+                        None => break,
+                    }
+                }
+                if !batch.outgoing.is_empty() {
+                    let (send, outgoing) = (&mut batch.send, &batch.outgoing);
+                    let sent = syscalls.call(Syscall::SendMmsg, || send.send(socket_fd, outgoing));
+                    let (sent, error) = match sent {
+                        Ok(sent) => (sent, None),
+                        Err(e) => (0, Some(e)),
+                    };
+                    let mut messages = batch.outgoing.drain(..);
+                    for message in messages.by_ref().take(sent) {
+                        println!("sent {} bytes to {}.", message.len(), message.addr);
+                        core.sent();
+                    }
+                    if sent > 0 {
+                        operations += sent;
+                        blocking = false;
+                    }
+
+                    // The call stopped at the first echo it couldn't send.  Return the ones after
+                    // it to the core, last first, so that they keep their order.
+                    let first = messages.next();
+                    for message in messages.rev() {
+                        core.send_unfinished(message);
+                    }
+                    match (first, error) {
+                        (Some(message), Some(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                            // Return outgoing message to the front of the buffer
+                            core.send_would_block(message);
+                        }
+                        (Some(message), Some(e)) => {
+                            // E.g. a Unix domain peer which has gone away.
+                            println!("failed to send to {}: {}", message.addr, e);
+                            core.send_failed();
+                            operations += 1;
+                            blocking = false;
+                        }
+                        (Some(message), None) => core.send_unfinished(message),
+                        (None, _) => {}
+                    }
+                }
+            }
+
+            if operations >= budget {
+                return Ok(core.wants_read() || core.wants_write());
+            }
+            if blocking {
+                return Ok(false);
+            }
+        }
+    }
+}
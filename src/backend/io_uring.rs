@@ -0,0 +1,448 @@
+//! A UDP echo server using Linux's io_uring, on one or more sockets (see
+//! `--sockets`).
+//!
+//! The select, epoll and mio backends wait for readiness, and then perform
+//! the receive or send themselves.  io_uring is completion-based instead:
+//! the server submits the operations themselves to a submission queue
+//! shared with the kernel, and the kernel reports the outcome of each on a
+//! completion queue once it has been performed.  A single io_uring_enter()
+//! call per turn submits the turn's new operations and waits for at least
+//! one to complete, so nothing ever returns WouldBlock.
+//!
+//! Each socket keeps one receive in flight while its echo core wants to
+//! read, and one send in flight while it has echoes pending, so its echoes
+//! still go out in order.  The sockets are registered with the ring once,
+//! as fixed files.
+//!
+//! Each socket has a send buffer of its own, registered with the ring as
+//! a fixed buffer.  An echo is copied into it and sent with SendZc, which
+//! takes the peer's address as a sendto() would, and sends from the fixed
+//! buffer without copying it again.  A zero-copy send completes twice:
+//! once with its result, and once more with a notification when the
+//! kernel is done with the buffer, and only then is the socket's next echo
+//! sent.  Unix domain sockets have no zero-copy sends (they fail with
+//! EOPNOTSUPP), so given `--unix` the echo is sent from the same buffer
+//! with a plain Send, which copies it.
+//!
+//! The receive side has no such opcode: RecvMsg is the only receive which
+//! returns the sender's address, and it cannot use a fixed buffer.  So the
+//! receive buffers are handed to the kernel as a provided buffer group
+//! instead: a receive names the group rather than a buffer, and the kernel
+//! picks a free buffer from it once a datagram arrives.  The buffer goes
+//! back to the group once the datagram is queued for echoing.  Should a
+//! receive find no buffer free (ENOBUFS), as it may if it runs before the
+//! return of its buffer, it is simply submitted again.
+//!
+//! Signals are blocked, and accepted through a signalfd which the ring
+//! polls along with the sockets (see `SignalFd` in the `signal` module).
+//!
+//! Given `--unix`, the backend serves a Unix domain datagram socket
+//! instead, as the epoll backends do.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use io_uring::{cqueue, opcode, squeue, types};
+use io_uring::IoUring as Ring;
+use libc;
+use nix::sys::socket::*;
+use backend::EchoBackend;
+use config::Config;
+use echo::{EchoCore, Received};
+use message::Message;
+use net::{bind_sockets, io_error, local_addr, RawAddr};
+use signal::{self, SignalFd};
+
+/// The largest number of entries the kernel allows in a submission queue.
+const MAX_ENTRIES: usize = 32_768;
+
+/// The provided buffer group of the receive buffers.
+const BUFFER_GROUP: u16 = 0;
+
+/// The user data of the signalfd poll.  The operations on sockets carry
+/// the socket's index and the kind of operation (see `Op`).
+const SIGNAL: u64 = u64::MAX;
+
+/// The io_uring backend.
+pub struct IoUring;
+
+impl EchoBackend for IoUring {
+    fn name(&self) -> &'static str {
+        "io-uring"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        let mut signals = SignalFd::new()?;
+        let mut config = config.clone();
+        let mut drain = config.drain();
+
+        // Open the configured number of UDP sockets, and bind them to consecutive ports on each
+        // configured address (by default IPv4 localhost, port 2000), or the Unix domain socket,
+        // whose file is removed once `_socket_file` is dropped.
+        let (socket_fds, _socket_file) = bind_sockets(&config, SockType::Datagram)?;
+        let mut sockets = vec![];
+        let zero_copy = config.unix.is_none();
+        for &socket_fd in &socket_fds {
+            println!("listening on {}", local_addr(socket_fd).map_err(io_error)?);
+            sockets.push(EchoSocket::new(config.echo_core(), zero_copy));
+        }
+
+        // Each turn submits at most a receive, a send and the return of a buffer per socket, and
+        // the signal poll.
+        let entries = 3 * sockets.len() + 1;
+        if entries > MAX_ENTRIES {
+            return Err(io::Error::other(format!(
+                "too many sockets for one ring (at most {})",
+                (MAX_ENTRIES - 1) / 3
+            )));
+        }
+
+        // One receive and one send buffer per socket are enough, since each has at most one
+        // receive and one send in flight.  (The buffers are dropped after the ring, which may
+        // still be using them.)
+        let mut buffers = BufferPool::new(sockets.len(), config.max_message_size);
+        let mut send_buffers = SendBuffers::new(sockets.len(), config.max_message_size);
+        let mut ring = Ring::new(entries.next_power_of_two() as u32)?;
+        ring.submitter().register_files(&socket_fds)?;
+        unsafe {
+            ring.submitter().register_buffers(&[send_buffers.iovec()])?;
+        }
+        push(&mut ring, &buffers.provide_all())?;
+
+        let mut signal_polled = false;
+        let mut signalled = false;
+        loop {
+            // Submit a receive for each socket whose echo core wants to read, and a send for each
+            // socket with echoes pending, unless one is already in flight.
+            for (index, socket) in sockets.iter_mut().enumerate() {
+                if !socket.receiving && socket.core.wants_read() {
+                    push(&mut ring, &socket.recv(index, buffers.size))?;
+                }
+                if !socket.is_sending() {
+                    if let Some(message) = socket.core.poll_send() {
+                        let buffer = send_buffers.fill(index, &message.buffer);
+                        push(&mut ring, &socket.send(index, message, buffer))?;
+                    }
+                }
+            }
+            if !signal_polled {
+                let fd = types::Fd(signals.as_raw_fd());
+                let poll = opcode::PollAdd::new(fd, libc::POLLIN as u32).build();
+                push(&mut ring, &poll.user_data(SIGNAL))?;
+                signal_polled = true;
+            }
+
+            // A send in flight is still pending, so only check on the shutdown once there are
+            // none, unless the drain deadline has passed.
+            if sockets.iter().all(|socket| !socket.is_sending()) || drain.expired() {
+                let cores = sockets.iter_mut().map(|socket| &mut socket.core);
+                if drain.check(cores, signalled) {
+                    report(&sockets);
+                    return Ok(());
+                }
+            }
+
+            // Submit, and wait for a completion.  While shutting down, only wait until the drain
+            // deadline.
+            let submitted = match drain.wait() {
+                Some(wait) => {
+                    let timeout = types::Timespec::from(wait);
+                    let args = types::SubmitArgs::new().timespec(&timeout);
+                    ring.submitter().submit_with_args(1, &args)
+                }
+                None => ring.submit_and_wait(1),
+            };
+            match submitted {
+                Ok(_) => {}
+                Err(ref e) if e.raw_os_error() == Some(libc::ETIME) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+
+            // Process completions
+            let completions: Vec<cqueue::Entry> = ring.completion().collect();
+            for completion in completions {
+                if completion.user_data() == SIGNAL {
+                    for signal in signals.signals() {
+                        let cores = sockets.iter_mut().map(|socket| &mut socket.core);
+                        signalled |= signal::handle(signal, &mut config, &mut drain, cores);
+                    }
+                    signal_polled = false;
+                    continue;
+                }
+                let (index, op) = Op::from_user_data(completion.user_data());
+                let result = completion.result();
+                match op {
+                    Op::Recv => {
+                        let socket = &mut sockets[index];
+                        socket.receiving = false;
+                        if result == -libc::ENOBUFS {
+                            // The buffer's return hadn't completed; receive again next turn.
+                            println!("no receive buffer free; retrying.");
+                            continue;
+                        }
+                        if result < 0 {
+                            return Err(io::Error::from_raw_os_error(-result));
+                        }
+                        let buffer = cqueue::buffer_select(completion.flags())
+                            .expect("a receive completed without a buffer");
+                        let addr = socket.recv_addr()?;
+                        let datagram = buffers.get(buffer, result as usize).to_vec();
+                        push(&mut ring, &buffers.provide(buffer))?;
+                        println!("recv {} bytes from {}.", datagram.len(), addr);
+                        match socket.core.received(Message::new(datagram, addr)) {
+                            Received::Queued(pending) => {
+                                println!("total pending writes: {}", pending)
+                            }
+                            overflow => println!("{} ({})", overflow, socket.core.stats()),
+                        }
+                    }
+                    Op::Send => {
+                        let socket = &mut sockets[index];
+                        if cqueue::notif(completion.flags()) {
+                            // The kernel is done with the send buffer.
+                            socket.notifying = false;
+                            continue;
+                        }
+                        let message = socket.sending.take().expect("no send was in flight");
+                        socket.notifying = cqueue::more(completion.flags());
+                        if result < 0 {
+                            // E.g. a Unix domain peer which has gone away.
+                            let e = io::Error::from_raw_os_error(-result);
+                            println!("failed to send to {}: {}", message.addr, e);
+                            socket.core.send_failed();
+                        } else {
+                            println!("sent {} bytes to {}.", result, message.addr);
+                            socket.core.sent();
+                        }
+                    }
+                    Op::Provide => {
+                        if result < 0 {
+                            return Err(io::Error::from_raw_os_error(-result));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The kind of an operation on a socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Recv,
+    Send,
+    Provide, // The return of a receive buffer to its group.
+}
+
+impl Op {
+    /// The user data of this operation on socket `index`.
+    fn user_data(self, index: usize) -> u64 {
+        (index as u64) << 2 | self as u64
+    }
+
+    fn from_user_data(user_data: u64) -> (usize, Op) {
+        let op = match user_data & 3 {
+            0 => Op::Recv,
+            1 => Op::Send,
+            _ => Op::Provide,
+        };
+        ((user_data >> 2) as usize, op)
+    }
+}
+
+/// Queue an operation for the next submission, first submitting those
+/// already queued if the submission queue is full.
+fn push(ring: &mut Ring, entry: &squeue::Entry) -> io::Result<()> {
+    if ring.submission().is_full() {
+        ring.submit()?;
+    }
+    // The operation's buffers and headers stay put until it completes: they belong to the socket
+    // or to the buffer pools, which outlive the ring's use of them.
+    unsafe {
+        ring.submission()
+            .push(entry)
+            .map_err(|_| io::Error::other("submission queue full"))
+    }
+}
+
+/// Print the final statistics of each socket.
+fn report(sockets: &[EchoSocket]) {
+    for (index, socket) in sockets.iter().enumerate() {
+        println!("socket #{}: {}", index, socket.core.stats());
+    }
+}
+
+/// The receive buffers, one per socket, provided to the kernel as a
+/// buffer group (not registered as fixed buffers, which no receive with a
+/// peer address can use; see the module documentation).
+struct BufferPool {
+    buffers: Vec<u8>,
+    size: usize,
+}
+
+impl BufferPool {
+    fn new(count: usize, size: usize) -> BufferPool {
+        BufferPool {
+            buffers: vec![0; count * size],
+            size,
+        }
+    }
+
+    /// The operation which provides all of the buffers.
+    fn provide_all(&mut self) -> squeue::Entry {
+        let count = (self.buffers.len() / self.size) as u16;
+        let size = self.size as i32;
+        opcode::ProvideBuffers::new(self.buffers.as_mut_ptr(), size, count, BUFFER_GROUP, 0)
+            .build()
+            .user_data(Op::Provide.user_data(0))
+    }
+
+    /// The operation which returns buffer `id` to the group.
+    fn provide(&mut self, id: u16) -> squeue::Entry {
+        let start = id as usize * self.size;
+        let buffer = self.buffers[start..start + self.size].as_mut_ptr();
+        opcode::ProvideBuffers::new(buffer, self.size as i32, 1, BUFFER_GROUP, id)
+            .build()
+            .user_data(Op::Provide.user_data(0))
+    }
+
+    /// The first `len` bytes of buffer `id`.
+    fn get(&self, id: u16, len: usize) -> &[u8] {
+        let start = id as usize * self.size;
+        &self.buffers[start..start + len.min(self.size)]
+    }
+}
+
+/// The send buffers, one per socket, registered with the ring as a single
+/// fixed buffer.
+struct SendBuffers {
+    buffers: Vec<u8>,
+    size: usize,
+}
+
+impl SendBuffers {
+    fn new(count: usize, size: usize) -> SendBuffers {
+        SendBuffers {
+            buffers: vec![0; count * size],
+            size,
+        }
+    }
+
+    /// The buffers, for registering with the ring (as fixed buffer 0).
+    fn iovec(&mut self) -> libc::iovec {
+        libc::iovec {
+            iov_base: self.buffers.as_mut_ptr() as *mut libc::c_void,
+            iov_len: self.buffers.len(),
+        }
+    }
+
+    /// Copy `data` into the send buffer of socket `index`, returning the
+    /// copy.
+    fn fill(&mut self, index: usize, data: &[u8]) -> &[u8] {
+        let start = index * self.size;
+        let buffer = &mut self.buffers[start..start + data.len().min(self.size)];
+        buffer.copy_from_slice(&data[..buffer.len()]);
+        buffer
+    }
+}
+
+/// The C structures of a receive, which the kernel uses until it completes.
+struct Header {
+    addr: libc::sockaddr_storage,
+    iov: libc::iovec,
+    msg: libc::msghdr,
+}
+
+impl Header {
+    fn new() -> Box<Header> {
+        Box::new(unsafe { mem::zeroed() })
+    }
+
+    /// Point the message header at `len` bytes of `buffer` (which may be
+    /// null, for the kernel to pick a buffer) and an address of `addr_len`
+    /// bytes.
+    fn set(&mut self, buffer: *mut u8, len: usize, addr_len: libc::socklen_t) {
+        self.iov = libc::iovec {
+            iov_base: buffer as *mut libc::c_void,
+            iov_len: len,
+        };
+        self.msg.msg_name = &mut self.addr as *mut _ as *mut libc::c_void;
+        self.msg.msg_namelen = addr_len;
+        self.msg.msg_iov = &mut self.iov;
+        self.msg.msg_iovlen = 1;
+    }
+}
+
+/// A socket with its own echo core, and its operations in flight.
+struct EchoSocket {
+    core: EchoCore<SockAddr>,
+    receiving: bool,
+    sending: Option<Message<SockAddr>>, // The echo being sent.
+    notifying: bool, // Whether the kernel is still using the send buffer.
+    zero_copy: bool, // Whether sends are zero-copy, which Unix domain sockets don't support.
+    recv_header: Box<Header>,
+    send_addr: Box<libc::sockaddr_storage>, // The peer of the echo being sent.
+}
+
+impl EchoSocket {
+    fn new(core: EchoCore<SockAddr>, zero_copy: bool) -> EchoSocket {
+        EchoSocket {
+            core,
+            receiving: false,
+            sending: None,
+            notifying: false,
+            zero_copy,
+            recv_header: Header::new(),
+            send_addr: Box::new(unsafe { mem::zeroed() }),
+        }
+    }
+
+    /// Whether a send is in flight, or has yet to release the send buffer.
+    fn is_sending(&self) -> bool {
+        self.sending.is_some() || self.notifying
+    }
+
+    /// The receive operation for socket `index`, into a buffer of up to
+    /// `size` bytes picked by the kernel.
+    fn recv(&mut self, index: usize, size: usize) -> squeue::Entry {
+        let addr_len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        self.recv_header.set(ptr::null_mut(), size, addr_len);
+        self.receiving = true;
+        opcode::RecvMsg::new(types::Fixed(index as u32), &mut self.recv_header.msg)
+            .buf_group(BUFFER_GROUP)
+            .build()
+            .flags(squeue::Flags::BUFFER_SELECT)
+            .user_data(Op::Recv.user_data(index))
+    }
+
+    /// The sender of the datagram received by the last receive.
+    fn recv_addr(&self) -> io::Result<SockAddr> {
+        SockAddr::from_raw(&self.recv_header.addr, self.recv_header.msg.msg_namelen)
+    }
+
+    /// The send operation of `message` for socket `index`, from `buffer`,
+    /// its copy in the socket's fixed send buffer: zero-copy unless the
+    /// socket is a Unix domain socket.
+    fn send(&mut self, index: usize, message: Message<SockAddr>, buffer: &[u8]) -> squeue::Entry {
+        let (addr, addr_len) = message.addr.to_raw();
+        *self.send_addr = addr;
+        self.sending = Some(message);
+        let fd = types::Fixed(index as u32);
+        let dest_addr = &*self.send_addr as *const _ as *const libc::sockaddr;
+        let send = if self.zero_copy {
+            opcode::SendZc::new(fd, buffer.as_ptr(), buffer.len() as u32)
+                .buf_index(Some(0))
+                .dest_addr(dest_addr)
+                .dest_addr_len(addr_len)
+                .build()
+        } else {
+            opcode::Send::new(fd, buffer.as_ptr(), buffer.len() as u32)
+                .dest_addr(dest_addr)
+                .dest_addr_len(addr_len)
+                .build()
+        };
+        send.user_data(Op::Send.user_data(index))
+    }
+}
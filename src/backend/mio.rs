@@ -0,0 +1,354 @@
+//! UDP echo servers using the cross-platform mio crate to multiplex reads
+//! and writes on one or more sockets (see `--sockets`).  Each socket is
+//! registered with its index as its `Token`.
+//!
+//! `MioLevel` uses level-triggered events.
+//!
+//! `MioEdge` uses edge-triggered events, which can theoretically provide
+//! better performance than level-triggering by reducing the overhead
+//! related to selection.  Handlers are expected to perform I/O until
+//! WouldBlock is indicated, since no further event will arrive until the
+//! readiness changes again.  To keep one busy socket from starving the
+//! others, each socket is limited to `--budget` receives and sends per
+//! turn; a socket which uses up its budget is put on a `ReadyList` and
+//! serviced again in round-robin order, without waiting for a new edge.
+//!
+//! Signals are accepted by a thread of their own, which reports them as
+//! the readiness of a `Registration` (see the `signal` module).
+//!
+//! Given `--unix`, either backend serves a Unix domain datagram socket
+//! instead, through the mio-uds style `UnixDatagram` of the `unix` module.
+
+use std::io;
+use std::time::Duration;
+use mio::net::UdpSocket;
+use mio::{Evented, Events, Poll, PollOpt, Ready, Token};
+use backend::{DatagramSocket, EchoBackend};
+use config::{Config, MAX_EVENTS};
+use echo::{EchoCore, Received};
+use message::Message;
+use net::udp_socket;
+use ready::ReadyList;
+use signal::{self, SignalRegistration};
+use unix::UnixDatagram;
+
+/// The token of the signal registration.  Sockets are identified by their index.  (mio reserves
+/// `Token(usize::MAX)` for itself.)
+pub const SIGNAL: Token = Token(usize::MAX - 1);
+
+/// The level-triggered mio backend.
+pub struct MioLevel;
+
+/// The edge-triggered mio backend.
+pub struct MioEdge;
+
+impl EchoBackend for MioLevel {
+    fn name(&self) -> &'static str {
+        "mio-level"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        match config.unix {
+            Some(ref addr) => serve_level(vec![UnixDatagram::bind(addr)?], config),
+            None => serve_level(udp_sockets(config)?, config),
+        }
+    }
+}
+
+impl EchoBackend for MioEdge {
+    fn name(&self) -> &'static str {
+        "mio-edge"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        match config.unix {
+            Some(ref addr) => serve_edge(vec![UnixDatagram::bind(addr)?], config),
+            None => serve_edge(udp_sockets(config)?, config),
+        }
+    }
+}
+
+/// Open the configured number of UDP sockets in non-blocking mode bound to
+/// consecutive ports on each configured address (by default IPv4 localhost
+/// port 2000).
+fn udp_sockets(config: &Config) -> io::Result<Vec<UdpSocket>> {
+    config
+        .addrs()
+        .iter()
+        .map(|addr| UdpSocket::from_socket(udp_socket(addr, config.v6_only)?))
+        .collect()
+}
+
+/// Echo on `sockets` with level-triggered events.
+fn serve_level<S: DatagramSocket + Evented>(sockets: Vec<S>, config: &Config) -> io::Result<()> {
+    let poll = Poll::new()?;
+
+    // Signals make the signal registration readable.
+    let signals = register_signals(&poll)?;
+    let mut config = config.clone();
+    let mut drain = config.drain();
+
+    // Each socket is registered with its index as its Token.
+    let mut sockets = register(&poll, sockets, &config, PollOpt::level())?;
+
+    let mut inbuf = config.buffer();
+    let mut events = Events::with_capacity(MAX_EVENTS);
+
+    // Main loop
+    loop {
+        // Set up events for each socket whose echo core's interest has changed.  (A core only
+        // asks to write when there are outgoing packets to send.)
+        for (index, socket) in sockets.iter_mut().enumerate() {
+            if let Some(interest) = socket.core.poll_interest() {
+                poll.reregister(&socket.socket, Token(index), interest.into(), PollOpt::level())?;
+            }
+        }
+
+        // Poll.  While shutting down, only wait until the drain deadline.
+        match poll.poll(&mut events, drain.wait()) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+
+        // Process events
+        let mut signalled = false;
+        for event in &events {
+            if event.token() == SIGNAL {
+                for signal in signals.signals() {
+                    let cores = sockets.iter_mut().map(|socket| &mut socket.core);
+                    signalled |= signal::handle(signal, &mut config, &mut drain, cores);
+                }
+                continue;
+            }
+            let Token(index) = event.token();
+            let EchoSocket {
+                ref socket,
+                ref mut core,
+            } = sockets[index];
+            if event.readiness().is_readable() {
+                // Read from the socket.
+                core.readable();
+                let (nbytes, addr) = socket.recv_from(&mut inbuf)?;
+                println!("recv {} bytes from {}.", nbytes, addr);
+
+                // Echo by handing the message to the echo core, which queues it for sending.
+                match core.received(Message::new(inbuf[0..nbytes].to_vec(), addr)) {
+                    Received::Queued(pending) => println!("total pending writes: {}", pending),
+                    overflow => println!("{} ({})", overflow, core.stats()),
+                }
+            }
+            if event.readiness().is_writable() {
+                // Write to the socket.
+                core.writable();
+                let message = core.poll_send().unwrap();
+                match socket.send_to(&message.buffer, &message.addr) {
+                    Ok(nbytes) => {
+                        core.sent();
+                        println!("sent {} bytes to {}.", nbytes, message.addr);
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        core.send_would_block(message);
+                    }
+                    Err(e) => {
+                        // E.g. a Unix domain peer which has gone away.
+                        println!("failed to send to {}: {}", message.addr, e);
+                        core.send_failed();
+                    }
+                }
+            }
+        }
+
+        if drain.check(sockets.iter_mut().map(|socket| &mut socket.core), signalled) {
+            report(&sockets);
+            return Ok(());
+        }
+    }
+}
+
+/// Echo on `sockets` with edge-triggered events.
+fn serve_edge<S: DatagramSocket + Evented>(sockets: Vec<S>, config: &Config) -> io::Result<()> {
+    let poll = Poll::new()?;
+
+    // Signals make the signal registration readable.
+    let signals = register_signals(&poll)?;
+    let mut config = config.clone();
+    let mut drain = config.drain();
+
+    // Each socket is registered with its index as its Token.
+    let mut sockets = register(&poll, sockets, &config, PollOpt::edge())?;
+
+    let mut inbuf = config.buffer();
+    let mut events = Events::with_capacity(MAX_EVENTS);
+
+    // Sockets which may be able to make progress without a new edge.  We haven't seen
+    // WouldBlock on any socket yet, so they all start out here.
+    let mut ready = ReadyList::new(sockets.len());
+    for index in 0..sockets.len() {
+        ready.push(index);
+    }
+
+    // Main loop
+    let mut signalled = false;
+    loop {
+        // Give each socket on the ready list one turn, in round-robin order.  A socket which
+        // uses up its budget goes to the back of the list instead of monopolizing the loop.
+        for _ in 0..ready.len() {
+            let index = ready.pop().unwrap();
+            let socket = &mut sockets[index];
+            if socket.service(&mut inbuf, config.budget_limit())? {
+                println!("socket #{} used its budget; deferring.", index);
+                ready.push(index);
+            }
+            if let Some(interest) = socket.core.poll_interest() {
+                poll.reregister(&socket.socket, Token(index), interest.into(), PollOpt::edge())?;
+            }
+        }
+
+        if drain.check(sockets.iter_mut().map(|socket| &mut socket.core), signalled) {
+            report(&sockets);
+            return Ok(());
+        }
+
+        // Collect new edges.  Only block if no socket has work left over from its last turn,
+        // and while shutting down, only until the drain deadline.
+        let timeout = if ready.is_empty() {
+            drain.wait()
+        } else {
+            Some(Duration::from_millis(0))
+        };
+        match poll.poll(&mut events, timeout) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+
+        // Process events
+        for event in &events {
+            if event.token() == SIGNAL {
+                for signal in signals.signals() {
+                    let cores = sockets.iter_mut().map(|socket| &mut socket.core);
+                    signalled |= signal::handle(signal, &mut config, &mut drain, cores);
+                }
+                continue;
+            }
+            let Token(index) = event.token();
+            let core = &mut sockets[index].core;
+            if event.readiness().is_readable() {
+                core.readable();
+            }
+            if event.readiness().is_writable() {
+                core.writable();
+            }
+            ready.push(index);
+        }
+    }
+}
+
+/// Register `sockets` with `poll`, each with its index as its Token, and
+/// give each an echo core of its own.
+fn register<S: DatagramSocket + Evented>(
+    poll: &Poll,
+    sockets: Vec<S>,
+    config: &Config,
+    opts: PollOpt,
+) -> io::Result<Vec<EchoSocket<S>>> {
+    let mut echo_sockets = vec![];
+    for (index, socket) in sockets.into_iter().enumerate() {
+        println!("listening on {}", socket.local_addr()?);
+        let mut core: EchoCore<S::Addr> = config.echo_core();
+        let interest = core.poll_interest().unwrap();
+        poll.register(&socket, Token(index), interest.into(), opts)?;
+        echo_sockets.push(EchoSocket { socket, core });
+    }
+    Ok(echo_sockets)
+}
+
+/// Start accepting signals, and register their readiness with `poll`.
+pub fn register_signals(poll: &Poll) -> io::Result<SignalRegistration> {
+    let signals = SignalRegistration::new()?;
+    poll.register(&signals, SIGNAL, Ready::readable(), PollOpt::edge())?;
+    Ok(signals)
+}
+
+/// Print the final statistics of each socket.
+fn report<S: DatagramSocket>(sockets: &[EchoSocket<S>]) {
+    for (index, socket) in sockets.iter().enumerate() {
+        println!("socket #{}: {}", index, socket.core.stats());
+    }
+}
+
+/// A socket with its own echo core.
+struct EchoSocket<S: DatagramSocket> {
+    socket: S,
+    core: EchoCore<S::Addr>,
+}
+
+impl<S: DatagramSocket> EchoSocket<S> {
+    // Receive and send until both would block, or until `budget` operations have been performed.
+    // Returns true if the budget ran out while the socket could still make progress.
+    fn service(&mut self, inbuf: &mut [u8], budget: usize) -> io::Result<bool> {
+        let core = &mut self.core;
+        let mut operations = 0;
+        loop {
+            // Either read or write can set this to false to try the other again.
+            let mut blocking = true;
+
+            // Try to read
+            if core.wants_read() && operations < budget {
+                match self.socket.recv_from(inbuf) {
+                    Ok((nbytes, addr)) => {
+                        println!("recv {} bytes from {}.", nbytes, addr);
+                        // Since we are edge-polling, the echo core will have us at least try to
+                        // write, and we only poll for writability if the write returns a
+                        // WouldBlock error.
+                        match core.received(Message::new(inbuf[0..nbytes].to_vec(), addr)) {
+                            Received::Queued(pending) => {
+                                println!("total pending writes: {}", pending)
+                            }
+                            overflow => println!("{} ({})", overflow, core.stats()),
+                        }
+                        operations += 1;
+                        blocking = false;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        core.read_would_block();
+                    }
+                    Err(e) => return Err(e),
+                };
+            }
+
+            // Try to write
+            if operations < budget {
+                if let Some(message) = core.poll_send() {
+                    match self.socket.send_to(&message.buffer, &message.addr) {
+                        Ok(nbytes) => {
+                            println!("sent {} bytes to {}.", nbytes, message.addr);
+                            core.sent();
+                            operations += 1;
+                            blocking = false;
+                        }
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            // Return outgoing message to the front of the buffer
+                            core.send_would_block(message);
+                        }
+                        Err(e) => {
+                            // E.g. a Unix domain peer which has gone away.
+                            println!("failed to send to {}: {}", message.addr, e);
+                            core.send_failed();
+                            operations += 1;
+                            blocking = false;
+                        }
+                    }
+                }
+            }
+
+            if operations >= budget {
+                return Ok(core.wants_read() || core.wants_write());
+            }
+            if blocking {
+                return Ok(false);
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! The echo server backends, each driving an `EchoCore` with a different
+//! I/O facility.
+//!
+//! Every backend implements `EchoBackend`, so a single program can choose
+//! one at run time by name (see `find()`), while the `echo-*` programs
+//! each run one backend directly.  The `select`, `poll`, `epoll` and
+//! `io-uring` backends are only compiled when the corresponding feature
+//! flags are given; asking for them otherwise is reported as
+//! `BackendError::Unavailable`.
+
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::io;
+use std::net::SocketAddr;
+use ::mio::net::UdpSocket as MioUdpSocket;
+use tokio_core::net::UdpSocket as TokioUdpSocket;
+use config::Config;
+
+#[cfg(feature = "epoll")]
+pub mod epoll;
+#[cfg(feature = "io-uring")]
+pub mod io_uring;
+pub mod mio;
+#[cfg(feature = "poll")]
+pub mod poll;
+#[cfg(feature = "select")]
+pub mod select;
+pub mod tokio;
+pub mod tokio_mpsc;
+
+/// The names of all backends, whether or not they were compiled in.
+pub const BACKENDS: &[&str] = &[
+    "select",
+    "poll",
+    "epoll-level",
+    "epoll-edge",
+    "io-uring",
+    "mio-level",
+    "mio-edge",
+    "tokio",
+    "tokio-mpsc",
+];
+
+/// An echo server implementation.
+pub trait EchoBackend {
+    /// The name used to select this backend.
+    fn name(&self) -> &'static str;
+
+    /// Bind sockets according to `config` and echo datagrams (or the
+    /// bytes of each connection) until the server is shut down (see the
+    /// `shutdown` module), or an I/O error occurs.
+    fn run(&self, config: &Config) -> io::Result<()>;
+}
+
+/// A datagram socket which the mio and Tokio backends can serve: a UDP
+/// socket, or a Unix domain datagram socket (see the `unix` module).
+pub trait DatagramSocket {
+    /// The address of a peer, to which its echoes are sent.
+    type Addr: Clone + Eq + Hash + fmt::Display;
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, Self::Addr)>;
+    fn send_to(&self, buf: &[u8], addr: &Self::Addr) -> io::Result<usize>;
+    fn local_addr(&self) -> io::Result<Self::Addr>;
+}
+
+impl DatagramSocket for MioUdpSocket {
+    type Addr = SocketAddr;
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        MioUdpSocket::recv_from(self, buf)
+    }
+
+    fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> io::Result<usize> {
+        MioUdpSocket::send_to(self, buf, addr)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        MioUdpSocket::local_addr(self)
+    }
+}
+
+impl DatagramSocket for TokioUdpSocket {
+    type Addr = SocketAddr;
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        TokioUdpSocket::recv_from(self, buf)
+    }
+
+    fn send_to(&self, buf: &[u8], addr: &SocketAddr) -> io::Result<usize> {
+        TokioUdpSocket::send_to(self, buf, addr)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TokioUdpSocket::local_addr(self)
+    }
+}
+
+/// Look up a backend by name.
+pub fn find(name: &str) -> Result<Box<dyn EchoBackend>, BackendError> {
+    match name {
+        #[cfg(feature = "select")]
+        "select" => Ok(Box::new(select::Select)),
+        #[cfg(feature = "poll")]
+        "poll" => Ok(Box::new(poll::Poll)),
+        #[cfg(feature = "epoll")]
+        "epoll-level" => Ok(Box::new(epoll::EpollLevel)),
+        #[cfg(feature = "epoll")]
+        "epoll-edge" => Ok(Box::new(epoll::EpollEdge)),
+        #[cfg(feature = "io-uring")]
+        "io-uring" => Ok(Box::new(io_uring::IoUring)),
+        "mio-level" => Ok(Box::new(mio::MioLevel)),
+        "mio-edge" => Ok(Box::new(mio::MioEdge)),
+        "tokio" => Ok(Box::new(tokio::Tokio)),
+        "tokio-mpsc" => Ok(Box::new(tokio_mpsc::TokioMpsc)),
+        #[cfg(not(feature = "select"))]
+        "select" => Err(BackendError::Unavailable(name.to_string(), "select")),
+        #[cfg(not(feature = "poll"))]
+        "poll" => Err(BackendError::Unavailable(name.to_string(), "poll")),
+        #[cfg(not(feature = "epoll"))]
+        "epoll-level" | "epoll-edge" => Err(BackendError::Unavailable(name.to_string(), "epoll")),
+        #[cfg(not(feature = "io-uring"))]
+        "io-uring" => Err(BackendError::Unavailable(name.to_string(), "io-uring")),
+        _ => Err(BackendError::Unknown(name.to_string())),
+    }
+}
+
+/// The names of the backends compiled into this build.
+pub fn available() -> Vec<&'static str> {
+    BACKENDS
+        .iter()
+        .cloned()
+        .filter(|name| find(name).is_ok())
+        .collect()
+}
+
+/// An error looking up a backend by name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackendError {
+    Unknown(String),
+    Unavailable(String, &'static str), // The backend, and the feature it requires.
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BackendError::Unknown(ref name) => write!(
+                f,
+                "unknown backend '{}' (expected one of: {})",
+                name,
+                BACKENDS.join(", ")
+            ),
+            BackendError::Unavailable(ref name, feature) => write!(
+                f,
+                "backend '{}' is not available in this build (rebuild with --features={})",
+                name, feature
+            ),
+        }
+    }
+}
+
+impl Error for BackendError {}
@@ -0,0 +1,224 @@
+//! A simple UDP echo server using POSIX poll() to multiplex reads and
+//! writes on one or more sockets (see `--sockets`).  This backend can only
+//! be compiled on platforms which support poll() (Mac, Linux, etc.).
+//!
+//! Unlike select(), whose descriptor sets are bitmaps of FD_SETSIZE (1024
+//! on Linux) bits, poll() takes an array with one entry per descriptor, so
+//! it can watch descriptors of any value, and as many as the process may
+//! open.  The server raises its soft limit on open files if `--sockets`
+//! needs more than it allows.
+//!
+//! Each entry asks for POLLOUT only while its echo core has outgoing
+//! datagrams, as select() only puts a socket in the write set then.
+//!
+//! Its ppoll(), receive and send calls are accounted for in a `Syscalls`
+//! ledger (see the `syscalls` module).  Given a `--syscall-interval`, the
+//! ledger is reported whenever a timerfd with an entry after the sockets'
+//! expires, busy or not.
+//!
+//! Signals are blocked except while waiting in ppoll(), so that they can
+//! only interrupt the wait (see the `signal` module).
+//!
+//! Given `--unix`, the server serves a Unix domain datagram socket instead.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::time::Duration;
+use libc;
+use nix::poll::*;
+use nix::sys::socket::*;
+use nix::Errno;
+use backend::EchoBackend;
+use config::Config;
+use echo::{EchoCore, Received};
+use message::Message;
+use net::{bind_sockets, io_error, local_addr, recv_from, send_to};
+use signal::{self, SignalMask};
+use syscalls::{Syscall, Syscalls};
+
+/// The poll() backend.
+pub struct Poll;
+
+impl EchoBackend for Poll {
+    fn name(&self) -> &'static str {
+        "poll"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        // Block the signals until we wait for the sockets.
+        let signals = SignalMask::block()?;
+        let mut config = config.clone();
+        let mut drain = config.drain();
+        let mut syscalls = Syscalls::new();
+        let report_timer = config.report_timer()?;
+        raise_descriptor_limit(config.sockets * config.bind.len())?;
+
+        // Open the configured number of UDP sockets in non-blocking mode, and bind them to
+        // consecutive ports on each configured address (by default IPv4 localhost, port 2000), or
+        // the Unix domain socket.  Its file is removed once `_socket_file` is dropped.
+        let (socket_fds, _socket_file) = bind_sockets(&config, SockType::Datagram)?;
+        let mut sockets = vec![];
+        for socket_fd in socket_fds {
+            println!("listening on {}", local_addr(socket_fd).map_err(io_error)?);
+            let core: EchoCore<SockAddr> = config.echo_core();
+            sockets.push(EchoSocket { socket_fd, core });
+        }
+
+        let mut inbuf = config.buffer();
+        let entries = sockets.len() + report_timer.iter().count();
+        let mut poll_fds = vec![PollFd::new(-1, EventFlags::empty()); entries];
+        loop {
+            // Set up each socket's entry according to its echo core's interest.  (A core only asks
+            // to write when there are outgoing packets to send.)  The report timer's entry, if any,
+            // follows theirs.
+            for (poll_fd, socket) in poll_fds.iter_mut().zip(&sockets) {
+                *poll_fd = PollFd::new(socket.socket_fd, socket.core.interest().poll_flags());
+            }
+            if let Some(ref timer) = report_timer {
+                poll_fds[sockets.len()] = PollFd::new(timer.as_raw_fd(), POLLIN);
+            }
+
+            // Wait for the sockets to be ready for reading
+            // (and/or writing, if there are outgoing packets to send), for the report timer to
+            // expire, or for a signal.  While shutting down, only wait until the drain deadline.
+            let wait = drain.wait();
+            match syscalls.call(Syscall::Poll, || ppoll(&mut poll_fds, wait, signals.unblocked())) {
+                Ok(_) => (),
+                Err(nix::Error::Sys(nix::errno::EINTR)) => {
+                    // A signal handler interrupted ppoll(), which returned no events.
+                }
+                Err(e) => return Err(io_error(e)),
+            }
+            let mut signalled = false;
+            for signal in signals.signals() {
+                let cores = sockets.iter_mut().map(|socket| &mut socket.core);
+                signalled |= signal::handle(signal, &mut config, &mut drain, cores);
+            }
+
+            // Process events.  Like select(), poll() doesn't say which descriptors are ready, so
+            // every entry must be checked, but there is one entry per socket however high its
+            // descriptor.
+            for (poll_fd, socket) in poll_fds.iter().zip(&mut sockets) {
+                let revents = poll_fd.revents().unwrap_or_else(EventFlags::empty);
+                let EchoSocket {
+                    socket_fd,
+                    ref mut core,
+                } = *socket;
+                if revents.contains(POLLIN) {
+                    // Read from the socket.
+                    core.readable();
+                    let (nbytes, addr) = syscalls
+                        .call(Syscall::RecvFrom, || recv_from(socket_fd, &mut inbuf))
+                        .map_err(io_error)?;
+                    println!("recv {} bytes from {}.", nbytes, addr);
+
+                    // Echo by handing the message to the echo core, which queues it for sending.
+                    match core.received(Message::new(inbuf[0..nbytes].to_vec(), addr)) {
+                        Received::Queued(_) => (),
+                        overflow => println!("{} ({})", overflow, core.stats()),
+                    }
+                }
+                if revents.contains(POLLOUT) {
+                    // Write to the socket.
+                    core.writable();
+                    let message = core.poll_send().unwrap();
+                    match syscalls.call(Syscall::SendTo, || {
+                        send_to(socket_fd, &message.buffer, &message.addr)
+                    }) {
+                        Ok(nbytes) => {
+                            core.sent();
+                            println!("sent {} bytes to {}.", nbytes, message.addr);
+                        }
+                        Err(nix::Error::Sys(errno)) if errno == nix::errno::EWOULDBLOCK => {
+                            core.send_would_block(message);
+                        }
+                        Err(e) => {
+                            // E.g. a Unix domain peer which has gone away.
+                            println!("failed to send to {}: {}", message.addr, e);
+                            core.send_failed();
+                        }
+                    }
+                }
+            }
+
+            if let Some(ref timer) = report_timer {
+                let revents = poll_fds[sockets.len()].revents();
+                if revents.is_some_and(|revents| revents.contains(POLLIN)) {
+                    syscalls.report_expired(timer, received(&sockets))?;
+                }
+            }
+
+            if drain.check(sockets.iter_mut().map(|socket| &mut socket.core), signalled) {
+                break;
+            }
+        }
+
+        for (index, socket) in sockets.iter().enumerate() {
+            println!("socket #{}: {}", index, socket.core.stats());
+        }
+        syscalls.report(received(&sockets));
+        Ok(())
+    }
+}
+
+/// poll(), except that the signal mask is atomically replaced by `mask`
+/// for the duration of the wait.  (nix's own `ppoll()` insists on a
+/// timeout.)
+pub fn ppoll(
+    poll_fds: &mut [PollFd],
+    timeout: Option<Duration>,
+    mask: &libc::sigset_t,
+) -> nix::Result<libc::c_int> {
+    let timeout = timeout.map(|timeout| libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_nsec: libc::c_long::from(timeout.subsec_nanos()),
+    });
+    // nix's PollFd has the same layout as pollfd.
+    let result = unsafe {
+        libc::ppoll(
+            poll_fds.as_mut_ptr() as *mut libc::pollfd,
+            poll_fds.len() as libc::nfds_t,
+            timeout.as_ref().map_or(ptr::null(), |timeout| timeout),
+            mask,
+        )
+    };
+    Errno::result(result)
+}
+
+// Descriptors which the process needs besides its sockets: the standard streams, and any opened by
+// the libraries.
+const SPARE_DESCRIPTORS: usize = 32;
+
+// Raise the soft limit on open files (up to the hard limit) if it is too low for `sockets`
+// sockets.  The default soft limit is often 1024, which would stop a poll() server at about the
+// same number of sockets as a select() one.
+fn raise_descriptor_limit(sockets: usize) -> io::Result<()> {
+    let needed = (sockets + SPARE_DESCRIPTORS) as libc::rlim_t;
+    let mut limit: libc::rlimit = unsafe { mem::zeroed() };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let previous = limit.rlim_cur;
+    limit.rlim_cur = limit.rlim_max.min(needed);
+    if limit.rlim_cur <= previous {
+        return Ok(());
+    }
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    println!("raised the open file limit from {} to {}.", previous, limit.rlim_cur);
+    Ok(())
+}
+
+// The datagrams received by all the sockets.
+fn received(sockets: &[EchoSocket]) -> u64 {
+    sockets.iter().map(|socket| socket.core.stats().received).sum()
+}
+
+/// A socket with its own echo core.
+struct EchoSocket {
+    socket_fd: RawFd,
+    core: EchoCore<SockAddr>,
+}
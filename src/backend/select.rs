@@ -0,0 +1,215 @@
+//! A simple UDP echo server using POSIX select() to multiplex reads and
+//! writes on one or more sockets (see `--sockets`).  This backend can only
+//! be compiled on platforms which support select() (Mac, Linux, etc.).
+//!
+//! select() can only watch descriptors below FD_SETSIZE (1024 on Linux),
+//! so the server refuses to start if `--sockets` opens sockets beyond it.
+//! The poll backend has no such limit.
+//!
+//! Its pselect(), receive and send calls are accounted for in a `Syscalls`
+//! ledger (see the `syscalls` module).  Given a `--syscall-interval`, the
+//! ledger is reported whenever a timerfd in the read set expires, busy or
+//! not.
+//!
+//! Signals are blocked except while waiting in pselect(), so that they can
+//! only interrupt the wait (see the `signal` module).
+//!
+//! Given `--unix`, the server serves a Unix domain datagram socket instead.
+//! nix's socket addresses cover both families, so nothing else changes.
+
+use std::cmp;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::time::Duration;
+use libc;
+use nix::sys::select::*;
+use nix::sys::socket::*;
+use nix::Errno;
+use backend::EchoBackend;
+use config::Config;
+use echo::{EchoCore, Received};
+use message::Message;
+use net::{bind_sockets, io_error, local_addr, recv_from, send_to};
+use signal::{self, SignalMask};
+use syscalls::{Syscall, Syscalls};
+
+/// The select() backend.
+pub struct Select;
+
+impl EchoBackend for Select {
+    fn name(&self) -> &'static str {
+        "select"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        // Block the signals until we wait for the sockets.
+        let signals = SignalMask::block()?;
+        let mut config = config.clone();
+        let mut drain = config.drain();
+        let mut syscalls = Syscalls::new();
+        let report_timer = config.report_timer()?;
+
+        // Open the configured number of UDP sockets in non-blocking mode, and bind them to
+        // consecutive ports on each configured address (by default IPv4 localhost, port 2000), or
+        // the Unix domain socket.  Its file is removed once `_socket_file` is dropped.
+        let (socket_fds, _socket_file) = bind_sockets(&config, SockType::Datagram)?;
+        let mut sockets = vec![];
+        for socket_fd in socket_fds {
+            if socket_fd >= libc::FD_SETSIZE as RawFd {
+                return Err(io::Error::other(format!(
+                    "socket descriptor {} is beyond FD_SETSIZE ({}); try the poll backend",
+                    socket_fd,
+                    libc::FD_SETSIZE
+                )));
+            }
+            println!("listening on {}", local_addr(socket_fd).map_err(io_error)?);
+            let core: EchoCore<SockAddr> = config.echo_core();
+            sockets.push(EchoSocket { socket_fd, core });
+        }
+
+        let mut inbuf = config.buffer();
+        let mut read_fd_set = FdSet::new();
+        let mut write_fd_set = FdSet::new();
+        loop {
+            // Set up read/write file descriptor sets according to each echo core's interest.  (A
+            // core only asks to write when there are outgoing packets to send.)
+            read_fd_set.clear();
+            write_fd_set.clear();
+            let mut nfds = 0;
+            if let Some(ref timer) = report_timer {
+                read_fd_set.insert(timer.as_raw_fd());
+                nfds = timer.as_raw_fd() + 1;
+            }
+            for socket in &sockets {
+                let interest = socket.core.interest();
+                if interest.read {
+                    read_fd_set.insert(socket.socket_fd);
+                }
+                if interest.write {
+                    write_fd_set.insert(socket.socket_fd);
+                }
+                nfds = cmp::max(nfds, socket.socket_fd + 1);
+            }
+
+            // Wait for the sockets to be ready for reading
+            // (and/or writing, if there are outgoing packets to send), for the report timer to
+            // expire, or for a signal.  While shutting down, only wait until the drain deadline.
+            let wait = drain.wait();
+            match syscalls.call(Syscall::Select, || {
+                pselect(nfds, &mut read_fd_set, &mut write_fd_set, wait, signals.unblocked())
+            }) {
+                Ok(_) => (),
+                Err(nix::Error::Sys(nix::errno::EINTR)) => {
+                    // A signal handler interrupted pselect(), and the sets are undefined.
+                    read_fd_set.clear();
+                    write_fd_set.clear();
+                }
+                Err(e) => return Err(io_error(e)),
+            }
+            let mut signalled = false;
+            for signal in signals.signals() {
+                let cores = sockets.iter_mut().map(|socket| &mut socket.core);
+                signalled |= signal::handle(signal, &mut config, &mut drain, cores);
+            }
+
+            // Process events.  Unlike epoll, select() doesn't tell us which descriptors are
+            // ready, so every socket must be checked against the sets.
+            for socket in &mut sockets {
+                let EchoSocket {
+                    socket_fd,
+                    ref mut core,
+                } = *socket;
+                if read_fd_set.contains(socket_fd) {
+                    // Read from the socket.
+                    core.readable();
+                    let (nbytes, addr) = syscalls
+                        .call(Syscall::RecvFrom, || recv_from(socket_fd, &mut inbuf))
+                        .map_err(io_error)?;
+                    println!("recv {} bytes from {}.", nbytes, addr);
+
+                    // Echo by handing the message to the echo core, which queues it for sending.
+                    match core.received(Message::new(inbuf[0..nbytes].to_vec(), addr)) {
+                        Received::Queued(_) => (),
+                        overflow => println!("{} ({})", overflow, core.stats()),
+                    }
+                }
+                if write_fd_set.contains(socket_fd) {
+                    // Write to the socket.
+                    core.writable();
+                    let message = core.poll_send().unwrap();
+                    match syscalls.call(Syscall::SendTo, || {
+                        send_to(socket_fd, &message.buffer, &message.addr)
+                    }) {
+                        Ok(nbytes) => {
+                            core.sent();
+                            println!("sent {} bytes to {}.", nbytes, message.addr);
+                        }
+                        Err(nix::Error::Sys(errno)) if errno == nix::errno::EWOULDBLOCK => {
+                            core.send_would_block(message);
+                        }
+                        Err(e) => {
+                            // E.g. a Unix domain peer which has gone away.
+                            println!("failed to send to {}: {}", message.addr, e);
+                            core.send_failed();
+                        }
+                    }
+                }
+            }
+
+            if let Some(ref timer) = report_timer {
+                if read_fd_set.contains(timer.as_raw_fd()) {
+                    syscalls.report_expired(timer, received(&sockets))?;
+                }
+            }
+
+            if drain.check(sockets.iter_mut().map(|socket| &mut socket.core), signalled) {
+                break;
+            }
+        }
+
+        for (index, socket) in sockets.iter().enumerate() {
+            println!("socket #{}: {}", index, socket.core.stats());
+        }
+        syscalls.report(received(&sockets));
+        Ok(())
+    }
+}
+
+/// select(), except that the signal mask is atomically replaced by `mask`
+/// for the duration of the wait.
+pub fn pselect(
+    nfds: libc::c_int,
+    read_fd_set: &mut FdSet,
+    write_fd_set: &mut FdSet,
+    timeout: Option<Duration>,
+    mask: &libc::sigset_t,
+) -> nix::Result<libc::c_int> {
+    let timeout = timeout.map(|timeout| libc::timespec {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_nsec: libc::c_long::from(timeout.subsec_nanos()),
+    });
+    // nix's FdSet has the same layout as fd_set.
+    let result = unsafe {
+        libc::pselect(
+            nfds,
+            read_fd_set as *mut FdSet as *mut libc::fd_set,
+            write_fd_set as *mut FdSet as *mut libc::fd_set,
+            ptr::null_mut(),
+            timeout.as_ref().map_or(ptr::null(), |timeout| timeout),
+            mask,
+        )
+    };
+    Errno::result(result)
+}
+
+// The datagrams received by all the sockets.
+fn received(sockets: &[EchoSocket]) -> u64 {
+    sockets.iter().map(|socket| socket.core.stats().received).sum()
+}
+
+/// A socket with its own echo core.
+struct EchoSocket {
+    socket_fd: RawFd,
+    core: EchoCore<SockAddr>,
+}
@@ -0,0 +1,230 @@
+//! A simple UDP echo server using Tokio to multiplex reads and writes.
+//!
+//! This is similar to tokio-core's echo-udp.rs example program, but it
+//! performs true multiplexing of reads and writes through the use of an
+//! outgoing packet queue.  Incoming messages may be received and
+//! processed while outgoing writes are pending.  This is in contrast to
+//! the flip-flop operation of echo-udp.rs where the program is either in
+//! a sending state or receiving state at any given point in time.
+//!
+//! The server is a future which resolves, ending `Core::run()`, once it has
+//! been shut down and has drained its outgoing queues.  It serves one
+//! socket per bind address (see `--bind`), or more with `--sockets`, each
+//! with its own echo core.  Signals arrive as a stream (see the `signal`
+//! module), which the server polls along with its sockets.  Given
+//! `--unix`, it serves a Unix domain datagram socket instead, driven
+//! through `PollEvented` (see the `unix` module).
+//!
+//! For reference, the tokio-core echo-udp.rs source may be found here:
+//! https://github.com/tokio-rs/tokio-core/blob/master/examples/echo-udp.rs
+
+use std::io;
+use futures::{Async, Future, Poll, Stream};
+use tokio_core::net::UdpSocket;
+use tokio_core::reactor::{Core, Handle, PollEvented, Timeout};
+use backend::{DatagramSocket, EchoBackend};
+use config::Config;
+use echo::{EchoCore, Received};
+use message::Message;
+use net::udp_socket;
+use shutdown::Drain;
+use signal::{self, SignalStream};
+use unix::UnixDatagram;
+
+/// The Tokio backend.
+pub struct Tokio;
+
+impl EchoBackend for Tokio {
+    fn name(&self) -> &'static str {
+        "tokio"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        // Create the tokio event loop
+        let mut core = Core::new()?;
+        let handle = core.handle();
+
+        if let Some(ref addr) = config.unix {
+            let socket = PollEvented::new(UnixDatagram::bind(addr)?, &handle)?;
+            return run_server(&mut core, UdpServer::new(vec![socket], config, &handle)?);
+        }
+
+        // Open UDP sockets in non-blocking mode bound to the configured addresses (by default
+        // IPv4 localhost port 2000).
+        let mut sockets = vec![];
+        for addr in config.addrs() {
+            let socket = udp_socket(&addr, config.v6_only)?;
+            sockets.push(UdpSocket::from_socket(socket, &handle)?);
+        }
+        run_server(&mut core, UdpServer::new(sockets, config, &handle)?)
+    }
+}
+
+/// Run `server` on the tokio event loop.  Creating the server installed the
+/// signal handlers, so the addresses are only announced now that signals
+/// will be handled.
+fn run_server<S: DatagramSocket>(core: &mut Core, server: UdpServer<S>) -> io::Result<()> {
+    for socket in &server.sockets {
+        println!("listening on {}", socket.socket.local_addr()?);
+    }
+    core.run(server)
+}
+
+/// A future which echoes datagrams received on its sockets.  It completes
+/// once the server has been shut down and has drained its outgoing queues
+/// (or given up on them at the drain deadline), or if an I/O error occurs.
+pub struct UdpServer<S: DatagramSocket> {
+    sockets: Vec<EchoSocket<S>>,
+    max_message_size: usize,
+    config: Config,
+    signals: SignalStream,
+    drain: Drain,
+    deadline: Option<Timeout>,
+    handle: Handle,
+}
+
+impl<S: DatagramSocket> UdpServer<S> {
+    pub fn new(sockets: Vec<S>, config: &Config, handle: &Handle) -> io::Result<UdpServer<S>> {
+        let sockets = sockets
+            .into_iter()
+            .map(|socket| EchoSocket {
+                socket,
+                core: config.echo_core(),
+            })
+            .collect();
+        Ok(UdpServer {
+            sockets,
+            max_message_size: config.max_message_size,
+            config: config.clone(),
+            signals: SignalStream::new(handle)?,
+            drain: config.drain(),
+            deadline: None,
+            handle: handle.clone(),
+        })
+    }
+
+    // Return true once the server has shut down and drained, or the drain deadline has passed.  A
+    // timeout arranges for the task to be notified at the deadline.
+    fn poll_drained(&mut self) -> io::Result<bool> {
+        if self.drain.check(self.sockets.iter_mut().map(|s| &mut s.core), false) {
+            return Ok(true);
+        }
+        if let Some(wait) = self.drain.wait() {
+            if self.deadline.is_none() {
+                self.deadline = Some(Timeout::new(wait, &self.handle)?);
+            }
+            if let Some(ref mut deadline) = self.deadline {
+                if deadline.poll()?.is_ready() {
+                    let cores = self.sockets.iter_mut().map(|s| &mut s.core);
+                    return Ok(self.drain.check(cores, false));
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<S: DatagramSocket> Future for UdpServer<S> {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        // Handle any signals.  A shutdown signal stops reading just as the shutdown command does.
+        while let Async::Ready(Some(signal)) = self.signals.poll()? {
+            let cores = self.sockets.iter_mut().map(|s| &mut s.core);
+            if signal::handle(signal, &mut self.config, &mut self.drain, cores) {
+                for socket in &mut self.sockets {
+                    socket.core.shutdown();
+                }
+            }
+        }
+
+        for socket in &mut self.sockets {
+            socket.poll_io(self.max_message_size)?;
+        }
+
+        if self.poll_drained()? {
+            for (index, socket) in self.sockets.iter().enumerate() {
+                println!("socket #{}: {}", index, socket.core.stats());
+            }
+            return Ok(Async::Ready(()));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+/// A socket with its own echo core.
+struct EchoSocket<S: DatagramSocket> {
+    socket: S,
+    core: EchoCore<S::Addr>,
+}
+
+impl<S: DatagramSocket> EchoSocket<S> {
+    // Read and write as needed, storing read packets in the echo core's outgoing queue for later
+    // writing.  We avoid the try_nb! macro here (and its potential for early return) so that a
+    // WouldBlock on either the reading or writing doesn't prevent progress on the other.
+    fn poll_io(&mut self, max_message_size: usize) -> io::Result<()> {
+        // Tokio tracks the socket's readiness for us, so being polled means that reading and
+        // writing should both be attempted.  A WouldBlock arranges for the task to be notified
+        // when the socket becomes ready again.
+        self.core.readable();
+        self.core.writable();
+
+        // Loop until no progress can be made on either reading or writing.
+        loop {
+            let mut progress = false;
+
+            // If an outgoing buffer is present, try to send it.  The echo goes back to the address
+            // exactly as received, which for an IPv4 peer of a dual-stack socket is v4-mapped.
+            if let Some(message) = self.core.poll_send() {
+                match self.socket.send_to(&message.buffer, &message.addr) {
+                    Ok(nbytes) => {
+                        println!("sent {} bytes to {}", nbytes, message.addr);
+                        self.core.sent();
+                        progress = true;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        // Writing would block -- re-queue this buffer and move on to reading.
+                        println!("sending would block; defer.");
+                        self.core.send_would_block(message);
+                    }
+                    Err(e) => {
+                        // E.g. a Unix domain peer which has gone away.
+                        println!("failed to send to {}: {}", message.addr, e);
+                        self.core.send_failed();
+                        progress = true;
+                    }
+                }
+            }
+
+            // Read from the socket, if possible.
+            if self.core.wants_read() {
+                let mut buffer = vec![0; max_message_size];
+                match self.socket.recv_from(&mut buffer) {
+                    Ok((nbytes, addr)) => {
+                        println!("recv {} bytes from {}", nbytes, addr);
+
+                        // Trim the buffer, and hand it to the echo core for queueing.
+                        buffer.truncate(nbytes);
+                        match self.core.received(Message::new(buffer, addr)) {
+                            Received::Queued(_) => (),
+                            overflow => println!("{} ({})", overflow, self.core.stats()),
+                        }
+                        progress = true;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        println!("reading would block.");
+                        self.core.read_would_block();
+                    }
+                    Err(e) => {
+                        return Err(e);
+                    }
+                }
+            }
+
+            if !progress {
+                return Ok(());
+            }
+        }
+    }
+}
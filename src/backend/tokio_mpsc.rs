@@ -0,0 +1,428 @@
+//! A simple UDP echo server using Tokio to multiplex reads and writes.
+//! This is an alternate implementation that uses separate "reader" and
+//! "writer" futures connected by an MPSC queue.
+//!
+//! Here the MPSC queue is the outgoing queue, so the overflow policies
+//! are carried out by the reader when the queue has no room: it drops the
+//! new datagram, holds on to it and stops reading until there is room, or
+//! spills it to a secondary buffer which it forwards from first.  The
+//! reader can't take back a datagram it has already handed to the
+//! writer, so the drop-oldest policy is not supported.  Likewise, the
+//! MPSC queue is always served in FIFO order, so fair queuing is not
+//! supported either.  A channel holds one message per sender beyond its
+//! buffer, so it is created one short of `--queue-depth`, which must be at
+//! least 1.
+//!
+//! Each priority lane is a channel of its own (with a spill buffer of its
+//! own), into which the reader forwards the datagrams of that lane, and
+//! the writer always takes the next echo from the most urgent channel
+//! with any pending.
+//!
+//! There is a single reader and writer, and so a single socket: only one
+//! `--bind` address may be given, and `--sockets` may not exceed 1.  Nor
+//! does it serve Unix domain sockets (see `--unix`).
+//!
+//! On the shutdown command or a signal, the reader stops reading, forwards
+//! any datagram it is holding on to, and finishes, dropping its end of the
+//! MPSC queue.  The writer finishes once it has sent everything left in
+//! the queue, or when the drain deadline they share passes.  The reader
+//! also handles the other signals, which arrive as a stream.
+
+use std::cell::RefCell;
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Duration;
+use futures;
+use futures::{Async, Future, Poll};
+use futures::Sink;
+use futures::Stream;
+use futures::sync::mpsc;
+use tokio_core::net::UdpSocket;
+use tokio_core::reactor::{Core, Handle, Timeout};
+use backend::EchoBackend;
+use config::Config;
+use echo::{EchoStats, Received};
+use fair::Fairness;
+use lanes::Lanes;
+use net::udp_socket;
+use queue::{OutgoingQueue, Overflow};
+use shutdown::Drain;
+use signal::{self, Signal, SignalStream};
+
+type Message = ::message::Message<SocketAddr>;
+
+/// The Tokio backend with separate reader and writer futures.
+pub struct TokioMpsc;
+
+impl EchoBackend for TokioMpsc {
+    fn name(&self) -> &'static str {
+        "tokio-mpsc"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        if config.overflow == Overflow::DropOldest {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the tokio-mpsc backend does not support --overflow=drop-oldest",
+            ));
+        }
+        if config.fairness != Fairness::Fifo {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the tokio-mpsc backend only supports --fairness=fifo",
+            ));
+        }
+        if config.queue_depth == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the tokio-mpsc backend only supports a --queue-depth of at least 1",
+            ));
+        }
+        if config.bind.len() > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the tokio-mpsc backend only supports a single --bind address",
+            ));
+        }
+        if config.sockets > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the tokio-mpsc backend only supports --sockets=1",
+            ));
+        }
+        if config.unix.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the tokio-mpsc backend does not support --unix",
+            ));
+        }
+
+        // Create the tokio event loop
+        let mut core = Core::new()?;
+
+        // Open a UDP socket in non-blocking mode bound to the configured address (by default
+        // IPv4 localhost port 2000).
+        let socket = udp_socket(&config.addr(), config.v6_only)?;
+        let socket = UdpSocket::from_socket(socket, &core.handle())?;
+
+        // Create the reader and writer futures, connected by an MPSC queue per priority lane, and
+        // join them into a single composite future.  Arranging for the reader and writer futures
+        // to each run in their own separately-scheduled task (via spawn()) is left as an exercise
+        // for the reader.  The sender's own slot makes up the queue depth.
+        let (txs, rxs) = (0..config.priority_lanes)
+            .map(|_| mpsc::channel(config.queue_depth - 1))
+            .unzip();
+        let handle = core.handle();
+        let drain = Rc::new(RefCell::new(config.drain()));
+        let reader = UdpReader::new(&socket, txs, config, drain.clone(), &handle)?;
+        let writer = UdpWriter::new(&socket, rxs, drain, &handle);
+        // The reader installs the signal handlers, so announce the address only once signals will
+        // be handled.
+        println!("listening on {}", socket.local_addr()?);
+        let server = writer.join(reader);
+
+        // Run the tokio event loop
+        core.run(server).map(|_| ())
+    }
+}
+
+
+struct UdpReader<'a> {
+    socket: &'a UdpSocket,
+    txs: Vec<mpsc::Sender<Message>>, // One per priority lane, most urgent first.
+    message: Option<Message>,
+    message_poll: Option<usize>, // The lane whose MPSC queue needs flushing.
+    max_message_size: usize,
+    overflow: Overflow,
+    spill: Lanes<SocketAddr>,
+    paused: bool,
+    stats: EchoStats,
+    config: Config,
+    signals: SignalStream,
+    drain: Rc<RefCell<Drain>>,
+}
+
+impl<'a> UdpReader<'a> {
+    fn new(
+        socket: &'a UdpSocket,
+        txs: Vec<mpsc::Sender<Message>>,
+        config: &Config,
+        drain: Rc<RefCell<Drain>>,
+        handle: &Handle,
+    ) -> io::Result<UdpReader<'a>> {
+        let spill_depth = if config.overflow == Overflow::Spill {
+            config.spill_depth
+        } else {
+            0
+        };
+        let spill = (0..txs.len())
+            .map(|_| OutgoingQueue::with_capacity(spill_depth).into())
+            .collect();
+        Ok(UdpReader {
+            socket,
+            txs,
+            message: None,
+            message_poll: None,
+            max_message_size: config.max_message_size,
+            overflow: config.overflow,
+            spill: Lanes::new(spill),
+            paused: false,
+            stats: EchoStats::default(),
+            config: config.clone(),
+            signals: SignalStream::new(handle)?,
+            drain,
+        })
+    }
+
+    /// Stop reading, and start the drain deadline.
+    fn shutdown(&mut self) {
+        if self.drain.borrow_mut().start() {
+            println!("Reader: shutting down; draining pending writes.");
+        }
+    }
+
+    /// Add a message to the back of its lane's spill buffer, or drop it if that is full.
+    fn spill(&mut self, message: Message) {
+        let received = if self.spill.push(message).is_ok() {
+            self.stats.spilled += 1;
+            Received::Spilled(self.spill.len())
+        } else {
+            self.stats.dropped += 1;
+            Received::Dropped
+        };
+        println!("Reader: {} ({})", received, self.stats());
+    }
+
+    fn stats(&self) -> EchoStats {
+        EchoStats {
+            pending: self.spill.len(),
+            ..self.stats
+        }
+    }
+}
+
+impl<'a> Future for UdpReader<'a> {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        println!("Reader: poll()");
+
+        // Handle any signals.  A shutdown signal stops reading just as the shutdown command does.
+        while let Async::Ready(Some(signal)) = self.signals.poll()? {
+            match signal {
+                Signal::Shutdown => self.shutdown(),
+                Signal::Stats => println!("Reader: {}", self.stats()),
+                Signal::Reload => {
+                    if signal::reload(&mut self.config) {
+                        let timeout = Duration::from_millis(self.config.drain_timeout);
+                        self.drain.borrow_mut().set_timeout(timeout);
+                    }
+                }
+            }
+        }
+        if self.drain.borrow().expired() {
+            // Give up on anything not yet handed to the writer.
+            println!("Reader: {}", self.stats());
+            return Ok(Async::Ready(()));
+        }
+
+        if let Some(lane) = self.message_poll {
+            // A previous poll() set the message_poll flag indicating that the MPSC queue needs to
+            // be flushed, so flush it.
+            match self.txs[lane].poll_complete() {
+                Ok(Async::Ready(())) => {
+                    self.message_poll = None;
+                }
+                Ok(Async::NotReady) => {
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => {
+                    panic!("Error flushing MPSC sink: {:?}", e);
+                }
+            }
+        }
+
+        // Spilled messages are older than one received on the previous poll(), so they are sent to
+        // the writer first, the most urgent lane's first.
+        let from_spill = !self.spill.is_empty();
+        if from_spill {
+            if let Some(message) = self.message.take() {
+                self.spill(message);
+            }
+            self.message = self.spill.pop();
+        }
+
+        // If a message was received on the previous poll(), begin sending it to the writer via the
+        // MPSC queue of its lane.
+        let message = self.message.take();
+        if let Some(message) = message {
+            let lane = self.spill.lane_of(&message);
+            match self.txs[lane].start_send(message) {
+                Ok(futures::AsyncSink::Ready) => {
+                    println!("Reader: Message sent to the MPSC sink.");
+                    self.paused = false;
+                    // Flag that the next iteration of poll() should call poll_complete() on the
+                    // sink, and arrange to be polled again as soon as possible.
+                    self.message_poll = Some(lane);
+                    futures::task::current().notify();
+                    return Ok(Async::NotReady);
+                }
+                Ok(futures::AsyncSink::NotReady(m)) => {
+                    println!("Reader: Message NOT sent to the MPSC sink.");
+                    // The MPSC queue is full, so apply the overflow policy.  Except when we stop
+                    // reading (and try again later), we go on to read the next datagram.
+                    match self.overflow {
+                        Overflow::StopReading => {
+                            if !self.paused {
+                                self.paused = true;
+                                self.stats.pauses += 1;
+                                println!("Reader: {} ({})", Received::Paused(1), self.stats());
+                            }
+                            self.message = Some(m);
+                            return Ok(Async::NotReady);
+                        }
+                        Overflow::Spill if from_spill => self.spill.requeue(m),
+                        Overflow::Spill => self.spill(m),
+                        _ => {
+                            self.stats.dropped += 1;
+                            println!("Reader: {} ({})", Received::Dropped, self.stats());
+                        }
+                    }
+                }
+                Err(e) => {
+                    panic!("Error sending to MPSC sink: {:?}", e);
+                }
+            }
+        }
+
+        // Once shutting down, finish (dropping our end of the MPSC queue, which tells the writer
+        // that nothing more is coming) as soon as everything we held on to has been forwarded.
+        if self.drain.borrow().is_started() {
+            if self.message.is_none() && self.spill.is_empty() && self.message_poll.is_none() {
+                println!("Reader: {}", self.stats());
+                return Ok(Async::Ready(()));
+            }
+            return Ok(Async::NotReady);
+        }
+
+        // Read from the socket, if possible.
+        // Note that try_nb! will return if recv_from() returns a WouldBlock error.
+        let mut buffer = vec![0; self.max_message_size];
+        let (nbytes, addr) = try_nb!(self.socket.recv_from(&mut buffer));
+        println!("Reader: Message received.");
+        self.stats.received += 1;
+        if self.config.is_shutdown_command(&buffer[..nbytes]) {
+            self.shutdown();
+            futures::task::current().notify();
+            return Ok(Async::NotReady);
+        }
+
+        // If this point is reached, then we were able to read a datagram.  Trim the buffer and
+        // store the message.  It will be processed in the next poll().
+        //
+        // This is a bit different from the usual recommended method of trying to read as much as
+        // possible in each poll() by looping on recv_from() until WouldBlock is indicated.
+        // Instead, we only read (at most) one datagram per poll().  If the read was successful, we
+        // ask the event loop to poll us again as soon as possible (in notify() below), then
+        // return.  This way, the event loop could theoretically choose to run other tasks and
+        // futures before calling us again, thus preventing our future from starving other tasks of
+        // cycles.  (Google "edge-triggered starvation" for more on this.)
+        buffer.truncate(nbytes);
+        self.message = Some(Message::new(buffer, addr));
+
+        // Arrange to be polled again as soon as possible.
+        futures::task::current().notify();
+
+        Ok(Async::NotReady)
+    }
+}
+
+struct UdpWriter<'a> {
+    socket: &'a UdpSocket,
+    rxs: Vec<mpsc::Receiver<Message>>, // One per priority lane, most urgent first.
+    message: Option<Message>,
+    drain: Rc<RefCell<Drain>>,
+    deadline: Option<Timeout>,
+    handle: Handle,
+}
+
+impl<'a> UdpWriter<'a> {
+    fn new(
+        socket: &'a UdpSocket,
+        rxs: Vec<mpsc::Receiver<Message>>,
+        drain: Rc<RefCell<Drain>>,
+        handle: &Handle,
+    ) -> UdpWriter<'a> {
+        UdpWriter {
+            socket,
+            rxs,
+            message: None,
+            drain,
+            deadline: None,
+            handle: handle.clone(),
+        }
+    }
+}
+
+impl<'a> Future for UdpWriter<'a> {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        println!("Writer: poll()");
+
+        // Once the reader has started shutting down, give up at the drain deadline.  Polling the
+        // timeout arranges for the task (which the reader shares) to be notified then.
+        let wait = self.drain.borrow().wait();
+        if let Some(wait) = wait {
+            if self.deadline.is_none() {
+                self.deadline = Some(Timeout::new(wait, &self.handle)?);
+            }
+            if let Some(ref mut deadline) = self.deadline {
+                if deadline.poll()?.is_ready() {
+                    println!("Writer: drain deadline passed; abandoning pending writes.");
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+
+        // If a previous poll() received a new message from the MPSC queue, then try to send it.
+        if let Some(ref message) = self.message {
+            println!("Writer: Trying to send message...");
+            // Note that try_nb! will return if send_to() indicates a WouldBlock error.
+            try_nb!(self.socket.send_to(&message.buffer, &message.addr));
+            println!("Writer: Message sent.");
+        }
+        self.message = None;
+
+        // Poll the MPSC queues, most urgent first.
+        let mut terminated = 0;
+        for rx in &mut self.rxs {
+            match rx.poll() {
+                Ok(Async::Ready(Some(message))) => {
+                    println!("Writer: Message received from MPSC queue.");
+                    // If a message was received, store it in our state and arrange to be polled
+                    // again as soon as possible.  In the next poll() we will try to send the
+                    // message.
+                    self.message = Some(message);
+                    futures::task::current().notify();
+                    return Ok(Async::NotReady);
+                }
+                Ok(Async::Ready(None)) => terminated += 1,
+                Ok(Async::NotReady) => {}
+                Err(e) => {
+                    panic!("error polling mpsc future: {:?}", e);
+                }
+            }
+        }
+        if terminated == self.rxs.len() {
+            // Every incoming stream has terminated, so our work here is done.
+            println!("Writer: drained; exiting.");
+            return Ok(Async::Ready(()));
+        }
+
+        Ok(Async::NotReady)
+    }
+}
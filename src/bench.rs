@@ -0,0 +1,388 @@
+//! A benchmark of the echo backends, used by the `bench-echo` program.
+//!
+//! The comments of the epoll and Mio examples claim that edge triggering
+//! can perform better than level triggering, among other things.  To
+//! check such claims on a given machine, the benchmark runs the `echo`
+//! program with each backend in turn, on an ephemeral loopback port,
+//! drives it with the same load (see the `load` module), and then shuts
+//! it down.  Each backend's row of the results gives the throughput of
+//! echoes, their round-trip latency, the fraction of datagrams dropped,
+//! and the CPU time the server used, as CSV or JSON.
+//!
+//! The servers still print a line per datagram, which costs them all
+//! alike; their output is read and discarded.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+use std::mem;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use libc;
+use backend;
+use config::{options, ConfigError, Opt};
+use load::{self, LoadConfig, Report, Targets};
+
+/// The formats the results can be written in.
+pub const FORMATS: &[&str] = &["csv", "json"];
+
+/// The format of the results.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// A header line, and a line of comma-separated values per backend.
+    #[default]
+    Csv,
+    /// An array with an object per backend.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("expected one of: {}", FORMATS.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Format::Csv => "csv",
+            Format::Json => "json",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The configuration of a benchmark, normally taken from the command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BenchConfig {
+    pub backends: Vec<String>, // The backends to run, in order.
+    pub load: LoadConfig, // The load each backend is driven with.  The target is ignored.
+    pub echo_options: Vec<String>, // Options given to every echo server.
+    pub format: Format, // The format of the results.
+    pub output: Option<PathBuf>, // A file to write the results to, instead of stdout.
+}
+
+impl Default for BenchConfig {
+    fn default() -> BenchConfig {
+        BenchConfig {
+            backends: backend::available().iter().map(|name| name.to_string()).collect(),
+            load: LoadConfig {
+                count: 10_000,
+                rate: 5000,
+                ..LoadConfig::default()
+            },
+            echo_options: vec![],
+            format: Format::default(),
+            output: None,
+        }
+    }
+}
+
+impl BenchConfig {
+    /// Parse command-line arguments (not including the program name) on
+    /// top of this configuration.
+    pub fn parse<I>(mut self, args: I) -> Result<BenchConfig, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        for opt in options(args)? {
+            if !self.apply(&opt)? {
+                return Err(ConfigError::Unknown(opt.name));
+            }
+        }
+        if self.backends.is_empty() {
+            return Err(ConfigError::Invalid(
+                "backends".to_string(),
+                "must name at least one backend".to_string(),
+            ));
+        }
+        for name in &self.backends {
+            if let Err(e) = backend::find(name) {
+                return Err(ConfigError::Invalid("backends".to_string(), e.to_string()));
+            }
+        }
+        // Check the load options as udp-load would.
+        let load = self.load.clone();
+        self.load = load.parse(Vec::<String>::new())?;
+        Ok(self)
+    }
+
+    /// Apply one command-line option.  Returns false if the option is not
+    /// recognized.  The load options are those of `udp-load`, except that
+    /// the target is always the server being benchmarked.
+    pub fn apply(&mut self, opt: &Opt) -> Result<bool, ConfigError> {
+        match opt.name.as_str() {
+            "backends" => self.backends = opt.parse_list()?,
+            "echo-options" => {
+                self.echo_options = opt.value()?.split_whitespace().map(String::from).collect()
+            }
+            "format" => self.format = opt.parse()?,
+            "output" => self.output = Some(PathBuf::from(opt.value()?)),
+            "target" | "compare" => return Ok(false),
+            _ => return self.load.apply(opt),
+        }
+        Ok(true)
+    }
+
+    /// A usage message, with this configuration's values as the defaults.
+    pub fn usage(&self, program: &str) -> String {
+        format!(
+            "Usage: {} [OPTIONS]
+
+Options:
+    --backends NAMES            backends to benchmark, separated by commas
+                                (default: {})
+    --echo-options 'OPTIONS'    options given to every echo server (default: none)
+    --format csv|json           format of the results (default: {})
+    --output FILE               write the results to FILE instead of stdout
+    --clients N                 number of clients, each with its own socket (default: {})
+    --count N                   datagrams to send across all clients (default: {})
+    --rate N                    datagrams per second across all clients;
+                                0 for no limit (default: {})
+    --size N|MIN-MAX            datagram size, or range of sizes (default: {}-{})
+    --wait MS                   time to wait for echoes after the last send (default: {})
+    --batch N                   datagrams per sendmmsg()/recvmmsg() call;
+                                1 uses sendto()/recvfrom() (default: {})
+    --help                      print this message
+",
+            program,
+            self.backends.join(","),
+            self.format,
+            self.load.clients,
+            self.load.count,
+            self.load.rate,
+            self.load.sizes.min,
+            self.load.sizes.max,
+            self.load.wait.as_millis(),
+            self.load.batch
+        )
+    }
+}
+
+/// The results of benchmarking one backend.
+#[derive(Clone, Debug)]
+pub struct BenchResult {
+    pub backend: String,
+    pub report: Report, // The load generator's report.
+    pub cpu: Duration, // The user and system time used by the server.
+}
+
+impl BenchResult {
+    /// Echoes received per second.
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.report.elapsed.as_secs_f64();
+        if seconds == 0.0 {
+            0.0
+        } else {
+            self.report.received as f64 / seconds
+        }
+    }
+
+    /// The fraction of the datagrams sent which were not echoed intact.
+    pub fn drop_rate(&self) -> f64 {
+        if self.report.sent == 0 {
+            0.0
+        } else {
+            (self.report.sent - self.report.received) as f64 / self.report.sent as f64
+        }
+    }
+
+    /// The values of this result's row, by column, or `None` for a
+    /// latency if no echoes came back.
+    pub fn values(&self) -> Vec<Option<String>> {
+        let latency = |percent| {
+            self.report
+                .percentile(percent)
+                .map(|latency| format!("{:.1}", latency.as_secs_f64() * 1e6))
+        };
+        vec![
+            Some(self.backend.clone()),
+            Some(self.report.sent.to_string()),
+            Some(self.report.received.to_string()),
+            Some(format!("{:.1}", self.throughput())),
+            latency(50.0),
+            latency(99.0),
+            Some(format!("{:.4}", self.drop_rate())),
+            Some(format!("{:.1}", self.cpu.as_secs_f64() * 1e3)),
+        ]
+    }
+}
+
+/// The columns of the results, in order.
+pub const COLUMNS: &[&str] = &[
+    "backend",
+    "sent",
+    "received",
+    "throughput",
+    "p50_us",
+    "p99_us",
+    "drop_rate",
+    "cpu_ms",
+];
+
+/// Format `results` as a table in `format`.  A missing latency is left
+/// empty in CSV, and is `null` in JSON.  (Backend names need no quoting or
+/// escaping in either.)
+pub fn table(results: &[BenchResult], format: Format) -> String {
+    let mut table = String::new();
+    match format {
+        Format::Csv => {
+            table.push_str(&COLUMNS.join(","));
+            table.push('\n');
+            for result in results {
+                let values: Vec<String> = result
+                    .values()
+                    .into_iter()
+                    .map(|value| value.unwrap_or_default())
+                    .collect();
+                table.push_str(&values.join(","));
+                table.push('\n');
+            }
+        }
+        Format::Json => {
+            let rows: Vec<String> = results
+                .iter()
+                .map(|result| {
+                    let mut values = result.values();
+                    values[0] = values[0].take().map(|backend| format!("\"{}\"", backend));
+                    let fields: Vec<String> = COLUMNS
+                        .iter()
+                        .zip(values)
+                        .map(|(column, value)| {
+                            format!("\"{}\": {}", column, value.unwrap_or_else(|| "null".into()))
+                        })
+                        .collect();
+                    format!("  {{{}}}", fields.join(", "))
+                })
+                .collect();
+            table.push_str("[\n");
+            table.push_str(&rows.join(",\n"));
+            table.push_str("\n]\n");
+        }
+    }
+    table
+}
+
+/// Benchmark each configured backend in turn, with the `echo` program at
+/// `echo`.  Progress is reported on stderr.
+pub fn run(echo: &Path, config: &BenchConfig) -> io::Result<Vec<BenchResult>> {
+    let mut results = vec![];
+    for backend in &config.backends {
+        eprintln!("benchmarking {}...", backend);
+        results.push(run_backend(echo, backend, config)?);
+    }
+    Ok(results)
+}
+
+/// Start the `echo` program with `backend`, drive it with the configured
+/// load, and shut it down.
+pub fn run_backend(echo: &Path, backend: &str, config: &BenchConfig) -> io::Result<BenchResult> {
+    let server = EchoServer::start(echo, backend, &config.echo_options)?;
+    let load = LoadConfig {
+        targets: Targets(vec![server.addr()]),
+        ..config.load.clone()
+    };
+    let report = load::run(&load);
+
+    // Shut the server down in an orderly way, so that its CPU time is accounted to us.
+    let before = children_cpu_time()?;
+    server.stop()?;
+    let cpu = children_cpu_time()? - before;
+    Ok(BenchResult {
+        backend: backend.to_string(),
+        report: report?,
+        cpu,
+    })
+}
+
+/// The `echo` program, run with one backend on an ephemeral loopback port.
+pub struct EchoServer {
+    backend: String,
+    child: Child,
+    addr: SocketAddr,
+    syscalls: JoinHandle<Vec<String>>, // Collects the lines of its system call reports.
+}
+
+impl EchoServer {
+    /// Run the `echo` program at `echo` with `backend` and `options`, once it
+    /// has announced where it is listening.
+    pub fn start(echo: &Path, backend: &str, options: &[String]) -> io::Result<EchoServer> {
+        let mut child = Command::new(echo)
+            .arg(format!("--backend={}", backend))
+            .arg("--port=0")
+            .args(options)
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("can't run {}: {}", echo.display(), e)))?;
+
+        // Wait for the server to say where it is listening, and then keep reading its output, so
+        // that it never blocks writing to it.  Only its system call reports are kept.
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut addr = None;
+        let mut line = String::new();
+        while addr.is_none() {
+            line.clear();
+            if stdout.read_line(&mut line)? == 0 {
+                let _ = child.wait();
+                return Err(io::Error::other(format!("the {} server didn't start", backend)));
+            }
+            if let Some(listening) = line.trim_end().strip_prefix("listening on ") {
+                addr = listening.parse::<SocketAddr>().ok();
+            }
+        }
+        let syscalls = thread::spawn(move || {
+            stdout
+                .split(b'\n')
+                .map_while(Result::ok)
+                .filter(|line| line.starts_with(b"syscall"))
+                .map(|line| String::from_utf8_lossy(&line).into_owned())
+                .collect()
+        });
+        Ok(EchoServer {
+            backend: backend.to_string(),
+            child,
+            addr: addr.unwrap(),
+            syscalls,
+        })
+    }
+
+    /// The address the server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Shut the server down with SIGTERM, and wait for it to exit.  Returns
+    /// the lines of the system call reports it printed, if any.
+    pub fn stop(mut self) -> io::Result<Vec<String>> {
+        unsafe {
+            libc::kill(self.child.id() as libc::pid_t, libc::SIGTERM);
+        }
+        let status = self.child.wait()?;
+        let syscalls = self.syscalls.join().unwrap_or_default();
+        if !status.success() {
+            return Err(io::Error::other(format!("the {} server failed: {}", self.backend, status)));
+        }
+        Ok(syscalls)
+    }
+}
+
+// The user and system time used by the children which have been waited for.
+fn children_cpu_time() -> io::Result<Duration> {
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let time = |tv: libc::timeval| {
+        Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000)
+    };
+    Ok(time(usage.ru_utime) + time(usage.ru_stime))
+}
@@ -0,0 +1,24 @@
+// A simple UDP echo server using Linux's io_uring.  Unlike the select,
+// epoll and mio servers, which wait until a socket is ready and then
+// receive or send themselves, this program submits the receives and sends
+// to the kernel through a submission queue, and collects their results
+// from a completion queue once the kernel has performed them.  The
+// sockets and the send buffers are registered with the ring once, and the
+// receive buffers provided to it once, so that no operation has to pass
+// them in again.  Compare it with echo-epoll-edge.rs, e.g. under udp-load.
+//
+// The server itself is implemented by the io-uring backend in
+// src/backend/io_uring.rs, which the echo program can also run via
+// --backend=io-uring.  It requires the io-uring feature flag, and Linux
+// 6.0 or later.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::backend::io_uring::IoUring;
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::EchoBackend;
+
+fn main() {
+    let config = Config::default().with_args();
+    IoUring.run(&config).unwrap();
+}
@@ -0,0 +1,19 @@
+// A simple UDP echo server using POSIX poll() to multiplex reads and
+// writes.  Unlike select(), poll() can watch descriptors of any value.
+// This program can only be compiled on platforms which support poll()
+// (Mac, Linux, etc.).
+//
+// The server itself is implemented by the poll backend in
+// src/backend/poll.rs, which the echo program can also run via
+// --backend=poll.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::backend::poll::Poll;
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::EchoBackend;
+
+fn main() {
+    let config = Config::from_args();
+    Poll.run(&config).unwrap();
+}
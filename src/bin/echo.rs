@@ -0,0 +1,56 @@
+// A UDP echo server which runs any of the echo backends, chosen at run
+// time with --backend.  Each backend behaves exactly like the
+// corresponding echo-* program, so scripts and tests can sweep across
+// all of the implementations with this single executable.
+//
+// The select, poll, epoll and io-uring backends are only available when the
+// corresponding feature flags are given; --list-backends prints the
+// backends compiled into this build.
+
+extern crate tokio_aio_examples;
+
+use std::process;
+use tokio_aio_examples::backend::{self, BACKENDS};
+use tokio_aio_examples::config::{program_name, Config};
+
+const DEFAULT_BACKEND: &str = "tokio";
+
+fn main() {
+    let mut backend_name = DEFAULT_BACKEND.to_string();
+    let mut list = false;
+    let options_usage = format!(
+        "    --backend NAME          one of {} (default: {})
+    --list-backends         print the backends available in this build
+",
+        BACKENDS.join(", "),
+        DEFAULT_BACKEND
+    );
+    let config = Config::default().with_args_and(&options_usage, |opt| {
+        match opt.name.as_str() {
+            "backend" => backend_name = opt.value()?.to_string(),
+            "list-backends" => list = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    });
+
+    if list {
+        for name in backend::available() {
+            println!("{}", name);
+        }
+        return;
+    }
+
+    let backend = match backend::find(&backend_name) {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("{}: {}", program_name(), e);
+            process::exit(2);
+        }
+    };
+    println!("backend: {}", backend.name());
+    if let Err(e) = backend.run(&config) {
+        eprintln!("{}: {} backend failed: {}", program_name(), backend.name(), e);
+        process::exit(1);
+    }
+}
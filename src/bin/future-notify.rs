@@ -2,10 +2,12 @@
 // for polling by calling the notify() method on their task.
 
 extern crate futures;
+extern crate tokio_aio_examples;
 
 use std::thread;
 use std::time::Instant;
 use futures::{Async, Future, Poll, task};
+use tokio_aio_examples::config::Config;
 
 struct MyFuture {
     count: usize,
@@ -41,6 +43,9 @@ impl Future for MyFuture {
 }
 
 fn main() {
+    // Accept the common command-line options, although no sockets are opened here.
+    Config::from_args();
+
     let future = MyFuture::new();
     future.wait().unwrap();
 }
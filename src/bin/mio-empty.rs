@@ -15,11 +15,14 @@
 // epoll_wait(3, 0x7f417262b000, 16, -1)   = ...
 
 extern crate mio;
+extern crate tokio_aio_examples;
 
 use mio::{Events, Poll};
+use tokio_aio_examples::config::{Config, MAX_EVENTS};
 
 fn main() {
-    const MAX_EVENTS: usize = 16;
+    // Accept the common command-line options, although no sockets are opened here.
+    Config::from_args();
 
     // Set up mio polling
     let poll = Poll::new().unwrap();
@@ -3,7 +3,8 @@
 // non-system events (e.g. events sourced on user-space threads other
 // than the thread running the mio poll).  We listen for incoming UDP
 // datagrams on port 2000, and also listen for events created by our
-// timer thread every three seconds.
+// timer thread every three seconds.  (The timer is a `MioTimer`, whose
+// thread is shared by any number of timers; see the timer module.)
 //
 // Running this program on Linux via strace shows how mio notifies the
 // polling thread of the non-system event by writing to a pipe:
@@ -15,97 +16,39 @@
 // 28364 read(5, 0x7ffc96a72cf8, 128)      = -1 EAGAIN (Resource temporarily unavailable)
 // 28364 write(1, "after poll\n", 11)      = 11
 // 28364 write(1, "3-second timer\n", 15)  = 15
+//
+// The trace was captured with the original timer, a thread of its own
+// which slept between ticks.  The shared timer thread writes to the pipe
+// in the same way, but waits for its next deadline on a futex instead of
+// the nanosleep().
 
 extern crate mio;
+extern crate tokio_aio_examples;
 
-use std::io;
-use std::thread;
-use std::time::{Duration, Instant};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
 use mio::net::UdpSocket;
-use mio::event::Evented;
-use mio::{Events, Poll, PollOpt, Ready, Registration, Token};
+use mio::{Events, Poll, PollOpt, Ready, Token};
+use tokio_aio_examples::config::{Config, MAX_EVENTS};
+use tokio_aio_examples::timer::TimerThread;
 
-const MAX_MESSAGE_SIZE: usize = 1500;
-const MAX_EVENTS: usize = 16;
-const ECHO_PORT: u16 = 2000;
 const TIMER_INTERVAL_SECONDS: u64 = 3;
 
-/// An Evented implementation which implements a simple timer by indicating readiness at periodic
-/// intervals.
-struct PeriodicTimer {
-    registration: Registration,
-    set_readiness: mio::SetReadiness,
-}
-
-impl PeriodicTimer {
-    /// Create a PeriodicTimer and begin signalling readiness at the specified interval.
-    fn new(interval: u64) -> PeriodicTimer {
-        let (registration, set_readiness) = Registration::new2();
-        let set_readiness_clone = set_readiness.clone();
-
-        // Spawn a thread to periodically trigger the timer by setting read readiness.
-        // Note: This example code omits important things like arranging termination of the thread
-        // when the PeriodicTimer is dropped.
-        thread::spawn(move || loop {
-            let now = Instant::now();
-            let when = now + Duration::from_secs(interval);
-            if now < when {
-                thread::sleep(when - now);
-            }
-            set_readiness_clone
-                .set_readiness(Ready::readable())
-                .unwrap();
-        });
-        PeriodicTimer {
-            registration,
-            set_readiness,
-        }
-    }
-
-    /// Clear the read readiness of this timer.
-    fn reset(&self) {
-        self.set_readiness.set_readiness(Ready::empty()).unwrap();
-    }
-}
-
-/// Proxy Evented functions to the Registration.
-impl Evented for PeriodicTimer {
-    fn register(
-        &self,
-        poll: &Poll,
-        token: Token,
-        interest: Ready,
-        opts: PollOpt,
-    ) -> io::Result<()> {
-        self.registration.register(poll, token, interest, opts)
-    }
-    fn reregister(
-        &self,
-        poll: &Poll,
-        token: Token,
-        interest: Ready,
-        opts: PollOpt,
-    ) -> io::Result<()> {
-        self.registration.reregister(poll, token, interest, opts)
-    }
-    fn deregister(&self, poll: &Poll) -> io::Result<()> {
-        <Registration as Evented>::deregister(&self.registration, poll)
-    }
-}
-
 fn main() {
-    let localhost = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+    let config = Config::from_args();
 
     // Create and bind the socket
-    let socket = UdpSocket::bind(&SocketAddr::new(localhost, ECHO_PORT)).unwrap();
+    let socket = UdpSocket::bind(&config.addr()).unwrap();
+    println!("listening on {}", socket.local_addr().unwrap());
+    let mut inbuf = config.buffer();
 
     // Set up mio polling
     let poll = Poll::new().unwrap();
     let mut events = Events::with_capacity(MAX_EVENTS);
     poll.register(&socket, Token(0), Ready::readable(), PollOpt::level())
         .unwrap();
-    let timer = PeriodicTimer::new(TIMER_INTERVAL_SECONDS);
+    let timers = TimerThread::new().unwrap();
+    let timer = timers.timer();
+    timer.set_interval(Duration::from_secs(TIMER_INTERVAL_SECONDS));
     poll.register(&timer, Token(1), Ready::readable(), PollOpt::level())
         .unwrap();
 
@@ -122,13 +65,14 @@ fn main() {
             assert!(event.readiness().is_readable());
             match event.token() {
                 Token(0) => {
-                    let mut inbuf = [0u8; MAX_MESSAGE_SIZE];
                     let (nbytes, addr) = socket.recv_from(&mut inbuf).unwrap();
                     println!("recv {} bytes from {}.", nbytes, addr);
                 }
                 Token(1) => {
-                    println!("{}-second timer", TIMER_INTERVAL_SECONDS);
-                    timer.reset();
+                    // Taking the expirations clears the timer's readiness.
+                    for _ in 0..timer.expirations() {
+                        println!("{}-second timer", TIMER_INTERVAL_SECONDS);
+                    }
                 }
                 Token(_) => {
                     panic!("Unknown token in poll.");
@@ -25,21 +25,21 @@
 // ...
 
 extern crate mio;
+extern crate tokio_aio_examples;
 
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::SocketAddr;
 use mio::net::UdpSocket;
 use mio::{Events, Poll, PollOpt, Ready, Registration, Token};
+use tokio_aio_examples::config::{Config, MAX_EVENTS};
 
 fn main() {
-    const MAX_MESSAGE_SIZE: usize = 1500;
-    const MAX_EVENTS: usize = 16;
-    const PORT: u16 = 2000;
-    let localhost = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
-    let send_address = SocketAddr::new(localhost, 0);
-    let recv_address = SocketAddr::new(localhost, PORT);
+    let config = Config::from_args();
+    let send_address = SocketAddr::new(config.bind[0], 0);
 
     // Create and bind the socket
-    let recv_socket = UdpSocket::bind(&recv_address).unwrap();
+    let recv_socket = UdpSocket::bind(&config.addr()).unwrap();
+    let recv_address = recv_socket.local_addr().unwrap();
+    println!("listening on {}", recv_address);
 
     // Set up mio polling
     let poll = Poll::new().unwrap();
@@ -53,7 +53,7 @@ fn main() {
     // Send a datagram to the listening socket.
     let send_socket = std::net::UdpSocket::bind(send_address).unwrap();
     send_socket
-        .send_to("hello".as_bytes(), &recv_address)
+        .send_to("hello".as_bytes(), recv_address)
         .unwrap();
 
     // Main loop
@@ -67,7 +67,7 @@ fn main() {
             assert!(event.readiness().is_readable());
             match event.token() {
                 Token(0) => {
-                    let mut inbuf = [0u8; MAX_MESSAGE_SIZE];
+                    let mut inbuf = config.buffer();
                     let (nbytes, addr) = recv_socket.recv_from(&mut inbuf).unwrap();
                     println!("recv {} bytes from {}.", nbytes, addr);
                     set_readiness.set_readiness(Ready::readable()).unwrap();
@@ -0,0 +1,81 @@
+// This program runs two periodic timers side by side on one mio instance:
+// a timerfd registered through `EventedFd`, and a `MioTimer`, whose shared
+// timer thread makes it ready through a `SetReadiness` (see mio-mixed.rs).
+// Both tick every second, and the poll reports which of them woke it.
+//
+// Running this program on Linux via strace shows the difference.  When the
+// timerfd expires, the kernel makes it readable, and epoll_wait() returns
+// its own event, which is followed by a read() of the timerfd's expiration
+// count.  When the MioTimer expires, the timer thread writes to mio's
+// readiness pipe, and epoll_wait() returns the pipe's event, which mio
+// drains with read()s until EAGAIN before reporting the timer's token (see
+// the trace in mio-mixed.rs).  So a timerfd tick costs the polling thread
+// an epoll_wait() and a read(), while a MioTimer tick costs it the pipe
+// reads, and costs the timer thread a pipe write and a futex wait besides.
+//
+// Every fifth turn, the program stalls for three and a half seconds before
+// polling again, as a busy event loop would.  Neither timer fires in a
+// burst afterwards: each reports the ticks it missed as a count of
+// expirations instead.
+
+extern crate mio;
+extern crate tokio_aio_examples;
+
+use std::thread;
+use std::time::Duration;
+use mio::{Events, Poll, PollOpt, Ready, Token};
+use tokio_aio_examples::config::{Config, MAX_EVENTS};
+use tokio_aio_examples::timer::{TimerFd, TimerThread};
+
+const TIMER_INTERVAL: Duration = Duration::from_secs(1);
+const STALL_EVERY: u64 = 5;
+const STALL: Duration = Duration::from_millis(3500);
+
+const TIMERFD: Token = Token(0);
+const MIO_TIMER: Token = Token(1);
+
+fn main() {
+    // Accept the common command-line options, although no sockets are opened here.
+    Config::from_args();
+
+    // Set up mio polling, with both timers ticking at the same interval.
+    let poll = Poll::new().unwrap();
+    let mut events = Events::with_capacity(MAX_EVENTS);
+    let timerfd = TimerFd::new().unwrap();
+    poll.register(&timerfd, TIMERFD, Ready::readable(), PollOpt::level())
+        .unwrap();
+    let timers = TimerThread::new().unwrap();
+    let mio_timer = timers.timer();
+    poll.register(&mio_timer, MIO_TIMER, Ready::readable(), PollOpt::level())
+        .unwrap();
+    timerfd.set_interval(TIMER_INTERVAL).unwrap();
+    mio_timer.set_interval(TIMER_INTERVAL);
+
+    // Main loop
+    for turn in 1.. {
+        if turn % STALL_EVERY == 0 {
+            println!("stalling for {:?}", STALL);
+            thread::sleep(STALL);
+        }
+
+        // Poll.  (mio may return without events after the MioTimer's readiness is cleared.)
+        poll.poll(&mut events, None).unwrap();
+
+        // Process events.  Taking each timer's expirations clears its readiness.
+        for event in &events {
+            match event.token() {
+                TIMERFD => {
+                    let expirations = timerfd.expirations().unwrap();
+                    println!("timerfd: {} expiration(s)", expirations);
+                }
+                MIO_TIMER => {
+                    let expirations = mio_timer.expirations();
+                    println!("mio timer: {} expiration(s)", expirations);
+                }
+                Token(_) => {
+                    panic!("Unknown token in poll.");
+                }
+            }
+        }
+    }
+}
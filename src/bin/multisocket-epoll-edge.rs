@@ -0,0 +1,21 @@
+// A multi-socket UDP echo server using the Linux epoll facility in
+// edge-triggered mode to multiplex reads and writes on IPv4 localhost
+// ports 2000 through 2009.  As with multisocket-epoll-level, the epoll
+// event data identifies the socket.  Each socket is limited to --budget
+// receives and sends per turn, and sockets with work left over are
+// serviced round-robin from a ready list, so a flood on one port cannot
+// starve the others.
+//
+// The server itself is implemented by the epoll-edge backend in
+// src/backend/epoll.rs, which is given ten sockets here.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::backend::epoll::EpollEdge;
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::EchoBackend;
+
+fn main() {
+    let config = Config::multisocket().with_args();
+    EpollEdge.run(&config).unwrap();
+}
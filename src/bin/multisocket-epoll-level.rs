@@ -0,0 +1,20 @@
+// A multi-socket UDP echo server using the Linux epoll facility in
+// level-triggered mode to multiplex reads and writes on IPv4 localhost
+// ports 2000 through 2009.  Each socket is registered with its index as
+// the epoll event data, so each event leads directly to the socket (and
+// echo core) it concerns, without scanning all of the sockets as
+// select() requires.
+//
+// The server itself is implemented by the epoll-level backend in
+// src/backend/epoll.rs, which is given ten sockets here.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::backend::epoll::EpollLevel;
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::EchoBackend;
+
+fn main() {
+    let config = Config::multisocket().with_args();
+    EpollLevel.run(&config).unwrap();
+}
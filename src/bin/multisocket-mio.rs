@@ -0,0 +1,21 @@
+// A multi-socket UDP echo server using the mio crate with
+// level-triggered events to multiplex reads and writes on IPv4 localhost
+// ports 2000 through 2009.  Each socket is registered with a distinct
+// Token (its index), which is how events are traced back to sockets.
+// The edge-triggered equivalent can be run as:
+//
+//     echo --backend=mio-edge --sockets=10
+//
+// The server itself is implemented by the mio-level backend in
+// src/backend/mio.rs, which is given ten sockets here.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::backend::mio::MioLevel;
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::EchoBackend;
+
+fn main() {
+    let config = Config::multisocket().with_args();
+    MioLevel.run(&config).unwrap();
+}
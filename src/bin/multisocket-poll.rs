@@ -0,0 +1,30 @@
+// A multi-socket UDP echo server using POSIX poll() to multiplex reads
+// and writes on IPv4 localhost ports 2000 through 3999.  Each socket has
+// its own echo core.  Two thousand sockets need descriptors beyond
+// FD_SETSIZE (1024), which select() can't watch (multisocket-select
+// refuses to start given --sockets=2000), but poll() takes an array of
+// descriptors rather than a bitmap of them.  Like select(), though, every
+// call must pass the whole array, and afterwards every entry must be
+// checked to see which sockets are ready.  The soft limit on open files
+// is raised if needed.  This program can only be compiled on platforms
+// which support poll() (Mac, Linux, etc.).
+//
+// The server itself is implemented by the poll backend in
+// src/backend/poll.rs, which is given two thousand sockets here.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::backend::poll::Poll;
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::EchoBackend;
+
+// More sockets than select() can watch.
+const SOCKETS: usize = 2000;
+
+fn main() {
+    let config = Config {
+        sockets: SOCKETS,
+        ..Config::multisocket()
+    };
+    Poll.run(&config.with_args()).unwrap();
+}
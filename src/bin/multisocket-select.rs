@@ -0,0 +1,21 @@
+// A multi-socket UDP echo server using POSIX select() to multiplex reads
+// and writes on IPv4 localhost ports 2000 through 2009.  Each socket
+// has its own echo core.  Every call to select() must rebuild the
+// descriptor sets for all ten sockets, and afterwards every socket must
+// be checked to see which are ready; this O(n) cost per wakeup is what
+// epoll was designed to avoid.  This program can only be compiled on
+// platforms which support select() (Mac, Linux, etc.).
+//
+// The server itself is implemented by the select backend in
+// src/backend/select.rs, which is given ten sockets here.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::backend::select::Select;
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::EchoBackend;
+
+fn main() {
+    let config = Config::multisocket().with_args();
+    Select.run(&config).unwrap();
+}
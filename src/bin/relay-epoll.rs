@@ -0,0 +1,199 @@
+// A producer-consumer UDP relay server using the Linux epoll facility to
+// multiplex reads and writes on two sockets.  This program uses
+// level-triggered events, and uses the epoll event data to tell the two
+// sockets apart.
+//
+// Producers send datagrams to IPv4 localhost port 2000.  Consumers
+// register their interest by sending an ASCII "1" to localhost port
+// 2001, and de-register by sending an ASCII "0".  Every datagram
+// received from a producer is relayed (from port 2001) to each
+// registered consumer.  The consumer port is always the one after the
+// configured --port, unless --port=0 requests ephemeral ports.
+//
+// The copies wait in the --priority-lanes of the outgoing queue, selected
+// by each datagram's first byte as in the echo servers, so that urgent
+// datagrams overtake bulk ones.
+//
+// Sending the --shutdown-command to the consumer port, SIGINT or SIGTERM
+// shuts the relay down: it stops reading, sends what remains in its
+// outgoing queue (until the --drain-timeout passes), prints its final
+// statistics and exits.
+
+extern crate nix;
+extern crate tokio_aio_examples;
+
+use nix::sys::epoll::*;
+use nix::sys::socket::*;
+use tokio_aio_examples::config::{Config, MAX_EVENTS};
+use tokio_aio_examples::net::{bind_udp, local_addr};
+use tokio_aio_examples::shutdown::SHUTDOWN_SIGNALS;
+use tokio_aio_examples::signal::{Signal, SignalPipe};
+use tokio_aio_examples::lanes::Lanes;
+use tokio_aio_examples::Message;
+
+const PRODUCER: u64 = 0;
+const CONSUMER: u64 = 1;
+const SIGNAL: u64 = 2;
+
+fn main() {
+    let config = Config::relay().with_args();
+
+    // Open two UDP sockets in non-blocking mode, and bind them to the first configured address
+    // and consecutive ports (by default IPv4 localhost, ports 2000 and 2001).
+    let producer_fd = bind_udp(&config.addr_at(0), config.v6_only).unwrap();
+    let consumer_fd = bind_udp(&config.addr_at(1), config.v6_only).unwrap();
+
+    // Create epoll events.  The event data identifies which socket is ready.
+    let mut producer_event = EpollEvent::new(EPOLLIN, PRODUCER);
+    let mut consumer_read_only = EpollEvent::new(EPOLLIN, CONSUMER);
+    let mut consumer_read_write = EpollEvent::new(EPOLLIN | EPOLLOUT, CONSUMER);
+    let mut consumer_write_only = EpollEvent::new(EPOLLOUT, CONSUMER);
+    let mut signal_event = EpollEvent::new(EPOLLIN, SIGNAL);
+    let mut current_events = [EpollEvent::empty(); MAX_EVENTS];
+
+    // Set up epoll
+    let epoll_fd = epoll_create1(EpollCreateFlags::empty()).unwrap();
+    epoll_ctl(
+        epoll_fd,
+        EpollOp::EpollCtlAdd,
+        producer_fd,
+        &mut producer_event,
+    ).unwrap();
+    epoll_ctl(
+        epoll_fd,
+        EpollOp::EpollCtlAdd,
+        consumer_fd,
+        &mut consumer_read_only,
+    ).unwrap();
+
+    // SIGINT and SIGTERM make the signal pipe readable.
+    let signal = SignalPipe::install(SHUTDOWN_SIGNALS).unwrap();
+    epoll_ctl(epoll_fd, EpollOp::EpollCtlAdd, signal.fd(), &mut signal_event).unwrap();
+    let mut drain = config.drain();
+
+    // Announce the addresses only once signals will be handled.
+    println!("producers: listening on {}", local_addr(producer_fd).unwrap());
+    println!("consumers: listening on {}", local_addr(consumer_fd).unwrap());
+
+    let mut sent = 0;
+    let mut consumers: Vec<SockAddr> = vec![];
+    let mut outgoing_queue: Lanes<SockAddr> = config.lanes();
+    let mut inbuf = config.buffer();
+    loop {
+        // Set up events.  Only the consumer socket is ever written to, and it is only read from
+        // until shutting down.
+        if drain.is_started() {
+            epoll_ctl(
+                epoll_fd,
+                EpollOp::EpollCtlMod,
+                consumer_fd,
+                &mut consumer_write_only,
+            ).unwrap();
+        } else if outgoing_queue.is_empty() {
+            epoll_ctl(
+                epoll_fd,
+                EpollOp::EpollCtlMod,
+                consumer_fd,
+                &mut consumer_read_only,
+            ).unwrap();
+        } else {
+            epoll_ctl(
+                epoll_fd,
+                EpollOp::EpollCtlMod,
+                consumer_fd,
+                &mut consumer_read_write,
+            ).unwrap();
+        }
+
+        // Select via epoll.  While shutting down, only wait until the drain deadline.
+        let timeout = drain
+            .wait()
+            .map_or(-1, |wait| wait.as_micros().div_ceil(1000) as isize);
+        let num_events = match epoll_wait(epoll_fd, &mut current_events, timeout) {
+            Err(nix::Error::Sys(nix::errno::EINTR)) => 0,
+            result => result.unwrap(),
+        };
+
+        // Process events
+        for event in &current_events[0..num_events] {
+            match event.data() {
+                SIGNAL => {
+                    if signal.signals().contains(&Signal::Shutdown) && drain.start() {
+                        // Stop reading from producers.
+                        epoll_ctl(epoll_fd, EpollOp::EpollCtlDel, producer_fd, &mut producer_event)
+                            .unwrap();
+                        println!("shutting down; draining {} pending writes.", outgoing_queue.len());
+                    }
+                }
+                PRODUCER if drain.is_started() => (),
+                PRODUCER => {
+                    let (nbytes, addr) = recvfrom(producer_fd, &mut inbuf).unwrap();
+                    println!("recv {} bytes from producer {}.", nbytes, addr);
+
+                    // Relay by pushing one copy of the message per consumer to our outgoing queue.
+                    for consumer in &consumers {
+                        let message = Message::new(inbuf[0..nbytes].to_vec(), *consumer);
+                        if outgoing_queue.push(message).is_err() {
+                            println!("outgoing buffers exhausted; dropping packet for {}.", consumer);
+                        }
+                    }
+                    println!("total pending writes: {}", outgoing_queue.len());
+                }
+                CONSUMER => {
+                    if event.events().contains(EPOLLIN) {
+                        let (nbytes, addr) = recvfrom(consumer_fd, &mut inbuf).unwrap();
+                        match inbuf[0..nbytes].trim_ascii() {
+                            command if config.is_shutdown_command(command) => {
+                                if drain.start() {
+                                    // Stop reading from producers.
+                                    epoll_ctl(
+                                        epoll_fd,
+                                        EpollOp::EpollCtlDel,
+                                        producer_fd,
+                                        &mut producer_event,
+                                    ).unwrap();
+                                    println!(
+                                        "shutdown requested by {}; draining {} pending writes.",
+                                        addr,
+                                        outgoing_queue.len()
+                                    );
+                                }
+                            }
+                            b"1" => {
+                                if !consumers.contains(&addr) {
+                                    consumers.push(addr);
+                                }
+                                println!("consumer {} registered; {} total.", addr, consumers.len());
+                            }
+                            b"0" => {
+                                consumers.retain(|consumer| *consumer != addr);
+                                println!("consumer {} de-registered; {} total.", addr, consumers.len());
+                            }
+                            _ => println!("ignoring unknown command from {}.", addr),
+                        }
+                    }
+                    if event.events().contains(EPOLLOUT) {
+                        // Write to the consumer socket.
+                        let message = outgoing_queue.pop().unwrap();
+                        let nbytes =
+                            sendto(consumer_fd, &message.buffer, &message.addr, MsgFlags::empty())
+                                .unwrap();
+                        sent += 1;
+                        println!("sent {} bytes to {}.", nbytes, message.addr);
+                    }
+                }
+                _ => panic!("Unknown epoll event data."),
+            }
+        }
+
+        if drain.finished(outgoing_queue.len()) {
+            break;
+        }
+    }
+    println!(
+        "sent={} dropped={} pending={}",
+        sent,
+        outgoing_queue.dropped(),
+        outgoing_queue.len()
+    );
+}
@@ -0,0 +1,174 @@
+// A producer-consumer UDP relay server using the cross-platform mio
+// crate to multiplex reads and writes on two sockets.  This program uses
+// level-triggered events, and a distinct Token for each socket.
+//
+// Producers send datagrams to IPv4 localhost port 2000.  Consumers
+// register their interest by sending an ASCII "1" to localhost port
+// 2001, and de-register by sending an ASCII "0".  Every datagram
+// received from a producer is relayed (from port 2001) to each
+// registered consumer.  The consumer port is always the one after the
+// configured --port, unless --port=0 requests ephemeral ports.
+//
+// The copies wait in the --priority-lanes of the outgoing queue, selected
+// by each datagram's first byte as in the echo servers, so that urgent
+// datagrams overtake bulk ones.
+//
+// Sending the --shutdown-command to the consumer port, SIGINT or SIGTERM
+// shuts the relay down: it stops reading, sends what remains in its
+// outgoing queue (until the --drain-timeout passes), prints its final
+// statistics and exits.
+
+extern crate mio;
+extern crate tokio_aio_examples;
+
+use std::io;
+use std::net::SocketAddr;
+use mio::net::UdpSocket;
+use mio::{Events, Poll, PollOpt, Ready, Token};
+use tokio_aio_examples::config::{Config, MAX_EVENTS};
+use tokio_aio_examples::net::udp_socket;
+use tokio_aio_examples::shutdown::SHUTDOWN_SIGNALS;
+use tokio_aio_examples::signal::{Signal, SignalPipe};
+use tokio_aio_examples::lanes::Lanes;
+use tokio_aio_examples::Message;
+
+const PRODUCER: Token = Token(0);
+const CONSUMER: Token = Token(1);
+const SIGNAL: Token = Token(2);
+
+fn main() {
+    let config = Config::relay().with_args();
+
+    // Open two UDP sockets in non-blocking mode bound to the first configured address and
+    // consecutive ports (by default IPv4 localhost ports 2000 and 2001).
+    let producer_socket = udp_socket(&config.addr_at(0), config.v6_only).unwrap();
+    let producer_socket = UdpSocket::from_socket(producer_socket).unwrap();
+    let consumer_socket = udp_socket(&config.addr_at(1), config.v6_only).unwrap();
+    let consumer_socket = UdpSocket::from_socket(consumer_socket).unwrap();
+
+    // Set up mio polling
+    let poll = Poll::new().unwrap();
+    let mut events = Events::with_capacity(MAX_EVENTS);
+    poll.register(&producer_socket, PRODUCER, Ready::readable(), PollOpt::level())
+        .unwrap();
+    poll.register(&consumer_socket, CONSUMER, Ready::readable(), PollOpt::level())
+        .unwrap();
+
+    // SIGINT and SIGTERM make the signal pipe readable.
+    let signal = SignalPipe::install(SHUTDOWN_SIGNALS).unwrap();
+    poll.register(&signal, SIGNAL, Ready::readable(), PollOpt::level())
+        .unwrap();
+    let mut drain = config.drain();
+
+    // Announce the addresses only once signals will be handled.
+    println!("producers: listening on {}", producer_socket.local_addr().unwrap());
+    println!("consumers: listening on {}", consumer_socket.local_addr().unwrap());
+
+    // Main loop
+    let mut sent = 0;
+    let mut consumers: Vec<SocketAddr> = vec![];
+    let mut outgoing_queue: Lanes<SocketAddr> = config.lanes();
+    let mut inbuf = config.buffer();
+    loop {
+        // Set up events.  Only the consumer socket is ever written to, and it is only read from
+        // until shutting down.
+        if drain.is_started() {
+            poll.reregister(&consumer_socket, CONSUMER, Ready::writable(), PollOpt::level())
+                .unwrap();
+        } else if outgoing_queue.is_empty() {
+            poll.reregister(&consumer_socket, CONSUMER, Ready::readable(), PollOpt::level())
+                .unwrap();
+        } else {
+            poll.reregister(
+                &consumer_socket,
+                CONSUMER,
+                Ready::readable() | Ready::writable(),
+                PollOpt::level(),
+            ).unwrap();
+        }
+
+        // Poll.  While shutting down, only wait until the drain deadline.
+        match poll.poll(&mut events, drain.wait()) {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => result.unwrap(),
+        };
+
+        // Process events
+        for event in &events {
+            match event.token() {
+                SIGNAL => {
+                    if signal.signals().contains(&Signal::Shutdown) && drain.start() {
+                        // Stop reading from producers.
+                        poll.deregister(&producer_socket).unwrap();
+                        println!("shutting down; draining {} pending writes.", outgoing_queue.len());
+                    }
+                }
+                PRODUCER if drain.is_started() => (),
+                PRODUCER => {
+                    let (nbytes, addr) = producer_socket.recv_from(&mut inbuf).unwrap();
+                    println!("recv {} bytes from producer {}.", nbytes, addr);
+
+                    // Relay by pushing one copy of the message per consumer to our outgoing queue.
+                    for consumer in &consumers {
+                        let message = Message::new(inbuf[0..nbytes].to_vec(), *consumer);
+                        if outgoing_queue.push(message).is_err() {
+                            println!("outgoing buffers exhausted; dropping packet for {}.", consumer);
+                        }
+                    }
+                    println!("total pending writes: {}", outgoing_queue.len());
+                }
+                CONSUMER => {
+                    if event.readiness().is_readable() {
+                        let (nbytes, addr) = consumer_socket.recv_from(&mut inbuf).unwrap();
+                        match inbuf[0..nbytes].trim_ascii() {
+                            command if config.is_shutdown_command(command) => {
+                                if drain.start() {
+                                    // Stop reading from producers.
+                                    poll.deregister(&producer_socket).unwrap();
+                                    println!(
+                                        "shutdown requested by {}; draining {} pending writes.",
+                                        addr,
+                                        outgoing_queue.len()
+                                    );
+                                }
+                            }
+                            b"1" => {
+                                if !consumers.contains(&addr) {
+                                    consumers.push(addr);
+                                }
+                                println!("consumer {} registered; {} total.", addr, consumers.len());
+                            }
+                            b"0" => {
+                                consumers.retain(|consumer| *consumer != addr);
+                                println!("consumer {} de-registered; {} total.", addr, consumers.len());
+                            }
+                            _ => println!("ignoring unknown command from {}.", addr),
+                        }
+                    }
+                    if event.readiness().is_writable() {
+                        // Write to the consumer socket.
+                        let message = outgoing_queue.pop().unwrap();
+                        let nbytes = consumer_socket
+                            .send_to(&message.buffer, &message.addr)
+                            .unwrap();
+                        sent += 1;
+                        println!("sent {} bytes to {}.", nbytes, message.addr);
+                    }
+                }
+                Token(_) => {
+                    panic!("Unknown token in poll.");
+                }
+            }
+        }
+
+        if drain.finished(outgoing_queue.len()) {
+            break;
+        }
+    }
+    println!(
+        "sent={} dropped={} pending={}",
+        sent,
+        outgoing_queue.dropped(),
+        outgoing_queue.len()
+    );
+}
@@ -0,0 +1,155 @@
+// A producer-consumer UDP relay server using POSIX select() to multiplex
+// reads and writes on two sockets.  This program can only be compiled on
+// platforms which support select() (Mac, Linux, etc.).
+//
+// Producers send datagrams to IPv4 localhost port 2000.  Consumers
+// register their interest by sending an ASCII "1" to localhost port
+// 2001, and de-register by sending an ASCII "0".  Every datagram
+// received from a producer is relayed (from port 2001) to each
+// registered consumer.  The consumer port is always the one after the
+// configured --port, unless --port=0 requests ephemeral ports.
+//
+// The copies wait in the --priority-lanes of the outgoing queue, selected
+// by each datagram's first byte as in the echo servers, so that urgent
+// datagrams overtake bulk ones.
+//
+// Sending the --shutdown-command to the consumer port, SIGINT or SIGTERM
+// shuts the relay down: it stops reading, sends what remains in its
+// outgoing queue (until the --drain-timeout passes), prints its final
+// statistics and exits.
+
+extern crate nix;
+extern crate tokio_aio_examples;
+
+use nix::sys::select::*;
+use nix::sys::socket::*;
+use nix::sys::time::{TimeVal, TimeValLike};
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::net::{bind_udp, local_addr};
+use tokio_aio_examples::shutdown::SHUTDOWN_SIGNALS;
+use tokio_aio_examples::signal::{Signal, SignalPipe};
+use tokio_aio_examples::lanes::Lanes;
+use tokio_aio_examples::Message;
+
+fn main() {
+    let config = Config::relay().with_args();
+
+    // Open two UDP sockets in non-blocking mode, and bind them to the first configured address
+    // and consecutive ports (by default IPv4 localhost, ports 2000 and 2001).
+    let producer_fd = bind_udp(&config.addr_at(0), config.v6_only).unwrap();
+    let consumer_fd = bind_udp(&config.addr_at(1), config.v6_only).unwrap();
+
+    // SIGINT and SIGTERM make the signal pipe readable.
+    let signal = SignalPipe::install(SHUTDOWN_SIGNALS).unwrap();
+    let mut drain = config.drain();
+    let nfds = *[producer_fd, consumer_fd, signal.fd()].iter().max().unwrap() + 1;
+
+    // Announce the addresses only once signals will be handled.
+    println!("producers: listening on {}", local_addr(producer_fd).unwrap());
+    println!("consumers: listening on {}", local_addr(consumer_fd).unwrap());
+
+    let mut sent = 0;
+    let mut consumers: Vec<SockAddr> = vec![];
+    let mut outgoing_queue: Lanes<SockAddr> = config.lanes();
+    let mut inbuf = config.buffer();
+    let mut read_fd_set = FdSet::new();
+    let mut write_fd_set = FdSet::new();
+    loop {
+        // Set up read/write file descriptor sets.  Only the consumer socket is ever written to, and
+        // neither socket is read from once shutting down.
+        read_fd_set.clear();
+        if !drain.is_started() {
+            read_fd_set.insert(producer_fd);
+            read_fd_set.insert(consumer_fd);
+        }
+        read_fd_set.insert(signal.fd());
+        write_fd_set.clear();
+        if !outgoing_queue.is_empty() {
+            write_fd_set.insert(consumer_fd);
+        }
+
+        // Wait for either socket to be ready for reading
+        // (and/or the consumer socket for writing, if there are outgoing packets to send), or for
+        // a signal.  While shutting down, only wait until the drain deadline.
+        let mut timeout = drain
+            .wait()
+            .map(|wait| TimeVal::microseconds(wait.as_micros() as i64));
+        match select(
+            nfds,
+            Some(&mut read_fd_set),
+            Some(&mut write_fd_set),
+            None,
+            timeout.as_mut(),
+        ) {
+            Err(nix::Error::Sys(nix::errno::EINTR)) => continue,
+            result => result.unwrap(),
+        };
+
+        // Process signals.
+        if read_fd_set.contains(signal.fd())
+            && signal.signals().contains(&Signal::Shutdown)
+            && drain.start()
+        {
+            println!("shutting down; draining {} pending writes.", outgoing_queue.len());
+        }
+
+        // Process producer events.
+        if read_fd_set.contains(producer_fd) {
+            let (nbytes, addr) = recvfrom(producer_fd, &mut inbuf).unwrap();
+            println!("recv {} bytes from producer {}.", nbytes, addr);
+
+            // Relay by pushing one copy of the message per consumer to our outgoing queue.
+            for consumer in &consumers {
+                let message = Message::new(inbuf[0..nbytes].to_vec(), *consumer);
+                if outgoing_queue.push(message).is_err() {
+                    println!("outgoing buffers exhausted; dropping packet for {}.", consumer);
+                }
+            }
+        }
+
+        // Process consumer events.
+        if read_fd_set.contains(consumer_fd) {
+            let (nbytes, addr) = recvfrom(consumer_fd, &mut inbuf).unwrap();
+            match inbuf[0..nbytes].trim_ascii() {
+                command if config.is_shutdown_command(command) => {
+                    if drain.start() {
+                        println!(
+                            "shutdown requested by {}; draining {} pending writes.",
+                            addr,
+                            outgoing_queue.len()
+                        );
+                    }
+                }
+                b"1" => {
+                    if !consumers.contains(&addr) {
+                        consumers.push(addr);
+                    }
+                    println!("consumer {} registered; {} total.", addr, consumers.len());
+                }
+                b"0" => {
+                    consumers.retain(|consumer| *consumer != addr);
+                    println!("consumer {} de-registered; {} total.", addr, consumers.len());
+                }
+                _ => println!("ignoring unknown command from {}.", addr),
+            }
+        }
+        if write_fd_set.contains(consumer_fd) {
+            // Write to the consumer socket.
+            let message = outgoing_queue.pop().unwrap();
+            let nbytes = sendto(consumer_fd, &message.buffer, &message.addr, MsgFlags::empty())
+                .unwrap();
+            sent += 1;
+            println!("sent {} bytes to {}.", nbytes, message.addr);
+        }
+
+        if drain.finished(outgoing_queue.len()) {
+            break;
+        }
+    }
+    println!(
+        "sent={} dropped={} pending={}",
+        sent,
+        outgoing_queue.dropped(),
+        outgoing_queue.len()
+    );
+}
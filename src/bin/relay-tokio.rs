@@ -0,0 +1,230 @@
+// A producer-consumer UDP relay server using Tokio to multiplex reads
+// and writes on two sockets.
+//
+// Producers send datagrams to IPv4 localhost port 2000.  Consumers
+// register their interest by sending an ASCII "1" to localhost port
+// 2001, and de-register by sending an ASCII "0".  Every datagram
+// received from a producer is relayed (from port 2001) to each
+// registered consumer.  The consumer port is always the one after the
+// configured --port, unless --port=0 requests ephemeral ports.
+//
+// The copies wait in the --priority-lanes of the outgoing queue, selected
+// by each datagram's first byte as in the echo servers, so that urgent
+// datagrams overtake bulk ones.
+//
+// Both sockets are managed by a single future, so a notification for
+// either socket causes the whole relay to be polled.  As in
+// echo-tokio.rs, we avoid the try_nb! macro so that a WouldBlock on one
+// operation doesn't prevent progress on the others.
+//
+// Sending the --shutdown-command to the consumer port, SIGINT or SIGTERM
+// shuts the relay down: it stops reading, sends what remains in its
+// outgoing queue (until the --drain-timeout passes), prints its final
+// statistics and exits.
+
+extern crate futures;
+extern crate tokio_aio_examples;
+extern crate tokio_core;
+
+use std::io;
+use std::net::SocketAddr;
+use futures::{Async, Future, Poll};
+use tokio_core::net::UdpSocket;
+use tokio_core::reactor::{Core, Handle, PollEvented, Timeout};
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::net::udp_socket;
+use tokio_aio_examples::shutdown::{Drain, SHUTDOWN_SIGNALS};
+use tokio_aio_examples::signal::{Signal, SignalPipe};
+use tokio_aio_examples::lanes::Lanes;
+use tokio_aio_examples::Message;
+
+struct RelayServer {
+    producer_socket: UdpSocket,
+    consumer_socket: UdpSocket,
+    consumers: Vec<SocketAddr>,
+    outgoing_queue: Lanes<SocketAddr>,
+    buffer: Vec<u8>,
+    sent: u64,
+    config: Config,
+    signal: PollEvented<SignalPipe>,
+    drain: Drain,
+    deadline: Option<Timeout>,
+    handle: Handle,
+}
+
+impl RelayServer {
+    fn new(
+        producer_socket: UdpSocket,
+        consumer_socket: UdpSocket,
+        config: &Config,
+        handle: &Handle,
+    ) -> io::Result<RelayServer> {
+        Ok(RelayServer {
+            producer_socket,
+            consumer_socket,
+            consumers: vec![],
+            outgoing_queue: config.lanes(),
+            buffer: config.buffer(),
+            sent: 0,
+            config: config.clone(),
+            signal: PollEvented::new(SignalPipe::install(SHUTDOWN_SIGNALS)?, handle)?,
+            drain: config.drain(),
+            deadline: None,
+            handle: handle.clone(),
+        })
+    }
+
+    // Return true once the relay has shut down and drained, or the drain deadline has passed.  A
+    // timeout arranges for the task to be notified at the deadline.
+    fn poll_drained(&mut self) -> io::Result<bool> {
+        if let Some(wait) = self.drain.wait() {
+            if self.deadline.is_none() {
+                self.deadline = Some(Timeout::new(wait, &self.handle)?);
+            }
+            if let Some(ref mut deadline) = self.deadline {
+                deadline.poll()?;
+            }
+        }
+        Ok(self.drain.finished(self.outgoing_queue.len()))
+    }
+}
+
+impl Future for RelayServer {
+    type Item = ();
+    type Error = io::Error;
+
+    // Relay producer datagrams to the outgoing queue, process consumer commands, and send queued
+    // datagrams, until no progress can be made on any of them.
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        // On a signal, stop reading just as on the shutdown command.
+        if let Async::Ready(()) = self.signal.poll_read() {
+            if self.signal.get_ref().signals().contains(&Signal::Shutdown) && self.drain.start() {
+                println!("shutting down; draining {} pending writes.", self.outgoing_queue.len());
+            }
+            self.signal.need_read();
+        }
+
+        let reading = !self.drain.is_started();
+        let (mut produce, mut command, mut write) = (reading, reading, true);
+
+        while produce || command || write {
+            // If an outgoing buffer is present, try to send it.
+            if let Some(message) = self.outgoing_queue.pop() {
+                match self.consumer_socket.send_to(&message.buffer, &message.addr) {
+                    Ok(nbytes) => {
+                        self.sent += 1;
+                        println!("sent {} bytes to {}", nbytes, message.addr);
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        println!("sending would block; defer.");
+                        self.outgoing_queue.requeue(message);
+                        write = false;
+                    }
+                    Err(e) => {
+                        return Err(e);
+                    }
+                }
+            } else {
+                write = false;
+            }
+
+            // Process a consumer registration command, unless shutting down.
+            if command {
+                match self.consumer_socket.recv_from(&mut self.buffer) {
+                    Ok((nbytes, addr)) => match self.buffer[0..nbytes].trim_ascii() {
+                        datagram if self.config.is_shutdown_command(datagram) => {
+                            if self.drain.start() {
+                                println!(
+                                    "shutdown requested by {}; draining {} pending writes.",
+                                    addr,
+                                    self.outgoing_queue.len()
+                                );
+                            }
+                            produce = false;
+                            command = false;
+                        }
+                        b"1" => {
+                            if !self.consumers.contains(&addr) {
+                                self.consumers.push(addr);
+                            }
+                            println!("consumer {} registered; {} total.", addr, self.consumers.len());
+                        }
+                        b"0" => {
+                            self.consumers.retain(|consumer| *consumer != addr);
+                            println!("consumer {} de-registered; {} total.", addr, self.consumers.len());
+                        }
+                        _ => println!("ignoring unknown command from {}.", addr),
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        command = false;
+                    }
+                    Err(e) => {
+                        return Err(e);
+                    }
+                }
+            }
+
+            // Read from the producer socket, unless shutting down.
+            if produce {
+                match self.producer_socket.recv_from(&mut self.buffer) {
+                    Ok((nbytes, addr)) => {
+                        println!("recv {} bytes from producer {}", nbytes, addr);
+
+                        // Relay by pushing one copy of the message per consumer to our outgoing queue.
+                        for consumer in &self.consumers {
+                            let message = Message::new(self.buffer[0..nbytes].to_vec(), *consumer);
+                            if self.outgoing_queue.push(message).is_err() {
+                                println!("outgoing buffers exhausted; dropping packet for {}.", consumer);
+                            } else {
+                                write = true;
+                            }
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        produce = false;
+                    }
+                    Err(e) => {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        if self.poll_drained()? {
+            println!(
+                "sent={} dropped={} pending={}",
+                self.sent,
+                self.outgoing_queue.dropped(),
+                self.outgoing_queue.len()
+            );
+            return Ok(Async::Ready(()));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+fn main() {
+    let config = Config::relay().with_args();
+
+    // Create the tokio event loop
+    let mut core = Core::new().unwrap();
+
+    // Open two UDP sockets in non-blocking mode bound to the first configured address and
+    // consecutive ports (by default IPv4 localhost ports 2000 and 2001).
+    let handle = core.handle();
+    let producer_socket = udp_socket(&config.addr_at(0), config.v6_only).unwrap();
+    let producer_socket = UdpSocket::from_socket(producer_socket, &handle).unwrap();
+    let consumer_socket = udp_socket(&config.addr_at(1), config.v6_only).unwrap();
+    let consumer_socket = UdpSocket::from_socket(consumer_socket, &handle).unwrap();
+    let producer_addr = producer_socket.local_addr().unwrap();
+    let consumer_addr = consumer_socket.local_addr().unwrap();
+
+    // Create the future.  This installs the signal handlers, so announce the addresses only once
+    // signals will be handled.
+    let server = RelayServer::new(producer_socket, consumer_socket, &config, &handle).unwrap();
+    println!("producers: listening on {}", producer_addr);
+    println!("consumers: listening on {}", consumer_addr);
+
+    // Run the tokio event loop
+    core.run(server).unwrap();
+}
@@ -0,0 +1,19 @@
+// A TCP echo server using the Linux epoll facility to multiplex accepts,
+// reads and writes on many connections.  This program uses edge-triggered
+// events, so it accepts until the listener would block, and reads and
+// writes each connection until both would block.  As in echo-epoll-edge,
+// each connection gets at most --budget reads and writes per turn, and
+// one which uses up its budget is serviced again from a ready list.
+//
+// The server itself is implemented in src/tcp/epoll.rs.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::tcp::epoll::TcpEpollEdge;
+use tokio_aio_examples::EchoBackend;
+
+fn main() {
+    let config = Config::from_args();
+    TcpEpollEdge.run(&config).unwrap();
+}
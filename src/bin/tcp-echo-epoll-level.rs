@@ -0,0 +1,18 @@
+// A TCP echo server using the Linux epoll facility to multiplex accepts,
+// reads and writes on many connections.  This program uses
+// level-triggered events, performing one accept, read or write per event,
+// like the select() version.  Each connection has a bounded write buffer,
+// and its read interest is withdrawn while the buffer is full.
+//
+// The server itself is implemented in src/tcp/epoll.rs.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::tcp::epoll::TcpEpollLevel;
+use tokio_aio_examples::EchoBackend;
+
+fn main() {
+    let config = Config::from_args();
+    TcpEpollLevel.run(&config).unwrap();
+}
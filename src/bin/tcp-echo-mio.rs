@@ -0,0 +1,17 @@
+// A TCP echo server using the cross-platform mio crate to multiplex
+// accepts, reads and writes on many connections, with level-triggered
+// events.  Accepted connections are kept in a slab, and the key of each
+// is its mio Token, so an event leads straight back to its connection.
+//
+// The server itself is implemented in src/tcp/mio.rs.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::tcp::mio::TcpMio;
+use tokio_aio_examples::EchoBackend;
+
+fn main() {
+    let config = Config::from_args();
+    TcpMio.run(&config).unwrap();
+}
@@ -0,0 +1,21 @@
+// A TCP echo server using POSIX select() to multiplex accepts, reads and
+// writes on many connections.  Each connection has a bounded write
+// buffer: once a client has that many bytes of echoes waiting, the server
+// stops reading from it until it reads some, rather than buffering
+// without limit.  A client may close its side of the connection and
+// still read the rest of its echoes, after which the server closes the
+// connection.  select() can't watch descriptors beyond FD_SETSIZE, so
+// connections which would need one are refused.
+//
+// The server itself is implemented in src/tcp/select.rs.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::tcp::select::TcpSelect;
+use tokio_aio_examples::EchoBackend;
+
+fn main() {
+    let config = Config::from_args();
+    TcpSelect.run(&config).unwrap();
+}
@@ -0,0 +1,20 @@
+// A TCP echo server using Tokio to multiplex accepts, reads and writes on
+// many connections.  Unlike tokio-core's echo.rs example, which spawns a
+// future per connection and copies with io::copy(), a single future
+// serves every connection, each with a bounded write buffer of its own.
+//
+// For reference, the tokio-core echo.rs source may be found here:
+// https://github.com/tokio-rs/tokio-core/blob/master/examples/echo.rs
+//
+// The server itself is implemented in src/tcp/tokio.rs.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::tcp::tokio::TcpTokio;
+use tokio_aio_examples::EchoBackend;
+
+fn main() {
+    let config = Config::from_args();
+    TcpTokio.run(&config).unwrap();
+}
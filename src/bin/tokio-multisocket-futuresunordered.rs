@@ -30,29 +30,47 @@
 // Future #4 poll()...
 // recv 5 bytes from 127.0.0.1:60522 at 127.0.0.1:2004
 //
+// The program reports the polls counted for each future when it exits:
+// after N datagrams with --datagrams=N, or on SIGINT or SIGTERM.  SIGUSR1
+// prints the report so far.  Unlike tokio-multisocket-join.rs,
+// there should be no spurious polls beyond the first poll of each
+// future.
 
 extern crate futures;
+extern crate tokio_aio_examples;
 #[macro_use]
 extern crate tokio_core;
 
 use std::io;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use futures::{Future, Poll, Stream};
 use futures::stream::FuturesUnordered;
 use tokio_core::net::UdpSocket;
 use tokio_core::reactor::Core;
-
-const NUM_SOCKETS: usize = 10;
-const START_PORT: u16 = 2000;
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::polls::{Counted, PollStats, Progress, DATAGRAMS_USAGE};
+use tokio_aio_examples::signal::SignalStream;
 
 struct UdpServer {
     socket: UdpSocket,
-    id: usize
+    id: usize,
+    buffer: Vec<u8>,
+    received: u64,
 }
 
 impl UdpServer {
-    fn new(socket: UdpSocket, id: usize) -> UdpServer {
-        UdpServer { socket, id }
+    fn new(socket: UdpSocket, id: usize, buffer: Vec<u8>) -> UdpServer {
+        UdpServer {
+            socket,
+            id,
+            buffer,
+            received: 0,
+        }
+    }
+}
+
+impl Progress for UdpServer {
+    fn progress(&self) -> u64 {
+        self.received
     }
 }
 
@@ -62,9 +80,9 @@ impl Future for UdpServer {
 
     fn poll(&mut self) -> Poll<(), io::Error> {
         println!("Future #{} poll()...", self.id);
-        let mut buffer = vec![0; 1024];
         loop {
-            let (nbytes, addr) = try_nb!(self.socket.recv_from(&mut buffer));
+            let (nbytes, addr) = try_nb!(self.socket.recv_from(&mut self.buffer));
+            self.received += 1;
             println!(
                 "recv {} bytes from {} at {}",
                 nbytes,
@@ -76,30 +94,48 @@ impl Future for UdpServer {
 }
 
 fn main() {
-    let localhost = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+    let mut datagrams = 0u64;
+    let config = Config::multisocket().with_args_and(DATAGRAMS_USAGE, |opt| {
+        match opt.name.as_str() {
+            "datagrams" => datagrams = opt.parse()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    });
+    let mut stats = PollStats::new(config.sockets);
+    if datagrams > 0 {
+        stats = stats.with_limit(datagrams);
+    }
 
     // Create the tokio event loop
     let mut core = Core::new().unwrap();
 
+    // Handle signals before any socket is announced, so that SIGINT or SIGTERM always stops the
+    // program with a report
+    let stopped = stats.stopped(SignalStream::new(&core.handle()).unwrap());
+
     // Create a future for each port, and add them to the FuturesUnordered set.
-    let mut future_set = FuturesUnordered::<UdpServer>::new();
-    for i in 0..NUM_SOCKETS {
+    let mut future_set = FuturesUnordered::<Counted<UdpServer>>::new();
+    for (i, addr) in config.addrs().iter().enumerate() {
         // Create and bind the socket
-        let port = START_PORT + (i as u16);
-        let socket = UdpSocket::bind(&SocketAddr::new(localhost, port), &core.handle()).unwrap();
+        let socket = UdpSocket::bind(addr, &core.handle()).unwrap();
+        println!("Future #{} listening on {}", i, socket.local_addr().unwrap());
 
-        // Create the future
-        let server = UdpServer::new(socket, i);
+        // Create the future, counting its polls
+        let server = stats.counted(i, UdpServer::new(socket, i, config.buffer()));
 
         // Add the future to the FuturesUnordered set.
         future_set.push(server);
     }
 
-    // Create a future that consumes the FuturesUnordered stream.
-    let future = future_set.for_each(|()| {
-        Ok(())
-    });
+    // Create a future that consumes the FuturesUnordered stream, stopping early if a datagram
+    // limit was given or on a signal.
+    let future = stopped
+        .select(future_set.for_each(|()| Ok(())))
+        .map(|_| ())
+        .map_err(|(e, _)| e);
 
-    // Run the tokio event loop
+    // Run the tokio event loop, then report how often each future was polled
     core.run(future).unwrap();
+    print!("{}", stats);
 }
@@ -30,29 +30,49 @@
 // Future #8 poll()...
 // Future #9 poll()...
 //
+// Each future's polls are counted, and the program exits with a report of
+// how many polls each future received and how many of those were spurious
+// (received no data): after N datagrams with --datagrams=N, or on SIGINT
+// or SIGTERM.  SIGUSR1 prints the report so far.
+// Here, every datagram costs a poll of all ten futures, nine of them
+// spurious.
+//
 // For an alternative approach, see tokio-multisocket-spawn.rs.
 
 extern crate futures;
+extern crate tokio_aio_examples;
 #[macro_use]
 extern crate tokio_core;
 
 use std::io;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use futures::{future, Future, Poll};
 use tokio_core::net::UdpSocket;
 use tokio_core::reactor::Core;
-
-const NUM_SOCKETS: usize = 10;
-const START_PORT: u16 = 2000;
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::polls::{PollStats, Progress, DATAGRAMS_USAGE};
+use tokio_aio_examples::signal::SignalStream;
 
 struct UdpServer {
     socket: UdpSocket,
-    id: usize
+    id: usize,
+    buffer: Vec<u8>,
+    received: u64,
 }
 
 impl UdpServer {
-    fn new(socket: UdpSocket, id: usize) -> UdpServer {
-        UdpServer { socket, id }
+    fn new(socket: UdpSocket, id: usize, buffer: Vec<u8>) -> UdpServer {
+        UdpServer {
+            socket,
+            id,
+            buffer,
+            received: 0,
+        }
+    }
+}
+
+impl Progress for UdpServer {
+    fn progress(&self) -> u64 {
+        self.received
     }
 }
 
@@ -62,9 +82,9 @@ impl Future for UdpServer {
 
     fn poll(&mut self) -> Poll<(), io::Error> {
         println!("Future #{} poll()...", self.id);
-        let mut buffer = vec![0; 1024];
         loop {
-            let (nbytes, addr) = try_nb!(self.socket.recv_from(&mut buffer));
+            let (nbytes, addr) = try_nb!(self.socket.recv_from(&mut self.buffer));
+            self.received += 1;
             println!(
                 "recv {} bytes from {} at {}",
                 nbytes,
@@ -76,27 +96,46 @@ impl Future for UdpServer {
 }
 
 fn main() {
-    let localhost = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+    let mut datagrams = 0u64;
+    let config = Config::multisocket().with_args_and(DATAGRAMS_USAGE, |opt| {
+        match opt.name.as_str() {
+            "datagrams" => datagrams = opt.parse()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    });
+    let mut stats = PollStats::new(config.sockets);
+    if datagrams > 0 {
+        stats = stats.with_limit(datagrams);
+    }
 
     // Create the tokio event loop
     let mut core = Core::new().unwrap();
 
+    // Handle signals before any socket is announced, so that SIGINT or SIGTERM always stops the
+    // program with a report
+    let stopped = stats.stopped(SignalStream::new(&core.handle()).unwrap());
+
     // Create a future for each port
     let mut socket_futures = vec![];
-    for i in 0..NUM_SOCKETS {
+    for (i, addr) in config.addrs().iter().enumerate() {
         // Create and bind the socket
-        let port = START_PORT + (i as u16);
-        let socket = UdpSocket::bind(&SocketAddr::new(localhost, port), &core.handle()).unwrap();
+        let socket = UdpSocket::bind(addr, &core.handle()).unwrap();
+        println!("Future #{} listening on {}", i, socket.local_addr().unwrap());
 
-        // Create the future
-        let server = UdpServer::new(socket, i);
+        // Create the future, counting its polls
+        let server = stats.counted(i, UdpServer::new(socket, i, config.buffer()));
 
         socket_futures.push(server);
     }
 
-    // Combine the futures via join
-    let future = future::join_all(socket_futures);
+    // Combine the futures via join, stopping early if a datagram limit was given or on a signal
+    let future = stopped
+        .select(future::join_all(socket_futures).map(|_| ()))
+        .map(|_| ())
+        .map_err(|(e, _)| e);
 
-    // Run the tokio event loop
+    // Run the tokio event loop, then report how often each future was polled
     core.run(future).unwrap();
+    print!("{}", stats);
 }
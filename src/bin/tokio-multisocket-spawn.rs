@@ -16,29 +16,47 @@
 //
 // Future #4 poll()...
 // recv 5 bytes from 127.0.0.1:60522 at 127.0.0.1:2004
+//
+// The program reports the polls counted for each future when it exits,
+// for comparison with the other tokio-multisocket programs: after N
+// datagrams with --datagrams=N, or on SIGINT or SIGTERM.  SIGUSR1 prints
+// the report so far.
 
 extern crate futures;
+extern crate tokio_aio_examples;
 #[macro_use]
 extern crate tokio_core;
 
 use std::io;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use futures::{Async, Future, Poll};
 use tokio_core::net::UdpSocket;
 use tokio_core::reactor::Core;
 use tokio_core::reactor::Handle;
-
-const NUM_SOCKETS: usize = 10;
-const START_PORT: u16 = 2000;
+use tokio_aio_examples::config::Config;
+use tokio_aio_examples::polls::{PollStats, Progress, DATAGRAMS_USAGE};
+use tokio_aio_examples::signal::SignalStream;
 
 struct UdpServer {
     socket: UdpSocket,
-    id: usize
+    id: usize,
+    buffer: Vec<u8>,
+    received: u64,
 }
 
 impl UdpServer {
-    fn new(socket: UdpSocket, id: usize) -> UdpServer {
-        UdpServer { socket, id }
+    fn new(socket: UdpSocket, id: usize, buffer: Vec<u8>) -> UdpServer {
+        UdpServer {
+            socket,
+            id,
+            buffer,
+            received: 0,
+        }
+    }
+}
+
+impl Progress for UdpServer {
+    fn progress(&self) -> u64 {
+        self.received
     }
 }
 
@@ -48,9 +66,9 @@ impl Future for UdpServer {
 
     fn poll(&mut self) -> Poll<(), io::Error> {
         println!("Future #{} poll()...", self.id);
-        let mut buffer = vec![0; 1024];
         loop {
-            let (nbytes, addr) = try_nb!(self.socket.recv_from(&mut buffer));
+            let (nbytes, addr) = try_nb!(self.socket.recv_from(&mut self.buffer));
+            self.received += 1;
             println!(
                 "recv {} bytes from {} at {}",
                 nbytes,
@@ -67,14 +85,18 @@ struct UdpMultiServer {
     handle: Handle,
     started: bool,
     sockets: Vec<UdpSocket>,
+    max_message_size: usize,
+    stats: PollStats,
 }
 
 impl UdpMultiServer {
-    fn new(handle: Handle) -> UdpMultiServer {
+    fn new(handle: Handle, max_message_size: usize, stats: PollStats) -> UdpMultiServer {
         UdpMultiServer {
-            handle: handle,
+            handle,
             started: false,
             sockets: vec![],
+            max_message_size,
+            stats,
         }
     }
 
@@ -97,8 +119,10 @@ impl Future for UdpMultiServer {
             while !self.sockets.is_empty() {
                 let socket = self.sockets.remove(0);
 
-                // Create the future
-                let future = UdpServer::new(socket, id).map_err(|_| ());
+                // Create the future, counting its polls
+                let buffer = vec![0; self.max_message_size];
+                let server = UdpServer::new(socket, id, buffer);
+                let future = self.stats.counted(id, server).map_err(|_| ());
                 id += 1;
 
                 // Spawn the future so that it is handled in a distinct task, and thus can receive
@@ -115,20 +139,38 @@ impl Future for UdpMultiServer {
 }
 
 fn main() {
-    let localhost = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+    let mut datagrams = 0u64;
+    let config = Config::multisocket().with_args_and(DATAGRAMS_USAGE, |opt| {
+        match opt.name.as_str() {
+            "datagrams" => datagrams = opt.parse()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    });
+    let mut stats = PollStats::new(config.sockets);
+    if datagrams > 0 {
+        stats = stats.with_limit(datagrams);
+    }
 
     // Create the tokio event loop
     let mut core = Core::new().unwrap();
 
-    // Create the UdpMultiServer future and initialize it with NUM_SOCKETS sockets.
-    let mut multi = UdpMultiServer::new(core.handle());
-    for i in 0..NUM_SOCKETS {
+    // Handle signals before any socket is announced, so that SIGINT or SIGTERM always stops the
+    // program with a report
+    let stopped = stats.stopped(SignalStream::new(&core.handle()).unwrap());
+
+    // Create the UdpMultiServer future and initialize it with the configured number of sockets.
+    let mut multi = UdpMultiServer::new(core.handle(), config.max_message_size, stats.clone());
+    for (i, addr) in config.addrs().iter().enumerate() {
         // Create and bind the socket
-        let port = START_PORT + (i as u16);
-        let socket = UdpSocket::bind(&SocketAddr::new(localhost, port), &core.handle()).unwrap();
+        let socket = UdpSocket::bind(addr, &core.handle()).unwrap();
+        println!("Future #{} listening on {}", i, socket.local_addr().unwrap());
         multi.add(socket);
     }
 
-    // Run the tokio event loop
-    core.run(multi).unwrap();
+    // Run the tokio event loop, stopping early if a datagram limit was given or on a signal, then
+    // report how often each future was polled.
+    let future = stopped.select(multi).map(|_| ()).map_err(|(e, _)| e);
+    core.run(future).unwrap();
+    print!("{}", stats);
 }
@@ -0,0 +1,69 @@
+// A load generator for the UDP examples.
+//
+// This program sends numbered datagrams to a target address, or to a
+// range of ports such as 127.0.0.1:2000-2009 for the multi-socket
+// servers, at a configured rate and size distribution from one or more
+// client sockets.  It checks that each echo matches the datagram it
+// answers byte for byte, and reports throughput, loss, and round-trip
+// latency percentiles once the echoes are in (or --wait has passed).
+//
+// For example, to send 10000 datagrams of 64 to 1400 bytes from four
+// clients at 5000 datagrams per second to the echo server on port 2000:
+//
+// udp-load --clients=4 --count=10000 --rate=5000 --size=64-1400
+//
+// With --batch, each client sends and receives up to that many datagrams
+// per sendmmsg() or recvmmsg() call, and the report counts the system
+// calls made.  --compare instead starts the echo program (which must be
+// built alongside it, with the epoll feature) with the epoll-edge backend,
+// once with a batch of 1 and once with --batch, runs the load against each
+// with the same batch, and reports how many system calls batching saved
+// the clients and the server:
+//
+// udp-load --count=100000 --rate=0 --batch=32 --compare
+//
+// The relay servers and the tokio-multisocket programs don't echo, so
+// every datagram sent to them is reported as lost.
+
+extern crate tokio_aio_examples;
+
+use std::env;
+use std::process;
+use tokio_aio_examples::config::program_name;
+use tokio_aio_examples::load::{self, LoadConfig};
+
+fn main() {
+    let usage = LoadConfig::default().usage(&program_name());
+    let config = match LoadConfig::default().parse(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => e.exit(&usage),
+    };
+
+    let result = if config.compare {
+        println!(
+            "sending {} datagrams to an {} echo server from {} clients",
+            config.count,
+            load::COMPARED_BACKEND,
+            config.clients
+        );
+        let echo = env::current_exe()
+            .expect("can't find this program")
+            .with_file_name("echo");
+        load::compare(&echo, &config).map(|comparison| comparison.to_string())
+    } else {
+        println!(
+            "sending {} datagrams to {} from {} clients",
+            config.count,
+            config.targets,
+            config.clients
+        );
+        load::run(&config).map(|report| report.to_string())
+    };
+    match result {
+        Ok(report) => print!("{}", report),
+        Err(e) => {
+            eprintln!("{}: {}", program_name(), e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,693 @@
+//! Configuration shared by the example programs: the defaults, and the
+//! command-line options which override them.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+use std::hash::Hash;
+use echo::EchoCore;
+use fair::{EchoQueue, FairQueue, Fairness};
+use lanes::Lanes;
+use mmsg::MAX_BATCH;
+use net::localhost;
+use queue::{OutgoingQueue, Overflow};
+use shutdown::Drain;
+use timer::TimerFd;
+use unix::UnixAddress;
+
+/// The largest datagram we are prepared to receive.
+pub const MAX_MESSAGE_SIZE: usize = 1500;
+
+/// The number of datagrams which may be waiting in an outgoing queue before
+/// new datagrams are dropped.
+pub const MAX_OUTGOING_MESSAGES: usize = 8;
+
+/// The number of datagrams the secondary buffer of the spill overflow
+/// policy can hold.
+pub const SPILL_DEPTH: usize = 64;
+
+/// The number of peers which the fair queuing disciplines keep sub-queues
+/// for.
+pub const MAX_PEERS: usize = 64;
+
+/// The number of bytes each peer may send per turn under deficit
+/// round-robin.
+pub const QUANTUM: usize = MAX_MESSAGE_SIZE;
+
+/// The number of priority lanes in the echo servers' outgoing path.  With
+/// a single lane, datagrams are not classified by priority.
+pub const PRIORITY_LANES: usize = 1;
+
+/// How long a server which is shutting down keeps sending its pending
+/// writes, in milliseconds.
+pub const DRAIN_TIMEOUT_MS: u64 = 1000;
+
+/// The number of events retrieved by each call to `epoll_wait()` or
+/// `Poll::poll()`.
+pub const MAX_EVENTS: usize = 16;
+
+/// The number of receives and sends an edge-triggered server performs on
+/// one socket before moving on to other ready sockets.
+pub const IO_BUDGET: usize = 16;
+
+/// The number of bytes of echoes each connection of a TCP echo server may
+/// have waiting to be written before the server stops reading from it.
+pub const WRITE_BUFFER: usize = 4096;
+
+/// The number of datagrams the epoll-edge backend receives or sends per
+/// system call.  A batch of 1 uses recvfrom() and sendto() rather than
+/// recvmmsg() and sendmmsg().
+pub const BATCH_SIZE: usize = 1;
+
+/// The number of connections a TCP echo server keeps open at once.
+/// Further connections are closed as soon as they are accepted.
+pub const MAX_CONNECTIONS: usize = 1000;
+
+/// The length of the queue of connections waiting to be accepted by a TCP
+/// echo server.
+pub const LISTEN_BACKLOG: usize = 128;
+
+/// The port the echo servers listen on.
+pub const ECHO_PORT: u16 = 2000;
+
+/// The port the relay servers accept producer datagrams on.  Consumer
+/// registrations are accepted (and datagrams relayed from) the next port.
+pub const PRODUCER_PORT: u16 = 2000;
+
+/// The first of the ports used by the multi-socket servers.
+pub const START_PORT: u16 = 2000;
+
+/// The number of sockets opened by the multi-socket servers.
+pub const NUM_SOCKETS: usize = 10;
+
+/// The configuration shared by the example programs, normally taken from
+/// the command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub bind: Vec<IpAddr>, // The addresses to bind sockets to.
+    pub v6_only: bool, // Whether IPv6 sockets refuse IPv4 (v4-mapped) peers.
+    pub unix: Option<UnixAddress>, // A Unix domain socket to serve instead of the bind addresses.
+    pub port: u16, // The (first) port to listen on, or 0 for an ephemeral port.
+    pub queue_depth: usize, // The capacity of the outgoing queue.
+    pub max_message_size: usize, // The size of the receive buffer.
+    pub sockets: usize, // The number of sockets opened by the multi-socket servers.
+    pub budget: usize, // Receives and sends per socket per turn when edge-triggered; 0 for no limit.
+    pub batch: usize, // Datagrams per recvmmsg() or sendmmsg() call in the epoll-edge backend.
+    pub overflow: Overflow, // What to do with a datagram when the outgoing queue is full.
+    pub spill_depth: usize, // The capacity of the secondary buffer used by the spill policy.
+    pub fairness: Fairness, // How the echoes of different peers are scheduled.
+    pub max_peers: usize, // The number of peers tracked by fair queuing.
+    pub quantum: usize, // Bytes per peer per turn under deficit round-robin.
+    pub priority_lanes: usize, // Outgoing queues selected by each datagram's first byte.
+    pub shutdown_command: Option<String>, // A datagram which shuts the server down.
+    pub drain_timeout: u64, // Milliseconds to keep sending pending writes when shutting down.
+    pub config_file: Option<PathBuf>, // Options read at startup, and again on SIGHUP.
+    pub write_buffer: usize, // Bytes of echoes pending per TCP connection.
+    pub max_connections: usize, // Connections a TCP server keeps open at once.
+    pub syscall_interval: u64, // Seconds between system call reports; 0 to report on exit only.
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: vec![localhost()],
+            v6_only: true,
+            unix: None,
+            port: ECHO_PORT,
+            queue_depth: MAX_OUTGOING_MESSAGES,
+            max_message_size: MAX_MESSAGE_SIZE,
+            sockets: 1,
+            budget: IO_BUDGET,
+            batch: BATCH_SIZE,
+            overflow: Overflow::default(),
+            spill_depth: SPILL_DEPTH,
+            fairness: Fairness::default(),
+            max_peers: MAX_PEERS,
+            quantum: QUANTUM,
+            priority_lanes: PRIORITY_LANES,
+            shutdown_command: None,
+            drain_timeout: DRAIN_TIMEOUT_MS,
+            config_file: None,
+            write_buffer: WRITE_BUFFER,
+            max_connections: MAX_CONNECTIONS,
+            syscall_interval: 0,
+        }
+    }
+}
+
+impl Config {
+    /// The default configuration of the multi-socket servers, which listen
+    /// on ports 2000 through 2009.
+    pub fn multisocket() -> Config {
+        Config {
+            port: START_PORT,
+            sockets: NUM_SOCKETS,
+            ..Config::default()
+        }
+    }
+
+    /// The default configuration of the relay servers, which accept
+    /// producer datagrams on port 2000 and consumer registrations on port
+    /// 2001.  The relays always open these two sockets.
+    pub fn relay() -> Config {
+        Config {
+            port: PRODUCER_PORT,
+            sockets: 2,
+            ..Config::default()
+        }
+    }
+
+    /// Parse the process's command line on top of the default
+    /// configuration.  See `with_args()`.
+    pub fn from_args() -> Config {
+        Config::default().with_args()
+    }
+
+    /// Parse the process's command line on top of this configuration.  On
+    /// `--help` or any error, a usage message is printed and the process
+    /// exits.
+    pub fn with_args(self) -> Config {
+        self.with_args_and("", |_| Ok(false))
+    }
+
+    /// Parse the process's command line on top of this configuration, as
+    /// `with_args()` does, passing any options which are not common options
+    /// to `handle`.  `options_usage` describes those extra options, for the
+    /// usage message.
+    pub fn with_args_and<F>(self, options_usage: &str, handle: F) -> Config
+    where
+        F: FnMut(&Opt) -> Result<bool, ConfigError>,
+    {
+        let usage = self.usage_with(options_usage);
+        match self.parse_with(env::args().skip(1), handle) {
+            Ok(config) => config,
+            Err(e) => e.exit(&usage),
+        }
+    }
+
+    /// Parse command-line arguments (not including the program name) on
+    /// top of this configuration.
+    pub fn parse<I: IntoIterator<Item = String>>(self, args: I) -> Result<Config, ConfigError> {
+        self.parse_with(args, |_| Ok(false))
+    }
+
+    /// Parse command-line arguments on top of this configuration, passing
+    /// any options which are not common options to `handle`.  `handle`
+    /// returns false for options it doesn't recognize either.
+    pub fn parse_with<I, F>(mut self, args: I, mut handle: F) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+        F: FnMut(&Opt) -> Result<bool, ConfigError>,
+    {
+        for opt in options(args)? {
+            if !self.apply(&opt)? && !handle(&opt)? {
+                return Err(ConfigError::Unknown(opt.name));
+            }
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Apply one command-line option.  Returns false if the option is not a
+    /// common configuration option, so that programs may handle options of
+    /// their own.
+    pub fn apply(&mut self, opt: &Opt) -> Result<bool, ConfigError> {
+        match opt.name.as_str() {
+            "bind" => self.bind = opt.parse_list()?,
+            "v6-only" => self.v6_only = opt.switch()?,
+            "unix" => self.unix = Some(opt.parse()?),
+            "port" => self.port = opt.parse()?,
+            "queue-depth" => self.queue_depth = opt.parse()?,
+            "max-message-size" => self.max_message_size = opt.parse()?,
+            "sockets" => self.sockets = opt.parse()?,
+            "budget" => self.budget = opt.parse()?,
+            "batch" => self.batch = opt.parse()?,
+            "overflow" => self.overflow = opt.parse()?,
+            "spill-depth" => self.spill_depth = opt.parse()?,
+            "fairness" => self.fairness = opt.parse()?,
+            "max-peers" => self.max_peers = opt.parse()?,
+            "quantum" => self.quantum = opt.parse()?,
+            "priority-lanes" => self.priority_lanes = opt.parse()?,
+            "shutdown-command" => self.shutdown_command = Some(opt.value()?.to_string()),
+            "drain-timeout" => self.drain_timeout = opt.parse()?,
+            "write-buffer" => self.write_buffer = opt.parse()?,
+            "max-connections" => self.max_connections = opt.parse()?,
+            "syscall-interval" => self.syscall_interval = opt.parse()?,
+            "config" => {
+                let path = PathBuf::from(opt.value()?);
+                self.apply_file(&path)?;
+                self.config_file = Some(path);
+            }
+            "help" => return Err(ConfigError::Help),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Apply the options in a configuration file: command-line options,
+    /// separated by whitespace, with comments from `#` to the end of the
+    /// line.  Only the common options may be given.
+    pub fn apply_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            ConfigError::Invalid("config".to_string(), format!("{}: {}", path.display(), e))
+        })?;
+        let args = text
+            .lines()
+            .flat_map(|line| line.split('#').next().unwrap().split_whitespace())
+            .map(|arg| arg.to_string());
+        for opt in options(args)? {
+            if opt.name == "config" {
+                return Err(ConfigError::Invalid(
+                    "config".to_string(),
+                    format!("{}: configuration files can't include others", path.display()),
+                ));
+            }
+            if !self.apply(&opt)? {
+                return Err(ConfigError::Unknown(opt.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-read the configuration file on top of this configuration, as on
+    /// SIGHUP.  Only the options which a running server can adopt are
+    /// taken from it: `--budget`, `--shutdown-command` and
+    /// `--drain-timeout`.  Changes to the others need a restart.
+    pub fn reload(&self) -> Result<Config, ConfigError> {
+        let mut reloaded = self.clone();
+        if let Some(ref path) = self.config_file {
+            reloaded.apply_file(path)?;
+        }
+        reloaded.validate()?;
+        Ok(Config {
+            budget: reloaded.budget,
+            shutdown_command: reloaded.shutdown_command,
+            drain_timeout: reloaded.drain_timeout,
+            ..self.clone()
+        })
+    }
+
+    /// Check that the options are consistent with each other.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_message_size == 0 || self.max_message_size > 65_535 {
+            return Err(ConfigError::Invalid(
+                "max-message-size".to_string(),
+                "must be between 1 and 65535".to_string(),
+            ));
+        }
+        if self.overflow == Overflow::StopReading && self.queue_depth == 0 {
+            return Err(ConfigError::Invalid(
+                "overflow".to_string(),
+                "stop-reading requires a queue depth of at least 1".to_string(),
+            ));
+        }
+        if self.batch == 0 || self.batch > MAX_BATCH {
+            return Err(ConfigError::Invalid(
+                "batch".to_string(),
+                format!("must be between 1 and {}", MAX_BATCH),
+            ));
+        }
+        if self.max_peers == 0 {
+            return Err(ConfigError::Invalid(
+                "max-peers".to_string(),
+                "must be at least 1".to_string(),
+            ));
+        }
+        if self.quantum == 0 {
+            return Err(ConfigError::Invalid(
+                "quantum".to_string(),
+                "must be at least 1".to_string(),
+            ));
+        }
+        if self.priority_lanes == 0 || self.priority_lanes > 256 {
+            return Err(ConfigError::Invalid(
+                "priority-lanes".to_string(),
+                "must be between 1 and 256".to_string(),
+            ));
+        }
+        if self.shutdown_command.as_ref().is_some_and(|command| command.trim().is_empty()) {
+            return Err(ConfigError::Invalid(
+                "shutdown-command".to_string(),
+                "must not be empty".to_string(),
+            ));
+        }
+        if self.write_buffer == 0 {
+            return Err(ConfigError::Invalid(
+                "write-buffer".to_string(),
+                "must be at least 1".to_string(),
+            ));
+        }
+        if self.max_connections == 0 {
+            return Err(ConfigError::Invalid(
+                "max-connections".to_string(),
+                "must be at least 1".to_string(),
+            ));
+        }
+        if self.bind.is_empty() {
+            return Err(ConfigError::Invalid(
+                "bind".to_string(),
+                "must name at least one address".to_string(),
+            ));
+        }
+        if self.sockets == 0 {
+            return Err(ConfigError::Invalid(
+                "sockets".to_string(),
+                "must be at least 1".to_string(),
+            ));
+        }
+        if self.unix.is_some() && self.sockets > 1 {
+            return Err(ConfigError::Invalid(
+                "sockets".to_string(),
+                "a server serves a single Unix domain socket".to_string(),
+            ));
+        }
+        if self.port != 0 && self.port as usize + self.sockets - 1 > 65_535 {
+            return Err(ConfigError::Invalid(
+                "sockets".to_string(),
+                format!("too many sockets starting at port {}", self.port),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The address to bind the (first) socket to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr_at(0)
+    }
+
+    /// The address to bind the `index`th of several sockets to: the first
+    /// bind address, with consecutive ports starting at `port`, or all
+    /// ephemeral if `port` is 0.
+    pub fn addr_at(&self, index: usize) -> SocketAddr {
+        SocketAddr::new(self.bind[0], self.port_at(index))
+    }
+
+    /// The addresses to bind each of the servers' sockets to: the
+    /// configured number of consecutive ports on each bind address in turn.
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        self.bind
+            .iter()
+            .flat_map(|&ip| (0..self.sockets).map(move |i| SocketAddr::new(ip, self.port_at(i))))
+            .collect()
+    }
+
+    /// The port of the `index`th of several sockets on one bind address.
+    fn port_at(&self, index: usize) -> u16 {
+        if self.port == 0 {
+            0
+        } else {
+            self.port
+                .checked_add(index as u16)
+                .expect("socket index beyond the last port")
+        }
+    }
+
+    /// The I/O budget as a limit which can always be compared against.
+    pub fn budget_limit(&self) -> usize {
+        if self.budget == 0 {
+            usize::MAX
+        } else {
+            self.budget
+        }
+    }
+
+    /// A receive buffer of the configured maximum message size.
+    pub fn buffer(&self) -> Vec<u8> {
+        vec![0; self.max_message_size]
+    }
+
+    /// An empty outgoing queue of the configured depth and overflow policy.
+    pub fn queue<A>(&self) -> OutgoingQueue<A> {
+        OutgoingQueue::with_overflow(self.queue_depth, self.overflow, self.spill_depth)
+    }
+
+    /// The configured number of priority lanes.  Each lane is an outgoing
+    /// queue of the configured depth, or per-peer sub-queues of that depth
+    /// if fair queuing is configured.
+    pub fn lanes<A: Clone + Eq + Hash>(&self) -> Lanes<A> {
+        Lanes::new(self.lane_queues())
+    }
+
+    /// An echo core with the configured priority lanes.
+    pub fn echo_core<A: Clone + Eq + Hash>(&self) -> EchoCore<A> {
+        let core = EchoCore::with_lanes(self.lane_queues());
+        match self.shutdown_datagram() {
+            Some(command) => core.with_shutdown_command(command),
+            None => core,
+        }
+    }
+
+    /// The shutdown command, as the datagram it matches (ignoring
+    /// surrounding whitespace).
+    pub fn shutdown_datagram(&self) -> Option<Vec<u8>> {
+        self.shutdown_command
+            .as_ref()
+            .map(|command| command.trim().as_bytes().to_vec())
+    }
+
+    /// Return true if `datagram` is the shutdown command, ignoring
+    /// surrounding whitespace.
+    pub fn is_shutdown_command(&self, datagram: &[u8]) -> bool {
+        match self.shutdown_command {
+            Some(ref command) => datagram.trim_ascii() == command.trim().as_bytes(),
+            None => false,
+        }
+    }
+
+    /// The drain deadline of a server which is not yet shutting down.
+    pub fn drain(&self) -> Drain {
+        Drain::new(Duration::from_millis(self.drain_timeout))
+    }
+
+    /// Given a `--syscall-interval`, a periodic timerfd which expires at
+    /// each interval, for a select, poll or epoll server to watch along
+    /// with its sockets, and report its system calls on.
+    pub fn report_timer(&self) -> io::Result<Option<TimerFd>> {
+        if self.syscall_interval == 0 {
+            return Ok(None);
+        }
+        let timer = TimerFd::new()?;
+        timer.set_interval(Duration::from_secs(self.syscall_interval))?;
+        Ok(Some(timer))
+    }
+
+    /// The queue of each priority lane.
+    fn lane_queues<A: Clone + Eq + Hash>(&self) -> Vec<EchoQueue<A>> {
+        (0..self.priority_lanes).map(|_| self.echo_queue()).collect()
+    }
+
+    /// One priority lane of an echo core or relay.
+    fn echo_queue<A: Clone + Eq + Hash>(&self) -> EchoQueue<A> {
+        match self.fairness {
+            Fairness::Fifo => self.queue().into(),
+            fairness => FairQueue::new(
+                fairness,
+                self.quantum,
+                self.max_peers,
+                self.queue_depth,
+                self.overflow,
+                self.spill_depth,
+            ).into(),
+        }
+    }
+
+    /// A usage message describing the common options, with this
+    /// configuration's values as the defaults.
+    pub fn usage(&self) -> String {
+        self.usage_with("")
+    }
+
+    /// A usage message describing the common options followed by
+    /// `options_usage`, which describes a program's own options.
+    pub fn usage_with(&self, options_usage: &str) -> String {
+        format!(
+            "Usage: {} [OPTIONS]
+
+Options:
+    --bind ADDRS            addresses to bind to, separated by commas (default: {})
+    --v6-only on|off        whether IPv6 sockets refuse IPv4 peers (default: {})
+    --unix PATH             serve a Unix domain socket at PATH, or @NAME in the
+                            abstract namespace, instead (default: none)
+    --port PORT             port to listen on; 0 picks an ephemeral port (default: {})
+    --queue-depth N         outgoing queue depth (default: {})
+    --max-message-size N    largest datagram to receive (default: {})
+    --sockets N             number of sockets for multi-socket servers (default: {})
+    --budget N              I/O operations per socket per turn when edge-triggered;
+                            0 for no limit (default: {})
+    --batch N               datagrams per recvmmsg()/sendmmsg() call in the epoll-edge
+                            backend; 1 uses recvfrom()/sendto() (default: {})
+    --overflow POLICY       what to do when the outgoing queue is full: drop-newest,
+                            drop-oldest, stop-reading, or spill (default: {})
+    --spill-depth N         secondary buffer size for --overflow=spill (default: {})
+    --fairness NAME         scheduling of different peers' echoes: fifo, or per-peer
+                            queues served round-robin or drr (default: {})
+    --max-peers N           peers tracked by round-robin and drr (default: {})
+    --quantum N             bytes per peer per turn under drr (default: {})
+    --priority-lanes N      outgoing queues, selected by each datagram's first byte
+                            and served most urgent (0) first (default: {})
+    --shutdown-command TEXT shut down on receiving a datagram of TEXT (default: none)
+    --drain-timeout MS      how long to keep sending pending writes when shutting
+                            down (default: {})
+    --write-buffer N        bytes of echoes pending per TCP connection (default: {})
+    --max-connections N     TCP connections to keep open at once (default: {})
+    --syscall-interval SECS report the select, poll and epoll servers' system calls
+                            every SECS seconds; 0 only on exit (default: {})
+    --config FILE           read options from FILE, and re-read it on SIGHUP
+{}    --help                  print this message
+",
+            program_name(),
+            self.bind
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            if self.v6_only { "on" } else { "off" },
+            self.port,
+            self.queue_depth,
+            self.max_message_size,
+            self.sockets,
+            self.budget,
+            self.batch,
+            self.overflow,
+            self.spill_depth,
+            self.fairness,
+            self.max_peers,
+            self.quantum,
+            self.priority_lanes,
+            self.drain_timeout,
+            self.write_buffer,
+            self.max_connections,
+            self.syscall_interval,
+            options_usage
+        )
+    }
+}
+
+/// A command-line option: `--name=value`, `--name value`, or a bare
+/// `--name`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Opt {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl Opt {
+    /// The option's value, which must be present.
+    pub fn value(&self) -> Result<&str, ConfigError> {
+        match self.value {
+            Some(ref value) => Ok(value),
+            None => Err(ConfigError::MissingValue(self.name.clone())),
+        }
+    }
+
+    /// The option's value, parsed as a `T`.
+    pub fn parse<T: FromStr>(&self) -> Result<T, ConfigError> {
+        let value = self.value()?;
+        value
+            .parse()
+            .map_err(|_| ConfigError::Invalid(self.name.clone(), value.to_string()))
+    }
+
+    /// The option's value, as a comma-separated list of `T`s.
+    pub fn parse_list<T: FromStr>(&self) -> Result<Vec<T>, ConfigError> {
+        let value = self.value()?;
+        value
+            .split(',')
+            .map(|item| item.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| ConfigError::Invalid(self.name.clone(), value.to_string()))
+    }
+
+    /// The option's value, which must be `on` or `off`.
+    pub fn switch(&self) -> Result<bool, ConfigError> {
+        match self.value()? {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            value => Err(ConfigError::Invalid(self.name.clone(), value.to_string())),
+        }
+    }
+}
+
+/// Split command-line arguments into options.  A value may be given after
+/// an `=` or as the following argument, unless that argument is itself an
+/// option.
+pub fn options<I: IntoIterator<Item = String>>(args: I) -> Result<Vec<Opt>, ConfigError> {
+    let mut args = args.into_iter().peekable();
+    let mut opts = vec![];
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") || arg.len() == 2 {
+            return Err(ConfigError::Unexpected(arg));
+        }
+        let opt = match arg[2..].find('=') {
+            Some(i) => Opt {
+                name: arg[2..i + 2].to_string(),
+                value: Some(arg[i + 3..].to_string()),
+            },
+            None => {
+                let takes_next = args.peek().is_some_and(|next| !next.starts_with("--"));
+                Opt {
+                    name: arg[2..].to_string(),
+                    value: if takes_next { args.next() } else { None },
+                }
+            }
+        };
+        opts.push(opt);
+    }
+    Ok(opts)
+}
+
+/// The name this program was invoked as, for usage messages.
+pub fn program_name() -> String {
+    env::args()
+        .next()
+        .as_ref()
+        .and_then(|arg0| Path::new(arg0).file_name())
+        .map_or("program".to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+/// An error (or request for help) encountered while parsing the command
+/// line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    Help,
+    Unexpected(String),
+    Unknown(String),
+    MissingValue(String),
+    Invalid(String, String),
+}
+
+impl ConfigError {
+    /// Print this error along with `usage`, and exit.  `--help` exits
+    /// successfully; any other error exits with status 2.
+    pub fn exit(&self, usage: &str) -> ! {
+        if *self == ConfigError::Help {
+            print!("{}", usage);
+            process::exit(0);
+        }
+        eprint!("error: {}\n\n{}", self, usage);
+        process::exit(2);
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Help => write!(f, "help requested"),
+            ConfigError::Unexpected(ref arg) => write!(f, "unexpected argument '{}'", arg),
+            ConfigError::Unknown(ref name) => write!(f, "unknown option '--{}'", name),
+            ConfigError::MissingValue(ref name) => write!(f, "option '--{}' requires a value", name),
+            ConfigError::Invalid(ref name, ref value) => {
+                write!(f, "invalid value for '--{}': {}", name, value)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
@@ -0,0 +1,432 @@
+//! A sans-I/O implementation of the echo protocol.
+//!
+//! `EchoCore` holds all of the protocol state of an echo server -- the
+//! outgoing queue and what is known about the socket's readiness -- but
+//! performs no I/O itself.  A backend feeds it inputs (readiness
+//! notifications, received datagrams, and `WouldBlock` results) and acts
+//! on its outputs (datagrams to send, and the interest to register with
+//! the event source).  Every echo backend drives the same core, so the
+//! backends differ only in how they wait for readiness.
+//!
+//! A level-triggered backend performs one receive per read event, and
+//! sends one datagram per write event:
+//!
+//! ```ignore
+//! if event.is_readable() {
+//!     core.readable();
+//!     let (nbytes, addr) = recv_from(&mut buffer)?;
+//!     core.received(Message::new(buffer[..nbytes].to_vec(), addr));
+//! }
+//! if event.is_writable() {
+//!     core.writable();
+//!     if let Some(message) = core.poll_send() { ... }
+//! }
+//! ```
+//!
+//! An edge-triggered backend instead keeps receiving while `wants_read()`
+//! and sending while `poll_send()` yields datagrams, reporting each
+//! `WouldBlock` back to the core, and only waits for new events once
+//! neither operation can make progress.
+//!
+//! When the outgoing queue is full, the queue's `Overflow` policy decides
+//! the fate of a received datagram.  Under `Overflow::StopReading`, the
+//! core withdraws its read interest as soon as the queue fills, and asks
+//! to read again once every pending echo has been sent.
+//!
+//! A core may have several priority lanes, each an outgoing queue of its
+//! own (see the `lanes` module), so that control traffic is never stuck
+//! behind bulk traffic.
+//!
+//! A core may also be given a shutdown command.  A datagram matching it
+//! is not echoed; instead the core stops reading for good, and the
+//! backend shuts down once the pending echoes have been sent (see the
+//! `shutdown` module).
+
+use std::fmt;
+use std::hash::Hash;
+use mio::Ready;
+#[cfg(all(feature = "nix", target_os = "linux"))]
+use nix::sys::epoll::{EpollFlags, EPOLLIN, EPOLLOUT};
+#[cfg(feature = "nix")]
+use nix::poll::{EventFlags, POLLIN, POLLOUT};
+use message::Message;
+use fair::EchoQueue;
+use lanes::Lanes;
+use queue::{OutgoingQueue, Overflow, Pushed};
+
+pub use lanes::priority_lane;
+
+/// The readiness a backend should register for with its event source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Interest {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Interest {
+    /// The equivalent epoll event flags.
+    #[cfg(all(feature = "nix", target_os = "linux"))]
+    pub fn epoll_flags(&self) -> EpollFlags {
+        let mut flags = EpollFlags::empty();
+        if self.read {
+            flags |= EPOLLIN;
+        }
+        if self.write {
+            flags |= EPOLLOUT;
+        }
+        flags
+    }
+
+    /// The equivalent poll() event flags.
+    #[cfg(feature = "nix")]
+    pub fn poll_flags(&self) -> EventFlags {
+        let mut flags = EventFlags::empty();
+        if self.read {
+            flags |= POLLIN;
+        }
+        if self.write {
+            flags |= POLLOUT;
+        }
+        flags
+    }
+}
+
+/// The equivalent mio readiness.
+impl From<Interest> for Ready {
+    fn from(interest: Interest) -> Ready {
+        let mut ready = Ready::empty();
+        if interest.read {
+            ready |= Ready::readable();
+        }
+        if interest.write {
+            ready |= Ready::writable();
+        }
+        ready
+    }
+}
+
+/// What the core did with a received datagram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Received {
+    /// The echo was queued; the payload is the number of pending echoes.
+    Queued(usize),
+    /// The echo was queued, filling the queue, so reading stops until the
+    /// pending echoes (the payload) have been sent.
+    Paused(usize),
+    /// The outgoing queue was full, so the echo went to the spill buffer;
+    /// the payload is the number of pending echoes.
+    Spilled(usize),
+    /// The outgoing queue was full, so the oldest pending echo was dropped
+    /// to make room.
+    DroppedOldest,
+    /// The outgoing queue was full, so the datagram was dropped.
+    Dropped,
+    /// The datagram was the shutdown command, so reading has stopped until
+    /// the pending echoes (the payload) have been sent.
+    Shutdown(usize),
+}
+
+impl fmt::Display for Received {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Received::Queued(pending) => write!(f, "total pending writes: {}", pending),
+            Received::Paused(pending) => write!(
+                f,
+                "outgoing buffers exhausted; pausing reads until {} pending writes drain.",
+                pending
+            ),
+            Received::Spilled(pending) => write!(
+                f,
+                "outgoing buffers exhausted; spilling packet (total pending writes: {}).",
+                pending
+            ),
+            Received::DroppedOldest => {
+                write!(f, "outgoing buffers exhausted; dropping oldest packet.")
+            }
+            Received::Dropped => write!(f, "outgoing buffers exhausted; dropping packet."),
+            Received::Shutdown(pending) => write!(
+                f,
+                "shutdown requested; draining {} pending writes.",
+                pending
+            ),
+        }
+    }
+}
+
+/// Counters describing the work done by an `EchoCore`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EchoStats {
+    pub received: u64, // Datagrams handed to the core.
+    pub sent: u64, // Echoes successfully sent.
+    pub dropped: u64, // Datagrams dropped because the outgoing queue was full.
+    pub dropped_oldest: u64, // Pending echoes dropped to make room for new ones.
+    pub spilled: u64, // Echoes moved to the spill buffer.
+    pub pauses: u64, // Times reading stopped because the outgoing queue filled.
+    pub rejected: u64, // Datagrams dropped because no more peers could be tracked.
+    pub evicted: u64, // Idle peers evicted to make room for new ones.
+    pub failed: u64, // Echoes discarded because sending them failed.
+    pub pending: usize, // Echoes waiting in the outgoing queue.
+}
+
+impl fmt::Display for EchoStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "received={} sent={} dropped={} dropped_oldest={} spilled={} pauses={} rejected={} \
+             evicted={} failed={} pending={}",
+            self.received,
+            self.sent,
+            self.dropped,
+            self.dropped_oldest,
+            self.spilled,
+            self.pauses,
+            self.rejected,
+            self.evicted,
+            self.failed,
+            self.pending
+        )
+    }
+}
+
+/// The protocol state machine of a UDP echo server.
+pub struct EchoCore<A> {
+    lanes: Lanes<A>,
+    readable: bool,
+    writable: bool,
+    registered: Option<Interest>,
+    paused: bool,
+    shutdown_command: Option<Vec<u8>>,
+    shutting_down: bool,
+    received: u64,
+    sent: u64,
+    failed: u64,
+    pauses: u64,
+}
+
+impl<A: Clone + Eq + Hash> EchoCore<A> {
+    /// Create a core with the default outgoing queue depth.
+    pub fn new() -> EchoCore<A> {
+        EchoCore::with_queue(OutgoingQueue::new())
+    }
+
+    /// Create a core which queues echoes in `queue`: an `OutgoingQueue`, or
+    /// a `FairQueue` of per-peer sub-queues.
+    pub fn with_queue<Q: Into<EchoQueue<A>>>(queue: Q) -> EchoCore<A> {
+        EchoCore::with_lanes(vec![queue.into()])
+    }
+
+    /// Create a core with one priority lane per queue in `lanes`, most
+    /// urgent first.
+    pub fn with_lanes(lanes: Vec<EchoQueue<A>>) -> EchoCore<A> {
+        EchoCore {
+            lanes: Lanes::new(lanes),
+            readable: true,
+            writable: false,
+            registered: None,
+            paused: false,
+            shutdown_command: None,
+            shutting_down: false,
+            received: 0,
+            sent: 0,
+            failed: 0,
+            pauses: 0,
+        }
+    }
+
+    /// Treat a datagram consisting of `command` (ignoring surrounding
+    /// whitespace) as a request to shut down.
+    pub fn with_shutdown_command(mut self, command: Vec<u8>) -> EchoCore<A> {
+        self.shutdown_command = Some(command);
+        self
+    }
+
+    /// Change (or remove) the shutdown command, e.g. on reloading the
+    /// configuration.
+    pub fn set_shutdown_command(&mut self, command: Option<Vec<u8>>) {
+        self.shutdown_command = command;
+    }
+
+    /// Input: the event source reported the socket as readable.
+    pub fn readable(&mut self) {
+        self.readable = true;
+    }
+
+    /// Input: the event source reported the socket as writable.
+    pub fn writable(&mut self) {
+        self.writable = true;
+    }
+
+    /// Input: a datagram was received.  Its echo is queued for sending in
+    /// its priority lane, or handled according to the overflow policy if
+    /// that lane is full.
+    pub fn received(&mut self, message: Message<A>) -> Received {
+        self.received += 1;
+        if self.shutdown_command.as_ref().map(|command| &command[..])
+            == Some(message.buffer.trim_ascii())
+        {
+            self.shutdown();
+            return Received::Shutdown(self.pending());
+        }
+        let addr = message.addr.clone();
+        let lane = self.lanes.lane_of(&message);
+        let pushed = match self.lanes.push(message) {
+            Ok(pushed) => pushed,
+            Err(_) => return Received::Dropped,
+        };
+
+        // We must at least try to send the echo.  An edge-triggered backend won't be told about
+        // writability until a send has returned WouldBlock.
+        self.writable = true;
+        let pending = self.pending();
+        let queue = &self.lanes.queues()[lane];
+        match pushed {
+            Pushed::Queued
+                if queue.overflow() == Overflow::StopReading && queue.is_full_for(&addr) =>
+            {
+                self.paused = true;
+                self.pauses += 1;
+                Received::Paused(pending)
+            }
+            Pushed::Queued => Received::Queued(pending),
+            Pushed::Spilled => Received::Spilled(pending),
+            Pushed::DroppedOldest => Received::DroppedOldest,
+        }
+    }
+
+    /// Input: a datagram taken from `poll_send()` was sent.  Reading
+    /// resumes once the queue has drained.
+    pub fn sent(&mut self) {
+        self.sent += 1;
+        if self.paused && self.is_drained() {
+            self.paused = false;
+        }
+    }
+
+    /// Input: sending a datagram taken from `poll_send()` failed, e.g.
+    /// because its Unix domain peer has gone away.  The echo is discarded,
+    /// since the failure concerns only that peer.
+    pub fn send_failed(&mut self) {
+        self.failed += 1;
+        if self.paused && self.is_drained() {
+            self.paused = false;
+        }
+    }
+
+    /// Input: the server is shutting down, e.g. on a signal.  The core stops
+    /// reading for good, and only sends the echoes already pending.
+    pub fn shutdown(&mut self) {
+        self.shutting_down = true;
+    }
+
+    /// Input: receiving would block.
+    pub fn read_would_block(&mut self) {
+        self.readable = false;
+    }
+
+    /// Input: sending a datagram taken from `poll_send()` would block.  The
+    /// datagram is returned to the front of its lane.
+    pub fn send_would_block(&mut self, message: Message<A>) {
+        self.send_unfinished(message);
+        self.writable = false;
+    }
+
+    /// Input: a datagram taken from `poll_send()` wasn't sent, because the
+    /// batch it was sent in was cut short.  The datagram is returned to the
+    /// front of its lane, to be sent first next time.  (Datagrams must be
+    /// returned in the reverse of the order they were taken.)
+    pub fn send_unfinished(&mut self, message: Message<A>) {
+        self.lanes.requeue(message);
+    }
+
+    /// Output: return true if the backend should try to receive.
+    pub fn wants_read(&self) -> bool {
+        self.readable && !self.paused && !self.shutting_down
+    }
+
+    /// Return true if the core has stopped reading for good.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down
+    }
+
+    /// Return true if reading has stopped until the outgoing queue drains.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Output: return true if the backend should try to send, i.e. an echo
+    /// is pending and the socket may be writable.
+    pub fn wants_write(&self) -> bool {
+        self.writable && !self.is_drained()
+    }
+
+    /// Output: the next datagram to send, if the socket may be writable and
+    /// an echo is pending.  This is the next echo of the most urgent lane
+    /// with any pending.
+    pub fn poll_send(&mut self) -> Option<Message<A>> {
+        if self.writable {
+            self.lanes.pop()
+        } else {
+            None
+        }
+    }
+
+    /// Output: the interest the backend should currently be registered for.
+    /// Write interest is only needed while echoes are pending, and read
+    /// interest is withdrawn while reading is paused or shutting down.
+    pub fn interest(&self) -> Interest {
+        Interest {
+            read: !self.paused && !self.shutting_down,
+            write: !self.is_drained(),
+        }
+    }
+
+    /// Output: the interest to register with the event source, if it has
+    /// changed since the last call.  The first call always returns the
+    /// current interest.
+    pub fn poll_interest(&mut self) -> Option<Interest> {
+        let interest = self.interest();
+        if self.registered == Some(interest) {
+            None
+        } else {
+            self.registered = Some(interest);
+            Some(interest)
+        }
+    }
+
+    /// The number of echoes waiting to be sent.
+    pub fn pending(&self) -> usize {
+        self.lanes.len()
+    }
+
+    /// The number of priority lanes.
+    pub fn lanes(&self) -> usize {
+        self.lanes.queues().len()
+    }
+
+    fn is_drained(&self) -> bool {
+        self.lanes.is_empty()
+    }
+
+    /// Counters describing the work done so far.
+    pub fn stats(&self) -> EchoStats {
+        let total = |count: fn(&EchoQueue<A>) -> u64| self.lanes.queues().iter().map(count).sum();
+        EchoStats {
+            received: self.received,
+            sent: self.sent,
+            dropped: self.lanes.dropped(),
+            dropped_oldest: total(EchoQueue::dropped_oldest),
+            spilled: total(EchoQueue::spilled),
+            pauses: self.pauses,
+            rejected: total(EchoQueue::rejected),
+            evicted: total(EchoQueue::evicted),
+            failed: self.failed,
+            pending: self.pending(),
+        }
+    }
+}
+
+impl<A: Clone + Eq + Hash> Default for EchoCore<A> {
+    fn default() -> EchoCore<A> {
+        EchoCore::new()
+    }
+}
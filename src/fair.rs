@@ -0,0 +1,411 @@
+//! Per-peer fair queuing of outgoing datagrams.
+//!
+//! With a single FIFO outgoing queue, one noisy sender can keep the queue
+//! full, so that every other client's datagrams are dropped.  A
+//! `FairQueue` instead gives each peer (source address) a sub-queue of its
+//! own, with the configured depth and overflow policy, and serves the
+//! peers in turn: one datagram per turn (round-robin), or up to a quantum
+//! of bytes per turn (deficit round-robin), so that peers sending large
+//! datagrams don't get more than their share of the link.
+//!
+//! At most `max_peers` peers are tracked.  A datagram from a new peer
+//! evicts the least recently active idle peer (one with nothing queued),
+//! or is dropped if every tracked peer has echoes pending.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+use message::Message;
+use queue::{OutgoingQueue, Overflow, Pushed};
+
+/// How the outgoing datagrams of different peers are scheduled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fairness {
+    /// A single FIFO queue shared by all peers.
+    #[default]
+    Fifo,
+    /// Per-peer sub-queues, served one datagram at a time.
+    RoundRobin,
+    /// Per-peer sub-queues, served up to a quantum of bytes at a time.
+    DeficitRoundRobin,
+}
+
+/// The names of the scheduling disciplines, as given to `--fairness`.
+pub const FAIRNESS: &[&str] = &["fifo", "round-robin", "drr"];
+
+impl FromStr for Fairness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Fairness, String> {
+        match s {
+            "fifo" => Ok(Fairness::Fifo),
+            "round-robin" => Ok(Fairness::RoundRobin),
+            "drr" => Ok(Fairness::DeficitRoundRobin),
+            _ => Err(format!("expected one of: {}", FAIRNESS.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for Fairness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Fairness::Fifo => "fifo",
+            Fairness::RoundRobin => "round-robin",
+            Fairness::DeficitRoundRobin => "drr",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A tracked peer.
+struct Peer<A> {
+    queue: OutgoingQueue<A>,
+    deficit: usize, // Bytes the peer may still send this turn (deficit round-robin only).
+    last_active: u64, // When the peer last had a datagram queued, in pushes.
+}
+
+/// Outgoing datagrams in per-peer sub-queues, served in turn.
+pub struct FairQueue<A> {
+    peers: HashMap<A, Peer<A>>,
+    active: VecDeque<A>, // Peers with datagrams queued, in the order they will be served.
+    fairness: Fairness,
+    quantum: usize,
+    max_peers: usize,
+    capacity: usize,
+    overflow: Overflow,
+    spill_capacity: usize,
+    len: usize,
+    pushes: u64,
+    dropped: u64,
+    dropped_oldest: u64,
+    spilled: u64,
+    rejected: u64,
+    evicted: u64,
+}
+
+impl<A: Clone + Eq + Hash> FairQueue<A> {
+    /// Create a queue which schedules peers according to `fairness` (which
+    /// should not be `Fairness::Fifo`), tracking at most `max_peers` peers.
+    /// Under deficit round-robin, each peer may send `quantum` bytes per
+    /// turn.  Each peer's sub-queue holds `capacity` datagrams, and handles
+    /// overflow as an `OutgoingQueue` with the given policy would.
+    pub fn new(
+        fairness: Fairness,
+        quantum: usize,
+        max_peers: usize,
+        capacity: usize,
+        overflow: Overflow,
+        spill_capacity: usize,
+    ) -> FairQueue<A> {
+        FairQueue {
+            peers: HashMap::new(),
+            active: VecDeque::new(),
+            fairness,
+            quantum,
+            max_peers,
+            capacity,
+            overflow,
+            spill_capacity,
+            len: 0,
+            pushes: 0,
+            dropped: 0,
+            dropped_oldest: 0,
+            spilled: 0,
+            rejected: 0,
+            evicted: 0,
+        }
+    }
+
+    /// Append a message to the back of its peer's sub-queue, applying the
+    /// overflow policy if the sub-queue is full.  A message from a new peer
+    /// is dropped if no more peers can be tracked.  If the message is
+    /// dropped, it is returned as the error.
+    pub fn push(&mut self, message: Message<A>) -> Result<Pushed, Message<A>> {
+        self.pushes += 1;
+        if !self.peers.contains_key(&message.addr) && !self.make_room() {
+            self.rejected += 1;
+            self.dropped += 1;
+            return Err(message);
+        }
+
+        let addr = message.addr.clone();
+        let pushes = self.pushes;
+        let quantum = self.quantum;
+        let (was_idle, pushed) = {
+            let peer = self.peer(addr.clone());
+            let was_idle = peer.queue.is_empty();
+            let pushed = peer.queue.push(message);
+            if pushed.is_ok() {
+                peer.last_active = pushes;
+                if was_idle {
+                    // The peer joins the back of the line, with a fresh quantum for its turn.
+                    peer.deficit = quantum;
+                }
+            }
+            (was_idle, pushed)
+        };
+        match pushed {
+            Ok(Pushed::Queued) => self.len += 1,
+            Ok(Pushed::Spilled) => {
+                self.len += 1;
+                self.spilled += 1;
+            }
+            Ok(Pushed::DroppedOldest) => self.dropped_oldest += 1,
+            Err(_) => self.dropped += 1,
+        }
+        if was_idle && pushed.is_ok() {
+            self.active.push_back(addr);
+        }
+        pushed
+    }
+
+    /// Remove the next message to send: the front of the sub-queue of the
+    /// peer whose turn it is.
+    pub fn pop(&mut self) -> Option<Message<A>> {
+        loop {
+            let addr = self.active.pop_front()?;
+            let peer = self.peers.get_mut(&addr).expect("active peer is not tracked");
+            if self.fairness == Fairness::DeficitRoundRobin {
+                let next_len = peer.queue.peek().map_or(0, |message| message.len());
+                if next_len > peer.deficit {
+                    // The peer's turn is over: it waits for its next turn, with another quantum.
+                    peer.deficit += self.quantum;
+                    self.active.push_back(addr);
+                    continue;
+                }
+                peer.deficit -= next_len;
+            }
+
+            let message = peer.queue.pop();
+            self.len -= 1;
+            if peer.queue.is_empty() {
+                peer.deficit = 0;
+            } else if self.fairness == Fairness::DeficitRoundRobin {
+                // The peer's turn continues for as long as its deficit lasts.
+                self.active.push_front(addr);
+            } else {
+                self.active.push_back(addr);
+            }
+            return message;
+        }
+    }
+
+    /// Return a message taken by `pop()` to the front of its peer's
+    /// sub-queue, and give the peer the next turn.
+    pub fn requeue(&mut self, message: Message<A>) {
+        let addr = message.addr.clone();
+        {
+            let peer = self.peer(addr.clone());
+            peer.deficit += message.len();
+            peer.queue.requeue(message);
+        }
+        self.len += 1;
+        self.active.retain(|active| *active != addr);
+        self.active.push_front(addr);
+    }
+
+    /// The peer with address `addr`, which is tracked from now on if it
+    /// wasn't already.
+    fn peer(&mut self, addr: A) -> &mut Peer<A> {
+        let (capacity, overflow, spill) = (self.capacity, self.overflow, self.spill_capacity);
+        let pushes = self.pushes;
+        self.peers.entry(addr).or_insert_with(|| Peer {
+            queue: OutgoingQueue::with_overflow(capacity, overflow, spill),
+            deficit: 0,
+            last_active: pushes,
+        })
+    }
+
+    /// Make room to track one more peer, by evicting the least recently
+    /// active idle peer if necessary.  Returns false if every tracked peer
+    /// has datagrams queued.
+    fn make_room(&mut self) -> bool {
+        if self.peers.len() < self.max_peers {
+            return true;
+        }
+        let idle = self
+            .peers
+            .iter()
+            .filter(|&(_, peer)| peer.queue.is_empty())
+            .min_by_key(|&(_, peer)| peer.last_active)
+            .map(|(addr, _)| addr.clone());
+        match idle {
+            Some(addr) => {
+                self.peers.remove(&addr);
+                self.evicted += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The number of messages waiting to be sent, across all peers.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if no messages are waiting to be sent.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return true if the next message from `addr` would invoke the
+    /// overflow policy, or be dropped because no more peers can be tracked.
+    pub fn is_full_for(&self, addr: &A) -> bool {
+        match self.peers.get(addr) {
+            Some(peer) => peer.queue.is_full(),
+            None => {
+                self.peers.len() >= self.max_peers
+                    && self.peers.values().all(|peer| !peer.queue.is_empty())
+            }
+        }
+    }
+
+    /// The overflow policy of the peers' sub-queues.
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// The number of peers being tracked.
+    pub fn peers(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// The number of messages dropped, whether because the peer's sub-queue
+    /// was full or because the peer could not be tracked.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The number of queued messages dropped to make room for new ones.
+    pub fn dropped_oldest(&self) -> u64 {
+        self.dropped_oldest
+    }
+
+    /// The number of messages moved to a peer's spill buffer.
+    pub fn spilled(&self) -> u64 {
+        self.spilled
+    }
+
+    /// The number of messages dropped because no more peers could be
+    /// tracked.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// The number of idle peers evicted to make room for new ones.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+}
+
+/// The outgoing queue of an `EchoCore`: a single FIFO queue, or per-peer
+/// fair queuing.
+pub enum EchoQueue<A> {
+    Fifo(OutgoingQueue<A>),
+    Fair(FairQueue<A>),
+}
+
+impl<A: Clone + Eq + Hash> EchoQueue<A> {
+    pub fn push(&mut self, message: Message<A>) -> Result<Pushed, Message<A>> {
+        match *self {
+            EchoQueue::Fifo(ref mut queue) => queue.push(message),
+            EchoQueue::Fair(ref mut queue) => queue.push(message),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Message<A>> {
+        match *self {
+            EchoQueue::Fifo(ref mut queue) => queue.pop(),
+            EchoQueue::Fair(ref mut queue) => queue.pop(),
+        }
+    }
+
+    pub fn requeue(&mut self, message: Message<A>) {
+        match *self {
+            EchoQueue::Fifo(ref mut queue) => queue.requeue(message),
+            EchoQueue::Fair(ref mut queue) => queue.requeue(message),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            EchoQueue::Fifo(ref queue) => queue.len(),
+            EchoQueue::Fair(ref queue) => queue.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match *self {
+            EchoQueue::Fifo(ref queue) => queue.is_empty(),
+            EchoQueue::Fair(ref queue) => queue.is_empty(),
+        }
+    }
+
+    /// Return true if the next message from `addr` would invoke the
+    /// overflow policy.
+    pub fn is_full_for(&self, addr: &A) -> bool {
+        match *self {
+            EchoQueue::Fifo(ref queue) => queue.is_full(),
+            EchoQueue::Fair(ref queue) => queue.is_full_for(addr),
+        }
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        match *self {
+            EchoQueue::Fifo(ref queue) => queue.overflow(),
+            EchoQueue::Fair(ref queue) => queue.overflow(),
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        match *self {
+            EchoQueue::Fifo(ref queue) => queue.dropped(),
+            EchoQueue::Fair(ref queue) => queue.dropped(),
+        }
+    }
+
+    pub fn dropped_oldest(&self) -> u64 {
+        match *self {
+            EchoQueue::Fifo(ref queue) => queue.dropped_oldest(),
+            EchoQueue::Fair(ref queue) => queue.dropped_oldest(),
+        }
+    }
+
+    pub fn spilled(&self) -> u64 {
+        match *self {
+            EchoQueue::Fifo(ref queue) => queue.spilled(),
+            EchoQueue::Fair(ref queue) => queue.spilled(),
+        }
+    }
+
+    /// The number of messages dropped because no more peers could be
+    /// tracked (always 0 for a FIFO queue).
+    pub fn rejected(&self) -> u64 {
+        match *self {
+            EchoQueue::Fifo(_) => 0,
+            EchoQueue::Fair(ref queue) => queue.rejected(),
+        }
+    }
+
+    /// The number of idle peers evicted (always 0 for a FIFO queue).
+    pub fn evicted(&self) -> u64 {
+        match *self {
+            EchoQueue::Fifo(_) => 0,
+            EchoQueue::Fair(ref queue) => queue.evicted(),
+        }
+    }
+}
+
+impl<A> From<OutgoingQueue<A>> for EchoQueue<A> {
+    fn from(queue: OutgoingQueue<A>) -> EchoQueue<A> {
+        EchoQueue::Fifo(queue)
+    }
+}
+
+impl<A> From<FairQueue<A>> for EchoQueue<A> {
+    fn from(queue: FairQueue<A>) -> EchoQueue<A> {
+        EchoQueue::Fair(queue)
+    }
+}
@@ -0,0 +1,83 @@
+//! Priority lanes of outgoing datagrams.
+//!
+//! A server may be configured with several priority lanes (see
+//! `--priority-lanes`), each an outgoing queue of its own.  The first byte
+//! of each datagram selects its lane, with lane 0 the most urgent, and
+//! datagrams are always taken from the most urgent non-empty lane, so that
+//! control traffic is never stuck behind bulk traffic.  The echo servers
+//! queue their echoes in `Lanes` (through `EchoCore`), and the relays the
+//! copies of each datagram they relay, so that both classify datagrams
+//! alike.
+
+use std::hash::Hash;
+use message::Message;
+use fair::EchoQueue;
+use queue::Pushed;
+
+/// The priority lane of `message` among `lanes` lanes: its first byte, or
+/// the least urgent lane if that byte is out of range or the datagram is
+/// empty.
+pub fn priority_lane<A>(message: &Message<A>, lanes: usize) -> usize {
+    let last = lanes.saturating_sub(1);
+    if last == 0 {
+        return 0;
+    }
+    message.buffer.first().map_or(last, |&byte| (byte as usize).min(last))
+}
+
+/// Outgoing queues, most urgent first, between which datagrams are
+/// classified by their priority lane.
+pub struct Lanes<A> {
+    queues: Vec<EchoQueue<A>>,
+}
+
+impl<A: Clone + Eq + Hash> Lanes<A> {
+    /// Create one lane per queue in `queues`, most urgent first.
+    pub fn new(queues: Vec<EchoQueue<A>>) -> Lanes<A> {
+        assert!(!queues.is_empty(), "at least one priority lane is needed");
+        Lanes { queues }
+    }
+
+    /// The lane `message` belongs in.
+    pub fn lane_of(&self, message: &Message<A>) -> usize {
+        priority_lane(message, self.queues.len())
+    }
+
+    /// Queue `message` in its lane, applying that lane's overflow policy if
+    /// it is full.  A message which is dropped is returned.
+    pub fn push(&mut self, message: Message<A>) -> Result<Pushed, Message<A>> {
+        let lane = self.lane_of(&message);
+        self.queues[lane].push(message)
+    }
+
+    /// Take the next message of the most urgent lane with any pending.
+    pub fn pop(&mut self) -> Option<Message<A>> {
+        self.queues.iter_mut().filter_map(EchoQueue::pop).next()
+    }
+
+    /// Return a message taken from `pop()` to the front of its lane, to be
+    /// taken first next time.
+    pub fn requeue(&mut self, message: Message<A>) {
+        let lane = self.lane_of(&message);
+        self.queues[lane].requeue(message);
+    }
+
+    /// The number of messages pending in every lane.
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(EchoQueue::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.iter().all(EchoQueue::is_empty)
+    }
+
+    /// The number of messages dropped by every lane's overflow policy.
+    pub fn dropped(&self) -> u64 {
+        self.queues.iter().map(EchoQueue::dropped).sum()
+    }
+
+    /// The queue of each lane, most urgent first.
+    pub fn queues(&self) -> &[EchoQueue<A>] {
+        &self.queues
+    }
+}
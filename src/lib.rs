@@ -1 +1,61 @@
+//! Shared building blocks for the asynchronous I/O example programs.
+//!
+//! Each example program is meant to be read on its own; what they share
+//! lives here:
+//!
+//! - `message`, `queue`, `fair` and `lanes`: the datagram type, and the
+//!   bounded outgoing queue of the echo and relay servers, with its
+//!   per-peer fair variant, and the priority lanes made of either.
+//! - `echo` and `stream`: the sans-I/O echo protocol over datagrams
+//!   (`EchoCore`) and over byte streams (`StreamCore`).
+//! - `backend` and `tcp`: the UDP echo backends driving `EchoCore`, and
+//!   their TCP counterparts.
+//! - `config`, `net` and `unix`: options and defaults, and binding the
+//!   servers' sockets, Unix domain sockets included.
+//! - `mmsg` and `ready`: batched datagram I/O, and the round-robin list of
+//!   sockets with work left over.
+//! - `signal` and `shutdown`: signals as event sources, and orderly
+//!   shutdown.
+//! - `syscalls`: system call accounting for the nix-based servers.
+//! - `timer`: mio timers serviced by a shared thread (`MioTimer`), and
+//!   Linux timerfds (`TimerFd`).
+//! - `polls`: the poll counters of the Tokio multi-socket programs.
+//! - `load` and `bench`: the load generator, and the benchmark of the echo
+//!   backends built on it.
 
+extern crate futures;
+extern crate libc;
+extern crate mio;
+extern crate slab;
+#[cfg(feature = "nix")]
+extern crate nix;
+#[cfg(feature = "io-uring")]
+extern crate io_uring;
+#[macro_use]
+extern crate tokio_core;
+
+pub mod backend;
+pub mod bench;
+pub mod config;
+pub mod echo;
+pub mod fair;
+pub mod lanes;
+pub mod load;
+pub mod message;
+pub mod mmsg;
+pub mod net;
+pub mod polls;
+pub mod queue;
+pub mod ready;
+pub mod shutdown;
+pub mod signal;
+pub mod stream;
+pub mod syscalls;
+pub mod tcp;
+pub mod timer;
+pub mod unix;
+
+pub use backend::EchoBackend;
+pub use echo::EchoCore;
+pub use message::Message;
+pub use queue::OutgoingQueue;
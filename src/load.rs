@@ -0,0 +1,759 @@
+//! A load generator for the UDP examples, used by the `udp-load` program.
+//!
+//! Each client sends numbered datagrams from its own socket to the target
+//! ports in turn, at a fixed pace, and checks the echoes which come back.
+//! Every datagram starts with the client's id and the datagram's sequence
+//! number, followed by filler derived from both, so an echo can be matched
+//! to the datagram it answers and checked byte for byte.
+//!
+//! With a `--batch` above 1, each client sends and receives up to that many
+//! datagrams per sendmmsg() or recvmmsg() call (see the `mmsg` module)
+//! instead of one per sendto() or recvfrom().  `--compare` starts the
+//! batching epoll-edge backend of the `echo` program (see the `bench`
+//! module) and runs the load against it once each way, the server and the
+//! clients batching alike, to show how many system calls batching saves
+//! each of them.  The server's count is read back from the system call
+//! report it prints on exit (see the `syscalls` module).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use libc;
+use bench::EchoServer;
+use config::{options, ConfigError, Opt, ECHO_PORT};
+use message::Message;
+use mmsg::{RecvBatch, SendBatch, MAX_BATCH};
+use net::localhost;
+use syscalls::SyscallCount;
+
+/// The length of the client id and sequence number which start every
+/// datagram.
+pub const HEADER_LEN: usize = 12;
+
+/// The largest payload of a UDP datagram.
+pub const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// The backend which `--compare` runs the load against: the only one which
+/// batches its system calls.
+pub const COMPARED_BACKEND: &str = "epoll-edge";
+
+/// How long a client waits for a datagram before checking whether it is
+/// finished.
+const RECV_TIMEOUT_MS: u64 = 10;
+
+/// The addresses datagrams are sent to, in turn: a single address, or a
+/// range of consecutive ports, written `ADDR:PORT` or `ADDR:FIRST-LAST`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Targets(pub Vec<SocketAddr>);
+
+impl FromStr for Targets {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Targets, String> {
+        let colon = s.rfind(':').ok_or("missing port")?;
+        let host = s[..colon].trim_start_matches('[').trim_end_matches(']');
+        let ip: IpAddr = host.parse().map_err(|_| format!("bad address '{}'", host))?;
+        let ports = &s[colon + 1..];
+        let (first, last) = match ports.find('-') {
+            Some(i) => (&ports[..i], &ports[i + 1..]),
+            None => (ports, ports),
+        };
+        let first: u16 = first.parse().map_err(|_| format!("bad port '{}'", first))?;
+        let last: u16 = last.parse().map_err(|_| format!("bad port '{}'", last))?;
+        if first == 0 || last < first {
+            return Err(format!("bad port range '{}'", ports));
+        }
+        Ok(Targets((first..=last).map(|port| SocketAddr::new(ip, port)).collect()))
+    }
+}
+
+impl fmt::Display for Targets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.0.first(), self.0.last()) {
+            (Some(first), Some(last)) if first != last => write!(f, "{}-{}", first, last.port()),
+            (Some(first), _) => write!(f, "{}", first),
+            _ => write!(f, "nowhere"),
+        }
+    }
+}
+
+/// The range of datagram sizes, from which each datagram's size is picked
+/// uniformly at random: written `N` for a fixed size, or `MIN-MAX`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sizes {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl Sizes {
+    /// Pick a size.
+    pub fn sample(&self, rng: &mut Rng) -> usize {
+        self.min + (rng.next_u64() % (self.max - self.min + 1) as u64) as usize
+    }
+}
+
+impl FromStr for Sizes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Sizes, String> {
+        let (min, max) = match s.find('-') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, s),
+        };
+        let min = min.parse().map_err(|_| format!("bad size '{}'", min))?;
+        let max = max.parse().map_err(|_| format!("bad size '{}'", max))?;
+        if min < HEADER_LEN || max > MAX_DATAGRAM_SIZE || min > max {
+            return Err(format!(
+                "sizes must be between {} and {} bytes",
+                HEADER_LEN, MAX_DATAGRAM_SIZE
+            ));
+        }
+        Ok(Sizes { min, max })
+    }
+}
+
+/// A small xorshift random number generator, so that runs with the same
+/// configuration send the same datagrams.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// The datagram of `len` bytes sent by `client` with sequence number `seq`.
+pub fn payload(client: u32, seq: u64, len: usize) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(len);
+    buffer.extend_from_slice(&client.to_be_bytes());
+    buffer.extend_from_slice(&seq.to_be_bytes());
+    let fill = (client as u64).wrapping_mul(31).wrapping_add(seq);
+    buffer.extend((HEADER_LEN..len).map(|i| fill.wrapping_add(i as u64) as u8));
+    buffer.truncate(len);
+    buffer
+}
+
+/// The client id and sequence number at the start of a datagram.
+pub fn parse_header(buffer: &[u8]) -> Option<(u32, u64)> {
+    if buffer.len() < HEADER_LEN {
+        return None;
+    }
+    let mut client = [0; 4];
+    let mut seq = [0; 8];
+    client.copy_from_slice(&buffer[..4]);
+    seq.copy_from_slice(&buffer[4..HEADER_LEN]);
+    Some((u32::from_be_bytes(client), u64::from_be_bytes(seq)))
+}
+
+/// The configuration of a load run, normally taken from the command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadConfig {
+    pub targets: Targets, // The addresses to send to, in turn.
+    pub clients: usize, // The number of clients, each with its own socket.
+    pub count: u64, // The number of datagrams to send, across all clients.
+    pub rate: u64, // Datagrams per second, across all clients; 0 for no limit.
+    pub sizes: Sizes, // The range of datagram sizes.
+    pub wait: Duration, // How long to wait for echoes after the last send.
+    pub batch: usize, // Datagrams per sendmmsg() or recvmmsg() call; 1 for sendto() and recvfrom().
+    pub compare: bool, // Run an epoll-edge server and the load with a batch of 1, then `batch`.
+}
+
+impl Default for LoadConfig {
+    fn default() -> LoadConfig {
+        LoadConfig {
+            targets: Targets(vec![SocketAddr::new(localhost(), ECHO_PORT)]),
+            clients: 1,
+            count: 1000,
+            rate: 1000,
+            sizes: Sizes { min: 64, max: 64 },
+            wait: Duration::from_millis(1000),
+            batch: 1,
+            compare: false,
+        }
+    }
+}
+
+impl LoadConfig {
+    /// Parse command-line arguments (not including the program name) on
+    /// top of this configuration.
+    pub fn parse<I: IntoIterator<Item = String>>(mut self, args: I) -> Result<LoadConfig, ConfigError> {
+        for opt in options(args)? {
+            if !self.apply(&opt)? {
+                return Err(ConfigError::Unknown(opt.name));
+            }
+        }
+        if self.clients == 0 {
+            return Err(ConfigError::Invalid(
+                "clients".to_string(),
+                "must be at least 1".to_string(),
+            ));
+        }
+        if self.batch == 0 || self.batch > MAX_BATCH {
+            return Err(ConfigError::Invalid(
+                "batch".to_string(),
+                format!("must be between 1 and {}", MAX_BATCH),
+            ));
+        }
+        if self.compare && self.batch == 1 {
+            return Err(ConfigError::Invalid(
+                "compare".to_string(),
+                "requires a batch of at least 2".to_string(),
+            ));
+        }
+        Ok(self)
+    }
+
+    /// Apply one command-line option.  Returns false if the option is not
+    /// recognized.
+    pub fn apply(&mut self, opt: &Opt) -> Result<bool, ConfigError> {
+        match opt.name.as_str() {
+            "target" => self.targets = opt.parse()?,
+            "clients" => self.clients = opt.parse()?,
+            "count" => self.count = opt.parse()?,
+            "rate" => self.rate = opt.parse()?,
+            "size" => self.sizes = opt.parse()?,
+            "wait" => self.wait = Duration::from_millis(opt.parse()?),
+            "batch" => self.batch = opt.parse()?,
+            "compare" => {
+                self.compare = match opt.value {
+                    Some(_) => opt.switch()?,
+                    None => true,
+                }
+            }
+            "help" => return Err(ConfigError::Help),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// A usage message, with this configuration's values as the defaults.
+    pub fn usage(&self, program: &str) -> String {
+        format!(
+            "Usage: {} [OPTIONS]
+
+Options:
+    --target ADDR:PORT[-PORT]   address, or range of ports, to send to (default: {})
+    --clients N                 number of clients, each with its own socket (default: {})
+    --count N                   datagrams to send across all clients (default: {})
+    --rate N                    datagrams per second across all clients;
+                                0 for no limit (default: {})
+    --size N|MIN-MAX            datagram size, or range of sizes (default: {}-{})
+    --wait MS                   time to wait for echoes after the last send (default: {})
+    --batch N                   datagrams per sendmmsg()/recvmmsg() call;
+                                1 uses sendto()/recvfrom() (default: {})
+    --compare                   start an epoll-edge echo server, run with a batch of 1
+                                and then with --batch on both sides, and compare the
+                                system calls made (--target is ignored)
+    --help                      print this message
+",
+            program,
+            self.targets.0[0],
+            self.clients,
+            self.count,
+            self.rate,
+            self.sizes.min,
+            self.sizes.max,
+            self.wait.as_millis(),
+            self.batch
+        )
+    }
+}
+
+/// The results of a load run.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub sent: u64, // Datagrams sent.
+    pub received: u64, // Echoes which matched the datagram sent.
+    pub corrupt: u64, // Echoes whose contents differed from the datagram sent.
+    pub unexpected: u64, // Datagrams which were not an echo of an outstanding datagram.
+    pub errors: u64, // Sends and receives which failed, e.g. with ECONNREFUSED.
+    pub bytes: u64, // The bytes of the matching echoes.
+    pub send_calls: u64, // System calls made to send.
+    pub recv_calls: u64, // System calls made to receive, including those which timed out.
+    pub elapsed: Duration, // The duration of the run.
+    pub latencies: Vec<Duration>, // The round-trip time of each matching echo.
+}
+
+impl Report {
+    /// The datagrams sent which were never echoed.
+    pub fn lost(&self) -> u64 {
+        self.sent - self.received - self.corrupt
+    }
+
+    /// Combine the report of another client into this one.
+    pub fn merge(&mut self, other: Report) {
+        self.sent += other.sent;
+        self.received += other.received;
+        self.corrupt += other.corrupt;
+        self.unexpected += other.unexpected;
+        self.errors += other.errors;
+        self.bytes += other.bytes;
+        self.send_calls += other.send_calls;
+        self.recv_calls += other.recv_calls;
+        self.elapsed = self.elapsed.max(other.elapsed);
+        self.latencies.extend(other.latencies);
+    }
+
+    /// The round-trip time which `percent` percent of the echoes beat, by
+    /// the nearest-rank method.
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        if latencies.is_empty() {
+            return None;
+        }
+        let rank = (percent / 100.0 * latencies.len() as f64).ceil() as usize;
+        Some(latencies[rank.max(1).min(latencies.len()) - 1])
+    }
+
+    /// The system calls made per datagram sent, counting both the sends and
+    /// the receives.
+    pub fn calls_per_datagram(&self) -> f64 {
+        if self.sent == 0 {
+            0.0
+        } else {
+            (self.send_calls + self.recv_calls) as f64 / self.sent as f64
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64();
+        let percent_lost = if self.sent == 0 {
+            0.0
+        } else {
+            100.0 * self.lost() as f64 / self.sent as f64
+        };
+        writeln!(f, "sent:       {}", self.sent)?;
+        writeln!(f, "received:   {}", self.received)?;
+        writeln!(f, "lost:       {} ({:.2}%)", self.lost(), percent_lost)?;
+        writeln!(f, "corrupt:    {}", self.corrupt)?;
+        writeln!(f, "unexpected: {}", self.unexpected)?;
+        writeln!(f, "errors:     {}", self.errors)?;
+        writeln!(
+            f,
+            "throughput: {:.0} datagrams/s, {:.0} bytes/s over {:.3}s",
+            self.received as f64 / seconds,
+            self.bytes as f64 / seconds,
+            seconds
+        )?;
+        writeln!(
+            f,
+            "syscalls:   send={} recv={} ({:.2} per datagram)",
+            self.send_calls,
+            self.recv_calls,
+            self.calls_per_datagram()
+        )?;
+        write!(f, "latency:   ")?;
+        for &(name, percent) in &[("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("max", 100.0)] {
+            match self.percentile(percent) {
+                Some(latency) => write!(f, " {}={}us", name, latency.as_micros())?,
+                None => write!(f, " {}=-", name)?,
+            }
+        }
+        writeln!(f)
+    }
+}
+
+/// Run all of the clients to completion, and combine their reports.
+pub fn run(config: &LoadConfig) -> io::Result<Report> {
+    let start = Instant::now();
+    let mut threads = vec![];
+    for id in 0..config.clients {
+        let config = config.clone();
+        threads.push(thread::spawn(move || run_client(&config, id as u32)));
+    }
+    let mut report = Report::default();
+    for thread in threads {
+        report.merge(thread.join().expect("client thread panicked")?);
+    }
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+/// The system calls a server reported making, on exit.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ServerCalls {
+    pub datagrams: u64, // The datagrams the server received.
+    pub calls: Vec<(String, SyscallCount)>, // The calls of each kind, in the order reported.
+}
+
+impl ServerCalls {
+    /// Read the last system call report among a server's output `lines`.
+    pub fn parse<S: AsRef<str>>(lines: &[S]) -> Result<ServerCalls, String> {
+        let mut report = None;
+        for line in lines.iter().map(AsRef::as_ref) {
+            if let Some(summary) = line.strip_prefix("syscalls: ") {
+                let datagrams = summary
+                    .split_whitespace()
+                    .find_map(|field| field.strip_prefix("datagrams="))
+                    .ok_or_else(|| format!("no datagrams in '{}'", line))?;
+                let datagrams = datagrams
+                    .parse()
+                    .map_err(|_| format!("invalid datagrams in '{}'", line))?;
+                report = Some(ServerCalls {
+                    datagrams,
+                    calls: vec![],
+                });
+            } else if let Some(call) = line.strip_prefix("syscall ") {
+                let report = report
+                    .as_mut()
+                    .ok_or_else(|| format!("'{}' before the summary", line))?;
+                let colon = call
+                    .find(": ")
+                    .ok_or_else(|| format!("expected NAME: COUNT, not '{}'", line))?;
+                report.calls.push((call[..colon].to_string(), call[colon + 2..].parse()?));
+            }
+        }
+        report.ok_or_else(|| "the server reported no system calls".to_string())
+    }
+
+    /// The number of calls of every kind.
+    pub fn total(&self) -> u64 {
+        self.calls.iter().map(|(_, count)| count.calls).sum()
+    }
+
+    /// The calls made per datagram received.
+    pub fn per_datagram(&self) -> f64 {
+        if self.datagrams == 0 {
+            0.0
+        } else {
+            self.total() as f64 / self.datagrams as f64
+        }
+    }
+}
+
+impl fmt::Display for ServerCalls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "server:     calls={} datagrams={} ({:.2} per datagram)",
+            self.total(),
+            self.datagrams,
+            self.per_datagram()
+        )?;
+        for (name, count) in &self.calls {
+            writeln!(f, "            {}: {}", name, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The reports of the same load run against an epoll-edge server with a
+/// batch of 1, and with a larger batch, and the system calls the server
+/// made each time.
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    pub batch: usize,
+    pub single: Report,
+    pub batched: Report,
+    pub single_server: ServerCalls,
+    pub batched_server: ServerCalls,
+}
+
+// The fraction of `single` calls per datagram which `batched` saved.
+fn savings(single: f64, batched: f64) -> f64 {
+    if single == 0.0 {
+        0.0
+    } else {
+        1.0 - batched / single
+    }
+}
+
+impl Comparison {
+    /// The fraction of the clients' system calls per datagram which
+    /// batching saved.
+    pub fn savings(&self) -> f64 {
+        savings(self.single.calls_per_datagram(), self.batched.calls_per_datagram())
+    }
+
+    /// The fraction of the server's system calls per datagram which
+    /// batching saved.
+    pub fn server_savings(&self) -> f64 {
+        savings(self.single_server.per_datagram(), self.batched_server.per_datagram())
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "single datagram per call (--batch=1):")?;
+        write!(f, "{}", self.single)?;
+        write!(f, "{}", self.single_server)?;
+        writeln!(f)?;
+        writeln!(f, "batched (--batch={}):", self.batch)?;
+        write!(f, "{}", self.batched)?;
+        write!(f, "{}", self.batched_server)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "savings:    clients {:.1}% fewer system calls per datagram ({:.2} -> {:.2})",
+            100.0 * self.savings(),
+            self.single.calls_per_datagram(),
+            self.batched.calls_per_datagram()
+        )?;
+        writeln!(
+            f,
+            "            server {:.1}% fewer system calls per datagram ({:.2} -> {:.2})",
+            100.0 * self.server_savings(),
+            self.single_server.per_datagram(),
+            self.batched_server.per_datagram()
+        )
+    }
+}
+
+/// Start the epoll-edge backend of the `echo` program at `echo` with a
+/// batch of 1, run the load against it with a batch of 1, and shut it down;
+/// then do the same with the configured batch.  The configured target is
+/// ignored.
+pub fn compare(echo: &Path, config: &LoadConfig) -> io::Result<Comparison> {
+    let single = LoadConfig {
+        batch: 1,
+        ..config.clone()
+    };
+    let (single, single_server) = run_server(echo, &single)?;
+    let (batched, batched_server) = run_server(echo, config)?;
+    Ok(Comparison {
+        batch: config.batch,
+        single,
+        batched,
+        single_server,
+        batched_server,
+    })
+}
+
+// Run the load against an epoll-edge server with the same batch, and read
+// back the system calls it reports on exit.
+fn run_server(echo: &Path, config: &LoadConfig) -> io::Result<(Report, ServerCalls)> {
+    let options = [format!("--batch={}", config.batch)];
+    let server = EchoServer::start(echo, COMPARED_BACKEND, &options)?;
+    let report = run(&LoadConfig {
+        targets: Targets(vec![server.addr()]),
+        ..config.clone()
+    });
+    let calls = ServerCalls::parse(&server.stop()?).map_err(io::Error::other)?;
+    Ok((report?, calls))
+}
+
+/// The datagrams sent by a client which have not been echoed yet, by
+/// sequence number, with their send times and sizes.
+struct Outstanding {
+    sent: HashMap<u64, (Instant, usize)>,
+    finished: Option<Instant>, // When the last datagram was sent.
+}
+
+/// Run client `id`, which sends its share of the configured datagrams
+/// from a socket of its own, and waits for their echoes.
+pub fn run_client(config: &LoadConfig, id: u32) -> io::Result<Report> {
+    let targets = &config.targets.0;
+    let bind = match targets[0] {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(Duration::from_millis(RECV_TIMEOUT_MS)))?;
+
+    // Divide the datagrams and the rate between the clients.
+    let clients = config.clients as u64;
+    let count = config.count / clients + if (id as u64) < config.count % clients { 1 } else { 0 };
+    let interval = (1_000_000_000 * clients)
+        .checked_div(config.rate)
+        .map(Duration::from_nanos);
+
+    let outstanding = Arc::new(Mutex::new(Outstanding {
+        sent: HashMap::new(),
+        finished: None,
+    }));
+    let start = Instant::now();
+
+    // The sender paces the datagrams, noting each send time just before the send.
+    let sender = {
+        let socket = socket.try_clone()?;
+        let outstanding = outstanding.clone();
+        let targets = targets.clone();
+        let sizes = config.sizes;
+        let batch = config.batch as u64;
+        thread::spawn(move || {
+            let mut rng = Rng::new(id as u64 + 1);
+            let mut send_batch = if batch > 1 {
+                Some(SendBatch::new(batch as usize))
+            } else {
+                None
+            };
+            let mut next_send = Instant::now();
+            let mut report = Report::default();
+            let mut first = 0;
+            while first < count {
+                // Pace whole batches, each taking the time its datagrams would have taken.
+                let seqs = first..count.min(first + batch);
+                first = seqs.end;
+                if let Some(interval) = interval {
+                    let now = Instant::now();
+                    if next_send > now {
+                        thread::sleep(next_send - now);
+                    }
+                    next_send += interval * (seqs.end - seqs.start) as u32;
+                }
+                let messages: Vec<_> = seqs
+                    .map(|seq| {
+                        let target = targets[seq as usize % targets.len()];
+                        Message::new(payload(id, seq, sizes.sample(&mut rng)), target)
+                    })
+                    .collect();
+                {
+                    let mut outstanding = outstanding.lock().unwrap();
+                    let now = Instant::now();
+                    for message in &messages {
+                        let (_, seq) = parse_header(&message.buffer).unwrap();
+                        outstanding.sent.insert(seq, (now, message.len()));
+                    }
+                }
+                for failed in send(&socket, send_batch.as_mut(), &messages, &mut report) {
+                    let (_, seq) = parse_header(&failed.buffer).unwrap();
+                    outstanding.lock().unwrap().sent.remove(&seq);
+                }
+            }
+            outstanding.lock().unwrap().finished = Some(Instant::now());
+            report
+        })
+    };
+
+    // Meanwhile, receive and check echoes until they are all in, or until the wait is over.
+    let mut report = Report::default();
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+    // A batch only needs room for the largest datagram sent, and one more byte to tell an echo
+    // which grew from one which didn't.
+    let mut recv_batch = if config.batch > 1 {
+        Some(RecvBatch::new(config.batch, config.sizes.max + 1))
+    } else {
+        None
+    };
+    loop {
+        report.recv_calls += 1;
+        match recv_batch {
+            // Wait for the first echo of a batch, but not for the rest.
+            Some(ref mut batch) => {
+                match batch.recv(socket.as_raw_fd(), config.batch, libc::MSG_WAITFORONE) {
+                    Ok(count) => {
+                        let now = Instant::now();
+                        for index in 0..count {
+                            check(id, batch.datagram(index), now, &outstanding, &mut report);
+                        }
+                    }
+                    Err(ref e) if is_timeout(e) => {}
+                    Err(_) => report.errors += 1,
+                }
+            }
+            None => match socket.recv_from(&mut buffer) {
+                Ok((nbytes, _)) => {
+                    check(id, &buffer[..nbytes], Instant::now(), &outstanding, &mut report);
+                }
+                Err(ref e) if is_timeout(e) => {}
+                Err(_) => report.errors += 1,
+            },
+        }
+        let outstanding = outstanding.lock().unwrap();
+        if let Some(finished) = outstanding.finished {
+            if outstanding.sent.is_empty() || finished.elapsed() >= config.wait {
+                break;
+            }
+        }
+    }
+
+    report.merge(sender.join().expect("sender thread panicked"));
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+/// Send `messages` with one sendto() each, or with sendmmsg() given a
+/// `batch`.  Returns the messages which couldn't be sent.
+fn send<'a>(
+    socket: &UdpSocket,
+    batch: Option<&mut SendBatch>,
+    messages: &'a [Message<SocketAddr>],
+    report: &mut Report,
+) -> Vec<&'a Message<SocketAddr>> {
+    let mut failed = vec![];
+    match batch {
+        Some(batch) => {
+            // A call stops at the first datagram which can't be sent, and the next call starts
+            // with it, so skip it after the error.
+            let mut next = 0;
+            while next < messages.len() {
+                report.send_calls += 1;
+                match batch.send(socket.as_raw_fd(), &messages[next..]) {
+                    Ok(sent) => {
+                        report.sent += sent as u64;
+                        next += sent;
+                    }
+                    Err(_) => {
+                        failed.push(&messages[next]);
+                        report.errors += 1;
+                        next += 1;
+                    }
+                }
+            }
+        }
+        None => {
+            for message in messages {
+                report.send_calls += 1;
+                match socket.send_to(&message.buffer, message.addr) {
+                    Ok(_) => report.sent += 1,
+                    Err(_) => {
+                        failed.push(message);
+                        report.errors += 1;
+                    }
+                }
+            }
+        }
+    }
+    failed
+}
+
+/// Check an echo received by client `id` at `now` against the datagram it
+/// answers, and count it in `report`.
+fn check(
+    id: u32,
+    received: &[u8],
+    now: Instant,
+    outstanding: &Mutex<Outstanding>,
+    report: &mut Report,
+) {
+    let entry = match parse_header(received) {
+        Some((client, seq)) if client == id => {
+            outstanding.lock().unwrap().sent.remove(&seq).map(|e| (seq, e))
+        }
+        _ => None,
+    };
+    match entry {
+        Some((seq, (sent_at, len))) => {
+            if received == &payload(id, seq, len)[..] {
+                report.received += 1;
+                report.bytes += received.len() as u64;
+                report.latencies.push(now - sent_at);
+            } else {
+                report.corrupt += 1;
+            }
+        }
+        None => report.unexpected += 1,
+    }
+}
+
+/// Return true if a receive only timed out.
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
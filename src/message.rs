@@ -0,0 +1,30 @@
+//! The datagram type passed between the reading and writing halves of the
+//! servers.
+
+/// A datagram along with its peer address.
+///
+/// The address type is generic so that the same type serves both the
+/// nix-based programs (`nix::sys::socket::SockAddr`) and the mio and Tokio
+/// programs (`std::net::SocketAddr`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message<A> {
+    pub buffer: Vec<u8>, // The contents of the message.
+    pub addr: A, // The peer address (a message's source, or its destination when sending).
+}
+
+impl<A> Message<A> {
+    /// Create a message from a received buffer and its source address.
+    pub fn new(buffer: Vec<u8>, addr: A) -> Message<A> {
+        Message { buffer, addr }
+    }
+
+    /// The length of the message contents, in bytes.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Return true if the message has no contents.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
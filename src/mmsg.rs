@@ -0,0 +1,158 @@
+//! Batched datagram I/O with Linux's recvmmsg() and sendmmsg(), which
+//! receive or send several datagrams in a single system call.
+//!
+//! A server which drains its socket with one recvfrom() and one sendto()
+//! per datagram makes two system calls per echo.  When datagrams arrive
+//! faster than they are echoed, a batch moves up to `--batch` of them per
+//! call instead.  The buffers, addresses and message headers of a batch
+//! are allocated once, and reused by every call.
+//!
+//! The epoll-edge backend batches its receives and sends this way, and so
+//! does the load generator, which can compare the batched path with the
+//! single-datagram one (see `udp-load --compare`).
+
+use std::cmp;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use libc;
+use message::Message;
+use net::RawAddr;
+
+/// The largest batch the kernel accepts (`UIO_MAXIOV`).
+pub const MAX_BATCH: usize = 1024;
+
+/// Buffers for receiving a batch of datagrams per call.
+pub struct RecvBatch {
+    buffers: Vec<u8>, // `size` bytes for each datagram.
+    size: usize,
+    addrs: Vec<libc::sockaddr_storage>,
+    iovecs: Vec<libc::iovec>,
+    headers: Vec<libc::mmsghdr>,
+    received: usize, // The datagrams received by the last call.
+}
+
+impl RecvBatch {
+    /// Buffers for `batch` datagrams of up to `size` bytes each.
+    pub fn new(batch: usize, size: usize) -> RecvBatch {
+        RecvBatch {
+            buffers: vec![0; batch * size],
+            size,
+            addrs: vec![unsafe { mem::zeroed() }; batch],
+            iovecs: vec![unsafe { mem::zeroed() }; batch],
+            headers: vec![unsafe { mem::zeroed() }; batch],
+            received: 0,
+        }
+    }
+
+    /// Receive up to `max` datagrams in one call (but no more than the
+    /// batch), with recvmmsg() `flags`.  Returns the number received,
+    /// which are then available from `datagram()` and `addr()` until the
+    /// next call.
+    pub fn recv(&mut self, socket_fd: RawFd, max: usize, flags: libc::c_int) -> io::Result<usize> {
+        self.received = 0;
+        let count = cmp::min(max, self.headers.len());
+        if count == 0 {
+            return Ok(0);
+        }
+        for index in 0..count {
+            let buffer = &mut self.buffers[index * self.size..(index + 1) * self.size];
+            self.iovecs[index] = libc::iovec {
+                iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buffer.len(),
+            };
+            let header = &mut self.headers[index].msg_hdr;
+            header.msg_name = &mut self.addrs[index] as *mut _ as *mut libc::c_void;
+            header.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            header.msg_iov = &mut self.iovecs[index];
+            header.msg_iovlen = 1;
+        }
+        let result = unsafe {
+            libc::recvmmsg(
+                socket_fd,
+                self.headers.as_mut_ptr(),
+                count as libc::c_uint,
+                flags,
+                ptr::null_mut(),
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.received = result as usize;
+        Ok(self.received)
+    }
+
+    /// The `index`th datagram received by the last call, truncated to the
+    /// buffer size as by recvfrom().
+    pub fn datagram(&self, index: usize) -> &[u8] {
+        assert!(index < self.received, "datagram #{} wasn't received", index);
+        let len = cmp::min(self.headers[index].msg_len as usize, self.size);
+        &self.buffers[index * self.size..index * self.size + len]
+    }
+
+    /// The address of the sender of the `index`th datagram received by the
+    /// last call.
+    pub fn addr<A: RawAddr>(&self, index: usize) -> io::Result<A> {
+        assert!(index < self.received, "datagram #{} wasn't received", index);
+        A::from_raw(&self.addrs[index], self.headers[index].msg_hdr.msg_namelen)
+    }
+}
+
+/// Headers for sending a batch of datagrams per call.
+pub struct SendBatch {
+    addrs: Vec<libc::sockaddr_storage>,
+    iovecs: Vec<libc::iovec>,
+    headers: Vec<libc::mmsghdr>,
+}
+
+impl SendBatch {
+    /// Headers for `batch` datagrams.
+    pub fn new(batch: usize) -> SendBatch {
+        SendBatch {
+            addrs: vec![unsafe { mem::zeroed() }; batch],
+            iovecs: vec![unsafe { mem::zeroed() }; batch],
+            headers: vec![unsafe { mem::zeroed() }; batch],
+        }
+    }
+
+    /// The number of datagrams a call can send.
+    pub fn size(&self) -> usize {
+        self.headers.len()
+    }
+
+    /// Send each of `messages` (no more than the batch) to its address in
+    /// one call.  Returns the number sent, which may be fewer if one of
+    /// them can't be sent: that one fails with an error if it is sent
+    /// first in the next call.
+    pub fn send<A: RawAddr>(
+        &mut self,
+        socket_fd: RawFd,
+        messages: &[Message<A>],
+    ) -> io::Result<usize> {
+        assert!(messages.len() <= self.size(), "too many datagrams for the batch");
+        if messages.is_empty() {
+            return Ok(0);
+        }
+        for (index, message) in messages.iter().enumerate() {
+            let (addr, len) = message.addr.to_raw();
+            self.addrs[index] = addr;
+            self.iovecs[index] = libc::iovec {
+                iov_base: message.buffer.as_ptr() as *mut libc::c_void,
+                iov_len: message.buffer.len(),
+            };
+            let header = &mut self.headers[index].msg_hdr;
+            header.msg_name = &mut self.addrs[index] as *mut _ as *mut libc::c_void;
+            header.msg_namelen = len;
+            header.msg_iov = &mut self.iovecs[index];
+            header.msg_iovlen = 1;
+        }
+        let count = messages.len() as libc::c_uint;
+        let result = unsafe { libc::sendmmsg(socket_fd, self.headers.as_mut_ptr(), count, 0) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(result as usize)
+    }
+}
@@ -0,0 +1,407 @@
+//! Helpers for creating and binding the sockets used by the servers.
+//!
+//! The servers may bind IPv4 and IPv6 addresses alike, and several at once
+//! (see `--bind`).  Unless it is restricted to IPv6 (see `--v6-only`), an
+//! IPv6 socket bound to the unspecified address `::` also receives IPv4
+//! datagrams and connections, from peers which appear as v4-mapped IPv6
+//! addresses (`::ffff:a.b.c.d`).  Echoes must be sent back to a peer's
+//! address exactly as it was received: an IPv6 socket can't send to a
+//! plain IPv4 address.
+//!
+//! Alternatively, a server may serve a Unix domain socket (see `--unix` and
+//! the `unix` module).
+
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::net::{TcpListener, UdpSocket};
+use std::os::unix::io::{FromRawFd, RawFd};
+#[cfg(feature = "nix")]
+use std::ptr;
+use libc;
+#[cfg(feature = "nix")]
+use config::Config;
+use config::LISTEN_BACKLOG;
+#[cfg(feature = "nix")]
+use nix::errno::Errno;
+#[cfg(feature = "nix")]
+use nix::sys::socket::*;
+#[cfg(feature = "nix")]
+use nix::unistd::close;
+#[cfg(feature = "nix")]
+use unix::{remove_stale, SocketFile, UnixAddress};
+
+/// IPv4 localhost, which the example servers bind to by default.
+pub fn localhost() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))
+}
+
+/// The IPv4 localhost socket address with the given port.
+pub fn localhost_addr(port: u16) -> SocketAddr {
+    SocketAddr::new(localhost(), port)
+}
+
+/// Open a UDP socket in non-blocking mode and bind it to `addr`.  An IPv6
+/// socket is restricted to IPv6 if `v6_only` is set, and also accepts
+/// IPv4 otherwise.  (The standard library can't do this, since the option
+/// must be set before binding.)
+pub fn udp_socket(addr: &SocketAddr, v6_only: bool) -> io::Result<UdpSocket> {
+    let socket_fd = new_socket(addr, libc::SOCK_DGRAM)?;
+    // The UdpSocket closes the descriptor if binding fails.
+    let socket = unsafe { UdpSocket::from_raw_fd(socket_fd) };
+    bind_socket(socket_fd, addr, v6_only)?;
+    Ok(socket)
+}
+
+/// Open a TCP socket in non-blocking mode, bind it to `addr` and listen for
+/// connections on it.  `v6_only` is as for `udp_socket()`.  The address may
+/// be reused at once, even while connections to it from a previous run of
+/// the server linger in TIME_WAIT.
+pub fn tcp_listener(addr: &SocketAddr, v6_only: bool) -> io::Result<TcpListener> {
+    let socket_fd = new_socket(addr, libc::SOCK_STREAM)?;
+    // The TcpListener closes the descriptor if anything fails.
+    let listener = unsafe { TcpListener::from_raw_fd(socket_fd) };
+    set_option(socket_fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, true)?;
+    bind_socket(socket_fd, addr, v6_only)?;
+    if unsafe { libc::listen(socket_fd, LISTEN_BACKLOG as libc::c_int) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(listener)
+}
+
+/// Open the sockets of a nix-based server, of type `ty`: one bound to each
+/// configured address (see `Config::addrs()`), listening if `ty` is
+/// `Stream`, or the single Unix domain socket given by `--unix`.  The
+/// socket file, if any, is removed once the `SocketFile` is dropped.
+#[cfg(feature = "nix")]
+pub fn bind_sockets(config: &Config, ty: SockType) -> io::Result<(Vec<RawFd>, Option<SocketFile>)> {
+    if let Some(ref addr) = config.unix {
+        let (socket_fd, file) = bind_unix(addr, ty)?;
+        return Ok((vec![socket_fd], Some(file)));
+    }
+    let mut socket_fds = vec![];
+    for addr in config.addrs() {
+        let socket_fd = match ty {
+            SockType::Stream => bind_tcp(&addr, config.v6_only),
+            _ => bind_udp(&addr, config.v6_only),
+        };
+        socket_fds.push(socket_fd.map_err(io_error)?);
+    }
+    Ok((socket_fds, None))
+}
+
+/// Open a UDP socket in non-blocking mode and bind it to `addr`, returning
+/// the raw file descriptor for use with the nix-based programs.  `v6_only`
+/// is as for `udp_socket()`.
+#[cfg(feature = "nix")]
+pub fn bind_udp(addr: &SocketAddr, v6_only: bool) -> nix::Result<RawFd> {
+    nix_socket(addr, SockType::Datagram, v6_only)
+}
+
+/// Open a TCP socket in non-blocking mode, bind it to `addr` and listen for
+/// connections on it, returning the raw file descriptor for use with the
+/// nix-based programs.  `v6_only` is as for `udp_socket()`.
+#[cfg(feature = "nix")]
+pub fn bind_tcp(addr: &SocketAddr, v6_only: bool) -> nix::Result<RawFd> {
+    let socket_fd = nix_socket(addr, SockType::Stream, v6_only)?;
+    if let Err(e) = listen(socket_fd, LISTEN_BACKLOG) {
+        let _ = close(socket_fd);
+        return Err(e);
+    }
+    Ok(socket_fd)
+}
+
+// Open a socket of type `ty` in non-blocking mode and bind it to `addr`.  A TCP socket's address
+// may be reused at once.
+#[cfg(feature = "nix")]
+fn nix_socket(addr: &SocketAddr, ty: SockType, v6_only: bool) -> nix::Result<RawFd> {
+    let family = match *addr {
+        SocketAddr::V4(_) => AddressFamily::Inet,
+        SocketAddr::V6(_) => AddressFamily::Inet6,
+    };
+    let socket_fd = socket(family, ty, SOCK_NONBLOCK, 0)?;
+    let mut result = Ok(());
+    if ty == SockType::Stream {
+        result = setsockopt(socket_fd, sockopt::ReuseAddr, &true);
+    }
+    if addr.is_ipv6() {
+        result = result.and_then(|_| {
+            set_v6_only(socket_fd, v6_only).map_err(|e| {
+                nix::Error::Sys(nix::Errno::from_i32(e.raw_os_error().unwrap_or(0)))
+            })
+        });
+    }
+    let sockaddr = SockAddr::new_inet(InetAddr::from_std(addr));
+    if let Err(e) = result.and_then(|_| bind(socket_fd, &sockaddr)) {
+        let _ = close(socket_fd);
+        return Err(e);
+    }
+    Ok(socket_fd)
+}
+
+/// Open a Unix domain socket of type `ty` in non-blocking mode, first
+/// removing any stale socket file (see the `unix` module), and bind it to
+/// `addr`, listening for connections if `ty` is `Stream`.  Returns the raw
+/// file descriptor, and the socket file to remove once it is closed.
+#[cfg(feature = "nix")]
+pub fn bind_unix(addr: &UnixAddress, ty: SockType) -> io::Result<(RawFd, SocketFile)> {
+    remove_stale(addr)?;
+    let sockaddr = SockAddr::Unix(addr.to_nix().map_err(io_error)?);
+    let socket_fd = socket(AddressFamily::Unix, ty, SOCK_NONBLOCK, 0).map_err(io_error)?;
+    let (ptr, len) = unsafe { sockaddr_ffi(&sockaddr) };
+    let mut result = Errno::result(unsafe { libc::bind(socket_fd, ptr, len) }).map(drop);
+    if ty == SockType::Stream {
+        result = result.and_then(|_| listen(socket_fd, LISTEN_BACKLOG));
+    }
+    if let Err(e) = result {
+        let _ = close(socket_fd);
+        return Err(io_error(e));
+    }
+    Ok((socket_fd, SocketFile::bound(addr)))
+}
+
+/// The address a socket is bound to, e.g. to learn the port chosen when
+/// binding to port 0.
+#[cfg(feature = "nix")]
+pub fn local_addr(socket_fd: RawFd) -> nix::Result<SockAddr> {
+    socket_name(socket_fd, libc::getsockname)
+}
+
+/// Accept a connection on a listening socket, in non-blocking mode.
+/// Returns the connected socket and its peer's address, which for a Unix
+/// domain socket is usually unnamed.
+#[cfg(feature = "nix")]
+pub fn accept_stream(listener_fd: RawFd) -> nix::Result<(RawFd, SockAddr)> {
+    let socket_fd = accept4(listener_fd, SOCK_NONBLOCK | SOCK_CLOEXEC)?;
+    match socket_name(socket_fd, libc::getpeername) {
+        Ok(addr) => Ok((socket_fd, addr)),
+        Err(e) => {
+            let _ = close(socket_fd);
+            Err(e)
+        }
+    }
+}
+
+/// Receive a datagram, returning its size and its sender's address, as
+/// nix's `recvfrom()` does (see `sockaddr_ffi()`).
+#[cfg(feature = "nix")]
+pub fn recv_from(socket_fd: RawFd, buf: &mut [u8]) -> nix::Result<(usize, SockAddr)> {
+    unsafe {
+        let mut storage: libc::sockaddr_storage = mem::zeroed();
+        let mut len = mem::size_of_val(&storage) as libc::socklen_t;
+        let nbytes = Errno::result(libc::recvfrom(
+            socket_fd,
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+            0,
+            &mut storage as *mut _ as *mut libc::sockaddr,
+            &mut len,
+        ))?;
+        Ok((nbytes as usize, from_storage(&storage, len)?))
+    }
+}
+
+/// Send a datagram to `addr`, as nix's `sendto()` does (see
+/// `sockaddr_ffi()`).
+#[cfg(feature = "nix")]
+pub fn send_to(socket_fd: RawFd, buf: &[u8], addr: &SockAddr) -> nix::Result<usize> {
+    unsafe {
+        let (ptr, len) = sockaddr_ffi(addr);
+        let buf_ptr = buf.as_ptr() as *const libc::c_void;
+        let nbytes = Errno::result(libc::sendto(socket_fd, buf_ptr, buf.len(), 0, ptr, len))?;
+        Ok(nbytes as usize)
+    }
+}
+
+// nix 0.9 converts Unix domain addresses to and from their C form with an `offset_of!` which
+// dereferences a null pointer, and which current compilers refuse to run.  So the nix-based servers
+// convert them here, and leave the other families to nix.
+#[cfg(feature = "nix")]
+unsafe fn sockaddr_ffi(addr: &SockAddr) -> (*const libc::sockaddr, libc::socklen_t) {
+    match *addr {
+        SockAddr::Unix(UnixAddr(ref sun, path_len)) => {
+            let len = mem::offset_of!(libc::sockaddr_un, sun_path) + path_len;
+            (sun as *const _ as *const libc::sockaddr, len as libc::socklen_t)
+        }
+        _ => {
+            let (sockaddr, len) = addr.as_ffi_pair();
+            (sockaddr, len)
+        }
+    }
+}
+
+#[cfg(feature = "nix")]
+unsafe fn from_storage(
+    storage: &libc::sockaddr_storage,
+    len: libc::socklen_t,
+) -> nix::Result<SockAddr> {
+    // Only a Unix domain socket can be unnamed, in which case its address may come back empty.
+    if len > 0 && storage.ss_family as libc::c_int != libc::AF_UNIX {
+        return sockaddr_storage_to_addr(storage, len as usize);
+    }
+    let mut sun = *(storage as *const _ as *const libc::sockaddr_un);
+    sun.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let path_len = (len as usize).saturating_sub(mem::offset_of!(libc::sockaddr_un, sun_path));
+    Ok(SockAddr::Unix(UnixAddr(sun, path_len)))
+}
+
+// The signature of `getsockname()` and `getpeername()`.
+#[cfg(feature = "nix")]
+type NameFn =
+    unsafe extern "C" fn(libc::c_int, *mut libc::sockaddr, *mut libc::socklen_t) -> libc::c_int;
+
+// The address a socket is bound or connected to, from `getsockname()` or `getpeername()`.
+#[cfg(feature = "nix")]
+fn socket_name(socket_fd: RawFd, name: NameFn) -> nix::Result<SockAddr> {
+    unsafe {
+        let mut storage: libc::sockaddr_storage = mem::zeroed();
+        let mut len = mem::size_of_val(&storage) as libc::socklen_t;
+        Errno::result(name(socket_fd, &mut storage as *mut _ as *mut libc::sockaddr, &mut len))?;
+        from_storage(&storage, len)
+    }
+}
+
+/// A socket address which can be converted to and from its C form, for
+/// the batched calls of the `mmsg` module.
+pub trait RawAddr: Sized {
+    /// This address as a C socket address, with its length.
+    fn to_raw(&self) -> (libc::sockaddr_storage, libc::socklen_t);
+
+    /// The address in the first `len` bytes of a C socket address.
+    fn from_raw(storage: &libc::sockaddr_storage, len: libc::socklen_t) -> io::Result<Self>;
+}
+
+impl RawAddr for SocketAddr {
+    fn to_raw(&self) -> (libc::sockaddr_storage, libc::socklen_t) {
+        raw_sockaddr(self)
+    }
+
+    fn from_raw(storage: &libc::sockaddr_storage, _len: libc::socklen_t) -> io::Result<SocketAddr> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let sin = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+                Ok(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(sin.sin_port))))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+                Ok(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(sin6.sin6_addr.s6_addr),
+                    u16::from_be(sin6.sin6_port),
+                    sin6.sin6_flowinfo,
+                    sin6.sin6_scope_id,
+                )))
+            }
+            _ => Err(io::Error::from_raw_os_error(libc::EAFNOSUPPORT)),
+        }
+    }
+}
+
+#[cfg(feature = "nix")]
+impl RawAddr for SockAddr {
+    fn to_raw(&self) -> (libc::sockaddr_storage, libc::socklen_t) {
+        unsafe {
+            let mut storage: libc::sockaddr_storage = mem::zeroed();
+            let (ptr, len) = sockaddr_ffi(self);
+            let dst = &mut storage as *mut _ as *mut u8;
+            ptr::copy_nonoverlapping(ptr as *const u8, dst, len as usize);
+            (storage, len)
+        }
+    }
+
+    fn from_raw(storage: &libc::sockaddr_storage, len: libc::socklen_t) -> io::Result<SockAddr> {
+        unsafe { from_storage(storage, len).map_err(io_error) }
+    }
+}
+
+/// Convert a nix error into the equivalent `std::io::Error`, so that the
+/// nix-based backends report errors the same way as the others.
+#[cfg(feature = "nix")]
+pub fn io_error(e: nix::Error) -> io::Error {
+    match e {
+        nix::Error::Sys(errno) => io::Error::from_raw_os_error(errno as i32),
+        e => io::Error::other(e),
+    }
+}
+
+// Open a socket of type `ty` (`SOCK_DGRAM` or `SOCK_STREAM`) in non-blocking mode, for binding to
+// `addr`.
+fn new_socket(addr: &SocketAddr, ty: libc::c_int) -> io::Result<RawFd> {
+    let family = match *addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let flags = libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+    let socket_fd = unsafe { libc::socket(family, ty | flags, 0) };
+    if socket_fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket_fd)
+}
+
+// Bind a socket to `addr`, restricting an IPv6 socket to IPv6 first if `v6_only` is set.
+fn bind_socket(socket_fd: RawFd, addr: &SocketAddr, v6_only: bool) -> io::Result<()> {
+    if addr.is_ipv6() {
+        set_v6_only(socket_fd, v6_only)?;
+    }
+    let (sockaddr, len) = raw_sockaddr(addr);
+    let result = unsafe {
+        libc::bind(socket_fd, &sockaddr as *const _ as *const libc::sockaddr, len)
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Restrict an IPv6 socket to IPv6, or let it accept IPv4 as well.
+fn set_v6_only(socket_fd: RawFd, v6_only: bool) -> io::Result<()> {
+    set_option(socket_fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, v6_only)
+}
+
+/// Set a boolean socket option.
+fn set_option(
+    socket_fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: bool,
+) -> io::Result<()> {
+    let value = libc::c_int::from(value);
+    let result = unsafe {
+        libc::setsockopt(
+            socket_fd,
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// `addr` as a C socket address, with its length.
+fn raw_sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match *addr {
+        SocketAddr::V4(ref addr) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(ref addr) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
@@ -0,0 +1,248 @@
+//! Poll-count instrumentation for the Tokio multi-socket programs.
+//!
+//! Each per-socket future is wrapped in a `Counted` future, which records
+//! every call to `poll()` in a shared `PollStats`.  A poll is productive
+//! if the wrapped future made progress (received at least one datagram)
+//! during it, and spurious otherwise.  Because all three programs wrap
+//! their futures the same way, their reports can be compared directly:
+//! with `join_all()`, every datagram costs a poll of every future.
+//!
+//! The programs report their polls when they stop: after `--datagrams`
+//! datagrams, or on SIGINT or SIGTERM (see `PollStats::stopped()`).
+//! SIGUSR1 prints the polls so far, though with `join_all()` the signal
+//! costs every future a spurious poll, as any notification of their task
+//! does.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+use futures::{task, Async, Future, Poll, Stream};
+use signal::{Signal, SignalStream};
+
+/// The usage of the `--datagrams` option accepted by the programs which
+/// report poll counts, for `Config::with_args_and()`.
+pub const DATAGRAMS_USAGE: &str = "    --datagrams N           exit and report poll counts after N datagrams;
+                            0 to run forever (default: 0)
+";
+
+/// A future which can report how much work it has done, so that a
+/// `Counted` wrapper can tell productive polls from spurious ones.
+pub trait Progress {
+    /// A count of the work done so far (e.g. datagrams received), which
+    /// never decreases.
+    fn progress(&self) -> u64;
+}
+
+/// The polls of one future.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PollCount {
+    pub polls: u64,
+    pub productive: u64,
+    pub datagrams: u64,
+}
+
+impl PollCount {
+    /// The polls during which the future made no progress.
+    pub fn spurious(&self) -> u64 {
+        self.polls - self.productive
+    }
+}
+
+struct Inner {
+    counts: Vec<PollCount>,
+    limit: Option<u64>,
+    waiter: Option<task::Task>,
+}
+
+/// Poll counts shared by a group of `Counted` futures, indexed by future
+/// id.  Cloning a `PollStats` yields another handle to the same counts.
+#[derive(Clone)]
+pub struct PollStats {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl PollStats {
+    /// Create empty statistics for `futures` futures.
+    pub fn new(futures: usize) -> PollStats {
+        PollStats {
+            inner: Rc::new(RefCell::new(Inner {
+                counts: vec![PollCount::default(); futures],
+                limit: None,
+                waiter: None,
+            })),
+        }
+    }
+
+    /// Consider the run finished once the futures have received `datagrams`
+    /// datagrams in total.  See `finished()`.
+    pub fn with_limit(self, datagrams: u64) -> PollStats {
+        self.inner.borrow_mut().limit = Some(datagrams);
+        self
+    }
+
+    /// Wrap `future` so that its polls are counted as those of future `id`.
+    pub fn counted<F>(&self, id: usize, future: F) -> Counted<F> {
+        Counted {
+            inner: future,
+            id,
+            stats: self.clone(),
+        }
+    }
+
+    /// Record one poll of future `id`, during which it made `progress`.
+    pub fn record(&self, id: usize, progress: u64) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.counts.len() <= id {
+            inner.counts.resize(id + 1, PollCount::default());
+        }
+        {
+            let count = &mut inner.counts[id];
+            count.polls += 1;
+            if progress > 0 {
+                count.productive += 1;
+                count.datagrams += progress;
+            }
+        }
+        if Self::reached_limit(&inner) {
+            if let Some(waiter) = inner.waiter.take() {
+                waiter.notify();
+            }
+        }
+    }
+
+    /// The polls of future `id`.
+    pub fn get(&self, id: usize) -> PollCount {
+        self.inner.borrow().counts.get(id).cloned().unwrap_or_default()
+    }
+
+    /// The polls of all the futures combined.
+    pub fn total(&self) -> PollCount {
+        self.inner
+            .borrow()
+            .counts
+            .iter()
+            .fold(PollCount::default(), |total, count| PollCount {
+                polls: total.polls + count.polls,
+                productive: total.productive + count.productive,
+                datagrams: total.datagrams + count.datagrams,
+            })
+    }
+
+    /// A future which completes once the datagram limit given to
+    /// `with_limit()` has been reached, or never if there is no limit.
+    pub fn finished(&self) -> Finished {
+        Finished { stats: self.clone() }
+    }
+
+    /// A future which completes once the datagram limit has been reached,
+    /// as `finished()` does, or SIGINT or SIGTERM arrives on `signals`.
+    /// SIGUSR1 prints the polls so far.  Select it ahead of the counted
+    /// futures, so that they are not polled again once it completes.
+    pub fn stopped(&self, signals: SignalStream) -> Stopped {
+        Stopped {
+            stats: self.clone(),
+            signals,
+            finished: self.finished(),
+        }
+    }
+
+    fn reached_limit(inner: &Inner) -> bool {
+        match inner.limit {
+            Some(limit) => inner.counts.iter().map(|c| c.datagrams).sum::<u64>() >= limit,
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for PollStats {
+    /// A table of the polls of each future, followed by the totals.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<8} {:>10} {:>10} {:>10} {:>10}",
+            "future", "polls", "productive", "spurious", "datagrams"
+        )?;
+        let counts = self.inner.borrow().counts.clone();
+        let rows = counts
+            .iter()
+            .enumerate()
+            .map(|(id, count)| (format!("#{}", id), *count));
+        for (name, count) in rows.chain(Some(("total".to_string(), self.total()))) {
+            writeln!(
+                f,
+                "{:<8} {:>10} {:>10} {:>10} {:>10}",
+                name,
+                count.polls,
+                count.productive,
+                count.spurious(),
+                count.datagrams
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A future which counts its polls in a `PollStats`.  Created by
+/// `PollStats::counted()`.
+pub struct Counted<F> {
+    inner: F,
+    id: usize,
+    stats: PollStats,
+}
+
+impl<F: Future + Progress> Future for Counted<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let before = self.inner.progress();
+        let result = self.inner.poll();
+        self.stats.record(self.id, self.inner.progress() - before);
+        result
+    }
+}
+
+/// A future which completes once a `PollStats` reaches its datagram
+/// limit.  Created by `PollStats::finished()`.
+pub struct Finished {
+    stats: PollStats,
+}
+
+impl Future for Finished {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        let mut inner = self.stats.inner.borrow_mut();
+        if PollStats::reached_limit(&inner) {
+            return Ok(Async::Ready(()));
+        }
+        inner.waiter = Some(task::current());
+        Ok(Async::NotReady)
+    }
+}
+
+/// A future which completes once a `PollStats` reaches its datagram limit,
+/// or a shutdown signal arrives.  Created by `PollStats::stopped()`.
+pub struct Stopped {
+    stats: PollStats,
+    signals: SignalStream,
+    finished: Finished,
+}
+
+impl Future for Stopped {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        while let Async::Ready(Some(signal)) = self.signals.poll()? {
+            match signal {
+                Signal::Shutdown => return Ok(Async::Ready(())),
+                Signal::Stats => print!("{}", self.stats),
+                Signal::Reload => {}
+            }
+        }
+        self.finished.poll()
+    }
+}
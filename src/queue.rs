@@ -0,0 +1,213 @@
+//! A bounded queue of outgoing datagrams.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use config::MAX_OUTGOING_MESSAGES;
+use message::Message;
+
+/// What to do with a datagram which arrives when the outgoing queue is
+/// full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the new datagram.
+    #[default]
+    DropNewest,
+    /// Drop the datagram at the front of the queue to make room.
+    DropOldest,
+    /// Queue the datagram, then stop reading until the queue has drained,
+    /// leaving further datagrams in the kernel's receive buffer.  (This is
+    /// carried out by the `EchoCore`; the queue itself drops the newest.)
+    StopReading,
+    /// Move the datagram to a larger secondary buffer, which refills the
+    /// queue as it drains.  The newest datagram is dropped when both are
+    /// full.
+    Spill,
+}
+
+/// The names of the overflow policies, as given to `--overflow`.
+pub const OVERFLOW_POLICIES: &[&str] = &["drop-newest", "drop-oldest", "stop-reading", "spill"];
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Overflow, String> {
+        match s {
+            "drop-newest" => Ok(Overflow::DropNewest),
+            "drop-oldest" => Ok(Overflow::DropOldest),
+            "stop-reading" => Ok(Overflow::StopReading),
+            "spill" => Ok(Overflow::Spill),
+            _ => Err(format!("expected one of: {}", OVERFLOW_POLICIES.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Overflow::DropNewest => "drop-newest",
+            Overflow::DropOldest => "drop-oldest",
+            Overflow::StopReading => "stop-reading",
+            Overflow::Spill => "spill",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How a message was added to an `OutgoingQueue`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pushed {
+    /// The message was queued.
+    Queued,
+    /// The queue was full, so the message went to the spill buffer.
+    Spilled,
+    /// The queue was full, so the oldest message was dropped to make room.
+    DroppedOldest,
+}
+
+/// A FIFO queue of datagrams waiting to be sent.
+///
+/// The queue holds at most `capacity` messages.  What happens when a
+/// message is pushed to a full queue depends on the queue's `Overflow`
+/// policy: by default the new message is dropped, so the servers never
+/// buffer more than a small, fixed amount of data in user space.  Each
+/// outcome is counted.
+pub struct OutgoingQueue<A> {
+    messages: VecDeque<Message<A>>,
+    capacity: usize,
+    overflow: Overflow,
+    spill: VecDeque<Message<A>>,
+    spill_capacity: usize,
+    dropped: u64,
+    dropped_oldest: u64,
+    spilled: u64,
+}
+
+impl<A> OutgoingQueue<A> {
+    /// Create a queue holding at most `MAX_OUTGOING_MESSAGES` messages.
+    pub fn new() -> OutgoingQueue<A> {
+        OutgoingQueue::with_capacity(MAX_OUTGOING_MESSAGES)
+    }
+
+    /// Create a queue holding at most `capacity` messages, which drops new
+    /// messages when full.
+    pub fn with_capacity(capacity: usize) -> OutgoingQueue<A> {
+        OutgoingQueue::with_overflow(capacity, Overflow::DropNewest, 0)
+    }
+
+    /// Create a queue holding at most `capacity` messages, which handles
+    /// overflow according to `overflow`.  `spill_capacity` is the size of
+    /// the secondary buffer used by `Overflow::Spill`.
+    pub fn with_overflow(
+        capacity: usize,
+        overflow: Overflow,
+        spill_capacity: usize,
+    ) -> OutgoingQueue<A> {
+        OutgoingQueue {
+            messages: VecDeque::with_capacity(capacity),
+            capacity,
+            overflow,
+            spill: VecDeque::new(),
+            spill_capacity: if overflow == Overflow::Spill { spill_capacity } else { 0 },
+            dropped: 0,
+            dropped_oldest: 0,
+            spilled: 0,
+        }
+    }
+
+    /// Append a message to the back of the queue, applying the overflow
+    /// policy if the queue is full.  If the message is dropped, it is
+    /// returned as the error.
+    pub fn push(&mut self, message: Message<A>) -> Result<Pushed, Message<A>> {
+        if !self.is_full() {
+            self.messages.push_back(message);
+            return Ok(Pushed::Queued);
+        }
+        match self.overflow {
+            Overflow::DropOldest if !self.messages.is_empty() => {
+                self.messages.pop_front();
+                self.messages.push_back(message);
+                self.dropped_oldest += 1;
+                Ok(Pushed::DroppedOldest)
+            }
+            Overflow::Spill if self.spill.len() < self.spill_capacity => {
+                self.spill.push_back(message);
+                self.spilled += 1;
+                Ok(Pushed::Spilled)
+            }
+            _ => {
+                self.dropped += 1;
+                Err(message)
+            }
+        }
+    }
+
+    /// Remove the message at the front of the queue.  Spilled messages move
+    /// up into the queue as it drains, preserving the send order.
+    pub fn pop(&mut self) -> Option<Message<A>> {
+        let message = self.messages.pop_front();
+        if let Some(spilled) = self.spill.pop_front() {
+            self.messages.push_back(spilled);
+        }
+        message
+    }
+
+    /// The message at the front of the queue, which `pop()` would remove.
+    pub fn peek(&self) -> Option<&Message<A>> {
+        self.messages.front()
+    }
+
+    /// Return a message taken by `pop()` to the front of the queue, e.g.
+    /// because sending it would block.  This never drops the message, even
+    /// if the queue has since filled up, and preserves the send order.
+    pub fn requeue(&mut self, message: Message<A>) {
+        self.messages.push_front(message);
+    }
+
+    /// The number of messages waiting to be sent, including any spilled
+    /// messages.
+    pub fn len(&self) -> usize {
+        self.messages.len() + self.spill.len()
+    }
+
+    /// Return true if no messages are waiting to be sent.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.spill.is_empty()
+    }
+
+    /// Return true if the next `push()` would invoke the overflow policy.
+    pub fn is_full(&self) -> bool {
+        self.messages.len() >= self.capacity
+    }
+
+    /// The maximum number of messages the queue will hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The overflow policy.
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// The number of new messages dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The number of queued messages dropped to make room for new ones.
+    pub fn dropped_oldest(&self) -> u64 {
+        self.dropped_oldest
+    }
+
+    /// The number of messages moved to the spill buffer.
+    pub fn spilled(&self) -> u64 {
+        self.spilled
+    }
+}
+
+impl<A> Default for OutgoingQueue<A> {
+    fn default() -> OutgoingQueue<A> {
+        OutgoingQueue::new()
+    }
+}
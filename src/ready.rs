@@ -0,0 +1,51 @@
+//! A round-robin list of sockets which still have work to do.
+//!
+//! An edge-triggered server is only told about a socket when its
+//! readiness changes, so a handler which stops before seeing `WouldBlock`
+//! (for example, because it used up its I/O budget) will not hear about
+//! that socket again.  Such sockets are remembered on a `ReadyList` and
+//! serviced again in turn, after the other ready sockets have had a go.
+
+use std::collections::VecDeque;
+
+/// A FIFO of socket indices, each of which appears at most once.
+pub struct ReadyList {
+    order: VecDeque<usize>,
+    listed: Vec<bool>,
+}
+
+impl ReadyList {
+    /// Create an empty list for sockets `0..sockets`.
+    pub fn new(sockets: usize) -> ReadyList {
+        ReadyList {
+            order: VecDeque::with_capacity(sockets),
+            listed: vec![false; sockets],
+        }
+    }
+
+    /// Add a socket to the back of the list, unless it is already listed.
+    /// Returns true if the socket was added.
+    pub fn push(&mut self, index: usize) -> bool {
+        if self.listed[index] {
+            return false;
+        }
+        self.listed[index] = true;
+        self.order.push_back(index);
+        true
+    }
+
+    /// Remove the socket at the front of the list.
+    pub fn pop(&mut self) -> Option<usize> {
+        let index = self.order.pop_front()?;
+        self.listed[index] = false;
+        Some(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
@@ -0,0 +1,103 @@
+//! Orderly shutdown of the servers.
+//!
+//! A server shuts down when it receives the configured shutdown datagram
+//! (see `--shutdown-command`), or on SIGINT or SIGTERM (see the `signal`
+//! module).  It then stops reading, keeps sending until its outgoing
+//! queues are empty or the drain deadline (see `--drain-timeout`) has
+//! passed, prints its final statistics and exits with status 0.
+
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+use libc;
+use echo::EchoCore;
+
+/// The signals which request a shutdown.
+pub const SHUTDOWN_SIGNALS: &[libc::c_int] = &[libc::SIGINT, libc::SIGTERM];
+
+/// The drain deadline of a server which is shutting down.
+pub struct Drain {
+    timeout: Duration,
+    deadline: Option<Instant>,
+}
+
+impl Drain {
+    /// A server which is not yet shutting down, and which will give up on
+    /// its pending writes `timeout` after it starts to.
+    pub fn new(timeout: Duration) -> Drain {
+        Drain {
+            timeout,
+            deadline: None,
+        }
+    }
+
+    /// Start draining, if not already.  Returns true the first time.
+    pub fn start(&mut self) -> bool {
+        if self.deadline.is_some() {
+            return false;
+        }
+        self.deadline = Some(Instant::now() + self.timeout);
+        true
+    }
+
+    /// Give up on pending writes `timeout` after starting to drain, from
+    /// now on.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Return true if the server is shutting down.
+    pub fn is_started(&self) -> bool {
+        self.deadline.is_some()
+    }
+
+    /// How long the event loop may wait for events: until the deadline
+    /// while draining, or indefinitely (`None`) otherwise.
+    pub fn wait(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Return true if the deadline has passed.
+    pub fn expired(&self) -> bool {
+        match self.deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            None => false,
+        }
+    }
+
+    /// Return true once a server which is shutting down has no `pending`
+    /// writes left, or the deadline has passed.
+    pub fn finished(&self, pending: usize) -> bool {
+        if !self.is_started() {
+            false
+        } else if pending == 0 {
+            println!("drained; exiting.");
+            true
+        } else if self.expired() {
+            println!("drain deadline passed; abandoning {} pending writes.", pending);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Check on the shutdown of a server with the given echo cores, after a
+    /// turn of its event loop.  Once any core has received the shutdown
+    /// command, or the server was `signalled`, every core stops reading.
+    /// Returns true once they have all drained, or the deadline has passed.
+    pub fn check<'a, A, I>(&mut self, cores: I, signalled: bool) -> bool
+    where
+        A: Clone + Eq + Hash + 'a,
+        I: IntoIterator<Item = &'a mut EchoCore<A>>,
+    {
+        let mut cores: Vec<_> = cores.into_iter().collect();
+        let pending = cores.iter().map(|core| core.pending()).sum();
+        if (signalled || cores.iter().any(|core| core.is_shutting_down())) && self.start() {
+            for core in &mut cores {
+                core.shutdown();
+            }
+            println!("shutting down; draining {} pending writes.", pending);
+        }
+        self.finished(pending)
+    }
+}
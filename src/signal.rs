@@ -0,0 +1,535 @@
+//! Signals as event sources.
+//!
+//! The echo servers handle signals as events like any other: SIGINT and
+//! SIGTERM shut a server down (see the `shutdown` module), SIGUSR1 prints
+//! its live statistics, and SIGHUP reloads its configuration file (see
+//! `--config`).
+//!
+//! Signal handlers run asynchronously, and may do little more than set a
+//! flag, which an event loop blocked waiting for its sockets would never
+//! see.  Each I/O layer therefore has its own way of waiting for signals
+//! along with the sockets, and the backends show one each:
+//!
+//! - select: the signals are blocked except during `pselect()`, which
+//!   unblocks them atomically for the wait, so a handler can only run (and
+//!   interrupt the wait) once the event loop is committed to waiting
+//!   (`SignalMask`).
+//! - poll: the same, with `ppoll()`.
+//! - epoll: the signals are blocked, and accepted through a `signalfd`
+//!   registered with epoll like a socket (`SignalFd`).
+//! - io_uring: the same `signalfd`, polled through the ring along with the
+//!   sockets' receives and sends.
+//! - mio: the signals are blocked, and a thread accepts them with
+//!   `sigwait()` and sets the readiness of a `Registration`
+//!   (`SignalRegistration`), as the timer thread of mio-mixed.rs does.
+//! - Tokio: the handlers write each signal to a pipe (`SignalPipe`), whose
+//!   read end is read as a `Stream` of signals (`SignalStream`).  The relay
+//!   servers use the same self-pipe for SIGINT and SIGTERM.
+
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::thread::JoinHandleExt;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use futures::{Async, Poll, Stream};
+use libc;
+use mio;
+use mio::unix::EventedFd;
+use mio::{Evented, PollOpt, Ready, Registration, SetReadiness, Token};
+use tokio_core::reactor::{Handle, PollEvented};
+use config::Config;
+use echo::EchoCore;
+use shutdown::Drain;
+
+/// The signals handled by the echo servers.
+pub const SIGNALS: &[libc::c_int] = &[libc::SIGINT, libc::SIGTERM, libc::SIGUSR1, libc::SIGHUP];
+
+/// What a signal asks of a server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGINT or SIGTERM: shut down.
+    Shutdown,
+    /// SIGUSR1: print the live statistics.
+    Stats,
+    /// SIGHUP: reload the configuration file.
+    Reload,
+}
+
+impl Signal {
+    /// The request made by signal number `signum`, if it is one of
+    /// `SIGNALS`.
+    pub fn from_raw(signum: libc::c_int) -> Option<Signal> {
+        match signum {
+            libc::SIGINT | libc::SIGTERM => Some(Signal::Shutdown),
+            libc::SIGUSR1 => Some(Signal::Stats),
+            libc::SIGHUP => Some(Signal::Reload),
+            _ => None,
+        }
+    }
+}
+
+/// Handle `signal`, received by an echo server with the given cores and
+/// configuration: print the cores' statistics, or reload the
+/// configuration and apply it to the cores and the drain deadline.
+/// Returns true if the signal requests a shutdown, which is left to
+/// `Drain::check()`.
+pub fn handle<'a, A, I>(signal: Signal, config: &mut Config, drain: &mut Drain, cores: I) -> bool
+where
+    A: Clone + Eq + Hash + 'a,
+    I: IntoIterator<Item = &'a mut EchoCore<A>>,
+{
+    match signal {
+        Signal::Shutdown => return true,
+        Signal::Stats => {
+            for (index, core) in cores.into_iter().enumerate() {
+                println!("socket #{}: {}", index, core.stats());
+            }
+        }
+        Signal::Reload => {
+            if reload(config) {
+                for core in cores {
+                    core.set_shutdown_command(config.shutdown_datagram());
+                }
+                drain.set_timeout(Duration::from_millis(config.drain_timeout));
+            }
+        }
+    }
+    false
+}
+
+/// Reload `config` from its configuration file.  Returns false, having
+/// said why, if there is no file or it is invalid.
+pub fn reload(config: &mut Config) -> bool {
+    let path = match config.config_file {
+        Some(ref path) => path.clone(),
+        None => {
+            println!("no configuration file to reload (see --config).");
+            return false;
+        }
+    };
+    match config.reload() {
+        Ok(reloaded) => {
+            *config = reloaded;
+            println!("reloaded configuration from {}.", path.display());
+            true
+        }
+        Err(e) => {
+            println!("not reloading configuration from {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+// The write end of the self-pipe, for the signal handler.
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn write_signal(signum: libc::c_int) {
+    // Only async-signal-safe calls are allowed here.  If the pipe is full, a wakeup is already
+    // pending, and the signal is lost, as it would be if it were already pending anyway.
+    let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = signum as u8;
+        unsafe {
+            libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        }
+    }
+}
+
+/// A self-pipe: handlers for the given signals write the signal number to
+/// the pipe, whose read end becomes readable.  Dropping it restores the
+/// default handlers.
+pub struct SignalPipe {
+    read_fd: RawFd,
+    write_fd: RawFd,
+    signals: &'static [libc::c_int],
+}
+
+impl SignalPipe {
+    /// Create the self-pipe, and install handlers for `signals` which write
+    /// to it.  Only one `SignalPipe` should exist at a time.
+    pub fn install(signals: &'static [libc::c_int]) -> io::Result<SignalPipe> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let pipe = SignalPipe {
+            read_fd: fds[0],
+            write_fd: fds[1],
+            signals,
+        };
+        for &fd in &fds {
+            set_nonblocking(fd)?;
+        }
+        SIGNAL_PIPE.store(pipe.write_fd, Ordering::Relaxed);
+        for &signum in signals {
+            set_handler(signum, write_signal as *const () as libc::sighandler_t)?;
+        }
+        Ok(pipe)
+    }
+
+    /// The read end of the pipe, which becomes readable when a signal
+    /// arrives.
+    pub fn fd(&self) -> RawFd {
+        self.read_fd
+    }
+
+    /// The signals which have arrived since the last call, in order,
+    /// emptying the pipe.
+    pub fn signals(&self) -> Vec<Signal> {
+        let mut buffer = [0u8; 16];
+        let mut signals = vec![];
+        loop {
+            let nbytes = unsafe {
+                libc::read(self.read_fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len())
+            };
+            if nbytes <= 0 {
+                return signals;
+            }
+            signals.extend(
+                buffer[..nbytes as usize]
+                    .iter()
+                    .filter_map(|&signum| Signal::from_raw(libc::c_int::from(signum))),
+            );
+        }
+    }
+}
+
+impl Drop for SignalPipe {
+    fn drop(&mut self) {
+        for &signum in self.signals {
+            let _ = set_handler(signum, libc::SIG_DFL);
+        }
+        SIGNAL_PIPE.store(-1, Ordering::Relaxed);
+        unsafe {
+            libc::close(self.read_fd);
+            libc::close(self.write_fd);
+        }
+    }
+}
+
+/// The read end of the pipe, for registering with mio (or Tokio, via
+/// `PollEvented`).
+impl Evented for SignalPipe {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.read_fd).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.read_fd).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.read_fd).deregister(poll)
+    }
+}
+
+/// The `SIGNALS` received by a Tokio server, as a stream.  The stream
+/// never ends.
+pub struct SignalStream {
+    pipe: PollEvented<SignalPipe>,
+    pending: VecDeque<Signal>,
+}
+
+impl SignalStream {
+    /// Install the signal handlers, and register their pipe with the
+    /// reactor.
+    pub fn new(handle: &Handle) -> io::Result<SignalStream> {
+        Ok(SignalStream {
+            pipe: PollEvented::new(SignalPipe::install(SIGNALS)?, handle)?,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+impl Stream for SignalStream {
+    type Item = Signal;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Signal>, io::Error> {
+        loop {
+            if let Some(signal) = self.pending.pop_front() {
+                return Ok(Async::Ready(Some(signal)));
+            }
+            if let Async::NotReady = self.pipe.poll_read() {
+                return Ok(Async::NotReady);
+            }
+            // The pipe is now empty, so wait for it to become readable again.
+            self.pending.extend(self.pipe.get_ref().signals());
+            self.pipe.need_read();
+        }
+    }
+}
+
+// The signals which have arrived during pselect(), one bit per signal number.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn record_signal(signum: libc::c_int) {
+    PENDING.fetch_or(1 << signum, Ordering::Relaxed);
+}
+
+/// The `SIGNALS`, blocked except while waiting in `pselect()` or
+/// `ppoll()` with the `unblocked()` mask.  Their handlers only record
+/// which arrived.  Dropping it restores the default handlers and the
+/// original mask.
+pub struct SignalMask {
+    unblocked: libc::sigset_t,
+}
+
+impl SignalMask {
+    /// Install the signal handlers, and block the signals in this thread.
+    pub fn block() -> io::Result<SignalMask> {
+        let unblocked = block(SIGNALS)?;
+        for &signum in SIGNALS {
+            set_handler(signum, record_signal as *const () as libc::sighandler_t)?;
+        }
+        Ok(SignalMask { unblocked })
+    }
+
+    /// The mask to wait with: the thread's original mask, which doesn't
+    /// block the signals.
+    pub fn unblocked(&self) -> &libc::sigset_t {
+        &self.unblocked
+    }
+
+    /// The signals which have arrived since the last call.  A signal which
+    /// arrived more than once is only reported once.
+    pub fn signals(&self) -> Vec<Signal> {
+        let pending = PENDING.swap(0, Ordering::Relaxed);
+        SIGNALS
+            .iter()
+            .filter(|&&signum| pending & (1 << signum) != 0)
+            .filter_map(|&signum| Signal::from_raw(signum))
+            .collect()
+    }
+}
+
+impl Drop for SignalMask {
+    fn drop(&mut self) {
+        for &signum in SIGNALS {
+            let _ = set_handler(signum, libc::SIG_DFL);
+        }
+        unsafe {
+            libc::pthread_sigmask(libc::SIG_SETMASK, &self.unblocked, ptr::null_mut());
+        }
+    }
+}
+
+/// The `SIGNALS`, blocked in this thread (and in any threads it spawns
+/// later) and accepted by a thread of their own, which reports them
+/// through a mio `Registration`.  Dropping it stops the thread, and
+/// restores the original mask.
+pub struct SignalRegistration {
+    registration: Registration,
+    set_readiness: SetReadiness,
+    rx: mpsc::Receiver<Signal>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    unblocked: libc::sigset_t,
+}
+
+impl SignalRegistration {
+    /// Block the signals, and start the thread which waits for them.
+    pub fn new() -> io::Result<SignalRegistration> {
+        let (registration, set_readiness) = Registration::new2();
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let unblocked = block(SIGNALS)?;
+
+        // The thread inherits the mask, so the signals stay pending until it accepts them.
+        let thread = {
+            let set_readiness = set_readiness.clone();
+            let stop = stop.clone();
+            let set = sigset(SIGNALS);
+            thread::spawn(move || loop {
+                let mut signum = 0;
+                if unsafe { libc::sigwait(&set, &mut signum) } != 0 || stop.load(Ordering::SeqCst) {
+                    return;
+                }
+                if let Some(signal) = Signal::from_raw(signum) {
+                    if tx.send(signal).is_err() {
+                        return;
+                    }
+                    let _ = set_readiness.set_readiness(Ready::readable());
+                }
+            })
+        };
+        Ok(SignalRegistration {
+            registration,
+            set_readiness,
+            rx,
+            stop,
+            thread: Some(thread),
+            unblocked,
+        })
+    }
+
+    /// The signals which have arrived since the last call, in order.
+    pub fn signals(&self) -> Vec<Signal> {
+        // Clear the readiness before taking the signals, so that a signal sent afterwards sets it
+        // again.
+        let _ = self.set_readiness.set_readiness(Ready::empty());
+        self.rx.try_iter().collect()
+    }
+}
+
+impl Drop for SignalRegistration {
+    fn drop(&mut self) {
+        // Wake the thread with one of the signals it is waiting for.
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            unsafe {
+                libc::pthread_kill(thread.as_pthread_t(), libc::SIGUSR1);
+            }
+            let _ = thread.join();
+        }
+        unsafe {
+            libc::pthread_sigmask(libc::SIG_SETMASK, &self.unblocked, ptr::null_mut());
+        }
+    }
+}
+
+/// Proxy Evented functions to the Registration.
+impl Evented for SignalRegistration {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        self.registration.register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        self.registration.reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        <Registration as Evented>::deregister(&self.registration, poll)
+    }
+}
+
+/// The `SIGNALS`, blocked and accepted through a non-blocking signalfd,
+/// which becomes readable when one arrives.  Dropping it closes the
+/// signalfd, and restores the original mask.
+pub struct SignalFd {
+    fd: RawFd,
+    unblocked: libc::sigset_t,
+}
+
+impl SignalFd {
+    /// Block the signals, and open the signalfd which accepts them.
+    pub fn new() -> io::Result<SignalFd> {
+        let unblocked = block(SIGNALS)?;
+        let flags = libc::SFD_NONBLOCK | libc::SFD_CLOEXEC;
+        let fd = unsafe { libc::signalfd(-1, &sigset(SIGNALS), flags) };
+        if fd < 0 {
+            let e = io::Error::last_os_error();
+            unsafe {
+                libc::pthread_sigmask(libc::SIG_SETMASK, &unblocked, ptr::null_mut());
+            }
+            return Err(e);
+        }
+        Ok(SignalFd { fd, unblocked })
+    }
+
+    /// The signals which have arrived since the last call, in order.
+    pub fn signals(&mut self) -> Vec<Signal> {
+        let mut signals = vec![];
+        loop {
+            let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+            let size = mem::size_of_val(&info);
+            let buffer = &mut info as *mut _ as *mut libc::c_void;
+            let nbytes = unsafe { libc::read(self.fd, buffer, size) };
+            if nbytes != size as isize {
+                return signals;
+            }
+            signals.extend(Signal::from_raw(info.ssi_signo as libc::c_int));
+        }
+    }
+}
+
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for SignalFd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+            libc::pthread_sigmask(libc::SIG_SETMASK, &self.unblocked, ptr::null_mut());
+        }
+    }
+}
+
+/// A signal set of `signals`.
+pub fn sigset(signals: &[libc::c_int]) -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for &signum in signals {
+            libc::sigaddset(&mut set, signum);
+        }
+        set
+    }
+}
+
+/// Block `signals` in this thread, returning the previous mask.
+pub fn block(signals: &[libc::c_int]) -> io::Result<libc::sigset_t> {
+    unsafe {
+        let mut previous: libc::sigset_t = mem::zeroed();
+        let errno = libc::pthread_sigmask(libc::SIG_BLOCK, &sigset(signals), &mut previous);
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        Ok(previous)
+    }
+}
+
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn set_handler(signum: libc::c_int, handler: libc::sighandler_t) -> io::Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handler;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signum, &action, ptr::null_mut()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,238 @@
+//! A sans-I/O implementation of the echo protocol over a byte stream.
+//!
+//! `StreamCore` is the TCP counterpart of `EchoCore`: it holds the state
+//! of one connection -- the echoes not yet written and what is known about
+//! the connection's readiness -- but performs no I/O itself.  A stream has
+//! no message boundaries, so the core echoes bytes rather than datagrams,
+//! and must cope with what datagrams let the UDP servers ignore:
+//!
+//! * A read returns as many bytes as happen to be available, and a write
+//!   may accept only part of the pending echoes.  The rest stays at the
+//!   front of the write buffer for the next write.
+//! * The write buffer is bounded (see `--write-buffer`).  Once it is full,
+//!   the core stops reading until the peer has read some echoes, leaving
+//!   the backpressure to TCP flow control rather than dropping anything.
+//! * A read of zero bytes means the peer has closed its side of the
+//!   connection.  A half-closed peer may still be reading, so the core goes
+//!   on writing the pending echoes, and only then is the connection
+//!   finished and closed.
+//!
+//! A backend drives the core just like an `EchoCore`: it reports readiness
+//! and `WouldBlock` results, reads while `wants_read()` into at most
+//! `room()` bytes, writes what `poll_send()` offers, and closes the
+//! connection once `is_finished()`.
+
+use std::cmp;
+use std::fmt;
+use echo::Interest;
+
+/// Counters describing the work done by a `StreamCore`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    pub received: u64, // Bytes read from the connection.
+    pub sent: u64, // Bytes of echoes written to the connection.
+    pub reads: u64, // Reads which returned data.
+    pub writes: u64, // Writes which accepted data.
+    pub partial_writes: u64, // Writes which accepted only part of the pending echoes.
+    pub pauses: u64, // Times reading stopped because the write buffer filled.
+    pub pending: usize, // Bytes waiting in the write buffer.
+}
+
+impl StreamStats {
+    /// Add the counters of another connection to these.
+    pub fn add(&mut self, other: &StreamStats) {
+        self.received += other.received;
+        self.sent += other.sent;
+        self.reads += other.reads;
+        self.writes += other.writes;
+        self.partial_writes += other.partial_writes;
+        self.pauses += other.pauses;
+        self.pending += other.pending;
+    }
+}
+
+impl fmt::Display for StreamStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "received={} sent={} reads={} writes={} partial_writes={} pauses={} pending={}",
+            self.received,
+            self.sent,
+            self.reads,
+            self.writes,
+            self.partial_writes,
+            self.pauses,
+            self.pending
+        )
+    }
+}
+
+/// The protocol state machine of one connection of a TCP echo server.
+pub struct StreamCore {
+    buffer: Vec<u8>, // Echoes not yet written, oldest first.
+    capacity: usize,
+    readable: bool,
+    writable: bool,
+    registered: Option<Interest>,
+    closed_by_peer: bool,
+    shutting_down: bool,
+    stats: StreamStats,
+}
+
+impl StreamCore {
+    /// Create a core whose write buffer holds up to `capacity` bytes.
+    pub fn new(capacity: usize) -> StreamCore {
+        assert!(capacity > 0, "a stream core needs room for at least one byte");
+        StreamCore {
+            buffer: Vec::with_capacity(capacity),
+            capacity,
+            readable: true,
+            writable: false,
+            registered: None,
+            closed_by_peer: false,
+            shutting_down: false,
+            stats: StreamStats::default(),
+        }
+    }
+
+    /// Input: the event source reported the connection as readable.
+    pub fn readable(&mut self) {
+        self.readable = true;
+    }
+
+    /// Input: the event source reported the connection as writable.
+    pub fn writable(&mut self) {
+        self.writable = true;
+    }
+
+    /// Input: a read returned `data`, which must fit in `room()`.  An empty
+    /// read means that the peer has closed its side of the connection.
+    pub fn received(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            self.closed_by_peer = true;
+            return;
+        }
+        assert!(data.len() <= self.room(), "read more than the write buffer can hold");
+        self.stats.reads += 1;
+        self.stats.received += data.len() as u64;
+        self.buffer.extend_from_slice(data);
+
+        // We must at least try to write the echo.  An edge-triggered backend won't be told about
+        // writability until a write has returned WouldBlock.
+        self.writable = true;
+        if self.room() == 0 {
+            self.stats.pauses += 1;
+        }
+    }
+
+    /// Input: a write accepted the first `nbytes` of `poll_send()`.  The
+    /// rest is offered again by the next `poll_send()`.
+    pub fn sent(&mut self, nbytes: usize) {
+        let nbytes = cmp::min(nbytes, self.buffer.len());
+        self.stats.writes += 1;
+        self.stats.sent += nbytes as u64;
+        if nbytes < self.buffer.len() {
+            self.stats.partial_writes += 1;
+        }
+        self.buffer.drain(..nbytes);
+    }
+
+    /// Input: the server is shutting down.  The core stops reading for
+    /// good, and finishes once the pending echoes have been written.
+    pub fn shutdown(&mut self) {
+        self.shutting_down = true;
+    }
+
+    /// Input: reading would block.
+    pub fn read_would_block(&mut self) {
+        self.readable = false;
+    }
+
+    /// Input: writing would block.
+    pub fn write_would_block(&mut self) {
+        self.writable = false;
+    }
+
+    /// The number of bytes the next read may return: the free space in the
+    /// write buffer.
+    pub fn room(&self) -> usize {
+        self.capacity - self.buffer.len()
+    }
+
+    /// Output: return true if the backend should try to read.
+    pub fn wants_read(&self) -> bool {
+        self.readable && self.wants_input()
+    }
+
+    /// Output: return true if the backend should try to write, i.e. echoes
+    /// are pending and the connection may be writable.
+    pub fn wants_write(&self) -> bool {
+        self.writable && !self.buffer.is_empty()
+    }
+
+    /// Output: the pending echoes, if the connection may be writable.
+    pub fn poll_send(&self) -> Option<&[u8]> {
+        if self.wants_write() {
+            Some(&self.buffer)
+        } else {
+            None
+        }
+    }
+
+    /// Output: the interest the backend should currently be registered for.
+    /// Write interest is only needed while echoes are pending, and read
+    /// interest is withdrawn while the write buffer is full, and for good
+    /// once the peer has closed its side or the server is shutting down.
+    pub fn interest(&self) -> Interest {
+        Interest {
+            read: self.wants_input(),
+            write: !self.buffer.is_empty(),
+        }
+    }
+
+    /// Output: the interest to register with the event source, if it has
+    /// changed since the last call.  The first call always returns the
+    /// current interest.
+    pub fn poll_interest(&mut self) -> Option<Interest> {
+        let interest = self.interest();
+        if self.registered == Some(interest) {
+            None
+        } else {
+            self.registered = Some(interest);
+            Some(interest)
+        }
+    }
+
+    /// Output: return true once the connection should be closed: no more
+    /// input will be read, and every echo has been written.
+    pub fn is_finished(&self) -> bool {
+        (self.closed_by_peer || self.shutting_down) && self.buffer.is_empty()
+    }
+
+    /// Return true if the peer has closed its side of the connection.
+    pub fn is_closed_by_peer(&self) -> bool {
+        self.closed_by_peer
+    }
+
+    /// Return true if reading has stopped until some echoes are written.
+    pub fn is_paused(&self) -> bool {
+        self.room() == 0
+    }
+
+    /// The number of bytes waiting to be written.
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Counters describing the work done so far.
+    pub fn stats(&self) -> StreamStats {
+        StreamStats {
+            pending: self.pending(),
+            ..self.stats
+        }
+    }
+
+    fn wants_input(&self) -> bool {
+        !self.closed_by_peer && !self.shutting_down && self.room() > 0
+    }
+}
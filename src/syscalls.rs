@@ -0,0 +1,226 @@
+//! System call accounting for the nix-based echo servers.
+//!
+//! Much of what the examples demonstrate is only visible in the system
+//! calls they make: the pipe writes behind mio's `Registration` (see
+//! mio-empty.rs and mio-mixed.rs), the WouldBlock results which end each
+//! turn of an edge-triggered server, or the calls saved by batching.
+//! Rather than run each server under strace, the select, poll and epoll
+//! backends make their socket and event calls through a `Syscalls`
+//! ledger, which counts the calls of each kind, the ones which would have
+//! blocked, and the time spent in them.
+//!
+//! The ledger is printed with the datagrams received on exit, and every
+//! `--syscall-interval` seconds, busy or idle: each server watches a
+//! periodic timerfd along with its sockets (see `Config::report_timer()`),
+//! and reports whenever it expires.  So the system calls per datagram of,
+//! say, level and edge triggering can be compared directly:
+//!
+//! ```text
+//! syscalls: calls=5 datagrams=1 per_datagram=5.00
+//! syscall recvfrom: calls=2 would_block=1 time=14.2µs
+//! ```
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use timer::TimerFd;
+
+/// The system calls made through a `Syscalls` ledger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syscall {
+    RecvFrom,
+    SendTo,
+    RecvMmsg,
+    SendMmsg,
+    Select,
+    Poll,
+    EpollWait,
+    EpollCtl,
+}
+
+/// Every `Syscall`, in the order they are reported.
+pub const SYSCALLS: &[Syscall] = &[
+    Syscall::RecvFrom,
+    Syscall::SendTo,
+    Syscall::RecvMmsg,
+    Syscall::SendMmsg,
+    Syscall::Select,
+    Syscall::Poll,
+    Syscall::EpollWait,
+    Syscall::EpollCtl,
+];
+
+impl Syscall {
+    /// The name of the system call, as strace would print it.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Syscall::RecvFrom => "recvfrom",
+            Syscall::SendTo => "sendto",
+            Syscall::RecvMmsg => "recvmmsg",
+            Syscall::SendMmsg => "sendmmsg",
+            Syscall::Select => "pselect6",
+            Syscall::Poll => "ppoll",
+            Syscall::EpollWait => "epoll_wait",
+            Syscall::EpollCtl => "epoll_ctl",
+        }
+    }
+}
+
+/// The calls of one kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyscallCount {
+    pub calls: u64,
+    pub would_block: u64, // Calls which failed with EWOULDBLOCK.
+    pub time: Duration, // Time spent in the calls, including any waiting.
+}
+
+impl fmt::Display for SyscallCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "calls={} would_block={} time={:?}", self.calls, self.would_block, self.time)
+    }
+}
+
+/// Parse a count as it is reported, so that the report can be read back
+/// (the time only to the precision printed).
+impl FromStr for SyscallCount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SyscallCount, String> {
+        let mut count = SyscallCount::default();
+        for field in s.split_whitespace() {
+            let (name, value) = match field.find('=') {
+                Some(i) => (&field[..i], &field[i + 1..]),
+                None => return Err(format!("expected name=value, not '{}'", field)),
+            };
+            let invalid = || format!("invalid {}: '{}'", name, value);
+            match name {
+                "calls" => count.calls = value.parse().map_err(|_| invalid())?,
+                "would_block" => count.would_block = value.parse().map_err(|_| invalid())?,
+                "time" => count.time = parse_duration(value).ok_or_else(invalid)?,
+                _ => return Err(format!("unknown field '{}'", name)),
+            }
+        }
+        Ok(count)
+    }
+}
+
+// Parse a duration as `Duration`'s Debug formats it, such as "14.2µs" or "1.5s".
+fn parse_duration(s: &str) -> Option<Duration> {
+    let units = [("ns", 1e-9), ("µs", 1e-6), ("ms", 1e-3), ("s", 1.0)];
+    let &(unit, scale) = units.iter().find(|&&(unit, _)| s.ends_with(unit))?;
+    let value: f64 = s[..s.len() - unit.len()].parse().ok()?;
+    if value.is_finite() && value >= 0.0 {
+        Some(Duration::from_secs_f64(value * scale))
+    } else {
+        None
+    }
+}
+
+/// An error which may report that a non-blocking call would have
+/// blocked.
+pub trait WouldBlock {
+    fn would_block(&self) -> bool;
+}
+
+impl WouldBlock for io::Error {
+    fn would_block(&self) -> bool {
+        self.kind() == io::ErrorKind::WouldBlock
+    }
+}
+
+#[cfg(feature = "nix")]
+impl WouldBlock for nix::Error {
+    fn would_block(&self) -> bool {
+        match *self {
+            nix::Error::Sys(errno) => errno == nix::errno::EWOULDBLOCK,
+            _ => false,
+        }
+    }
+}
+
+/// The system calls made by a server.
+pub struct Syscalls {
+    counts: Vec<SyscallCount>, // Indexed like `SYSCALLS`.
+}
+
+impl Syscalls {
+    /// An empty ledger.
+    pub fn new() -> Syscalls {
+        Syscalls {
+            counts: vec![SyscallCount::default(); SYSCALLS.len()],
+        }
+    }
+
+    /// Make a system call by calling `f`, and account for it as a call of
+    /// `syscall`.
+    pub fn call<T, E, F>(&mut self, syscall: Syscall, f: F) -> Result<T, E>
+    where
+        E: WouldBlock,
+        F: FnOnce() -> Result<T, E>,
+    {
+        let start = Instant::now();
+        let result = f();
+        let count = &mut self.counts[syscall as usize];
+        count.time += start.elapsed();
+        count.calls += 1;
+        if let Err(ref e) = result {
+            if e.would_block() {
+                count.would_block += 1;
+            }
+        }
+        result
+    }
+
+    /// The calls of `syscall` made so far.
+    pub fn count(&self, syscall: Syscall) -> SyscallCount {
+        self.counts[syscall as usize]
+    }
+
+    /// The number of calls of every kind made so far.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().map(|count| count.calls).sum()
+    }
+
+    /// The calls made so far per datagram, for a server which has received
+    /// `datagrams`, or 0 if it has received none.
+    pub fn per_datagram(&self, datagrams: u64) -> f64 {
+        if datagrams == 0 {
+            0.0
+        } else {
+            self.total() as f64 / datagrams as f64
+        }
+    }
+
+    /// Print the calls made so far, by a server which has received
+    /// `datagrams`.
+    pub fn report(&self, datagrams: u64) {
+        println!(
+            "syscalls: calls={} datagrams={} per_datagram={:.2}",
+            self.total(),
+            datagrams,
+            self.per_datagram(datagrams)
+        );
+        for (syscall, count) in SYSCALLS.iter().zip(&self.counts) {
+            if count.calls > 0 {
+                println!("syscall {}: {}", syscall.name(), count);
+            }
+        }
+    }
+
+    /// Print the calls made so far if the report timer `timer` has expired,
+    /// however many intervals have passed since it was last read.  Reading
+    /// it clears its readiness.
+    pub fn report_expired(&self, timer: &TimerFd, datagrams: u64) -> io::Result<()> {
+        if timer.expirations()? > 0 {
+            self.report(datagrams);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Syscalls {
+    fn default() -> Syscalls {
+        Syscalls::new()
+    }
+}
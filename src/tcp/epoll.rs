@@ -0,0 +1,367 @@
+//! TCP echo servers using the Linux epoll facility to multiplex accepts,
+//! reads and writes on many connections.  Each listener is registered
+//! with its index as the epoll event data, and each connection with its
+//! key in the connection slab, offset by the number of listeners.
+//!
+//! `TcpEpollLevel` uses level-triggered events: one accept, read or write
+//! per event.
+//!
+//! `TcpEpollEdge` uses edge-triggered events, so each listener is accepted
+//! on until it would block, and each connection is read and written until
+//! both would block.  As in the UDP epoll backend, a connection is limited
+//! to `--budget` reads and writes per turn, and one which uses up its
+//! budget is put on a `ReadyList` to be serviced again without a new edge.
+//!
+//! Signals are accepted through a signalfd registered with epoll, as in
+//! the UDP epoll backend.
+
+use std::io;
+use std::os::unix::io::RawFd;
+use nix::sys::epoll::*;
+use nix::sys::socket::*;
+use nix::unistd::close;
+use backend::EchoBackend;
+use backend::epoll::{epoll_timeout, register_signals, SIGNAL};
+use config::{Config, MAX_EVENTS};
+use net::{accept_stream, bind_sockets, io_error, local_addr};
+use ready::ReadyList;
+use tcp::{self, Connections};
+use unix::SocketFile;
+
+/// The level-triggered epoll TCP echo server.
+pub struct TcpEpollLevel;
+
+/// The edge-triggered epoll TCP echo server.
+pub struct TcpEpollEdge;
+
+impl EchoBackend for TcpEpollLevel {
+    fn name(&self) -> &'static str {
+        "tcp-epoll-level"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        let epoll_fd = epoll_create1(EpollCreateFlags::empty()).map_err(io_error)?;
+
+        // Signals make the signalfd readable.
+        let mut signals = register_signals(epoll_fd)?;
+        let mut config = config.clone();
+        let mut drain = config.drain();
+
+        // Open a listening TCP socket in non-blocking mode on each configured address (by default
+        // IPv4 localhost, port 2000), or the Unix domain socket, and register it with its index as
+        // the epoll event data.
+        let (listeners, _socket_file) = listen(epoll_fd, &config, EpollFlags::empty())?;
+
+        let mut connections: Connections<RawFd, SockAddr> = Connections::new(&config);
+        let mut inbuf = config.buffer();
+        let mut current_events = [EpollEvent::empty(); MAX_EVENTS];
+        let mut listening = true;
+
+        loop {
+            // Set up events for each connection whose echo core's interest has changed.  (A core
+            // only asks to write when there are echoes pending, and stops asking to read while
+            // its write buffer is full.)
+            for (key, connection) in connections.iter_mut() {
+                if let Some(interest) = connection.core.poll_interest() {
+                    let data = (listeners.len() + key) as u64;
+                    let mut event = EpollEvent::new(interest.epoll_flags(), data);
+                    epoll_ctl(epoll_fd, EpollOp::EpollCtlMod, connection.stream, &mut event)
+                        .map_err(io_error)?;
+                }
+            }
+
+            // Select via epoll.  While shutting down, only wait until the drain deadline.
+            let timeout = epoll_timeout(&drain, -1);
+            let num_events = match epoll_wait(epoll_fd, &mut current_events, timeout) {
+                Ok(num_events) => num_events,
+                Err(nix::Error::Sys(nix::errno::EINTR)) => 0,
+                Err(e) => return Err(io_error(e)),
+            };
+
+            // Process events
+            let mut signalled = false;
+            let mut failed = vec![];
+            for event in &current_events[0..num_events] {
+                let data = event.data();
+                if data == SIGNAL {
+                    for signal in signals.signals() {
+                        signalled |=
+                            tcp::handle_signal(signal, &mut config, &mut drain, &connections);
+                    }
+                    continue;
+                }
+                if (data as usize) < listeners.len() {
+                    // Accept one connection.
+                    if listening {
+                        let listener_fd = listeners[data as usize];
+                        let flags = EpollFlags::empty();
+                        accept(epoll_fd, listener_fd, flags, &listeners, &mut connections)?;
+                    }
+                    continue;
+                }
+                let key = data as usize - listeners.len();
+                let connection = match connections.get_mut(key) {
+                    Some(connection) => connection,
+                    None => continue, // Closed earlier in this batch of events.
+                };
+                let mut result = Ok(false);
+                if event.events().intersects(EPOLLIN | EPOLLHUP | EPOLLERR) {
+                    // Read from the connection, handing the data to the echo core.
+                    connection.core.readable();
+                    result = connection.read_with(&mut inbuf, |&mut socket_fd, buffer| {
+                        recv(socket_fd, buffer, MsgFlags::empty()).map_err(io_error)
+                    });
+                }
+                if result.is_ok() && event.events().intersects(EPOLLOUT | EPOLLHUP | EPOLLERR) {
+                    // Write as many of the pending echoes as the connection will take.
+                    connection.core.writable();
+                    result = connection.write_with(|&mut socket_fd, pending| {
+                        send(socket_fd, pending, MsgFlags::empty()).map_err(io_error)
+                    });
+                }
+                if let Err(e) = result {
+                    failed.push((key, e));
+                }
+            }
+
+            // Close the connections which have failed, or finished echoing.  Closing a socket
+            // also removes it from the epoll set.
+            for (key, e) in failed {
+                let _ = close(connections.fail(key, &e).stream);
+            }
+            for key in connections.finished() {
+                let _ = close(connections.remove(key).stream);
+            }
+
+            if connections.check_drain(&mut drain, signalled) {
+                break;
+            }
+            if drain.is_started() && listening {
+                stop_listening(epoll_fd, &listeners)?;
+                listening = false;
+            }
+        }
+
+        finish(epoll_fd, &connections, listeners);
+        Ok(())
+    }
+}
+
+impl EchoBackend for TcpEpollEdge {
+    fn name(&self) -> &'static str {
+        "tcp-epoll-edge"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        let epoll_fd = epoll_create1(EpollCreateFlags::empty()).map_err(io_error)?;
+
+        // Signals make the signalfd readable.
+        let mut signals = register_signals(epoll_fd)?;
+        let mut config = config.clone();
+        let mut drain = config.drain();
+
+        // Open a listening TCP socket in non-blocking mode on each configured address (by default
+        // IPv4 localhost, port 2000), or the Unix domain socket, and register it with its index as
+        // the epoll event data.
+        let (listeners, _socket_file) = listen(epoll_fd, &config, EPOLLET)?;
+
+        let mut connections: Connections<RawFd, SockAddr> = Connections::new(&config);
+        let mut inbuf = config.buffer();
+        let mut current_events = [EpollEvent::empty(); MAX_EVENTS];
+        let mut listening = true;
+
+        // Connections which may be able to make progress without a new edge, by key.  Keys are
+        // reused, so a key on the list may belong to a newer connection than the one which was
+        // listed, which at worst gives the newer connection an extra turn.
+        let mut ready = ReadyList::new(config.max_connections);
+
+        let mut signalled = false;
+        loop {
+            // Give each connection on the ready list one turn, in round-robin order.  A connection
+            // which uses up its budget goes to the back of the list instead of monopolizing the
+            // loop.
+            let mut failed = vec![];
+            for _ in 0..ready.len() {
+                let key = ready.pop().unwrap();
+                let connection = match connections.get_mut(key) {
+                    Some(connection) => connection,
+                    None => continue,
+                };
+                let result = connection.service_with(
+                    &mut inbuf,
+                    config.budget_limit(),
+                    |&mut socket_fd, buffer| {
+                        recv(socket_fd, buffer, MsgFlags::empty()).map_err(io_error)
+                    },
+                    |&mut socket_fd, pending| {
+                        send(socket_fd, pending, MsgFlags::empty()).map_err(io_error)
+                    },
+                );
+                match result {
+                    Ok(true) => {
+                        println!("connection from {} used its budget; deferring.", connection.peer);
+                        ready.push(key);
+                    }
+                    Ok(false) => (),
+                    Err(e) => {
+                        failed.push((key, e));
+                        continue;
+                    }
+                }
+                if let Some(interest) = connection.core.poll_interest() {
+                    let data = (listeners.len() + key) as u64;
+                    let mut event = EpollEvent::new(interest.epoll_flags() | EPOLLET, data);
+                    epoll_ctl(epoll_fd, EpollOp::EpollCtlMod, connection.stream, &mut event)
+                        .map_err(io_error)?;
+                }
+            }
+
+            // Close the connections which have failed, or finished echoing.  Closing a socket
+            // also removes it from the epoll set.
+            for (key, e) in failed {
+                let _ = close(connections.fail(key, &e).stream);
+            }
+            for key in connections.finished() {
+                let _ = close(connections.remove(key).stream);
+            }
+
+            if connections.check_drain(&mut drain, signalled) {
+                break;
+            }
+            if drain.is_started() && listening {
+                stop_listening(epoll_fd, &listeners)?;
+                listening = false;
+            }
+
+            // Collect new edges.  Only block if no connection has work left over from its last
+            // turn, and while shutting down, only until the drain deadline.
+            let timeout = epoll_timeout(&drain, if ready.is_empty() { -1 } else { 0 });
+            let num_events = match epoll_wait(epoll_fd, &mut current_events, timeout) {
+                Ok(num_events) => num_events,
+                Err(nix::Error::Sys(nix::errno::EINTR)) => 0,
+                Err(e) => return Err(io_error(e)),
+            };
+
+            // Process events
+            for event in &current_events[0..num_events] {
+                let data = event.data();
+                if data == SIGNAL {
+                    for signal in signals.signals() {
+                        signalled |=
+                            tcp::handle_signal(signal, &mut config, &mut drain, &connections);
+                    }
+                    continue;
+                }
+                if (data as usize) < listeners.len() {
+                    // Accept until the listener would block: there will be no new edge for the
+                    // connections already waiting.  A new connection may be readable already.
+                    if !listening {
+                        continue;
+                    }
+                    let listener_fd = listeners[data as usize];
+                    loop {
+                        let accepted =
+                            accept(epoll_fd, listener_fd, EPOLLET, &listeners, &mut connections)?;
+                        match accepted {
+                            Accepted::Connection(key) => {
+                                ready.push(key);
+                            }
+                            Accepted::Refused => (),
+                            Accepted::None => break,
+                        }
+                    }
+                    continue;
+                }
+                let key = data as usize - listeners.len();
+                if let Some(connection) = connections.get_mut(key) {
+                    if event.events().intersects(EPOLLIN | EPOLLHUP | EPOLLERR) {
+                        connection.core.readable();
+                    }
+                    if event.events().intersects(EPOLLOUT | EPOLLHUP | EPOLLERR) {
+                        connection.core.writable();
+                    }
+                    ready.push(key);
+                }
+            }
+        }
+
+        finish(epoll_fd, &connections, listeners);
+        Ok(())
+    }
+}
+
+/// What came of trying to accept a connection.
+enum Accepted {
+    Connection(usize), // Accepted, with this key.
+    Refused, // Accepted and closed at once, since there were too many.
+    None, // No connection was waiting.
+}
+
+// Open and register the listeners, with `flags` in addition to EPOLLIN.  The Unix domain socket
+// file, if any, is removed once the returned `SocketFile` is dropped.
+fn listen(
+    epoll_fd: RawFd,
+    config: &Config,
+    flags: EpollFlags,
+) -> io::Result<(Vec<RawFd>, Option<SocketFile>)> {
+    let (listener_fds, socket_file) = bind_sockets(config, SockType::Stream)?;
+    let mut listeners = vec![];
+    for (index, &listener_fd) in listener_fds.iter().enumerate() {
+        println!("listening on {}", local_addr(listener_fd).map_err(io_error)?);
+        let mut event = EpollEvent::new(EPOLLIN | flags, index as u64);
+        epoll_ctl(epoll_fd, EpollOp::EpollCtlAdd, listener_fd, &mut event).map_err(io_error)?;
+        listeners.push(listener_fd);
+    }
+    Ok((listeners, socket_file))
+}
+
+// Accept a connection, and register it with the interest of its new echo core and `flags`, unless
+// there are already too many.
+fn accept(
+    epoll_fd: RawFd,
+    listener_fd: RawFd,
+    flags: EpollFlags,
+    listeners: &[RawFd],
+    connections: &mut Connections<RawFd, SockAddr>,
+) -> io::Result<Accepted> {
+    let (socket_fd, peer) = match accept_stream(listener_fd) {
+        Ok(accepted) => accepted,
+        // The client may have given up already, in which case there may be others waiting.
+        Err(nix::Error::Sys(nix::errno::ECONNABORTED)) => return Ok(Accepted::Refused),
+        Err(nix::Error::Sys(errno)) if errno == nix::errno::EWOULDBLOCK => {
+            return Ok(Accepted::None)
+        }
+        Err(e) => return Err(io_error(e)),
+    };
+    if connections.is_full() {
+        connections.refuse(&peer);
+        let _ = close(socket_fd);
+        return Ok(Accepted::Refused);
+    }
+    let key = connections.insert(socket_fd, peer);
+    let interest = connections.get_mut(key).unwrap().core.poll_interest().unwrap();
+    let mut event = EpollEvent::new(interest.epoll_flags() | flags, (listeners.len() + key) as u64);
+    epoll_ctl(epoll_fd, EpollOp::EpollCtlAdd, socket_fd, &mut event).map_err(io_error)?;
+    Ok(Accepted::Connection(key))
+}
+
+// Stop accepting connections, on shutting down.
+fn stop_listening(epoll_fd: RawFd, listeners: &[RawFd]) -> io::Result<()> {
+    let mut event = EpollEvent::empty();
+    for &listener_fd in listeners {
+        epoll_ctl(epoll_fd, EpollOp::EpollCtlDel, listener_fd, &mut event).map_err(io_error)?;
+    }
+    Ok(())
+}
+
+// Print the final statistics, and close every socket.
+fn finish(epoll_fd: RawFd, connections: &Connections<RawFd, SockAddr>, listeners: Vec<RawFd>) {
+    println!("server: {}", connections.stats());
+    for (_, connection) in connections.iter() {
+        let _ = close(connection.stream);
+    }
+    for listener_fd in listeners {
+        let _ = close(listener_fd);
+    }
+    let _ = close(epoll_fd);
+}
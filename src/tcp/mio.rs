@@ -0,0 +1,184 @@
+//! A TCP echo server using the cross-platform mio crate to multiplex
+//! accepts, reads and writes on many connections, with level-triggered
+//! events.
+//!
+//! This is the classic shape of a mio server: each listener is registered
+//! with its index as its `Token`, and each accepted connection goes into a
+//! slab, whose key (offset by the number of listeners) is its `Token`.  An
+//! event's token thus leads straight back to its connection, and the keys
+//! of closed connections are reused for new ones.
+//!
+//! Signals are accepted by a thread of their own, which reports them as
+//! the readiness of a `Registration`, as in the UDP mio backends.
+//!
+//! Given `--unix`, the server listens on a Unix domain socket instead,
+//! through the mio-uds style types of the `unix` module.
+
+use std::io::{self, Read, Write};
+use mio::net::TcpListener;
+use mio::{Evented, Events, Poll, PollOpt, Ready, Token};
+use backend::EchoBackend;
+use backend::mio::{register_signals, SIGNAL};
+use config::{Config, MAX_EVENTS};
+use net::tcp_listener;
+use tcp::{self, Connections, StreamListener};
+use unix::UnixListener;
+
+/// The mio TCP echo server.
+pub struct TcpMio;
+
+impl EchoBackend for TcpMio {
+    fn name(&self) -> &'static str {
+        "tcp-mio"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        if let Some(ref addr) = config.unix {
+            return serve(vec![UnixListener::bind(addr)?], config);
+        }
+
+        // Open a listening TCP socket in non-blocking mode on each configured address (by default
+        // IPv4 localhost port 2000).
+        let mut listeners = vec![];
+        for addr in config.addrs() {
+            let listener = tcp_listener(&addr, config.v6_only)?;
+            listeners.push(TcpListener::from_listener(listener, &addr)?);
+        }
+        serve(listeners, config)
+    }
+}
+
+// Accept connections on the `listeners`, and echo them until shut down.
+fn serve<L>(mut listeners: Vec<L>, config: &Config) -> io::Result<()>
+where
+    L: StreamListener + Evented,
+    L::Stream: Evented,
+{
+    let poll = Poll::new()?;
+
+    // Signals make the signal registration readable.
+    let signals = register_signals(&poll)?;
+    let mut config = config.clone();
+    let mut drain = config.drain();
+
+    // Register each listener with its index as its Token.
+    for (index, listener) in listeners.iter().enumerate() {
+        println!("listening on {}", listener.local_addr()?);
+        poll.register(listener, Token(index), Ready::readable(), PollOpt::level())?;
+    }
+
+    let mut connections: Connections<L::Stream, L::Addr> = Connections::new(&config);
+    let mut inbuf = config.buffer();
+    let mut events = Events::with_capacity(MAX_EVENTS);
+    let mut listening = true;
+
+    // Main loop
+    loop {
+        // Set up events for each connection whose echo core's interest has changed.  (A core only
+        // asks to write when there are echoes pending, and stops asking to read while its write
+        // buffer is full.)
+        for (key, connection) in connections.iter_mut() {
+            if let Some(interest) = connection.core.poll_interest() {
+                let token = Token(listeners.len() + key);
+                poll.reregister(&connection.stream, token, interest.into(), PollOpt::level())?;
+            }
+        }
+
+        // Poll.  While shutting down, only wait until the drain deadline.
+        match poll.poll(&mut events, drain.wait()) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+
+        // Process events
+        let mut signalled = false;
+        let mut failed = vec![];
+        for event in &events {
+            if event.token() == SIGNAL {
+                for signal in signals.signals() {
+                    signalled |= tcp::handle_signal(signal, &mut config, &mut drain, &connections);
+                }
+                continue;
+            }
+            let Token(index) = event.token();
+            if index < listeners.len() {
+                // Accept one connection.
+                if listening {
+                    let count = listeners.len();
+                    accept(&poll, &mut listeners[index], count, &mut connections)?;
+                }
+                continue;
+            }
+            let key = index - listeners.len();
+            let connection = match connections.get_mut(key) {
+                Some(connection) => connection,
+                None => continue, // Closed earlier in this batch of events.
+            };
+            let mut result = Ok(false);
+            if event.readiness().is_readable() {
+                // Read from the connection, handing the data to the echo core.
+                connection.core.readable();
+                result = connection.read_with(&mut inbuf, |stream, buffer| stream.read(buffer));
+            }
+            if result.is_ok() && event.readiness().is_writable() {
+                // Write as many of the pending echoes as the connection will take.
+                connection.core.writable();
+                result = connection.write_with(|stream, pending| stream.write(pending));
+            }
+            if let Err(e) = result {
+                failed.push((key, e));
+            }
+        }
+
+        // Close the connections which have failed, or finished echoing.  Dropping a stream closes
+        // it, which also removes it from the poll set.
+        for (key, e) in failed {
+            connections.fail(key, &e);
+        }
+        for key in connections.finished() {
+            connections.remove(key);
+        }
+
+        if connections.check_drain(&mut drain, signalled) {
+            println!("server: {}", connections.stats());
+            return Ok(());
+        }
+        if drain.is_started() && listening {
+            for listener in &listeners {
+                poll.deregister(listener)?;
+            }
+            listening = false;
+        }
+    }
+}
+
+// Accept a connection, and register it with the interest of its new echo core, unless there are
+// already too many.  Its token follows those of the `listeners`.
+fn accept<L>(
+    poll: &Poll,
+    listener: &mut L,
+    listeners: usize,
+    connections: &mut Connections<L::Stream, L::Addr>,
+) -> io::Result<()>
+where
+    L: StreamListener,
+    L::Stream: Evented,
+{
+    let (stream, peer) = match listener.accept() {
+        Ok(accepted) => accepted,
+        // The client may have given up already.
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if connections.is_full() {
+        // Dropping the stream closes it.
+        connections.refuse(&peer);
+        return Ok(());
+    }
+    let key = connections.insert(stream, peer);
+    let connection = connections.get_mut(key).unwrap();
+    let interest = connection.core.poll_interest().unwrap();
+    poll.register(&connection.stream, Token(listeners + key), interest.into(), PollOpt::level())
+}
@@ -0,0 +1,379 @@
+//! TCP echo servers, one for each I/O model of the UDP series.
+//!
+//! Where a UDP echo server echoes datagrams on a few sockets, a TCP echo
+//! server accepts connections on a listening socket per address (see
+//! `--bind`) and echoes the bytes of each connection back to it.  This
+//! brings out what datagrams let the UDP servers ignore: accept loops,
+//! partial reads and writes, half-closed connections, and a buffer per
+//! connection.  Each connection is driven by a sans-I/O `StreamCore` (see
+//! the `stream` module), whose write buffer is bounded (see
+//! `--write-buffer`), so a client which doesn't read its echoes only ever
+//! holds up its own connection.
+//!
+//! The open connections are kept in a `Connections` slab, whose keys
+//! identify the connections' events, after the listeners' indices.  At
+//! most `--max-connections` are kept open at once: any others are closed
+//! as soon as they are accepted, so that a level-triggered listener
+//! doesn't stay readable forever.
+//!
+//! The servers handle signals as the UDP servers do (see the `signal`
+//! module).  On shutdown, a server stops accepting and reading, and closes
+//! each connection once its pending echoes have been written, or when the
+//! drain deadline passes.
+//!
+//! Given `--unix`, a server listens on a Unix domain stream socket instead
+//! (see the `unix` module).  The mio and Tokio servers are generic over
+//! their listener (see `StreamListener`), and the nix-based ones take the
+//! peer's address as nix reports it, whatever its family.
+//!
+//! Like the UDP backends, each server implements `EchoBackend`, though
+//! they are run by the tcp-echo-* programs rather than by name.
+
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+use ::mio::net::{TcpListener as MioTcpListener, TcpStream as MioTcpStream};
+use slab::{self, Slab};
+use tokio_core::net::{TcpListener as TokioTcpListener, TcpStream as TokioTcpStream};
+use config::Config;
+use shutdown::Drain;
+use signal::{self, Signal};
+use stream::{StreamCore, StreamStats};
+use unix::{UnixAddress, UnixListener, UnixStream};
+
+#[cfg(feature = "epoll")]
+pub mod epoll;
+pub mod mio;
+#[cfg(feature = "select")]
+pub mod select;
+pub mod tokio;
+
+/// A listening stream socket, in non-blocking mode: a TCP listener, or a
+/// Unix domain one.
+pub trait StreamListener {
+    type Stream: Read + Write;
+    type Addr: fmt::Display;
+
+    /// Accept a connection, returning the stream in non-blocking mode and
+    /// its peer's address.
+    fn accept(&mut self) -> io::Result<(Self::Stream, Self::Addr)>;
+
+    fn local_addr(&self) -> io::Result<Self::Addr>;
+}
+
+impl StreamListener for MioTcpListener {
+    type Stream = MioTcpStream;
+    type Addr = SocketAddr;
+
+    fn accept(&mut self) -> io::Result<(MioTcpStream, SocketAddr)> {
+        MioTcpListener::accept(self)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        MioTcpListener::local_addr(self)
+    }
+}
+
+impl StreamListener for UnixListener {
+    type Stream = UnixStream;
+    type Addr = UnixAddress;
+
+    fn accept(&mut self) -> io::Result<(UnixStream, UnixAddress)> {
+        UnixListener::accept(self)
+    }
+
+    fn local_addr(&self) -> io::Result<UnixAddress> {
+        UnixListener::local_addr(self)
+    }
+}
+
+impl StreamListener for TokioTcpListener {
+    type Stream = TokioTcpStream;
+    type Addr = SocketAddr;
+
+    fn accept(&mut self) -> io::Result<(TokioTcpStream, SocketAddr)> {
+        TokioTcpListener::accept(self)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TokioTcpListener::local_addr(self)
+    }
+}
+
+/// An open connection: its stream, its peer's address, and its echo core.
+pub struct Connection<S, A> {
+    pub stream: S,
+    pub peer: A,
+    pub core: StreamCore,
+}
+
+impl<S, A: fmt::Display> Connection<S, A> {
+    /// Read from the stream once with `read`, into at most as many bytes of
+    /// `inbuf` as the write buffer has room for, and hand the data to the
+    /// echo core.  Returns true if the read made progress (reading the end
+    /// of the stream included), or false if it would block.
+    pub fn read_with<F>(&mut self, inbuf: &mut [u8], read: F) -> io::Result<bool>
+    where
+        F: FnOnce(&mut S, &mut [u8]) -> io::Result<usize>,
+    {
+        if !self.core.wants_read() {
+            return Ok(false);
+        }
+        let limit = cmp::min(self.core.room(), inbuf.len());
+        match read(&mut self.stream, &mut inbuf[..limit]) {
+            Ok(0) => {
+                // The peer may still be reading, so the pending echoes are written before closing.
+                println!("{} closed its side of the connection.", self.peer);
+                self.core.received(&[]);
+                Ok(true)
+            }
+            Ok(nbytes) => {
+                println!("recv {} bytes from {}.", nbytes, self.peer);
+                self.core.received(&inbuf[..nbytes]);
+                if self.core.is_paused() {
+                    println!("write buffer full; pausing reads from {}.", self.peer);
+                }
+                Ok(true)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.core.read_would_block();
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write the pending echoes to the stream once with `write`, if the
+    /// echo core offers any.  The stream may accept only some of them.
+    /// Returns true if the write made progress, or false if it would block
+    /// or there was nothing to write.
+    pub fn write_with<F>(&mut self, write: F) -> io::Result<bool>
+    where
+        F: FnOnce(&mut S, &[u8]) -> io::Result<usize>,
+    {
+        let result = match self.core.poll_send() {
+            Some(pending) => write(&mut self.stream, pending),
+            None => return Ok(false),
+        };
+        match result {
+            Ok(nbytes) => {
+                println!("sent {} bytes to {}.", nbytes, self.peer);
+                self.core.sent(nbytes);
+                Ok(true)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.core.write_would_block();
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read and write with `read` and `write` until both would block, or
+    /// until `budget` operations have been performed, as an edge-triggered
+    /// server must.  Returns true if the budget ran out while the
+    /// connection could still make progress.
+    pub fn service_with<R, W>(
+        &mut self,
+        inbuf: &mut [u8],
+        budget: usize,
+        mut read: R,
+        mut write: W,
+    ) -> io::Result<bool>
+    where
+        R: FnMut(&mut S, &mut [u8]) -> io::Result<usize>,
+        W: FnMut(&mut S, &[u8]) -> io::Result<usize>,
+    {
+        let mut operations = 0;
+        loop {
+            // Either read or write can set this to false to try the other again.
+            let mut blocking = true;
+            if self.core.wants_read() && operations < budget && self.read_with(inbuf, &mut read)? {
+                operations += 1;
+                blocking = false;
+            }
+            if operations < budget && self.write_with(&mut write)? {
+                operations += 1;
+                blocking = false;
+            }
+            if operations >= budget {
+                return Ok(self.core.wants_read() || self.core.wants_write());
+            }
+            if blocking {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+/// Counters describing the work done by a TCP echo server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TcpStats {
+    pub accepted: u64, // Connections accepted and kept open.
+    pub refused: u64, // Connections closed at once because too many were open.
+    pub closed: u64, // Connections closed after echoing.
+    pub open: usize, // Connections currently open.
+    pub streams: StreamStats, // The echo counters of every connection, open or closed.
+}
+
+impl fmt::Display for TcpStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "accepted={} refused={} closed={} open={} {}",
+            self.accepted, self.refused, self.closed, self.open, self.streams
+        )
+    }
+}
+
+/// The open connections of a TCP echo server, in a slab whose keys
+/// identify them.
+pub struct Connections<S, A> {
+    slab: Slab<Connection<S, A>>,
+    max_connections: usize,
+    write_buffer: usize,
+    stats: TcpStats, // Including the streams of closed connections only.
+}
+
+impl<S, A: fmt::Display> Connections<S, A> {
+    /// No connections yet, with the configured limit and write buffer size.
+    pub fn new(config: &Config) -> Connections<S, A> {
+        Connections {
+            slab: Slab::new(),
+            max_connections: config.max_connections,
+            write_buffer: config.write_buffer,
+            stats: TcpStats::default(),
+        }
+    }
+
+    /// Return true if no more connections may be kept open.
+    pub fn is_full(&self) -> bool {
+        self.slab.len() >= self.max_connections
+    }
+
+    /// Add a newly accepted connection, with an echo core of its own.
+    /// Returns its key.
+    pub fn insert(&mut self, stream: S, peer: A) -> usize {
+        println!("accepted connection from {}.", peer);
+        self.stats.accepted += 1;
+        self.slab.insert(Connection {
+            stream,
+            peer,
+            core: StreamCore::new(self.write_buffer),
+        })
+    }
+
+    /// Count a connection which the server is closing at once, because it
+    /// is full.
+    pub fn refuse(&mut self, peer: &A) {
+        println!("too many connections; refusing {}.", peer);
+        self.stats.refused += 1;
+    }
+
+    /// Remove a connection which has finished or failed, returning it so
+    /// that its stream can be closed.
+    pub fn remove(&mut self, key: usize) -> Connection<S, A> {
+        let connection = self.slab.remove(key);
+        println!("closing connection from {} ({}).", connection.peer, connection.core.stats());
+        self.stats.closed += 1;
+        self.stats.streams.add(&connection.core.stats());
+        connection
+    }
+
+    /// Remove a connection on which an I/O error occurred, such as a reset
+    /// by its peer, returning it so that its stream can be closed.  Errors
+    /// on one connection don't concern the others.
+    pub fn fail(&mut self, key: usize, error: &io::Error) -> Connection<S, A> {
+        println!("connection from {} failed: {}", self.slab[key].peer, error);
+        self.remove(key)
+    }
+
+    pub fn get_mut(&mut self, key: usize) -> Option<&mut Connection<S, A>> {
+        self.slab.get_mut(key)
+    }
+
+    /// The open connections, with their keys.
+    pub fn iter(&self) -> slab::Iter<'_, Connection<S, A>> {
+        self.slab.iter()
+    }
+
+    /// The open connections, with their keys.
+    pub fn iter_mut(&mut self) -> slab::IterMut<'_, Connection<S, A>> {
+        self.slab.iter_mut()
+    }
+
+    /// The keys of the connections which have finished, and should be
+    /// closed.
+    pub fn finished(&self) -> Vec<usize> {
+        self.slab
+            .iter()
+            .filter(|&(_, connection)| connection.core.is_finished())
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.slab.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slab.is_empty()
+    }
+
+    /// The number of bytes waiting to be written, over all connections.
+    pub fn pending(&self) -> usize {
+        self.slab.iter().map(|(_, connection)| connection.core.pending()).sum()
+    }
+
+    /// Check on the shutdown of the server after a turn of its event loop,
+    /// as `Drain::check()` does for the UDP servers.  Once the server has
+    /// been `signalled`, every connection stops reading.  Returns true once
+    /// they have all drained, or the deadline has passed.
+    pub fn check_drain(&mut self, drain: &mut Drain, signalled: bool) -> bool {
+        if signalled && drain.start() {
+            for (_, connection) in self.slab.iter_mut() {
+                connection.core.shutdown();
+            }
+            println!(
+                "shutting down; draining {} pending bytes on {} connections.",
+                self.pending(),
+                self.len()
+            );
+        }
+        drain.finished(self.pending())
+    }
+
+    /// Counters describing the work done so far.
+    pub fn stats(&self) -> TcpStats {
+        let mut stats = self.stats;
+        stats.open = self.len();
+        for (_, connection) in self.slab.iter() {
+            stats.streams.add(&connection.core.stats());
+        }
+        stats
+    }
+}
+
+/// Act on a signal, as `signal::handle()` does for the UDP servers: SIGUSR1
+/// prints the statistics, and SIGHUP reloads the configuration file, of
+/// which only the drain timeout applies to a TCP server.  Returns true for
+/// a shutdown signal.
+pub fn handle_signal<S, A: fmt::Display>(
+    signal: Signal,
+    config: &mut Config,
+    drain: &mut Drain,
+    connections: &Connections<S, A>,
+) -> bool {
+    match signal {
+        Signal::Shutdown => return true,
+        Signal::Stats => println!("server: {}", connections.stats()),
+        Signal::Reload => {
+            if signal::reload(config) {
+                drain.set_timeout(Duration::from_millis(config.drain_timeout));
+            }
+        }
+    }
+    false
+}
@@ -0,0 +1,175 @@
+//! A TCP echo server using POSIX select() to multiplex accepts, reads and
+//! writes on many connections.
+//!
+//! select() can only watch descriptors below FD_SETSIZE (1024 on Linux),
+//! however many connections `--max-connections` allows, so connections
+//! which are given higher descriptors are refused.  This is the limit
+//! which poll() and epoll lift.
+//!
+//! Signals are blocked except while waiting in pselect(), as in the UDP
+//! select backend.
+
+use std::cmp;
+use std::io;
+use std::os::unix::io::RawFd;
+use libc;
+use nix::sys::select::*;
+use nix::sys::socket::*;
+use nix::unistd::close;
+use backend::EchoBackend;
+use backend::select::pselect;
+use config::Config;
+use net::{accept_stream, bind_sockets, io_error, local_addr};
+use signal::SignalMask;
+use tcp::{self, Connections};
+
+/// The select() TCP echo server.
+pub struct TcpSelect;
+
+impl EchoBackend for TcpSelect {
+    fn name(&self) -> &'static str {
+        "tcp-select"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        // Block the signals until we wait for the sockets.
+        let signals = SignalMask::block()?;
+        let mut config = config.clone();
+        let mut drain = config.drain();
+
+        // Open a listening TCP socket in non-blocking mode on each configured address (by default
+        // IPv4 localhost, port 2000), or the Unix domain socket, whose file is removed once
+        // `_socket_file` is dropped.
+        let (listeners, _socket_file) = bind_sockets(&config, SockType::Stream)?;
+        for &listener_fd in &listeners {
+            println!("listening on {}", local_addr(listener_fd).map_err(io_error)?);
+        }
+
+        let mut connections: Connections<RawFd, SockAddr> = Connections::new(&config);
+        let mut inbuf = config.buffer();
+        let mut read_fd_set = FdSet::new();
+        let mut write_fd_set = FdSet::new();
+        loop {
+            // Set up read/write file descriptor sets: the listeners until shutting down, and each
+            // connection according to its echo core's interest.  (A core only asks to write when
+            // there are echoes pending, and stops asking to read while its write buffer is full.)
+            read_fd_set.clear();
+            write_fd_set.clear();
+            let mut nfds = 0;
+            if !drain.is_started() {
+                for &listener_fd in &listeners {
+                    read_fd_set.insert(listener_fd);
+                    nfds = cmp::max(nfds, listener_fd + 1);
+                }
+            }
+            for (_, connection) in connections.iter() {
+                let interest = connection.core.interest();
+                if interest.read {
+                    read_fd_set.insert(connection.stream);
+                }
+                if interest.write {
+                    write_fd_set.insert(connection.stream);
+                }
+                nfds = cmp::max(nfds, connection.stream + 1);
+            }
+
+            // Wait for the sockets to be ready, or for a signal.  While shutting down, only wait
+            // until the drain deadline.
+            match pselect(
+                nfds,
+                &mut read_fd_set,
+                &mut write_fd_set,
+                drain.wait(),
+                signals.unblocked(),
+            ) {
+                Ok(_) => (),
+                Err(nix::Error::Sys(nix::errno::EINTR)) => {
+                    // A signal handler interrupted pselect(), and the sets are undefined.
+                    read_fd_set.clear();
+                    write_fd_set.clear();
+                }
+                Err(e) => return Err(io_error(e)),
+            }
+            let mut signalled = false;
+            for signal in signals.signals() {
+                signalled |= tcp::handle_signal(signal, &mut config, &mut drain, &connections);
+            }
+
+            // Accept a connection on each readable listener.
+            for &listener_fd in &listeners {
+                if read_fd_set.contains(listener_fd) {
+                    accept(listener_fd, &mut connections)?;
+                }
+            }
+
+            // Process events.  Unlike epoll, select() doesn't tell us which descriptors are
+            // ready, so every connection must be checked against the sets.
+            let mut failed = vec![];
+            for (key, connection) in connections.iter_mut() {
+                let socket_fd = connection.stream;
+                let mut result = Ok(false);
+                if read_fd_set.contains(socket_fd) {
+                    // Read from the connection, handing the data to the echo core.
+                    connection.core.readable();
+                    result = connection.read_with(&mut inbuf, |&mut socket_fd, buffer| {
+                        recv(socket_fd, buffer, MsgFlags::empty()).map_err(io_error)
+                    });
+                }
+                if result.is_ok() && write_fd_set.contains(socket_fd) {
+                    // Write as many of the pending echoes as the connection will take.
+                    connection.core.writable();
+                    result = connection.write_with(|&mut socket_fd, pending| {
+                        send(socket_fd, pending, MsgFlags::empty()).map_err(io_error)
+                    });
+                }
+                if let Err(e) = result {
+                    failed.push((key, e));
+                }
+            }
+
+            // Close the connections which have failed, or finished echoing.
+            for (key, e) in failed {
+                let _ = close(connections.fail(key, &e).stream);
+            }
+            for key in connections.finished() {
+                let _ = close(connections.remove(key).stream);
+            }
+
+            if connections.check_drain(&mut drain, signalled) {
+                break;
+            }
+        }
+
+        println!("server: {}", connections.stats());
+        for (_, connection) in connections.iter() {
+            let _ = close(connection.stream);
+        }
+        for listener_fd in listeners {
+            let _ = close(listener_fd);
+        }
+        Ok(())
+    }
+}
+
+// Accept a connection, unless there are already too many or its descriptor is too high for
+// select().
+fn accept(listener_fd: RawFd, connections: &mut Connections<RawFd, SockAddr>) -> io::Result<()> {
+    match accept_stream(listener_fd) {
+        Ok((socket_fd, peer)) => {
+            if connections.is_full() || socket_fd >= libc::FD_SETSIZE as RawFd {
+                connections.refuse(&peer);
+                let _ = close(socket_fd);
+            } else {
+                connections.insert(socket_fd, peer);
+            }
+            Ok(())
+        }
+        // The client may have given up already.
+        Err(nix::Error::Sys(errno))
+            if errno == nix::errno::EWOULDBLOCK || errno == nix::errno::ECONNABORTED =>
+        {
+            Ok(())
+        }
+        Err(e) => Err(io_error(e)),
+    }
+}
@@ -0,0 +1,229 @@
+//! A TCP echo server using Tokio to multiplex accepts, reads and writes on
+//! many connections.
+//!
+//! tokio-core's own echo example spawns a future per connection which
+//! copies the connection's reads to its writes, and leaves the buffering
+//! to `io::copy()`.  Here instead, as in the UDP Tokio backend, a single
+//! future serves every listener and connection, with each connection's
+//! bounded write buffer in its `StreamCore`.  Tokio tracks the readiness
+//! of each listener and stream, so being polled means trying to accept on
+//! every listener and to read and write every connection: the ones which
+//! aren't ready return `WouldBlock` at once, which arranges for the task
+//! to be notified when they become ready.
+//!
+//! Given `--unix`, the server listens on a Unix domain socket instead, and
+//! drives it and its connections through `PollEvented` (see the `unix`
+//! module).
+//!
+//! For reference, the tokio-core echo.rs source may be found here:
+//! https://github.com/tokio-rs/tokio-core/blob/master/examples/echo.rs
+
+use std::io::{self, Read, Write};
+use futures::{Async, Future, Poll, Stream};
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle, PollEvented, Timeout};
+use backend::EchoBackend;
+use config::Config;
+use net::tcp_listener;
+use shutdown::Drain;
+use signal::SignalStream;
+use tcp::{self, Connections, StreamListener};
+use unix::{UnixAddress, UnixListener, UnixStream};
+
+/// The Tokio TCP echo server.
+pub struct TcpTokio;
+
+impl EchoBackend for TcpTokio {
+    fn name(&self) -> &'static str {
+        "tcp-tokio"
+    }
+
+    fn run(&self, config: &Config) -> io::Result<()> {
+        // Create the tokio event loop
+        let mut core = Core::new()?;
+        let handle = core.handle();
+
+        if let Some(ref addr) = config.unix {
+            let listener = UnixStreamListener {
+                listener: PollEvented::new(UnixListener::bind(addr)?, &handle)?,
+                handle: handle.clone(),
+            };
+            return run_server(&mut core, TcpServer::new(vec![listener], config, &handle)?);
+        }
+
+        // Open a listening TCP socket in non-blocking mode on each configured address (by default
+        // IPv4 localhost port 2000).
+        let mut listeners = vec![];
+        for addr in config.addrs() {
+            let listener = tcp_listener(&addr, config.v6_only)?;
+            listeners.push(TcpListener::from_listener(listener, &addr, &handle)?);
+        }
+        run_server(&mut core, TcpServer::new(listeners, config, &handle)?)
+    }
+}
+
+/// Run `server` on the tokio event loop.  Creating the server installed the
+/// signal handlers, so the addresses are only announced now that signals
+/// will be handled.
+fn run_server<L: StreamListener>(core: &mut Core, server: TcpServer<L>) -> io::Result<()> {
+    for listener in &server.listeners {
+        println!("listening on {}", listener.local_addr()?);
+    }
+    core.run(server)
+}
+
+/// A Unix domain listener driven by Tokio, whose connections are too.
+struct UnixStreamListener {
+    listener: PollEvented<UnixListener>,
+    handle: Handle,
+}
+
+// As for the Unix domain datagram sockets, an accept which would block arranges for the task to be
+// notified once the listener is ready again.
+impl StreamListener for UnixStreamListener {
+    type Stream = PollEvented<UnixStream>;
+    type Addr = UnixAddress;
+
+    fn accept(&mut self) -> io::Result<(PollEvented<UnixStream>, UnixAddress)> {
+        if let Async::NotReady = self.listener.poll_read() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        match self.listener.get_ref().accept() {
+            Ok((stream, peer)) => Ok((PollEvented::new(stream, &self.handle)?, peer)),
+            Err(e) => {
+                if e.kind() == io::ErrorKind::WouldBlock {
+                    self.listener.need_read();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<UnixAddress> {
+        self.listener.get_ref().local_addr()
+    }
+}
+
+/// A future which accepts connections on its listeners and echoes what it
+/// reads from each of them.  It completes once the server has been shut
+/// down and every connection has drained (or the drain deadline has
+/// passed), or if an I/O error occurs on a listener.
+pub struct TcpServer<L: StreamListener> {
+    listeners: Vec<L>,
+    connections: Connections<L::Stream, L::Addr>,
+    inbuf: Vec<u8>,
+    config: Config,
+    signals: SignalStream,
+    drain: Drain,
+    deadline: Option<Timeout>,
+    handle: Handle,
+}
+
+impl<L: StreamListener> TcpServer<L> {
+    pub fn new(listeners: Vec<L>, config: &Config, handle: &Handle) -> io::Result<TcpServer<L>> {
+        Ok(TcpServer {
+            listeners,
+            connections: Connections::new(config),
+            inbuf: config.buffer(),
+            config: config.clone(),
+            signals: SignalStream::new(handle)?,
+            drain: config.drain(),
+            deadline: None,
+            handle: handle.clone(),
+        })
+    }
+
+    // Accept connections until every listener would block, closing any beyond the limit.
+    fn accept(&mut self) -> io::Result<()> {
+        for listener in &mut self.listeners {
+            loop {
+                match listener.accept() {
+                    Ok((stream, peer)) => {
+                        if self.connections.is_full() {
+                            // Dropping the stream closes it.
+                            self.connections.refuse(&peer);
+                        } else {
+                            self.connections.insert(stream, peer);
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    // The client may have given up already.
+                    Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted => (),
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Return true once the server has shut down and drained, or the drain deadline has passed.  A
+    // timeout arranges for the task to be notified at the deadline.
+    fn poll_drained(&mut self, signalled: bool) -> io::Result<bool> {
+        if self.connections.check_drain(&mut self.drain, signalled) {
+            return Ok(true);
+        }
+        if let Some(wait) = self.drain.wait() {
+            if self.deadline.is_none() {
+                self.deadline = Some(Timeout::new(wait, &self.handle)?);
+            }
+            if let Some(ref mut deadline) = self.deadline {
+                if deadline.poll()?.is_ready() {
+                    return Ok(self.connections.check_drain(&mut self.drain, false));
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<L: StreamListener> Future for TcpServer<L> {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        // Handle any signals.  A shutdown signal stops accepting and reading.
+        let mut signalled = false;
+        while let Async::Ready(Some(signal)) = self.signals.poll()? {
+            signalled |=
+                tcp::handle_signal(signal, &mut self.config, &mut self.drain, &self.connections);
+        }
+
+        // Once shutting down, the listeners are no longer polled, and so never become ready
+        // again.
+        if !self.drain.is_started() && !signalled {
+            self.accept()?;
+        }
+
+        // Read and write each connection until both would block.  Tokio tracks the readiness
+        // for us, so every connection is reported as readable and writable, and a WouldBlock
+        // arranges for the task to be notified when it becomes ready again.
+        let mut failed = vec![];
+        for (key, connection) in self.connections.iter_mut() {
+            connection.core.readable();
+            connection.core.writable();
+            let result = connection.service_with(
+                &mut self.inbuf,
+                usize::MAX,
+                |stream, buffer| stream.read(buffer),
+                |stream, pending| stream.write(pending),
+            );
+            if let Err(e) = result {
+                failed.push((key, e));
+            }
+        }
+
+        // Close the connections which have failed, or finished echoing, by dropping them.
+        for (key, e) in failed {
+            self.connections.fail(key, &e);
+        }
+        for key in self.connections.finished() {
+            self.connections.remove(key);
+        }
+
+        if self.poll_drained(signalled)? {
+            println!("server: {}", self.connections.stats());
+            return Ok(Async::Ready(()));
+        }
+        Ok(Async::NotReady)
+    }
+}
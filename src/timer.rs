@@ -0,0 +1,422 @@
+//! Timers as mio event sources, serviced by a shared timer thread.
+//!
+//! mio 0.6 has no timers of its own, but any thread can make a
+//! `Registration` ready through its `SetReadiness`, as the timer of
+//! mio-mixed.rs once did with a sleeping thread per timer, which ran
+//! forever.  Here a single `TimerThread` services any number of
+//! `MioTimer`s instead: it keeps their deadlines in a heap, sleeps until
+//! the earliest one (or until a timer is armed, rearmed or cancelled), and
+//! makes each timer readable when it expires.
+//!
+//! A timer is either one-shot (`set_timeout()`) or periodic
+//! (`set_interval()`), and may be rescheduled or cancelled at any time.
+//! Each arming is a new generation of the timer, so a deadline left in the
+//! heap by an earlier one is skipped.  Like a timerfd, a timer counts its
+//! expirations, which `expirations()` takes, clearing the readiness.
+//!
+//! The thread exits, and is joined, once the `TimerThread` and all of its
+//! timers have been dropped.
+//!
+//! On Linux, a `TimerFd` does without the thread: the kernel makes the
+//! timerfd readable when it expires, so it can be watched directly with
+//! epoll, poll() or select() (as those backends do for
+//! `--syscall-interval`), or with mio through `EventedFd`, and wakes
+//! epoll_wait() without the pipe write behind each `SetReadiness` (see
+//! mio-timerfd.rs).  Reading it yields the number of expirations,
+//! including any missed while the reader was busy.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use libc;
+use mio::{self, Evented, PollOpt, Ready, Registration, SetReadiness, Token};
+use mio::unix::EventedFd;
+use slab::Slab;
+
+/// The name of the timer thread.
+pub const THREAD_NAME: &str = "mio-timer";
+
+/// A deadline in the heap: when, which timer, and which arming of it.
+type Deadline = Reverse<(Instant, usize, u64)>;
+
+/// The state of one timer.
+struct Entry {
+    set_readiness: SetReadiness,
+    generation: u64, // The current arming, or 0 if unarmed.
+    period: Option<Duration>, // The interval of a periodic timer.
+    expirations: u64, // Expirations not yet taken by `expirations()`.
+}
+
+/// The timers serviced by a thread, and their deadlines.
+struct State {
+    timers: Slab<Entry>,
+    deadlines: BinaryHeap<Deadline>,
+    generations: u64, // The last generation handed out.
+    shutdown: bool,
+}
+
+/// The state shared with the thread, which is woken whenever it changes.
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+/// The thread itself, which is stopped and joined when the last handle to
+/// it (the `TimerThread`, or any of its timers) is dropped.
+struct Thread {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.changed.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A thread which services many timers.  Cloning a `TimerThread` yields
+/// another handle to the same thread.
+#[derive(Clone)]
+pub struct TimerThread {
+    thread: Arc<Thread>,
+}
+
+impl TimerThread {
+    /// Start a timer thread, with no timers yet.
+    pub fn new() -> io::Result<TimerThread> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                timers: Slab::new(),
+                deadlines: BinaryHeap::new(),
+                generations: 0,
+                shutdown: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(THREAD_NAME.to_string())
+                .spawn(move || run(&shared))?
+        };
+        Ok(TimerThread {
+            thread: Arc::new(Thread {
+                shared,
+                thread: Some(thread),
+            }),
+        })
+    }
+
+    /// A new timer serviced by this thread, which is not yet armed.
+    pub fn timer(&self) -> MioTimer {
+        let (registration, set_readiness) = Registration::new2();
+        let entry = Entry {
+            set_readiness,
+            generation: 0,
+            period: None,
+            expirations: 0,
+        };
+        let key = self.thread.shared.state.lock().unwrap().timers.insert(entry);
+        MioTimer {
+            registration,
+            key,
+            thread: self.thread.clone(),
+        }
+    }
+
+    /// The number of timers serviced by this thread.
+    pub fn len(&self) -> usize {
+        self.thread.shared.state.lock().unwrap().timers.len()
+    }
+
+    /// Return true if the thread services no timers.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A one-shot or periodic timer, which is readable once it has expired.
+/// Dropping it cancels it.
+pub struct MioTimer {
+    registration: Registration,
+    key: usize, // The timer's entry in the thread's state.
+    thread: Arc<Thread>,
+}
+
+impl MioTimer {
+    /// Expire once, `delay` from now, replacing any earlier arming.
+    pub fn set_timeout(&self, delay: Duration) {
+        self.arm(delay, None);
+    }
+
+    /// Expire every `period`, starting `period` from now, replacing any
+    /// earlier arming.  A timer which falls behind counts the expirations
+    /// it missed, rather than firing in a burst.
+    pub fn set_interval(&self, period: Duration) {
+        assert!(period > Duration::from_secs(0), "a periodic timer needs a period");
+        self.arm(period, Some(period));
+    }
+
+    /// Disarm the timer, and forget any expirations not yet taken.
+    pub fn cancel(&self) {
+        let mut state = self.thread.shared.state.lock().unwrap();
+        let entry = &mut state.timers[self.key];
+        entry.generation = 0;
+        entry.period = None;
+        entry.expirations = 0;
+        let _ = entry.set_readiness.set_readiness(Ready::empty());
+        // The thread skips the stale deadline when it comes up, so it needn't be woken.
+    }
+
+    /// Return true if the timer is armed: periodic, or one-shot and not yet
+    /// expired.
+    pub fn is_armed(&self) -> bool {
+        self.thread.shared.state.lock().unwrap().timers[self.key].generation != 0
+    }
+
+    /// The number of times the timer has expired since the last call,
+    /// clearing its readiness.
+    pub fn expirations(&self) -> u64 {
+        let mut state = self.thread.shared.state.lock().unwrap();
+        let entry = &mut state.timers[self.key];
+        let _ = entry.set_readiness.set_readiness(Ready::empty());
+        let expirations = entry.expirations;
+        entry.expirations = 0;
+        expirations
+    }
+
+    // Arm the timer to expire `delay` from now, and then every `period`, if given.
+    fn arm(&self, delay: Duration, period: Option<Duration>) {
+        let shared = &self.thread.shared;
+        let mut state = shared.state.lock().unwrap();
+        state.generations += 1;
+        let generation = state.generations;
+        let entry = &mut state.timers[self.key];
+        entry.generation = generation;
+        entry.period = period;
+        let deadline = Instant::now() + delay;
+        let wakes_thread = match state.deadlines.peek() {
+            Some(&Reverse((earliest, _, _))) => deadline < earliest,
+            None => true,
+        };
+        state.deadlines.push(Reverse((deadline, self.key, generation)));
+        if wakes_thread {
+            shared.changed.notify_one();
+        }
+    }
+}
+
+impl Drop for MioTimer {
+    fn drop(&mut self) {
+        self.thread.shared.state.lock().unwrap().timers.remove(self.key);
+        // Its deadlines are skipped, as its key may be reused but its generations are not.
+    }
+}
+
+/// Proxy Evented functions to the Registration.
+impl Evented for MioTimer {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        self.registration.register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        self.registration.reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        <Registration as Evented>::deregister(&self.registration, poll)
+    }
+}
+
+/// A one-shot or periodic timer backed by a non-blocking timerfd on the
+/// monotonic clock, which is readable once it has expired.
+pub struct TimerFd {
+    fd: RawFd,
+}
+
+impl TimerFd {
+    /// Open a timerfd, which is not yet armed.
+    pub fn new() -> io::Result<TimerFd> {
+        let flags = libc::TFD_NONBLOCK | libc::TFD_CLOEXEC;
+        let fd = unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(TimerFd { fd })
+    }
+
+    /// Expire once, `delay` from now, replacing any earlier arming.
+    pub fn set_timeout(&self, delay: Duration) -> io::Result<()> {
+        // A zero expiration would disarm the timer instead.
+        self.settime(delay.max(Duration::new(0, 1)), Duration::from_secs(0))
+    }
+
+    /// Expire every `period`, starting `period` from now, replacing any
+    /// earlier arming.
+    pub fn set_interval(&self, period: Duration) -> io::Result<()> {
+        assert!(period > Duration::from_secs(0), "a periodic timer needs a period");
+        self.settime(period, period)
+    }
+
+    /// Disarm the timer, and forget any expirations not yet read.
+    pub fn cancel(&self) -> io::Result<()> {
+        self.settime(Duration::from_secs(0), Duration::from_secs(0))
+    }
+
+    /// Return true if the timer is armed: periodic, or one-shot and not yet
+    /// expired.
+    pub fn is_armed(&self) -> io::Result<bool> {
+        let mut spec: libc::itimerspec = unsafe { mem::zeroed() };
+        if unsafe { libc::timerfd_gettime(self.fd, &mut spec) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(spec.it_value.tv_sec != 0 || spec.it_value.tv_nsec != 0)
+    }
+
+    /// The number of times the timer has expired since the last call (or
+    /// the last arming), clearing its readiness.  A periodic timer which
+    /// was not read in time counts every period which passed.
+    pub fn expirations(&self) -> io::Result<u64> {
+        let mut expirations: u64 = 0;
+        let buffer = &mut expirations as *mut u64 as *mut libc::c_void;
+        let nbytes = unsafe { libc::read(self.fd, buffer, mem::size_of::<u64>()) };
+        if nbytes < 0 {
+            let e = io::Error::last_os_error();
+            return match e.kind() {
+                io::ErrorKind::WouldBlock => Ok(0),
+                _ => Err(e),
+            };
+        }
+        Ok(expirations)
+    }
+
+    fn settime(&self, value: Duration, interval: Duration) -> io::Result<()> {
+        let timespec = |duration: Duration| libc::timespec {
+            tv_sec: duration.as_secs() as libc::time_t,
+            tv_nsec: duration.subsec_nanos() as libc::c_long,
+        };
+        let spec = libc::itimerspec {
+            it_interval: timespec(interval),
+            it_value: timespec(value),
+        };
+        if unsafe { libc::timerfd_settime(self.fd, 0, &spec, ptr::null_mut()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for TimerFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for TimerFd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+impl Evented for TimerFd {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.fd).register(poll, token, interest, opts)
+    }
+
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: Token,
+        interest: Ready,
+        opts: PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.fd).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.fd).deregister(poll)
+    }
+}
+
+// The timer thread: expire the timers whose deadlines have passed, and sleep until the next
+// deadline or until a timer is armed sooner, until shut down.
+fn run(shared: &Shared) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        if state.shutdown {
+            return;
+        }
+        let now = Instant::now();
+        while let Some(&Reverse((deadline, key, generation))) = state.deadlines.peek() {
+            if deadline > now {
+                break;
+            }
+            state.deadlines.pop();
+            let next = match state.timers.get_mut(key) {
+                Some(entry) if entry.generation == generation => expire(entry, deadline, now),
+                _ => None, // Cancelled, rearmed or dropped since.
+            };
+            if let Some(next) = next {
+                state.deadlines.push(Reverse((next, key, generation)));
+            }
+        }
+        state = match state.deadlines.peek() {
+            Some(&Reverse((deadline, _, _))) => {
+                let wait = deadline.saturating_duration_since(Instant::now());
+                shared.changed.wait_timeout(state, wait).unwrap().0
+            }
+            None => shared.changed.wait(state).unwrap(),
+        };
+    }
+}
+
+// Expire a timer whose `deadline` has passed, as of `now`.  Returns the next deadline of a
+// periodic timer, having counted any periods which have also passed.
+fn expire(entry: &mut Entry, deadline: Instant, now: Instant) -> Option<Instant> {
+    entry.expirations += 1;
+    let _ = entry.set_readiness.set_readiness(Ready::readable());
+    match entry.period {
+        Some(period) => {
+            let mut next = deadline + period;
+            while next <= now {
+                entry.expirations += 1;
+                next += period;
+            }
+            Some(next)
+        }
+        None => {
+            entry.generation = 0;
+            None
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Unix domain sockets for the echo servers (see `--unix`).
+//!
+//! Given `--unix=PATH`, an echo server serves a single `AF_UNIX` socket
+//! instead of its UDP sockets or TCP listeners: a `SOCK_DGRAM` socket for
+//! the UDP echo servers, and a `SOCK_STREAM` listener for the TCP ones.  A
+//! path beginning with `@` names a socket in Linux's abstract namespace,
+//! which has no file in the filesystem, as `ss` and `socat` show them.
+//!
+//! A socket bound to a path leaves its file behind when it is closed, and
+//! binding the path again fails until the file is removed.  The servers
+//! remove the file when they exit, and on starting they remove a file left
+//! behind by a server which didn't exit cleanly -- but only once they have
+//! found that no socket is listening on it any more.
+//!
+//! A datagram is only echoed if its sender bound its socket to an address
+//! too: an unnamed socket can send, but can't be sent to.
+//!
+//! The select() and epoll servers create their sockets with nix (see
+//! `bind_unix()` in the `net` module).  For mio, which only supports UDP
+//! and TCP, this module provides `UnixDatagram`, `UnixListener` and
+//! `UnixStream` types in the style of the mio-uds crate: the standard
+//! library's sockets in non-blocking mode, registered with mio by their
+//! file descriptors.  Tokio can then drive them through `PollEvented`.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net;
+use std::path::PathBuf;
+use std::str::FromStr;
+use futures::Async;
+use mio::unix::EventedFd;
+use mio::{Evented, Poll, PollOpt, Ready, Token};
+use tokio_core::reactor::PollEvented;
+#[cfg(feature = "nix")]
+use nix;
+#[cfg(feature = "nix")]
+use nix::sys::socket::UnixAddr;
+use backend::DatagramSocket;
+
+/// The address of a Unix domain socket: a path in the filesystem, a name
+/// in Linux's abstract namespace, or neither, for an unnamed socket.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum UnixAddress {
+    Path(PathBuf),
+    Abstract(Vec<u8>),
+    Unnamed,
+}
+
+impl UnixAddress {
+    /// The address of a standard library socket.
+    pub fn from_std(addr: &net::SocketAddr) -> UnixAddress {
+        if let Some(path) = addr.as_pathname() {
+            return UnixAddress::Path(path.to_path_buf());
+        }
+        match abstract_name(addr) {
+            Some(name) => UnixAddress::Abstract(name.to_vec()),
+            None => UnixAddress::Unnamed,
+        }
+    }
+
+    /// This address as a standard library socket address, for binding or
+    /// sending to.  An unnamed address can be neither.
+    pub fn to_std(&self) -> io::Result<net::SocketAddr> {
+        match *self {
+            UnixAddress::Path(ref path) => net::SocketAddr::from_pathname(path),
+            UnixAddress::Abstract(ref name) => from_abstract_name(name),
+            UnixAddress::Unnamed => Err(unnamed()),
+        }
+    }
+
+    /// This address as a nix socket address, for binding or sending to.
+    #[cfg(feature = "nix")]
+    pub fn to_nix(&self) -> nix::Result<UnixAddr> {
+        match *self {
+            UnixAddress::Path(ref path) => UnixAddr::new(path.as_path()),
+            UnixAddress::Abstract(ref name) => UnixAddr::new_abstract(name),
+            UnixAddress::Unnamed => Err(nix::Error::Sys(nix::errno::EDESTADDRREQ)),
+        }
+    }
+}
+
+impl FromStr for UnixAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<UnixAddress, String> {
+        if s.is_empty() || s == "@" {
+            return Err(format!("'{}' names no socket", s));
+        }
+        match s.strip_prefix('@') {
+            Some(name) => Ok(UnixAddress::Abstract(name.as_bytes().to_vec())),
+            None => Ok(UnixAddress::Path(PathBuf::from(s))),
+        }
+    }
+}
+
+// As nix displays its UnixAddr, so that every server prints the same.
+impl fmt::Display for UnixAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnixAddress::Path(ref path) => path.display().fmt(f),
+            UnixAddress::Abstract(ref name) => write!(f, "@{}", String::from_utf8_lossy(name)),
+            UnixAddress::Unnamed => f.write_str("<unbound UNIX socket>"),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_name(addr: &net::SocketAddr) -> Option<&[u8]> {
+    use std::os::linux::net::SocketAddrExt;
+    addr.as_abstract_name()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_name(_addr: &net::SocketAddr) -> Option<&[u8]> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn from_abstract_name(name: &[u8]) -> io::Result<net::SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    net::SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn from_abstract_name(_name: &[u8]) -> io::Result<net::SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "abstract socket addresses are only supported on Linux",
+    ))
+}
+
+fn unnamed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "an unnamed socket can't be sent to")
+}
+
+/// Remove the socket file at `addr`'s path, if a server left it behind
+/// without a socket listening on it.  A live socket is left alone, so that
+/// binding its path fails with `EADDRINUSE`.  Abstract addresses have no
+/// file to remove.
+pub fn remove_stale(addr: &UnixAddress) -> io::Result<()> {
+    let path = match *addr {
+        UnixAddress::Path(ref path) => path,
+        _ => return Ok(()),
+    };
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if !metadata.file_type().is_socket() => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Ok(_) => {
+            // Connecting to a socket file which no socket is bound to is refused.  (Connecting to
+            // a live socket of another type fails with EPROTOTYPE instead.)
+            match net::UnixStream::connect(path) {
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    println!("removing stale socket file {}.", path.display());
+                    fs::remove_file(path)
+                }
+                _ => Ok(()),
+            }
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The socket file of a bound Unix domain socket, which is removed when
+/// this is dropped: when the server exits, or the socket is closed.
+/// Abstract addresses have no file, and vanish along with their socket.
+#[derive(Debug)]
+pub struct SocketFile {
+    path: Option<PathBuf>,
+}
+
+impl SocketFile {
+    /// The file of a socket which has just been bound to `addr`.
+    pub fn bound(addr: &UnixAddress) -> SocketFile {
+        SocketFile {
+            path: match *addr {
+                UnixAddress::Path(ref path) => Some(path.clone()),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// A non-blocking Unix domain datagram socket which mio can poll.
+#[derive(Debug)]
+pub struct UnixDatagram {
+    socket: net::UnixDatagram,
+    _file: SocketFile, // After the socket, so that the file is removed once it is closed.
+}
+
+impl UnixDatagram {
+    /// Bind a socket to `addr`, first removing any stale socket file.
+    pub fn bind(addr: &UnixAddress) -> io::Result<UnixDatagram> {
+        remove_stale(addr)?;
+        let socket = net::UnixDatagram::bind_addr(&addr.to_std()?)?;
+        socket.set_nonblocking(true)?;
+        Ok(UnixDatagram {
+            socket,
+            _file: SocketFile::bound(addr),
+        })
+    }
+}
+
+impl DatagramSocket for UnixDatagram {
+    type Addr = UnixAddress;
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, UnixAddress)> {
+        let (nbytes, addr) = self.socket.recv_from(buf)?;
+        Ok((nbytes, UnixAddress::from_std(&addr)))
+    }
+
+    fn send_to(&self, buf: &[u8], addr: &UnixAddress) -> io::Result<usize> {
+        self.socket.send_to_addr(buf, &addr.to_std()?)
+    }
+
+    fn local_addr(&self) -> io::Result<UnixAddress> {
+        Ok(UnixAddress::from_std(&self.socket.local_addr()?))
+    }
+}
+
+impl AsRawFd for UnixDatagram {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+/// A non-blocking Unix domain stream listener which mio can poll.
+#[derive(Debug)]
+pub struct UnixListener {
+    listener: net::UnixListener,
+    _file: SocketFile,
+}
+
+impl UnixListener {
+    /// Bind a listener to `addr`, first removing any stale socket file.
+    pub fn bind(addr: &UnixAddress) -> io::Result<UnixListener> {
+        remove_stale(addr)?;
+        let listener = net::UnixListener::bind_addr(&addr.to_std()?)?;
+        listener.set_nonblocking(true)?;
+        Ok(UnixListener {
+            listener,
+            _file: SocketFile::bound(addr),
+        })
+    }
+
+    /// Accept a connection, in non-blocking mode.  Returns the connected
+    /// stream and its peer's address, which is usually unnamed.
+    pub fn accept(&self) -> io::Result<(UnixStream, UnixAddress)> {
+        let (stream, addr) = self.listener.accept()?;
+        stream.set_nonblocking(true)?;
+        Ok((UnixStream { stream }, UnixAddress::from_std(&addr)))
+    }
+
+    pub fn local_addr(&self) -> io::Result<UnixAddress> {
+        Ok(UnixAddress::from_std(&self.listener.local_addr()?))
+    }
+}
+
+impl AsRawFd for UnixListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+/// A non-blocking, connected Unix domain stream which mio can poll.
+#[derive(Debug)]
+pub struct UnixStream {
+    stream: net::UnixStream,
+}
+
+impl Read for UnixStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for UnixStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl AsRawFd for UnixStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+// Register each socket with mio by its file descriptor, as mio-uds does.
+macro_rules! evented_fd {
+    ($ty:ty) => {
+        impl Evented for $ty {
+            fn register(
+                &self,
+                poll: &Poll,
+                token: Token,
+                interest: Ready,
+                opts: PollOpt,
+            ) -> io::Result<()> {
+                EventedFd(&self.as_raw_fd()).register(poll, token, interest, opts)
+            }
+
+            fn reregister(
+                &self,
+                poll: &Poll,
+                token: Token,
+                interest: Ready,
+                opts: PollOpt,
+            ) -> io::Result<()> {
+                EventedFd(&self.as_raw_fd()).reregister(poll, token, interest, opts)
+            }
+
+            fn deregister(&self, poll: &Poll) -> io::Result<()> {
+                EventedFd(&self.as_raw_fd()).deregister(poll)
+            }
+        }
+    };
+}
+
+evented_fd!(UnixDatagram);
+evented_fd!(UnixListener);
+evented_fd!(UnixStream);
+
+// Under Tokio, an operation which would block arranges for the task to be notified once the
+// socket is ready again, as tokio-core's own UdpSocket does.
+impl DatagramSocket for PollEvented<UnixDatagram> {
+    type Addr = UnixAddress;
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, UnixAddress)> {
+        if let Async::NotReady = self.poll_read() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let result = self.get_ref().recv_from(buf);
+        if is_would_block(&result) {
+            self.need_read();
+        }
+        result
+    }
+
+    fn send_to(&self, buf: &[u8], addr: &UnixAddress) -> io::Result<usize> {
+        if let Async::NotReady = self.poll_write() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let result = self.get_ref().send_to(buf, addr);
+        if is_would_block(&result) {
+            self.need_write();
+        }
+        result
+    }
+
+    fn local_addr(&self) -> io::Result<UnixAddress> {
+        self.get_ref().local_addr()
+    }
+}
+
+fn is_would_block<T>(result: &io::Result<T>) -> bool {
+    match *result {
+        Err(ref e) => e.kind() == io::ErrorKind::WouldBlock,
+        Ok(_) => false,
+    }
+}
@@ -0,0 +1,42 @@
+// Look up the echo backends by name.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::backend::{self, BackendError, BACKENDS};
+
+#[test]
+fn available_backends_report_their_names() {
+    for name in backend::available() {
+        assert_eq!(backend::find(name).unwrap().name(), name);
+    }
+}
+
+#[test]
+fn portable_backends_are_always_available() {
+    let available = backend::available();
+    for name in &["mio-level", "mio-edge", "tokio", "tokio-mpsc"] {
+        assert!(available.contains(name), "{} is missing", name);
+    }
+}
+
+#[test]
+fn every_backend_is_available_or_needs_a_feature() {
+    for name in BACKENDS {
+        match backend::find(name) {
+            Ok(_) => (),
+            Err(BackendError::Unavailable(ref unavailable, feature)) => {
+                assert_eq!(unavailable, name);
+                assert!(["select", "poll", "epoll", "io-uring"].contains(&feature));
+            }
+            Err(e) => panic!("{}: {}", name, e),
+        }
+    }
+}
+
+#[test]
+fn unknown_backends_are_rejected() {
+    assert_eq!(
+        backend::find("kqueue").err(),
+        Some(BackendError::Unknown("kqueue".to_string()))
+    );
+}
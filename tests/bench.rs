@@ -0,0 +1,119 @@
+// Parse benchmark options, format its results, and benchmark a couple of
+// backends with the bench-echo program.
+
+extern crate tokio_aio_examples;
+
+use std::env;
+use std::fs;
+use std::process::{self, Command};
+use std::time::Duration;
+use tokio_aio_examples::backend;
+use tokio_aio_examples::bench::{table, BenchConfig, BenchResult, Format, COLUMNS};
+use tokio_aio_examples::config::ConfigError;
+use tokio_aio_examples::load::Report;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn result(backend: &str, sent: u64, latencies: &[u64]) -> BenchResult {
+    BenchResult {
+        backend: backend.to_string(),
+        report: Report {
+            sent,
+            received: latencies.len() as u64,
+            elapsed: Duration::from_secs(2),
+            latencies: latencies.iter().map(|&us| Duration::from_micros(us)).collect(),
+            ..Report::default()
+        },
+        cpu: Duration::from_millis(15),
+    }
+}
+
+#[test]
+fn every_available_backend_is_benchmarked_by_default() {
+    let config = BenchConfig::default().parse(args(&[])).unwrap();
+    assert_eq!(config.backends, backend::available());
+    assert_eq!(config.format, Format::Csv);
+}
+
+#[test]
+fn options_override_the_defaults() {
+    let config = BenchConfig::default()
+        .parse(args(&[
+            "--backends=mio-edge,tokio",
+            "--format=json",
+            "--count=50",
+            "--clients=2",
+            "--echo-options=--queue-depth=4 --budget=8",
+        ]))
+        .unwrap();
+    assert_eq!(config.backends, vec!["mio-edge", "tokio"]);
+    assert_eq!(config.format, Format::Json);
+    assert_eq!((config.load.count, config.load.clients), (50, 2));
+    assert_eq!(config.echo_options, vec!["--queue-depth=4", "--budget=8"]);
+}
+
+#[test]
+fn unknown_backends_and_targets_are_rejected() {
+    let parse = |arg: &str| BenchConfig::default().parse(args(&[arg]));
+    match parse("--backends=kqueue") {
+        Err(ConfigError::Invalid(ref name, _)) => assert_eq!(name, "backends"),
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(
+        parse("--target=127.0.0.1:2000").err(),
+        Some(ConfigError::Unknown("target".to_string()))
+    );
+    assert!(parse("--format=xml").is_err());
+    assert!(parse("--clients=0").is_err());
+}
+
+#[test]
+fn results_are_tabulated_as_csv() {
+    let results = [result("mio-level", 4, &[100, 200, 300]), result("tokio", 4, &[])];
+    assert_eq!(
+        table(&results, Format::Csv),
+        "backend,sent,received,throughput,p50_us,p99_us,drop_rate,cpu_ms\n\
+         mio-level,4,3,1.5,200.0,300.0,0.2500,15.0\n\
+         tokio,4,0,0.0,,,1.0000,15.0\n"
+    );
+}
+
+#[test]
+fn results_are_tabulated_as_json() {
+    let results = [result("mio-level", 4, &[100, 200, 300]), result("tokio", 4, &[])];
+    assert_eq!(
+        table(&results, Format::Json),
+        "[\n  \
+         {\"backend\": \"mio-level\", \"sent\": 4, \"received\": 3, \"throughput\": 1.5, \
+         \"p50_us\": 200.0, \"p99_us\": 300.0, \"drop_rate\": 0.2500, \"cpu_ms\": 15.0},\n  \
+         {\"backend\": \"tokio\", \"sent\": 4, \"received\": 0, \"throughput\": 0.0, \
+         \"p50_us\": null, \"p99_us\": null, \"drop_rate\": 1.0000, \"cpu_ms\": 15.0}\n]\n"
+    );
+}
+
+#[test]
+fn backends_are_benchmarked_in_turn() {
+    let output = env::temp_dir().join(format!("bench-echo-{}.csv", process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_bench-echo"))
+        .args(["--backends=mio-edge,tokio", "--count=200", "--rate=2000", "--wait=500"])
+        .arg(format!("--output={}", output.display()))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let csv = fs::read_to_string(&output).unwrap();
+    let _ = fs::remove_file(&output);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], COLUMNS.join(","));
+    assert_eq!(lines.len(), 3, "{}", csv);
+    for (line, backend) in lines[1..].iter().zip(&["mio-edge", "tokio"]) {
+        let values: Vec<&str> = line.split(',').collect();
+        assert_eq!(values.len(), COLUMNS.len());
+        assert_eq!(values[0], *backend);
+        assert_eq!(values[1], "200");
+        let received: u64 = values[2].parse().unwrap();
+        assert!(received > 0, "{}", line);
+    }
+}
@@ -0,0 +1,142 @@
+// The fixture shared by the tests which run the example programs: a server
+// process whose output is collected as it runs.  Each test crate uses the
+// parts it needs.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+const LISTENING: &str = "listening on ";
+
+/// An example program, killed when dropped.
+pub struct Server {
+    child: Child,
+    output: Arc<Mutex<Vec<String>>>,
+    reader: Option<JoinHandle<()>>, // Collects the output until the server exits.
+}
+
+impl Server {
+    /// Run `command`, once it has announced that it is listening.
+    pub fn start(mut command: Command) -> Server {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to start the server");
+
+        // Keep draining the output, so that the server never blocks writing to it.
+        let output = Arc::new(Mutex::new(vec![]));
+        let (tx, rx) = mpsc::channel();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let reader = {
+            let output = output.clone();
+            thread::spawn(move || {
+                for line in stdout.lines() {
+                    let line = line.unwrap();
+                    if line.contains(LISTENING) {
+                        let _ = tx.send(());
+                    }
+                    output.lock().unwrap().push(line);
+                }
+            })
+        };
+        rx.recv_timeout(TIMEOUT).expect("the server didn't start listening");
+        Server {
+            child,
+            output,
+            reader: Some(reader),
+        }
+    }
+
+    /// The first address the server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addrs(1)[0]
+    }
+
+    /// The addresses the server listens on, in the order it announced them,
+    /// once it has announced `count` of them.
+    pub fn addrs(&self, count: usize) -> Vec<SocketAddr> {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let addrs: Vec<SocketAddr> = self
+                .output
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|line| line.split(LISTENING).nth(1))
+                .map(|addr| addr.parse().unwrap())
+                .collect();
+            if addrs.len() >= count {
+                return addrs;
+            }
+            assert!(Instant::now() < deadline, "the server didn't open {} sockets", count);
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// The number of datagrams the server has reported dropping.
+    pub fn dropped(&self) -> usize {
+        self.output
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|line| line.contains("dropping packet"))
+            .count()
+    }
+
+    /// Wait for the server to exit by itself, and for the last of its
+    /// output to be collected.
+    pub fn wait(&mut self) -> ExitStatus {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                if let Some(reader) = self.reader.take() {
+                    reader.join().unwrap();
+                }
+                return status;
+            }
+            assert!(Instant::now() < deadline, "the server didn't exit");
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Return true if the server has printed a line containing `text`.
+    pub fn printed(&self, text: &str) -> bool {
+        self.output.lock().unwrap().iter().any(|line| line.contains(text))
+    }
+
+    /// Wait for the server to print a line containing `text`.
+    pub fn wait_for(&self, text: &str) {
+        let deadline = Instant::now() + TIMEOUT;
+        while !self.printed(text) {
+            assert!(Instant::now() < deadline, "the server didn't print '{}'", text);
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// The lines the server has printed so far.
+    pub fn output(&self) -> Vec<String> {
+        self.output.lock().unwrap().clone()
+    }
+
+    /// Send the server a signal.
+    pub fn signal(&self, signum: ::libc::c_int) {
+        unsafe {
+            ::libc::kill(self.child.id() as ::libc::pid_t, signum);
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,566 @@
+// Drive every echo backend over loopback: each test starts the echo
+// program with one backend on an ephemeral port, and checks the echoes.
+//
+// The select, poll, epoll and io-uring backends are only tested when the
+// corresponding features are enabled.
+
+extern crate libc;
+extern crate tokio_aio_examples;
+
+mod common;
+
+#[cfg(any(feature = "select", feature = "poll", feature = "epoll"))]
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::net::{Ipv6Addr, SocketAddr, UdpSocket};
+use std::process::{self, Command, Stdio};
+#[cfg(feature = "epoll")]
+use std::thread;
+use std::time::Duration;
+use common::{Server, TIMEOUT};
+use tokio_aio_examples::config::MAX_OUTGOING_MESSAGES;
+use tokio_aio_examples::load::{parse_header, payload};
+#[cfg(any(feature = "select", feature = "poll", feature = "epoll"))]
+use tokio_aio_examples::syscalls::SyscallCount;
+
+/// Start `backend` on an ephemeral port, with any extra options.
+fn start(backend: &str, options: &[&str]) -> Server {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_echo"));
+    command.arg(format!("--backend={}", backend)).arg("--port=0").args(options);
+    Server::start(command)
+}
+
+fn client() -> UdpSocket {
+    client_on("127.0.0.1:0")
+}
+
+fn client_on(addr: &str) -> UdpSocket {
+    let socket = UdpSocket::bind(addr).unwrap();
+    socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+    socket
+}
+
+/// Send a datagram from `socket` to `to`, and check that the echo comes
+/// back from `to`.
+fn echoes_from(socket: &UdpSocket, to: SocketAddr, seq: u64) {
+    let datagram = payload(0, seq, 64);
+    socket.send_to(&datagram, to).unwrap();
+    let mut buffer = [0; 2048];
+    let (nbytes, from) = socket.recv_from(&mut buffer).unwrap();
+    assert_eq!(from, to);
+    assert_eq!(&buffer[..nbytes], &datagram[..]);
+}
+
+/// Receive datagrams until none arrives for a while.
+fn drain(socket: &UdpSocket) -> Vec<Vec<u8>> {
+    socket.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+    let mut buffer = [0; 2048];
+    let mut received = vec![];
+    while let Ok(nbytes) = socket.recv(&mut buffer) {
+        received.push(buffer[..nbytes].to_vec());
+    }
+    socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+    received
+}
+
+/// Echoes are returned to their sender unchanged, whatever their size.
+fn echoes_are_byte_exact(backend: &str) {
+    echoes_are_byte_exact_with(backend, &[]);
+}
+
+fn echoes_are_byte_exact_with(backend: &str, options: &[&str]) {
+    let server = start(backend, options);
+    let socket = client();
+    let mut buffer = [0; 2048];
+    for (seq, &len) in [12, 64, 1000, 1500].iter().enumerate() {
+        let datagram = payload(0, seq as u64, len);
+        socket.send_to(&datagram, server.addr()).unwrap();
+        let (nbytes, from) = socket.recv_from(&mut buffer).unwrap();
+        assert_eq!(from, server.addr());
+        assert_eq!(&buffer[..nbytes], &datagram[..]);
+    }
+}
+
+/// Each client's echoes arrive in the order it sent the datagrams, however
+/// the clients' datagrams are interleaved.
+fn echoes_are_ordered_per_client(backend: &str) {
+    let server = start(backend, &[]);
+    let clients = [client(), client(), client()];
+    for seq in 0..MAX_OUTGOING_MESSAGES as u64 {
+        for (id, socket) in clients.iter().enumerate() {
+            socket.send_to(&payload(id as u32, seq, 32), server.addr()).unwrap();
+        }
+    }
+    for (id, socket) in clients.iter().enumerate() {
+        let mut last = None;
+        for echo in drain(socket) {
+            let (client, seq) = parse_header(&echo).unwrap();
+            assert_eq!(client, id as u32);
+            assert_eq!(echo, payload(client, seq, 32));
+            assert!(last < Some(seq), "echo {} arrived after {:?}", seq, last);
+            last = Some(seq);
+        }
+        assert!(last.is_some(), "client {} received no echoes", id);
+    }
+}
+
+/// A burst far beyond the outgoing queue depth loses no echoes other than
+/// those the server reports dropping: the echoes are an in-order subset of
+/// the burst, and the server goes on echoing afterwards.
+fn bursts_only_lose_dropped_echoes(backend: &str, options: &[&str]) {
+    let server = start(backend, options);
+    let socket = client();
+    let burst = 20 * MAX_OUTGOING_MESSAGES as u64;
+    for seq in 0..burst {
+        socket.send_to(&payload(0, seq, 64), server.addr()).unwrap();
+    }
+    let echoes = drain(&socket);
+    let mut last = None;
+    for echo in &echoes {
+        let (_, seq) = parse_header(echo).unwrap();
+        assert_eq!(echo, &payload(0, seq, 64));
+        assert!(last < Some(seq), "echo {} arrived after {:?}", seq, last);
+        last = Some(seq);
+    }
+    assert!(echoes.len() + server.dropped() <= burst as usize);
+
+    let datagram = payload(0, burst, 64);
+    socket.send_to(&datagram, server.addr()).unwrap();
+    assert_eq!(drain(&socket), vec![datagram]);
+}
+
+/// With no room in the outgoing queue, every datagram is dropped (and
+/// reported) rather than echoed.
+fn full_queue_drops_datagrams(backend: &str) {
+    let server = start(backend, &["--queue-depth=0"]);
+    let socket = client();
+    let count = 2 * MAX_OUTGOING_MESSAGES;
+    for seq in 0..count {
+        socket.send_to(&payload(0, seq as u64, 64), server.addr()).unwrap();
+    }
+    assert!(drain(&socket).is_empty());
+    assert_eq!(server.dropped(), count);
+}
+
+/// A server bound to the IPv6 loopback address echoes IPv6 clients.
+fn echoes_over_ipv6(backend: &str) {
+    let server = start(backend, &["--bind=::1"]);
+    assert!(server.addr().is_ipv6());
+    echoes_from(&client_on("[::1]:0"), server.addr(), 0);
+}
+
+/// A dual-stack socket echoes IPv4 clients, whose addresses it sees as
+/// v4-mapped, as well as IPv6 clients.
+fn dual_stack_echoes_ipv4_and_ipv6(backend: &str) {
+    let server = start(backend, &["--bind=::", "--v6-only=off"]);
+    let port = server.addr().port();
+    echoes_from(&client(), SocketAddr::from(([127, 0, 0, 1], port)), 0);
+    echoes_from(&client_on("[::1]:0"), SocketAddr::new(Ipv6Addr::LOCALHOST.into(), port), 1);
+}
+
+/// An IPv6-only socket ignores IPv4 clients.
+fn v6_only_ignores_ipv4(backend: &str) {
+    let server = start(backend, &["--bind=::"]);
+    let port = server.addr().port();
+    let socket = client();
+    socket.send_to(&payload(0, 0, 64), ("127.0.0.1", port)).unwrap();
+    assert!(drain(&socket).is_empty());
+    echoes_from(&client_on("[::1]:0"), SocketAddr::new(Ipv6Addr::LOCALHOST.into(), port), 1);
+}
+
+/// A server bound to an IPv4 and an IPv6 address echoes on both sockets.
+fn echoes_on_ipv4_and_ipv6_sockets(backend: &str) {
+    let server = start(backend, &["--bind=127.0.0.1,::1"]);
+    let addrs = server.addrs(2);
+    assert!(addrs[0].is_ipv4() && addrs[1].is_ipv6());
+    echoes_from(&client(), addrs[0], 0);
+    echoes_from(&client_on("[::1]:0"), addrs[1], 1);
+}
+
+/// The shutdown command is not echoed, but the echoes already queued are
+/// sent before the server exits cleanly.
+fn shutdown_command_drains_and_exits(backend: &str) {
+    let mut server = start(backend, &["--shutdown-command=quit"]);
+    let socket = client();
+    let datagram = payload(0, 0, 64);
+    socket.send_to(&datagram, server.addr()).unwrap();
+    socket.send_to(b"quit\n", server.addr()).unwrap();
+    assert!(server.wait().success());
+    assert_eq!(drain(&socket), vec![datagram]);
+    assert!(server.printed("drained; exiting."));
+}
+
+/// SIGTERM shuts the server down just like the shutdown command.
+fn sigterm_exits_cleanly(backend: &str) {
+    let mut server = start(backend, &[]);
+    server.signal(libc::SIGTERM);
+    assert!(server.wait().success());
+    assert!(server.printed("drained; exiting."));
+}
+
+/// SIGUSR1 prints the live statistics, and the server carries on.
+fn sigusr1_prints_stats(backend: &str) {
+    let server = start(backend, &[]);
+    let socket = client();
+    let mut buffer = [0; 2048];
+    for seq in 0..2 {
+        let datagram = payload(0, seq, 64);
+        socket.send_to(&datagram, server.addr()).unwrap();
+        let nbytes = socket.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..nbytes], &datagram[..]);
+        if seq == 0 {
+            server.signal(libc::SIGUSR1);
+            server.wait_for("received=1 ");
+        }
+    }
+}
+
+/// SIGHUP re-reads the configuration file, and the running server adopts
+/// the new shutdown command.
+fn sighup_reloads_the_config_file(backend: &str) {
+    let path = env::temp_dir().join(format!("echo-backends-{}-{}.conf", backend, process::id()));
+    fs::write(&path, "--drain-timeout=500\n").unwrap();
+    let mut server = start(backend, &[&format!("--config={}", path.display())]);
+    fs::write(&path, "# Reloaded.\n--shutdown-command=quit\n").unwrap();
+    server.signal(libc::SIGHUP);
+    server.wait_for("reloaded configuration");
+    fs::remove_file(&path).unwrap();
+
+    client().send_to(b"quit", server.addr()).unwrap();
+    assert!(server.wait().success());
+}
+
+/// Echo one datagram with `backend`, shut the server down, and return
+/// the system calls it reported on exit, by name.
+#[cfg(any(feature = "select", feature = "poll", feature = "epoll"))]
+fn syscalls_for_one_echo(backend: &str, options: &[&str]) -> HashMap<String, SyscallCount> {
+    let mut server = start(backend, options);
+    let socket = client();
+    let datagram = payload(0, 0, 64);
+    socket.send_to(&datagram, server.addr()).unwrap();
+    assert_eq!(drain(&socket), vec![datagram]);
+
+    server.signal(libc::SIGTERM);
+    assert!(server.wait().success());
+    assert!(server.printed("syscalls: calls="));
+    assert!(server.printed(" datagrams=1 "));
+    let mut syscalls = HashMap::new();
+    for line in server.output() {
+        if let Some(rest) = line.strip_prefix("syscall ") {
+            let (name, count) = rest.split_at(rest.find(": ").unwrap());
+            syscalls.insert(name.to_string(), count[2..].parse().unwrap());
+        }
+    }
+    syscalls
+}
+
+/// A level-triggered server makes one receive and one send call per
+/// echo, and never sees EWOULDBLOCK, after waiting with `wait`.
+#[cfg(any(feature = "select", feature = "poll", feature = "epoll"))]
+fn level_triggering_makes_one_call_per_operation(backend: &str, wait: &str) {
+    let syscalls = syscalls_for_one_echo(backend, &[]);
+    assert_eq!(syscalls["recvfrom"].calls, 1, "{:?}", syscalls);
+    assert_eq!(syscalls["recvfrom"].would_block, 0, "{:?}", syscalls);
+    assert_eq!(syscalls["sendto"].calls, 1, "{:?}", syscalls);
+    assert!(syscalls[wait].calls >= 2, "{:?}", syscalls);
+}
+
+// More sockets than FD_SETSIZE (1024), so that the last ones have descriptors which select() can't
+// watch.
+#[cfg(any(feature = "select", feature = "poll"))]
+const BEYOND_FD_SETSIZE: usize = 1100;
+
+/// The select backend fails to start rather than overrun its descriptor sets.
+#[cfg(feature = "select")]
+#[test]
+fn select_refuses_descriptors_beyond_fd_setsize() {
+    let output = Command::new(env!("CARGO_BIN_EXE_echo"))
+        .arg("--backend=select")
+        .arg("--port=0")
+        .arg(format!("--sockets={}", BEYOND_FD_SETSIZE))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("beyond FD_SETSIZE"));
+}
+
+/// The poll backend echoes on every socket, however high its descriptor.
+#[cfg(feature = "poll")]
+#[test]
+fn poll_echoes_beyond_fd_setsize() {
+    let sockets = format!("--sockets={}", BEYOND_FD_SETSIZE);
+    let server = start("poll", &[&sockets]);
+    let addrs = server.addrs(BEYOND_FD_SETSIZE);
+    let socket = client();
+    for (seq, addr) in addrs.iter().enumerate().skip(BEYOND_FD_SETSIZE - 10) {
+        echoes_from(&socket, *addr, seq as u64);
+    }
+}
+
+macro_rules! backend_tests {
+    ($module:ident, $backend:expr) => {
+        backend_tests!($module, $backend, {
+            #[test]
+            fn full_queue_drops_datagrams() {
+                super::full_queue_drops_datagrams($backend);
+            }
+
+            #[test]
+            fn bursts_with_drop_oldest() {
+                super::bursts_only_lose_dropped_echoes(
+                    $backend,
+                    &["--queue-depth=1", "--overflow=drop-oldest"],
+                );
+            }
+
+            #[test]
+            fn bursts_with_round_robin() {
+                super::bursts_only_lose_dropped_echoes($backend, &["--fairness=round-robin"]);
+            }
+
+            #[test]
+            fn bursts_with_drr() {
+                super::bursts_only_lose_dropped_echoes($backend, &["--fairness=drr"]);
+            }
+
+            #[test]
+            fn echoes_with_priority_lanes() {
+                super::echoes_are_byte_exact_with($backend, &["--priority-lanes=4"]);
+            }
+
+            #[test]
+            fn echoes_on_ipv4_and_ipv6_sockets() {
+                super::echoes_on_ipv4_and_ipv6_sockets($backend);
+            }
+        });
+    };
+    ($module:ident, $backend:expr, { $($extra:tt)* }) => {
+        mod $module {
+            #[test]
+            fn echoes_are_byte_exact() {
+                super::echoes_are_byte_exact($backend);
+            }
+
+            #[test]
+            fn echoes_are_ordered_per_client() {
+                super::echoes_are_ordered_per_client($backend);
+            }
+
+            #[test]
+            fn bursts_only_lose_dropped_echoes() {
+                super::bursts_only_lose_dropped_echoes($backend, &[]);
+            }
+
+            #[test]
+            fn bursts_with_stop_reading() {
+                super::bursts_only_lose_dropped_echoes(
+                    $backend,
+                    &["--queue-depth=1", "--overflow=stop-reading"],
+                );
+            }
+
+            #[test]
+            fn bursts_with_spill() {
+                super::bursts_only_lose_dropped_echoes(
+                    $backend,
+                    &["--queue-depth=1", "--overflow=spill"],
+                );
+            }
+
+            #[test]
+            fn echoes_over_ipv6() {
+                super::echoes_over_ipv6($backend);
+            }
+
+            #[test]
+            fn dual_stack_echoes_ipv4_and_ipv6() {
+                super::dual_stack_echoes_ipv4_and_ipv6($backend);
+            }
+
+            #[test]
+            fn v6_only_ignores_ipv4() {
+                super::v6_only_ignores_ipv4($backend);
+            }
+
+            #[test]
+            fn shutdown_command_drains_and_exits() {
+                super::shutdown_command_drains_and_exits($backend);
+            }
+
+            #[test]
+            fn sigterm_exits_cleanly() {
+                super::sigterm_exits_cleanly($backend);
+            }
+
+            #[test]
+            fn sigusr1_prints_stats() {
+                super::sigusr1_prints_stats($backend);
+            }
+
+            #[test]
+            fn sighup_reloads_the_config_file() {
+                super::sighup_reloads_the_config_file($backend);
+            }
+
+            $($extra)*
+        }
+    };
+}
+
+#[cfg(feature = "select")]
+backend_tests!(select, "select");
+#[cfg(feature = "poll")]
+backend_tests!(poll, "poll");
+#[cfg(feature = "epoll")]
+backend_tests!(epoll_level, "epoll-level");
+#[cfg(feature = "epoll")]
+backend_tests!(epoll_edge, "epoll-edge");
+// The epoll-edge backend's batched path, which receives and sends with recvmmsg() and sendmmsg().
+#[cfg(feature = "epoll")]
+mod epoll_edge_batched {
+    const BATCH: &str = "--batch=8";
+
+    #[test]
+    fn echoes_are_byte_exact() {
+        super::echoes_are_byte_exact_with("epoll-edge", &[BATCH]);
+    }
+
+    #[test]
+    fn echoes_with_priority_lanes() {
+        super::echoes_are_byte_exact_with("epoll-edge", &[BATCH, "--priority-lanes=4"]);
+    }
+
+    #[test]
+    fn bursts_only_lose_dropped_echoes() {
+        super::bursts_only_lose_dropped_echoes("epoll-edge", &[BATCH]);
+    }
+
+    #[test]
+    fn bursts_with_a_small_budget() {
+        super::bursts_only_lose_dropped_echoes("epoll-edge", &[BATCH, "--budget=3"]);
+    }
+
+    #[test]
+    fn bursts_with_stop_reading() {
+        super::bursts_only_lose_dropped_echoes(
+            "epoll-edge",
+            &[BATCH, "--queue-depth=1", "--overflow=stop-reading"],
+        );
+    }
+
+    #[test]
+    fn bursts_with_drr() {
+        super::bursts_only_lose_dropped_echoes("epoll-edge", &[BATCH, "--fairness=drr"]);
+    }
+
+    // A batch for the datagram, and one to find the socket drained.
+    #[test]
+    fn syscalls_are_reported_on_exit() {
+        let syscalls = super::syscalls_for_one_echo("epoll-edge", &[BATCH]);
+        assert!(syscalls["recvmmsg"].calls >= 2, "{:?}", syscalls);
+        assert!(syscalls["recvmmsg"].would_block >= 1, "{:?}", syscalls);
+        assert!(syscalls["sendmmsg"].calls >= 1, "{:?}", syscalls);
+        assert!(!syscalls.contains_key("recvfrom"), "{:?}", syscalls);
+    }
+}
+#[cfg(feature = "select")]
+#[test]
+fn select_reports_syscalls_on_exit() {
+    level_triggering_makes_one_call_per_operation("select", "pselect6");
+}
+#[cfg(feature = "poll")]
+#[test]
+fn poll_reports_syscalls_on_exit() {
+    level_triggering_makes_one_call_per_operation("poll", "ppoll");
+}
+#[cfg(feature = "epoll")]
+#[test]
+fn epoll_level_reports_syscalls_on_exit() {
+    level_triggering_makes_one_call_per_operation("epoll-level", "epoll_wait");
+}
+// Edge triggering costs an extra receive call per turn, to find the socket drained.
+#[cfg(feature = "epoll")]
+#[test]
+fn epoll_edge_reports_syscalls_on_exit() {
+    let syscalls = syscalls_for_one_echo("epoll-edge", &[]);
+    assert!(syscalls["recvfrom"].calls >= 2, "{:?}", syscalls);
+    assert!(syscalls["recvfrom"].would_block >= 1, "{:?}", syscalls);
+    assert!(syscalls["sendto"].calls >= 1, "{:?}", syscalls);
+    assert!(syscalls["epoll_ctl"].calls >= 1, "{:?}", syscalls);
+}
+// The report is also printed every interval while the server is busy.
+#[cfg(feature = "epoll")]
+#[test]
+fn syscalls_are_reported_periodically() {
+    let server = start("epoll-level", &["--syscall-interval=1"]);
+    let socket = client();
+    for seq in 0..3 {
+        echoes_from(&socket, server.addr(), seq);
+        thread::sleep(Duration::from_millis(600));
+    }
+    server.wait_for("syscalls: calls=");
+}
+/// The report timer is a timerfd which the server waits for along with its
+/// sockets, so the report is printed every interval even while idle.
+#[cfg(any(feature = "select", feature = "poll", feature = "epoll"))]
+fn reports_syscalls_while_idle(backend: &str) {
+    let server = start(backend, &["--syscall-interval=1"]);
+    server.wait_for("syscalls: calls=");
+}
+#[cfg(feature = "select")]
+#[test]
+fn select_reports_syscalls_while_idle() {
+    reports_syscalls_while_idle("select");
+}
+#[cfg(feature = "poll")]
+#[test]
+fn poll_reports_syscalls_while_idle() {
+    reports_syscalls_while_idle("poll");
+}
+#[cfg(feature = "epoll")]
+#[test]
+fn epoll_reports_syscalls_while_idle() {
+    reports_syscalls_while_idle("epoll-edge");
+}
+#[cfg(feature = "io-uring")]
+backend_tests!(io_uring, "io-uring");
+backend_tests!(mio_level, "mio-level");
+backend_tests!(mio_edge, "mio-edge");
+backend_tests!(tokio, "tokio");
+// The tokio-mpsc backend's reader and writer are connected by an MPSC queue, which serves a single
+// socket, and so a single bind address, and which can't carry out every overflow policy.
+backend_tests!(tokio_mpsc, "tokio-mpsc", {
+    #[test]
+    fn bursts_with_a_full_queue() {
+        super::bursts_only_lose_dropped_echoes("tokio-mpsc", &["--queue-depth=1"]);
+    }
+
+    #[test]
+    fn echoes_with_priority_lanes() {
+        super::echoes_are_byte_exact_with("tokio-mpsc", &["--priority-lanes=4"]);
+    }
+
+    fn refuses(option: &str) {
+        let status = super::Command::new(env!("CARGO_BIN_EXE_echo"))
+            .args(["--backend=tokio-mpsc", "--port=0", option])
+            .stdout(super::Stdio::null())
+            .stderr(super::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!status.success(), "{}", option);
+    }
+
+    #[test]
+    fn refuses_several_sockets() {
+        refuses("--sockets=2");
+    }
+
+    #[test]
+    fn refuses_what_a_channel_cannot_express() {
+        for option in &["--overflow=drop-oldest", "--fairness=round-robin", "--fairness=drr"] {
+            refuses(option);
+        }
+        refuses("--queue-depth=0");
+    }
+});
@@ -0,0 +1,261 @@
+// Exercise the sans-I/O echo state machine without any sockets.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::echo::{priority_lane, Interest, Received};
+use tokio_aio_examples::queue::Overflow;
+use tokio_aio_examples::{EchoCore, Message, OutgoingQueue};
+
+fn message(payload: &str, addr: u32) -> Message<u32> {
+    Message::new(payload.as_bytes().to_vec(), addr)
+}
+
+#[test]
+fn echoes_are_sent_in_order() {
+    let mut core = EchoCore::new();
+    assert_eq!(core.received(message("one", 1)), Received::Queued(1));
+    assert_eq!(core.received(message("two", 2)), Received::Queued(2));
+
+    assert_eq!(core.poll_send(), Some(message("one", 1)));
+    core.sent();
+    assert_eq!(core.poll_send(), Some(message("two", 2)));
+    core.sent();
+    assert_eq!(core.poll_send(), None);
+    assert_eq!(core.stats().sent, 2);
+}
+
+#[test]
+fn full_queue_drops_newest() {
+    let mut core = EchoCore::with_queue(OutgoingQueue::with_capacity(2));
+    core.received(message("one", 1));
+    core.received(message("two", 1));
+    assert_eq!(core.received(message("three", 1)), Received::Dropped);
+
+    let stats = core.stats();
+    assert_eq!((stats.received, stats.dropped, stats.pending), (3, 1, 2));
+    assert_eq!(core.poll_send(), Some(message("one", 1)));
+}
+
+#[test]
+fn would_block_requeues_at_front_and_waits_for_writability() {
+    let mut core = EchoCore::new();
+    core.received(message("one", 1));
+    core.received(message("two", 1));
+
+    let first = core.poll_send().unwrap();
+    core.send_would_block(first);
+    assert_eq!(core.poll_send(), None);
+
+    core.writable();
+    assert_eq!(core.poll_send(), Some(message("one", 1)));
+}
+
+#[test]
+fn failed_sends_discard_the_echo_and_move_on() {
+    let mut core = core_with(Overflow::StopReading, 0);
+    core.received(message("one", 1));
+    assert_eq!(core.received(message("two", 2)), Received::Paused(2));
+
+    core.poll_send().unwrap();
+    core.send_failed();
+    assert_eq!(core.poll_send(), Some(message("two", 2)));
+    core.sent();
+    assert!(!core.is_paused());
+
+    let stats = core.stats();
+    assert_eq!((stats.sent, stats.failed, stats.pending), (1, 1, 0));
+}
+
+#[test]
+fn read_interest_follows_would_block() {
+    let mut core: EchoCore<u32> = EchoCore::new();
+    assert!(core.wants_read());
+    core.read_would_block();
+    assert!(!core.wants_read());
+    core.readable();
+    assert!(core.wants_read());
+}
+
+#[test]
+fn interest_changes_are_reported_once() {
+    let mut core = EchoCore::new();
+    let read_only = Interest { read: true, write: false };
+    let read_write = Interest { read: true, write: true };
+    assert_eq!(core.poll_interest(), Some(read_only));
+    assert_eq!(core.poll_interest(), None);
+
+    core.received(message("one", 1));
+    assert_eq!(core.poll_interest(), Some(read_write));
+    assert_eq!(core.poll_interest(), None);
+
+    core.poll_send().unwrap();
+    core.sent();
+    assert_eq!(core.poll_interest(), Some(read_only));
+}
+
+#[test]
+fn wants_write_only_while_echoes_are_sendable() {
+    let mut core = EchoCore::new();
+    assert!(!core.wants_write());
+    core.received(message("one", 1));
+    assert!(core.wants_write());
+
+    let echo = core.poll_send().unwrap();
+    core.send_would_block(echo);
+    assert!(!core.wants_write());
+    core.writable();
+    assert!(core.wants_write());
+}
+
+fn core_with(overflow: Overflow, spill_depth: usize) -> EchoCore<u32> {
+    EchoCore::with_queue(OutgoingQueue::with_overflow(2, overflow, spill_depth))
+}
+
+fn send_all(core: &mut EchoCore<u32>) -> Vec<Message<u32>> {
+    let mut sent = vec![];
+    while let Some(echo) = core.poll_send() {
+        core.sent();
+        sent.push(echo);
+    }
+    sent
+}
+
+#[test]
+fn drop_oldest_makes_room_for_new_echoes() {
+    let mut core = core_with(Overflow::DropOldest, 0);
+    core.received(message("one", 1));
+    core.received(message("two", 1));
+    assert_eq!(core.received(message("three", 1)), Received::DroppedOldest);
+
+    assert_eq!(send_all(&mut core), vec![message("two", 1), message("three", 1)]);
+    let stats = core.stats();
+    assert_eq!((stats.dropped, stats.dropped_oldest), (0, 1));
+}
+
+#[test]
+fn stop_reading_pauses_until_the_queue_drains() {
+    let mut core = core_with(Overflow::StopReading, 0);
+    let read_only = Interest { read: true, write: false };
+    let write_only = Interest { read: false, write: true };
+    assert_eq!(core.received(message("one", 1)), Received::Queued(1));
+    assert!(core.wants_read());
+    assert_eq!(core.received(message("two", 1)), Received::Paused(2));
+    assert!(!core.wants_read());
+    assert_eq!(core.poll_interest(), Some(write_only));
+
+    core.poll_send().unwrap();
+    core.sent();
+    assert!(core.is_paused());
+    core.poll_send().unwrap();
+    core.sent();
+    assert!(!core.is_paused());
+    assert!(core.wants_read());
+    assert_eq!(core.poll_interest(), Some(read_only));
+    assert_eq!(core.stats().pauses, 1);
+}
+
+#[test]
+fn spill_preserves_order_until_both_buffers_fill() {
+    let mut core = core_with(Overflow::Spill, 2);
+    for payload in &["one", "two"] {
+        core.received(message(payload, 1));
+    }
+    assert_eq!(core.received(message("three", 1)), Received::Spilled(3));
+    assert_eq!(core.received(message("four", 1)), Received::Spilled(4));
+    assert_eq!(core.received(message("five", 1)), Received::Dropped);
+
+    let sent: Vec<_> = send_all(&mut core).into_iter().map(|echo| echo.buffer).collect();
+    let expected: Vec<_> = ["one", "two", "three", "four"]
+        .iter()
+        .map(|payload| payload.as_bytes().to_vec())
+        .collect();
+    assert_eq!(sent, expected);
+    let stats = core.stats();
+    assert_eq!((stats.spilled, stats.dropped, stats.pending), (2, 1, 0));
+}
+
+fn laned_core(lanes: usize) -> EchoCore<u32> {
+    EchoCore::with_lanes((0..lanes).map(|_| OutgoingQueue::with_capacity(2).into()).collect())
+}
+
+#[test]
+fn urgent_lanes_are_sent_first() {
+    let mut core = laned_core(3);
+    core.received(message("\x02bulk", 1));
+    core.received(message("\x01normal", 1));
+    core.received(message("\x02more bulk", 1));
+    core.received(message("\x00control", 2));
+
+    let sent: Vec<_> = send_all(&mut core).into_iter().map(|echo| echo.buffer[0]).collect();
+    assert_eq!(sent, vec![0, 1, 2, 2]);
+}
+
+#[test]
+fn unknown_priorities_go_to_the_least_urgent_lane() {
+    assert_eq!(priority_lane(&message("\x07", 1), 3), 2);
+    assert_eq!(priority_lane(&message("", 1), 3), 2);
+    assert_eq!(priority_lane(&message("\x00", 1), 1), 0);
+    assert_eq!(priority_lane(&message("\x05", 1), 1), 0);
+}
+
+#[test]
+fn a_full_bulk_lane_does_not_drop_control_traffic() {
+    let mut core = laned_core(2);
+    core.received(message("\x01one", 1));
+    core.received(message("\x01two", 1));
+    assert_eq!(core.received(message("\x01three", 1)), Received::Dropped);
+    assert_eq!(core.received(message("\x00stop", 2)), Received::Queued(3));
+    assert_eq!(core.poll_send(), Some(message("\x00stop", 2)));
+}
+
+#[test]
+fn blocked_echoes_return_to_their_lane() {
+    let mut core = laned_core(2);
+    core.received(message("\x01bulk", 1));
+    core.received(message("\x00control", 1));
+
+    let control = core.poll_send().unwrap();
+    core.send_would_block(control);
+    core.writable();
+    assert_eq!(core.poll_send(), Some(message("\x00control", 1)));
+    assert_eq!(core.poll_send(), Some(message("\x01bulk", 1)));
+}
+
+#[test]
+fn unfinished_batches_keep_their_order_and_lanes() {
+    let mut core = laned_core(2);
+    core.received(message("\x01one", 1));
+    core.received(message("\x01two", 1));
+    core.received(message("\x00control", 1));
+
+    // Only the first of a batch of three was sent: the rest go back, last first.
+    let batch: Vec<_> = (0..3).map(|_| core.poll_send().unwrap()).collect();
+    core.sent();
+    for echo in batch.into_iter().skip(1).rev() {
+        core.send_unfinished(echo);
+    }
+    assert!(core.wants_write());
+    assert_eq!(send_all(&mut core), vec![message("\x01one", 1), message("\x01two", 1)]);
+}
+
+#[test]
+fn the_shutdown_command_stops_reading_but_not_sending() {
+    let mut core = EchoCore::new().with_shutdown_command(b"quit".to_vec());
+    core.received(message("one", 1));
+    assert_eq!(core.received(message("quit\n", 2)), Received::Shutdown(1));
+    assert!(core.is_shutting_down());
+    assert!(!core.wants_read());
+    core.readable();
+    assert!(!core.wants_read());
+
+    // The command itself isn't echoed, but the earlier datagram still is.
+    assert_eq!(send_all(&mut core), vec![message("one", 1)]);
+    assert_eq!(core.pending(), 0);
+}
+
+#[test]
+fn other_datagrams_are_echoed_as_usual() {
+    let mut core = EchoCore::new().with_shutdown_command(b"quit".to_vec());
+    assert_eq!(core.received(message("quite", 1)), Received::Queued(1));
+    assert!(!core.is_shutting_down());
+}
@@ -0,0 +1,150 @@
+// Exercise per-peer fair queuing, directly and through the echo core.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::echo::Received;
+use tokio_aio_examples::fair::{FairQueue, Fairness};
+use tokio_aio_examples::queue::Overflow;
+use tokio_aio_examples::{EchoCore, Message};
+
+fn message(len: usize, addr: u32) -> Message<u32> {
+    Message::new(vec![0; len], addr)
+}
+
+fn fair_queue(fairness: Fairness, quantum: usize, max_peers: usize) -> FairQueue<u32> {
+    FairQueue::new(fairness, quantum, max_peers, 4, Overflow::DropNewest, 0)
+}
+
+fn drain(queue: &mut FairQueue<u32>) -> Vec<(u32, usize)> {
+    let mut sent = vec![];
+    while let Some(message) = queue.pop() {
+        sent.push((message.addr, message.len()));
+    }
+    sent
+}
+
+#[test]
+fn fairness_names_round_trip() {
+    for fairness in &[Fairness::Fifo, Fairness::RoundRobin, Fairness::DeficitRoundRobin] {
+        assert_eq!(fairness.to_string().parse::<Fairness>(), Ok(*fairness));
+    }
+    assert!("fair".parse::<Fairness>().is_err());
+}
+
+#[test]
+fn round_robin_interleaves_peers() {
+    let mut queue = fair_queue(Fairness::RoundRobin, 0, 8);
+    for _ in 0..3 {
+        queue.push(message(10, 1)).unwrap();
+    }
+    queue.push(message(10, 2)).unwrap();
+    queue.push(message(10, 3)).unwrap();
+    assert_eq!(queue.len(), 5);
+
+    let order: Vec<_> = drain(&mut queue).into_iter().map(|(addr, _)| addr).collect();
+    assert_eq!(order, vec![1, 2, 3, 1, 1]);
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn deficit_round_robin_shares_bytes() {
+    let mut queue = fair_queue(Fairness::DeficitRoundRobin, 1000, 8);
+    for _ in 0..4 {
+        queue.push(message(1000, 1)).unwrap();
+    }
+    for _ in 0..4 {
+        queue.push(message(250, 2)).unwrap();
+    }
+
+    // Each turn, peer 1 sends one large datagram and peer 2 four small ones.
+    let sent = drain(&mut queue);
+    assert_eq!(sent[0], (1, 1000));
+    assert_eq!(&sent[1..5], &[(2, 250); 4]);
+    assert_eq!(&sent[5..], &[(1, 1000); 3]);
+}
+
+#[test]
+fn deficit_round_robin_eventually_sends_datagrams_larger_than_the_quantum() {
+    let mut queue = fair_queue(Fairness::DeficitRoundRobin, 100, 8);
+    queue.push(message(250, 1)).unwrap();
+    queue.push(message(100, 2)).unwrap();
+    queue.push(message(100, 2)).unwrap();
+    assert_eq!(drain(&mut queue), vec![(2, 100), (2, 100), (1, 250)]);
+}
+
+#[test]
+fn requeued_datagrams_are_sent_next() {
+    let mut queue = fair_queue(Fairness::RoundRobin, 0, 8);
+    queue.push(message(10, 1)).unwrap();
+    queue.push(message(10, 1)).unwrap();
+    queue.push(message(10, 2)).unwrap();
+
+    let first = queue.pop().unwrap();
+    queue.requeue(first);
+    let order: Vec<_> = drain(&mut queue).into_iter().map(|(addr, _)| addr).collect();
+    assert_eq!(order, vec![1, 2, 1]);
+}
+
+#[test]
+fn each_peer_has_its_own_sub_queue() {
+    let mut queue = fair_queue(Fairness::RoundRobin, 0, 8);
+    for _ in 0..4 {
+        queue.push(message(10, 1)).unwrap();
+    }
+    assert!(queue.is_full_for(&1));
+    assert!(!queue.is_full_for(&2));
+    assert!(queue.push(message(10, 1)).is_err());
+    assert!(queue.push(message(10, 2)).is_ok());
+    assert_eq!((queue.len(), queue.dropped()), (5, 1));
+}
+
+#[test]
+fn idle_peers_are_evicted_for_new_ones() {
+    let mut queue = fair_queue(Fairness::RoundRobin, 0, 2);
+    queue.push(message(10, 1)).unwrap();
+    queue.push(message(10, 2)).unwrap();
+    assert!(queue.is_full_for(&3));
+    assert!(queue.push(message(10, 3)).is_err());
+    assert_eq!((queue.rejected(), queue.dropped()), (1, 1));
+
+    // Peer 1's echo is sent first, so it becomes idle, and is evicted in favour of peer 3.
+    assert_eq!(queue.pop().unwrap().addr, 1);
+    assert!(!queue.is_full_for(&3));
+    queue.push(message(10, 3)).unwrap();
+    assert_eq!((queue.peers(), queue.evicted()), (2, 1));
+    let order: Vec<_> = drain(&mut queue).into_iter().map(|(addr, _)| addr).collect();
+    assert_eq!(order, vec![2, 3]);
+}
+
+#[test]
+fn a_noisy_peer_cannot_starve_a_quiet_one() {
+    let mut core = EchoCore::with_queue(fair_queue(Fairness::RoundRobin, 0, 8));
+    for _ in 0..10 {
+        core.received(message(10, 1));
+    }
+    assert_eq!(core.received(message(10, 2)), Received::Queued(5));
+
+    let first = core.poll_send().unwrap();
+    core.sent();
+    let second = core.poll_send().unwrap();
+    core.sent();
+    assert_eq!((first.addr, second.addr), (1, 2));
+    let stats = core.stats();
+    assert_eq!((stats.received, stats.dropped, stats.pending), (11, 6, 3));
+}
+
+#[test]
+fn stop_reading_pauses_when_a_peer_fills_its_sub_queue() {
+    let mut core = EchoCore::with_queue(FairQueue::new(
+        Fairness::RoundRobin,
+        0,
+        8,
+        2,
+        Overflow::StopReading,
+        0,
+    ));
+    assert_eq!(core.received(message(10, 1)), Received::Queued(1));
+    assert_eq!(core.received(message(10, 2)), Received::Queued(2));
+    assert_eq!(core.received(message(10, 1)), Received::Paused(3));
+    assert!(!core.wants_read());
+}
@@ -0,0 +1,69 @@
+// Classify datagrams into priority lanes, as the relays queue them.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::lanes::Lanes;
+use tokio_aio_examples::queue::{Overflow, Pushed};
+use tokio_aio_examples::{Message, OutgoingQueue};
+
+fn message(payload: &str, addr: u32) -> Message<u32> {
+    Message::new(payload.as_bytes().to_vec(), addr)
+}
+
+fn lanes(count: usize, overflow: Overflow) -> Lanes<u32> {
+    Lanes::new((0..count).map(|_| OutgoingQueue::with_overflow(2, overflow, 0).into()).collect())
+}
+
+#[test]
+fn messages_are_taken_from_the_most_urgent_lane() {
+    let mut lanes = lanes(3, Overflow::DropNewest);
+    for payload in &["\x02bulk", "\x01normal", "\x00control", "\x09unknown"] {
+        assert_eq!(lanes.push(message(payload, 1)), Ok(Pushed::Queued));
+    }
+    assert_eq!(lanes.len(), 4);
+    assert_eq!(lanes.queues()[2].len(), 2);
+
+    let mut taken = vec![];
+    while let Some(message) = lanes.pop() {
+        taken.push(message);
+    }
+    assert_eq!(
+        taken,
+        vec![
+            message("\x00control", 1),
+            message("\x01normal", 1),
+            message("\x02bulk", 1),
+            message("\x09unknown", 1),
+        ]
+    );
+    assert!(lanes.is_empty());
+}
+
+#[test]
+fn each_lane_overflows_on_its_own() {
+    let mut lanes = lanes(2, Overflow::DropNewest);
+    lanes.push(message("\x01one", 1)).unwrap();
+    lanes.push(message("\x01two", 1)).unwrap();
+    assert_eq!(lanes.push(message("\x01three", 1)), Err(message("\x01three", 1)));
+    assert_eq!(lanes.push(message("\x00stop", 2)), Ok(Pushed::Queued));
+    assert_eq!(lanes.dropped(), 1);
+
+    let mut lanes = self::lanes(2, Overflow::DropOldest);
+    lanes.push(message("\x01one", 1)).unwrap();
+    lanes.push(message("\x01two", 1)).unwrap();
+    assert_eq!(lanes.push(message("\x01three", 1)), Ok(Pushed::DroppedOldest));
+    assert_eq!(lanes.pop(), Some(message("\x01two", 1)));
+}
+
+#[test]
+fn requeued_messages_return_to_the_front_of_their_lane() {
+    let mut lanes = lanes(2, Overflow::DropNewest);
+    lanes.push(message("\x01one", 1)).unwrap();
+    lanes.push(message("\x01two", 1)).unwrap();
+    let one = lanes.pop().unwrap();
+    lanes.push(message("\x00control", 1)).unwrap();
+    lanes.requeue(one);
+    assert_eq!(lanes.pop(), Some(message("\x00control", 1)));
+    assert_eq!(lanes.pop(), Some(message("\x01one", 1)));
+    assert_eq!(lanes.lane_of(&message("\x01two", 1)), 1);
+}
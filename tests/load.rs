@@ -0,0 +1,146 @@
+// Parse load generator options and check its datagrams and reports.
+
+extern crate tokio_aio_examples;
+
+#[cfg(feature = "epoll")]
+use std::path::Path;
+use std::time::Duration;
+use tokio_aio_examples::load::{
+    parse_header, payload, LoadConfig, Report, Rng, ServerCalls, Sizes, Targets,
+};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn targets_may_be_a_port_range() {
+    let targets: Targets = "127.0.0.1:2000-2009".parse().unwrap();
+    assert_eq!(targets.0.len(), 10);
+    assert_eq!(targets.0[9], "127.0.0.1:2009".parse().unwrap());
+    assert_eq!(targets.to_string(), "127.0.0.1:2000-2009");
+
+    let targets: Targets = "[::1]:2000".parse().unwrap();
+    assert_eq!(targets.0, vec!["[::1]:2000".parse().unwrap()]);
+    assert!("127.0.0.1:2009-2000".parse::<Targets>().is_err());
+    assert!("127.0.0.1".parse::<Targets>().is_err());
+}
+
+#[test]
+fn sizes_stay_in_range() {
+    let sizes: Sizes = "20-30".parse().unwrap();
+    let mut rng = Rng::new(1);
+    for _ in 0..1000 {
+        let size = sizes.sample(&mut rng);
+        assert!((20..=30).contains(&size));
+    }
+    assert_eq!("64".parse(), Ok(Sizes { min: 64, max: 64 }));
+    assert!("4".parse::<Sizes>().is_err());
+    assert!("100-70000".parse::<Sizes>().is_err());
+}
+
+#[test]
+fn payloads_carry_their_header() {
+    let buffer = payload(3, 42, 100);
+    assert_eq!(buffer.len(), 100);
+    assert_eq!(parse_header(&buffer), Some((3, 42)));
+    assert_ne!(payload(3, 43, 100), buffer);
+    assert_eq!(parse_header(&buffer[..11]), None);
+}
+
+#[test]
+fn options_override_the_defaults() {
+    let config = LoadConfig::default()
+        .parse(args(&["--clients=4", "--count", "10", "--rate=0", "--size=12-20"]))
+        .unwrap();
+    assert_eq!(config.clients, 4);
+    assert_eq!(config.count, 10);
+    assert_eq!(config.rate, 0);
+    assert_eq!(config.sizes, Sizes { min: 12, max: 20 });
+    assert!(LoadConfig::default().parse(args(&["--clients=0"])).is_err());
+    assert!(LoadConfig::default().parse(args(&["--port=1"])).is_err());
+}
+
+#[test]
+fn comparisons_need_a_batch() {
+    let config = LoadConfig::default().parse(args(&["--batch=16", "--compare"])).unwrap();
+    assert_eq!((config.batch, config.compare), (16, true));
+    let config = LoadConfig::default().parse(args(&["--compare=off"])).unwrap();
+    assert!(!config.compare);
+    assert!(LoadConfig::default().parse(args(&["--compare"])).is_err());
+    assert!(LoadConfig::default().parse(args(&["--batch=0"])).is_err());
+    assert!(LoadConfig::default().parse(args(&["--batch=1025"])).is_err());
+}
+
+/// Batching saves both the clients and an epoll-edge server system calls,
+/// and the same echoes come back.
+#[cfg(feature = "epoll")]
+#[test]
+fn batching_saves_client_and_server_calls() {
+    let config = LoadConfig::default()
+        .parse(args(&["--count=64", "--rate=10000", "--batch=16", "--compare"]))
+        .unwrap();
+    let echo = Path::new(env!("CARGO_BIN_EXE_echo"));
+    let comparison = tokio_aio_examples::load::compare(echo, &config).unwrap();
+    let (single, batched) = (&comparison.single, &comparison.batched);
+    assert_eq!((single.sent, batched.sent), (64, 64));
+    assert_eq!((single.corrupt, batched.corrupt), (0, 0));
+    assert!(batched.received > 0);
+    assert_eq!(single.send_calls, 64);
+    assert_eq!(batched.send_calls, 4);
+    assert!(comparison.savings() > 0.0);
+
+    let calls = |server: &ServerCalls, name: &str| {
+        server.calls.iter().find(|call| call.0 == name).map_or(0, |call| call.1.calls)
+    };
+    let (single, batched) = (&comparison.single_server, &comparison.batched_server);
+    assert!(single.datagrams > 0 && batched.datagrams > 0);
+    assert!(calls(single, "recvfrom") >= single.datagrams, "{:?}", single);
+    assert_eq!(calls(single, "recvmmsg"), 0, "{:?}", single);
+    assert_eq!(calls(batched, "recvfrom"), 0, "{:?}", batched);
+    assert!(calls(batched, "recvmmsg") > 0, "{:?}", batched);
+    assert!(comparison.server_savings() > 0.0, "{}", comparison);
+}
+
+#[test]
+fn the_last_server_report_is_read_back() {
+    let output = [
+        "listening on 127.0.0.1:2000",
+        "syscalls: calls=1 datagrams=0 per_datagram=0.00",
+        "syscall epoll_wait: calls=1 would_block=0 time=1s",
+        "socket #0: received=2 sent=2",
+        "syscalls: calls=7 datagrams=2 per_datagram=3.50",
+        "syscall recvfrom: calls=3 would_block=1 time=14.2µs",
+        "syscall sendto: calls=2 would_block=0 time=8µs",
+        "syscall epoll_wait: calls=2 would_block=0 time=1.5s",
+    ];
+    let server = ServerCalls::parse(&output).unwrap();
+    assert_eq!(server.datagrams, 2);
+    assert_eq!(server.total(), 7);
+    assert_eq!(server.per_datagram(), 3.5);
+    assert_eq!(server.calls[0].0, "recvfrom");
+    assert_eq!((server.calls[0].1.calls, server.calls[0].1.would_block), (3, 1));
+    assert!(ServerCalls::parse(&output[..1]).is_err());
+    assert!(ServerCalls::parse(&["syscall sendto: calls=2"]).is_err());
+}
+
+#[test]
+fn calls_per_datagram_count_sends_and_receives() {
+    let mut report = Report::default();
+    assert_eq!(report.calls_per_datagram(), 0.0);
+    report.sent = 10;
+    report.send_calls = 10;
+    report.recv_calls = 5;
+    assert_eq!(report.calls_per_datagram(), 1.5);
+}
+
+#[test]
+fn percentiles_use_the_nearest_rank() {
+    let mut report = Report::default();
+    assert_eq!(report.percentile(50.0), None);
+    report.latencies = (1..=10).rev().map(Duration::from_millis).collect();
+    assert_eq!(report.percentile(50.0), Some(Duration::from_millis(5)));
+    assert_eq!(report.percentile(90.0), Some(Duration::from_millis(9)));
+    assert_eq!(report.percentile(100.0), Some(Duration::from_millis(10)));
+    assert_eq!(report.percentile(0.0), Some(Duration::from_millis(1)));
+}
@@ -0,0 +1,126 @@
+// Count the polls of futures wrapped by the poll-count instrumentation.
+
+extern crate futures;
+extern crate libc;
+extern crate tokio_aio_examples;
+
+mod common;
+
+use std::net::UdpSocket;
+use std::process::Command;
+use std::sync::Arc;
+use futures::{Async, Future, Poll};
+use futures::executor::{self, Notify};
+use tokio_aio_examples::polls::{PollCount, PollStats, Progress};
+use common::Server;
+
+struct NoNotify;
+
+impl Notify for NoNotify {
+    fn notify(&self, _id: usize) {}
+}
+
+/// A future which makes `progress` on each poll as scripted, and never
+/// completes.
+struct Scripted {
+    script: Vec<u64>,
+    done: u64,
+}
+
+impl Future for Scripted {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        if !self.script.is_empty() {
+            self.done += self.script.remove(0);
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl Progress for Scripted {
+    fn progress(&self) -> u64 {
+        self.done
+    }
+}
+
+fn poll_times<F: Future>(future: F, times: usize) -> executor::Spawn<F> {
+    let notify = Arc::new(NoNotify);
+    let mut spawn = executor::spawn(future);
+    for _ in 0..times {
+        let _ = spawn.poll_future_notify(&notify, 0);
+    }
+    spawn
+}
+
+#[test]
+fn polls_without_progress_are_spurious() {
+    let stats = PollStats::new(2);
+    let script = vec![0, 2, 0, 1];
+    poll_times(stats.counted(1, Scripted { script, done: 0 }), 4);
+    assert_eq!(stats.get(0), PollCount::default());
+    let count = stats.get(1);
+    assert_eq!(count.polls, 4);
+    assert_eq!(count.productive, 2);
+    assert_eq!(count.spurious(), 2);
+    assert_eq!(count.datagrams, 3);
+}
+
+#[test]
+fn totals_combine_every_future() {
+    let stats = PollStats::new(2);
+    poll_times(stats.counted(0, Scripted { script: vec![1], done: 0 }), 3);
+    poll_times(stats.counted(1, Scripted { script: vec![0, 1], done: 0 }), 3);
+    let total = stats.total();
+    assert_eq!(total.polls, 6);
+    assert_eq!(total.productive, 2);
+    assert_eq!(total.spurious(), 4);
+    assert_eq!(total.datagrams, 2);
+}
+
+#[test]
+fn finished_after_the_datagram_limit() {
+    let stats = PollStats::new(1).with_limit(2);
+    let notify = Arc::new(NoNotify);
+    let mut finished = executor::spawn(stats.finished());
+    assert_eq!(finished.poll_future_notify(&notify, 0).unwrap(), Async::NotReady);
+    poll_times(stats.counted(0, Scripted { script: vec![1, 1], done: 0 }), 1);
+    assert_eq!(finished.poll_future_notify(&notify, 0).unwrap(), Async::NotReady);
+    poll_times(stats.counted(0, Scripted { script: vec![1], done: 0 }), 1);
+    assert_eq!(finished.poll_future_notify(&notify, 0).unwrap(), Async::Ready(()));
+}
+
+#[test]
+fn never_finished_without_a_limit() {
+    let stats = PollStats::new(1);
+    poll_times(stats.counted(0, Scripted { script: vec![100], done: 0 }), 1);
+    let notify = Arc::new(NoNotify);
+    let mut finished = executor::spawn(stats.finished());
+    assert_eq!(finished.poll_future_notify(&notify, 0).unwrap(), Async::NotReady);
+}
+
+/// Each tokio-multisocket program reports its polls when it is shut down
+/// by a signal, not only after a `--datagrams` limit.
+#[test]
+fn polls_are_reported_on_shutdown_signals() {
+    let programs = [
+        env!("CARGO_BIN_EXE_tokio-multisocket-join"),
+        env!("CARGO_BIN_EXE_tokio-multisocket-spawn"),
+        env!("CARGO_BIN_EXE_tokio-multisocket-futuresunordered"),
+    ];
+    for (program, &signum) in programs.iter().zip(&[libc::SIGTERM, libc::SIGINT, libc::SIGTERM]) {
+        let mut command = Command::new(program);
+        command.args(["--port=0", "--sockets=2"]);
+        let mut server = Server::start(command);
+        let addrs = server.addrs(2);
+        UdpSocket::bind("127.0.0.1:0").unwrap().send_to(b"hello", addrs[1]).unwrap();
+        server.wait_for("recv 5 bytes");
+
+        server.signal(signum);
+        assert!(server.wait().success(), "{}", program);
+        let output = server.output();
+        let total = output.iter().find(|line| line.starts_with("total ")).unwrap();
+        assert_eq!(total.split_whitespace().last(), Some("1"), "{}", program);
+    }
+}
@@ -0,0 +1,30 @@
+// Exercise the round-robin ready list used by the edge-triggered servers.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::ready::ReadyList;
+
+#[test]
+fn sockets_are_serviced_in_turn() {
+    let mut ready = ReadyList::new(3);
+    ready.push(2);
+    ready.push(0);
+    ready.push(1);
+    assert_eq!(ready.pop(), Some(2));
+    ready.push(2);
+    assert_eq!(ready.pop(), Some(0));
+    assert_eq!(ready.pop(), Some(1));
+    assert_eq!(ready.pop(), Some(2));
+    assert_eq!(ready.pop(), None);
+}
+
+#[test]
+fn sockets_are_listed_at_most_once() {
+    let mut ready = ReadyList::new(2);
+    assert!(ready.push(1));
+    assert!(!ready.push(1));
+    assert_eq!(ready.len(), 1);
+    assert_eq!(ready.pop(), Some(1));
+    assert!(ready.is_empty());
+    assert!(ready.push(1));
+}
@@ -0,0 +1,91 @@
+// Drive every relay over loopback: each test starts a relay-* program on
+// ephemeral ports, registers consumers, and checks what is relayed to
+// them.
+//
+// The select and epoll relays are only tested when the select and epoll
+// features are enabled.
+
+extern crate libc;
+
+mod common;
+
+use std::net::{SocketAddr, UdpSocket};
+use std::process::Command;
+use common::{Server, TIMEOUT};
+
+/// Start the relay `program` on ephemeral ports, with any extra options.
+/// Returns the relay, and its producer and consumer addresses.
+fn start(program: &str, options: &[&str]) -> (Server, SocketAddr, SocketAddr) {
+    let mut command = Command::new(program);
+    command.arg("--port=0").args(options);
+    let server = Server::start(command);
+    let addrs = server.addrs(2);
+    (server, addrs[0], addrs[1])
+}
+
+/// A consumer registered with the relay at `consumers`.
+fn consumer(server: &Server, consumers: SocketAddr) -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+    socket.send_to(b"1", consumers).unwrap();
+    server.wait_for(&format!("consumer {} registered", socket.local_addr().unwrap()));
+    socket
+}
+
+/// Every datagram is relayed to every registered consumer, from the
+/// consumer port, whichever priority lane it is queued in.
+fn relays_to_every_consumer(program: &str) {
+    let (server, producers, consumers) = start(program, &["--priority-lanes=4"]);
+    let first = consumer(&server, consumers);
+    let second = consumer(&server, consumers);
+
+    let producer = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let datagrams: Vec<&[u8]> = vec![b"\x03bulk", b"\x00control", b"", b"\x09unknown"];
+    for datagram in &datagrams {
+        producer.send_to(datagram, producers).unwrap();
+    }
+    for socket in &[first, second] {
+        let mut buffer = [0; 2048];
+        let mut relayed = vec![];
+        for _ in 0..datagrams.len() {
+            let (nbytes, from) = socket.recv_from(&mut buffer).unwrap();
+            assert_eq!(from, consumers);
+            relayed.push(buffer[..nbytes].to_vec());
+        }
+        relayed.sort();
+        let mut expected: Vec<Vec<u8>> = datagrams.iter().map(|data| data.to_vec()).collect();
+        expected.sort();
+        assert_eq!(relayed, expected);
+    }
+}
+
+/// The shutdown command, sent to the consumer port, stops the relay.
+fn shutdown_command_drains_and_exits(program: &str) {
+    let (mut server, _, consumers) = start(program, &["--shutdown-command=quit"]);
+    UdpSocket::bind("127.0.0.1:0").unwrap().send_to(b"quit\n", consumers).unwrap();
+    assert!(server.wait().success());
+    assert!(server.printed("sent=0 dropped=0 pending=0"));
+}
+
+macro_rules! relay_tests {
+    ($module:ident, $program:expr) => {
+        mod $module {
+            #[test]
+            fn relays_to_every_consumer() {
+                super::relays_to_every_consumer($program);
+            }
+
+            #[test]
+            fn shutdown_command_drains_and_exits() {
+                super::shutdown_command_drains_and_exits($program);
+            }
+        }
+    };
+}
+
+#[cfg(feature = "select")]
+relay_tests!(select, env!("CARGO_BIN_EXE_relay-select"));
+#[cfg(feature = "epoll")]
+relay_tests!(epoll, env!("CARGO_BIN_EXE_relay-epoll"));
+relay_tests!(mio, env!("CARGO_BIN_EXE_relay-mio"));
+relay_tests!(tokio, env!("CARGO_BIN_EXE_relay-tokio"));
@@ -0,0 +1,49 @@
+// Check how signals are interpreted, and what reloading the configuration
+// file changes.
+
+extern crate libc;
+extern crate tokio_aio_examples;
+
+use std::env;
+use std::fs;
+use std::process;
+use tokio_aio_examples::config::{Config, ConfigError};
+use tokio_aio_examples::signal::{Signal, SIGNALS};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn every_handled_signal_has_a_meaning() {
+    for &signum in SIGNALS {
+        assert!(Signal::from_raw(signum).is_some());
+    }
+    assert_eq!(Signal::from_raw(libc::SIGTERM), Some(Signal::Shutdown));
+    assert_eq!(Signal::from_raw(libc::SIGUSR1), Some(Signal::Stats));
+    assert_eq!(Signal::from_raw(libc::SIGHUP), Some(Signal::Reload));
+    assert_eq!(Signal::from_raw(libc::SIGPIPE), None);
+}
+
+#[test]
+fn reloading_only_adopts_run_time_options() {
+    let path = env::temp_dir().join(format!("signal-{}.conf", process::id()));
+    fs::write(&path, "--queue-depth=8  # Comments are ignored.\n--budget=4\n").unwrap();
+    let config = Config::default()
+        .parse(args(&["--config", path.to_str().unwrap(), "--budget=2"]))
+        .unwrap();
+    assert_eq!((config.queue_depth, config.budget), (8, 2));
+
+    fs::write(&path, "--queue-depth=16 --budget=4\n--shutdown-command=quit\n").unwrap();
+    let reloaded = config.reload().unwrap();
+    assert_eq!(reloaded.queue_depth, 8);
+    assert_eq!(reloaded.budget, 4);
+    assert_eq!(reloaded.shutdown_datagram(), Some(b"quit".to_vec()));
+
+    // An invalid file leaves the configuration as it was.
+    fs::write(&path, "--drain-timeout=soon\n").unwrap();
+    assert!(config.reload().is_err());
+    fs::write(&path, "--backend=tokio\n").unwrap();
+    assert_eq!(config.reload(), Err(ConfigError::Unknown("backend".to_string())));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,98 @@
+// Exercise the sans-I/O stream echo state machine without any sockets.
+
+extern crate tokio_aio_examples;
+
+use tokio_aio_examples::echo::Interest;
+use tokio_aio_examples::stream::StreamCore;
+
+#[test]
+fn bytes_are_echoed_in_order() {
+    let mut core = StreamCore::new(16);
+    core.received(b"one");
+    core.received(b"two");
+    assert_eq!(core.poll_send(), Some(&b"onetwo"[..]));
+    core.sent(6);
+    assert_eq!(core.poll_send(), None);
+
+    let stats = core.stats();
+    assert_eq!((stats.received, stats.sent, stats.reads, stats.writes), (6, 6, 2, 1));
+}
+
+#[test]
+fn partial_writes_leave_the_rest_at_the_front() {
+    let mut core = StreamCore::new(16);
+    core.received(b"abcdef");
+    core.sent(4);
+    assert_eq!(core.poll_send(), Some(&b"ef"[..]));
+    core.received(b"gh");
+    assert_eq!(core.poll_send(), Some(&b"efgh"[..]));
+    assert_eq!(core.stats().partial_writes, 1);
+}
+
+#[test]
+fn a_full_write_buffer_pauses_reading() {
+    let mut core = StreamCore::new(4);
+    assert_eq!(core.room(), 4);
+    core.received(b"abcd");
+    assert!(core.is_paused());
+    assert!(!core.wants_read());
+    assert_eq!(core.interest(), Interest { read: false, write: true });
+
+    core.sent(3);
+    assert_eq!(core.room(), 3);
+    assert!(core.wants_read());
+    assert_eq!(core.stats().pauses, 1);
+}
+
+#[test]
+fn would_block_waits_for_readiness() {
+    let mut core = StreamCore::new(16);
+    core.read_would_block();
+    assert!(!core.wants_read());
+    core.readable();
+    assert!(core.wants_read());
+
+    core.received(b"echo");
+    core.write_would_block();
+    assert_eq!(core.poll_send(), None);
+    core.writable();
+    assert_eq!(core.poll_send(), Some(&b"echo"[..]));
+}
+
+#[test]
+fn write_interest_only_while_echoes_are_pending() {
+    let mut core = StreamCore::new(16);
+    assert_eq!(core.poll_interest(), Some(Interest { read: true, write: false }));
+    assert_eq!(core.poll_interest(), None);
+    core.received(b"echo");
+    assert_eq!(core.poll_interest(), Some(Interest { read: true, write: true }));
+    core.sent(4);
+    assert_eq!(core.poll_interest(), Some(Interest { read: true, write: false }));
+}
+
+#[test]
+fn a_half_closed_peer_still_gets_its_echoes() {
+    let mut core = StreamCore::new(16);
+    core.received(b"last");
+    core.received(&[]);
+    assert!(core.is_closed_by_peer());
+    assert!(!core.wants_read());
+    assert!(!core.is_finished());
+
+    core.sent(2);
+    assert!(!core.is_finished());
+    core.sent(2);
+    assert!(core.is_finished());
+}
+
+#[test]
+fn shutdown_stops_reading_and_finishes_once_drained() {
+    let mut core = StreamCore::new(16);
+    core.received(b"echo");
+    core.shutdown();
+    assert!(!core.wants_read());
+    assert_eq!(core.interest(), Interest { read: false, write: true });
+    assert!(!core.is_finished());
+    core.sent(4);
+    assert!(core.is_finished());
+}
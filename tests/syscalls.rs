@@ -0,0 +1,76 @@
+// Account for system calls with the syscall ledger.
+
+extern crate tokio_aio_examples;
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+use tokio_aio_examples::syscalls::{Syscall, SyscallCount, Syscalls, SYSCALLS};
+
+fn would_block() -> io::Result<usize> {
+    Err(io::Error::from(io::ErrorKind::WouldBlock))
+}
+
+#[test]
+fn calls_are_counted_by_kind() {
+    let mut syscalls = Syscalls::new();
+    assert_eq!(syscalls.call(Syscall::RecvFrom, || Ok::<_, io::Error>(64)).unwrap(), 64);
+    assert!(syscalls.call(Syscall::RecvFrom, would_block).is_err());
+    assert!(syscalls
+        .call(Syscall::SendTo, || Err::<usize, _>(io::Error::from(io::ErrorKind::NotFound)))
+        .is_err());
+
+    let recv = syscalls.count(Syscall::RecvFrom);
+    assert_eq!((recv.calls, recv.would_block), (2, 1));
+    let send = syscalls.count(Syscall::SendTo);
+    assert_eq!((send.calls, send.would_block), (1, 0));
+    assert_eq!(syscalls.count(Syscall::EpollWait), SyscallCount::default());
+    assert_eq!(syscalls.total(), 3);
+}
+
+#[test]
+fn time_in_calls_is_accumulated() {
+    let mut syscalls = Syscalls::new();
+    for _ in 0..2 {
+        let _ = syscalls.call(Syscall::Poll, || {
+            thread::sleep(Duration::from_millis(10));
+            Ok::<_, io::Error>(0)
+        });
+    }
+    assert!(syscalls.count(Syscall::Poll).time >= Duration::from_millis(20));
+}
+
+#[test]
+fn calls_per_datagram() {
+    let mut syscalls = Syscalls::new();
+    assert_eq!(syscalls.per_datagram(0), 0.0);
+    for _ in 0..5 {
+        let _ = syscalls.call(Syscall::EpollWait, || Ok::<_, io::Error>(1));
+    }
+    assert_eq!(syscalls.per_datagram(2), 2.5);
+}
+
+#[test]
+fn counts_are_read_back_from_the_report() {
+    let count = SyscallCount {
+        calls: 2,
+        would_block: 1,
+        time: Duration::from_micros(14),
+    };
+    assert_eq!(count.to_string(), "calls=2 would_block=1 time=14µs");
+    assert_eq!(count.to_string().parse(), Ok(count));
+    let count: SyscallCount = "calls=3 would_block=0 time=1.5ms".parse().unwrap();
+    assert_eq!(count.time, Duration::from_micros(1500));
+
+    assert!("calls=3 would_block=0 time=1.5".parse::<SyscallCount>().is_err());
+    assert!("calls=-1".parse::<SyscallCount>().is_err());
+    assert!("sent=3".parse::<SyscallCount>().is_err());
+}
+
+#[test]
+fn every_syscall_has_a_distinct_name() {
+    let mut names: Vec<_> = SYSCALLS.iter().map(|syscall| syscall.name()).collect();
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), SYSCALLS.len());
+}
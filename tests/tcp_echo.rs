@@ -0,0 +1,187 @@
+// Drive every TCP echo server over loopback: each test starts a tcp-echo-*
+// program on an ephemeral port, and checks the echoes of its connections.
+//
+// The select and epoll servers are only tested when the select and epoll
+// features are enabled.
+
+extern crate libc;
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::process::Command;
+use std::thread;
+use common::{Server, TIMEOUT};
+
+/// Start the TCP echo `program` on an ephemeral port, with any extra
+/// options.
+fn start(program: &str, options: &[&str]) -> Server {
+    let mut command = Command::new(program);
+    command.arg("--port=0").args(options);
+    Server::start(command)
+}
+
+fn connect(server: &Server) -> TcpStream {
+    let stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    stream
+}
+
+/// `len` bytes of a pattern which doesn't repeat at any power-of-two
+/// offset, so that lost, duplicated or reordered chunks show up.
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// Write `data` from another thread and then close the writing side, while
+/// reading the echoes until the server closes the connection.  Returns the
+/// echoes.
+fn echo_all(stream: TcpStream, data: Vec<u8>) -> Vec<u8> {
+    let mut writer = stream.try_clone().unwrap();
+    let writer = thread::spawn(move || {
+        writer.write_all(&data).unwrap();
+        writer.shutdown(Shutdown::Write).unwrap();
+    });
+    let mut reader = stream;
+    let mut echoes = vec![];
+    reader.read_to_end(&mut echoes).unwrap();
+    writer.join().unwrap();
+    echoes
+}
+
+/// A stream much larger than the write buffer comes back byte for byte,
+/// however the reads and writes split it.
+fn large_streams_are_echoed_byte_exact(program: &str) {
+    let server = start(program, &["--write-buffer=512"]);
+    let data = pattern(1 << 20);
+    assert_eq!(echo_all(connect(&server), data.clone()), data);
+}
+
+/// Many connections are echoed at once, each getting back only its own
+/// bytes.
+fn concurrent_connections_are_echoed_separately(program: &str) {
+    let server = start(program, &[]);
+    let clients: Vec<_> = (0..32)
+        .map(|id| {
+            let stream = connect(&server);
+            thread::spawn(move || {
+                let data: Vec<u8> = pattern(64 << 10).iter().map(|byte| byte ^ id).collect();
+                assert_eq!(echo_all(stream, data.clone()), data);
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+}
+
+/// A client which closes its writing side still reads its last echoes,
+/// and then the server closes the connection.
+fn half_closed_clients_get_their_echoes(program: &str) {
+    let server = start(program, &[]);
+    let mut stream = connect(&server);
+    stream.write_all(b"last words").unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut echoes = vec![];
+    stream.read_to_end(&mut echoes).unwrap();
+    assert_eq!(echoes, b"last words");
+    server.wait_for("closed its side of the connection.");
+    server.wait_for("closing connection from");
+}
+
+/// Connections beyond the limit are closed at once, without disturbing the
+/// open ones.
+fn excess_connections_are_refused(program: &str) {
+    let server = start(program, &["--max-connections=1"]);
+    let mut first = connect(&server);
+    first.write_all(b"ping").unwrap();
+    let mut echo = [0; 4];
+    first.read_exact(&mut echo).unwrap();
+
+    let mut second = connect(&server);
+    let mut buffer = [0; 4];
+    match second.read(&mut buffer) {
+        Ok(nbytes) => assert_eq!(nbytes, 0),
+        Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset),
+    }
+    server.wait_for("too many connections; refusing");
+
+    first.write_all(b"pong").unwrap();
+    first.read_exact(&mut echo).unwrap();
+    assert_eq!(&echo, b"pong");
+}
+
+/// SIGTERM stops accepting and reading, but the connections still open
+/// are closed cleanly, and the server exits.
+fn sigterm_drains_and_exits(program: &str) {
+    let mut server = start(program, &[]);
+    let mut stream = connect(&server);
+    stream.write_all(b"ping").unwrap();
+    let mut echo = [0; 4];
+    stream.read_exact(&mut echo).unwrap();
+
+    server.signal(libc::SIGTERM);
+    assert!(server.wait().success());
+    server.wait_for("drained; exiting.");
+    assert_eq!(stream.read(&mut echo).unwrap(), 0);
+}
+
+/// SIGUSR1 prints the connection counters, and the server keeps running.
+fn sigusr1_prints_stats(program: &str) {
+    let server = start(program, &[]);
+    let mut stream = connect(&server);
+    stream.write_all(b"ping").unwrap();
+    let mut echo = [0; 4];
+    stream.read_exact(&mut echo).unwrap();
+
+    server.signal(libc::SIGUSR1);
+    server.wait_for("server: accepted=1 refused=0 closed=0 open=1 received=4 sent=4");
+    stream.write_all(b"pong").unwrap();
+    stream.read_exact(&mut echo).unwrap();
+}
+
+macro_rules! tcp_tests {
+    ($module:ident, $program:expr) => {
+        mod $module {
+            #[test]
+            fn large_streams_are_echoed_byte_exact() {
+                super::large_streams_are_echoed_byte_exact($program);
+            }
+
+            #[test]
+            fn concurrent_connections_are_echoed_separately() {
+                super::concurrent_connections_are_echoed_separately($program);
+            }
+
+            #[test]
+            fn half_closed_clients_get_their_echoes() {
+                super::half_closed_clients_get_their_echoes($program);
+            }
+
+            #[test]
+            fn excess_connections_are_refused() {
+                super::excess_connections_are_refused($program);
+            }
+
+            #[test]
+            fn sigterm_drains_and_exits() {
+                super::sigterm_drains_and_exits($program);
+            }
+
+            #[test]
+            fn sigusr1_prints_stats() {
+                super::sigusr1_prints_stats($program);
+            }
+        }
+    };
+}
+
+#[cfg(feature = "select")]
+tcp_tests!(select, env!("CARGO_BIN_EXE_tcp-echo-select"));
+#[cfg(feature = "epoll")]
+tcp_tests!(epoll_level, env!("CARGO_BIN_EXE_tcp-echo-epoll-level"));
+#[cfg(feature = "epoll")]
+tcp_tests!(epoll_edge, env!("CARGO_BIN_EXE_tcp-echo-epoll-edge"));
+tcp_tests!(mio, env!("CARGO_BIN_EXE_tcp-echo-mio"));
+tcp_tests!(tokio, env!("CARGO_BIN_EXE_tcp-echo-tokio"));
@@ -0,0 +1,270 @@
+// Arm, rearm and cancel timers serviced by a shared timer thread, and
+// timerfds, and wait for them with mio.
+
+extern crate mio;
+extern crate tokio_aio_examples;
+
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use mio::{Events, Poll, PollOpt, Ready, Token};
+use tokio_aio_examples::timer::{MioTimer, TimerFd, TimerThread, THREAD_NAME};
+
+// The tests count the process's timer threads, so they take turns.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn serial() -> std::sync::MutexGuard<'static, ()> {
+    SERIAL.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn register<E: mio::Evented>(poll: &Poll, timer: &E, token: usize) {
+    poll.register(timer, Token(token), Ready::readable(), PollOpt::level())
+        .unwrap();
+}
+
+// The tokens of the timers which are ready within `timeout`.  (mio may return from a poll
+// without events when a registration's readiness has been cleared, so keep polling.)
+fn ready(poll: &Poll, timeout: Duration) -> Vec<usize> {
+    let deadline = Instant::now() + timeout;
+    let mut events = Events::with_capacity(64);
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return vec![];
+        }
+        poll.poll(&mut events, Some(deadline - now)).unwrap();
+        if !events.is_empty() {
+            let mut tokens: Vec<usize> = events.iter().map(|event| event.token().0).collect();
+            tokens.sort();
+            return tokens;
+        }
+    }
+}
+
+// The number of timer threads in this process.
+fn timer_threads() -> usize {
+    fs::read_dir("/proc/self/task")
+        .unwrap()
+        .filter_map(|task| fs::read_to_string(task.unwrap().path().join("comm")).ok())
+        .filter(|name| name.trim_end() == THREAD_NAME)
+        .count()
+}
+
+// Wait for the number of timer threads to reach `count`, as a thread only takes its name once
+// it runs, and leaves /proc a moment after it is joined.
+fn await_timer_threads(count: usize) {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while timer_threads() != count && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(timer_threads(), count);
+}
+
+#[test]
+fn a_one_shot_timer_expires_once() {
+    let _serial = serial();
+    let timers = TimerThread::new().unwrap();
+    let timer = timers.timer();
+    let poll = Poll::new().unwrap();
+    register(&poll, &timer, 1);
+    assert!(!timer.is_armed());
+
+    let start = Instant::now();
+    timer.set_timeout(Duration::from_millis(50));
+    assert!(timer.is_armed());
+    assert_eq!(ready(&poll, Duration::from_secs(2)), vec![1]);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert!(!timer.is_armed());
+    assert_eq!(timer.expirations(), 1);
+
+    // Taking the expirations cleared the readiness, and the timer doesn't expire again.
+    assert_eq!(ready(&poll, Duration::from_millis(150)), Vec::<usize>::new());
+    assert_eq!(timer.expirations(), 0);
+}
+
+#[test]
+fn a_periodic_timer_expires_until_cancelled() {
+    let _serial = serial();
+    let timers = TimerThread::new().unwrap();
+    let timer = timers.timer();
+    let poll = Poll::new().unwrap();
+    register(&poll, &timer, 1);
+
+    timer.set_interval(Duration::from_millis(20));
+    let mut expirations = 0;
+    while expirations < 3 {
+        assert_eq!(ready(&poll, Duration::from_secs(2)), vec![1]);
+        expirations += timer.expirations();
+    }
+    assert!(timer.is_armed());
+
+    timer.cancel();
+    assert!(!timer.is_armed());
+    assert_eq!(ready(&poll, Duration::from_millis(100)), Vec::<usize>::new());
+    assert_eq!(timer.expirations(), 0);
+}
+
+#[test]
+fn a_periodic_timer_counts_missed_expirations() {
+    let _serial = serial();
+    let timers = TimerThread::new().unwrap();
+    let timer = timers.timer();
+    timer.set_interval(Duration::from_millis(20));
+    thread::sleep(Duration::from_millis(110));
+    // At least the five intervals slept through, and more if the sleep overran.
+    let expirations = timer.expirations();
+    assert!(expirations >= 5, "{}", expirations);
+}
+
+#[test]
+fn a_cancelled_timer_never_expires() {
+    let _serial = serial();
+    let timers = TimerThread::new().unwrap();
+    let timer = timers.timer();
+    let poll = Poll::new().unwrap();
+    register(&poll, &timer, 1);
+
+    timer.set_timeout(Duration::from_millis(30));
+    timer.cancel();
+    assert_eq!(ready(&poll, Duration::from_millis(100)), Vec::<usize>::new());
+    assert_eq!(timer.expirations(), 0);
+}
+
+#[test]
+fn rearming_a_timer_reschedules_it() {
+    let _serial = serial();
+    let timers = TimerThread::new().unwrap();
+    let timer = timers.timer();
+    let poll = Poll::new().unwrap();
+    register(&poll, &timer, 1);
+
+    // Postponed: the first deadline passes without an expiration.
+    let start = Instant::now();
+    timer.set_timeout(Duration::from_millis(30));
+    timer.set_timeout(Duration::from_millis(150));
+    assert_eq!(ready(&poll, Duration::from_secs(2)), vec![1]);
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert_eq!(timer.expirations(), 1);
+
+    // Brought forward, from a periodic timer to a one-shot one.
+    let start = Instant::now();
+    timer.set_interval(Duration::from_secs(10));
+    timer.set_timeout(Duration::from_millis(20));
+    assert_eq!(ready(&poll, Duration::from_secs(2)), vec![1]);
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(timer.expirations(), 1);
+    assert!(!timer.is_armed());
+}
+
+#[test]
+fn one_thread_services_many_timers() {
+    let _serial = serial();
+    let before = timer_threads();
+    let timers = TimerThread::new().unwrap();
+    let poll = Poll::new().unwrap();
+    let all: Vec<MioTimer> = (0..50).map(|_| timers.timer()).collect();
+    for (token, timer) in all.iter().enumerate() {
+        register(&poll, timer, token);
+        timer.set_timeout(Duration::from_millis(10 + token as u64 % 5 * 10));
+    }
+    assert_eq!(timers.len(), 50);
+    await_timer_threads(before + 1);
+
+    let mut expired = vec![];
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while expired.len() < all.len() && Instant::now() < deadline {
+        for token in ready(&poll, Duration::from_millis(100)) {
+            assert_eq!(all[token].expirations(), 1);
+            expired.push(token);
+        }
+    }
+    expired.sort();
+    assert_eq!(expired, (0..50).collect::<Vec<_>>());
+}
+
+#[test]
+fn the_thread_is_joined_when_the_last_handle_is_dropped() {
+    let _serial = serial();
+    let before = timer_threads();
+    let timers = TimerThread::new().unwrap();
+    let timer = timers.timer();
+    timer.set_interval(Duration::from_millis(10));
+    await_timer_threads(before + 1);
+
+    // The timer keeps the thread alive without the TimerThread.
+    drop(timers);
+    thread::sleep(Duration::from_millis(30));
+    assert!(timer.expirations() > 0);
+    assert_eq!(timer_threads(), before + 1);
+
+    drop(timer);
+    await_timer_threads(before);
+}
+
+#[test]
+fn dropping_a_timer_removes_it() {
+    let _serial = serial();
+    let timers = TimerThread::new().unwrap();
+    let first = timers.timer();
+    let second = timers.timer();
+    first.set_timeout(Duration::from_millis(20));
+    drop(first);
+    assert_eq!(timers.len(), 1);
+
+    // The dropped timer's deadline doesn't expire the timer which reuses its entry.
+    let third = timers.timer();
+    assert_eq!(timers.len(), 2);
+    thread::sleep(Duration::from_millis(60));
+    assert_eq!(third.expirations(), 0);
+    assert_eq!(second.expirations(), 0);
+}
+
+#[test]
+fn a_timerfd_wakes_mio_directly() {
+    let timerfd = TimerFd::new().unwrap();
+    let poll = Poll::new().unwrap();
+    register(&poll, &timerfd, 1);
+    assert!(!timerfd.is_armed().unwrap());
+
+    let start = Instant::now();
+    timerfd.set_timeout(Duration::from_millis(50)).unwrap();
+    assert!(timerfd.is_armed().unwrap());
+    assert_eq!(ready(&poll, Duration::from_secs(2)), vec![1]);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert!(!timerfd.is_armed().unwrap());
+    assert_eq!(timerfd.expirations().unwrap(), 1);
+
+    // Reading the expirations cleared the readiness.
+    assert_eq!(ready(&poll, Duration::from_millis(100)), Vec::<usize>::new());
+    assert_eq!(timerfd.expirations().unwrap(), 0);
+}
+
+#[test]
+fn a_timerfd_counts_missed_expirations() {
+    let timerfd = TimerFd::new().unwrap();
+    timerfd.set_interval(Duration::from_millis(20)).unwrap();
+    thread::sleep(Duration::from_millis(110));
+    // At least the five intervals slept through, and more if the sleep overran.
+    let expirations = timerfd.expirations().unwrap();
+    assert!(expirations >= 5, "{}", expirations);
+    assert!(timerfd.is_armed().unwrap());
+}
+
+#[test]
+fn a_cancelled_timerfd_forgets_its_expirations() {
+    let timerfd = TimerFd::new().unwrap();
+    let poll = Poll::new().unwrap();
+    register(&poll, &timerfd, 1);
+
+    // A zero timeout expires at once, rather than disarming the timerfd.
+    timerfd.set_timeout(Duration::from_secs(0)).unwrap();
+    assert_eq!(ready(&poll, Duration::from_secs(2)), vec![1]);
+    timerfd.cancel().unwrap();
+    assert_eq!(timerfd.expirations().unwrap(), 0);
+
+    timerfd.set_interval(Duration::from_millis(20)).unwrap();
+    timerfd.cancel().unwrap();
+    assert!(!timerfd.is_armed().unwrap());
+    assert_eq!(ready(&poll, Duration::from_millis(100)), Vec::<usize>::new());
+}
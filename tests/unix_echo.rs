@@ -0,0 +1,268 @@
+// Drive the echo servers over Unix domain sockets (see `--unix`): the UDP
+// backends over a datagram socket, and the TCP servers over a stream one,
+// each bound to a fresh path in the temporary directory, or to a name in
+// the abstract namespace.
+//
+// The select, poll, epoll and io-uring servers are only tested when the
+// corresponding features are enabled.
+
+extern crate libc;
+
+mod common;
+
+use std::env;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use common::{Server, TIMEOUT};
+
+/// Start `program` (the program and its first options) serving `unix`,
+/// once it has announced that it is listening.
+fn start(program: &[&str], unix: &str) -> Server {
+    Server::start(command(program, unix))
+}
+
+fn command(program: &[&str], unix: &str) -> Command {
+    let mut command = Command::new(program[0]);
+    command.args(&program[1..]).arg(format!("--unix={}", unix));
+    command
+}
+
+/// A path in the temporary directory, or a name in the abstract namespace,
+/// which no other test uses.
+fn unique(what: &str) -> String {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT.fetch_add(1, Ordering::SeqCst);
+    format!("unix-echo-{}-{}-{}", process::id(), id, what)
+}
+
+fn socket_path(what: &str) -> PathBuf {
+    env::temp_dir().join(format!("{}.sock", unique(what)))
+}
+
+/// A client datagram socket bound to a path of its own, so that it can be
+/// echoed to.
+struct Client {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl Client {
+    fn bind() -> Client {
+        let path = socket_path("client");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+        Client { socket, path }
+    }
+
+    /// Send `data` to the server at `addr`, and return the echo.
+    fn echo(&self, addr: &SocketAddr, data: &[u8]) -> Vec<u8> {
+        self.socket.send_to_addr(data, addr).unwrap();
+        let mut buffer = [0; 2048];
+        let nbytes = self.socket.recv(&mut buffer).expect("no echo");
+        buffer[..nbytes].to_vec()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Write `data` and then close the writing side, and return the echoes
+/// read until the server closes the connection.
+fn echo_stream(mut stream: UnixStream, data: &[u8]) -> Vec<u8> {
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    stream.write_all(data).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut echoes = vec![];
+    stream.read_to_end(&mut echoes).unwrap();
+    echoes
+}
+
+/// Datagrams sent to the socket's path are echoed to their sender, and
+/// the socket file is removed when the server exits.
+fn datagrams_are_echoed(program: &[&str]) {
+    let path = socket_path("server");
+    let mut server = start(program, path.to_str().unwrap());
+    let addr = SocketAddr::from_pathname(&path).unwrap();
+    let client = Client::bind();
+    for size in &[1, 100, 1400] {
+        let data = vec![*size as u8; *size];
+        assert_eq!(client.echo(&addr, &data), data);
+    }
+
+    server.signal(libc::SIGTERM);
+    assert!(server.wait().success());
+    assert!(!path.exists(), "the socket file was left behind");
+}
+
+/// A socket in the abstract namespace is served like one with a path.
+fn datagrams_are_echoed_on_abstract_names(program: &[&str]) {
+    let name = unique("server");
+    let _server = start(program, &format!("@{}", name));
+    let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+    let client = Client::bind();
+    assert_eq!(client.echo(&addr, b"abstract"), b"abstract");
+}
+
+/// A sender which didn't bind its socket can't be echoed to, which the
+/// server reports without disturbing anyone else's echoes.
+fn unnamed_senders_get_no_echo(program: &[&str]) {
+    let path = socket_path("server");
+    let server = start(program, path.to_str().unwrap());
+    let unnamed = UnixDatagram::unbound().unwrap();
+    unnamed.send_to(b"nobody", &path).unwrap();
+    server.wait_for("failed to send to");
+
+    let addr = SocketAddr::from_pathname(&path).unwrap();
+    assert_eq!(Client::bind().echo(&addr, b"somebody"), b"somebody");
+}
+
+/// Bytes written to a connection to the socket's path are echoed back,
+/// and the socket file is removed when the server exits.
+fn streams_are_echoed(program: &[&str]) {
+    let path = socket_path("server");
+    let mut server = start(program, path.to_str().unwrap());
+    let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    assert_eq!(echo_stream(UnixStream::connect(&path).unwrap(), &data), data);
+
+    server.signal(libc::SIGTERM);
+    assert!(server.wait().success());
+    assert!(!path.exists(), "the socket file was left behind");
+}
+
+/// A listener in the abstract namespace is served like one with a path.
+fn streams_are_echoed_on_abstract_names(program: &[&str]) {
+    let name = unique("server");
+    let _server = start(program, &format!("@{}", name));
+    let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+    let stream = UnixStream::connect_addr(&addr).unwrap();
+    assert_eq!(echo_stream(stream, b"abstract"), b"abstract");
+}
+
+/// A socket file which nothing is listening on any more is removed, so
+/// that the server can bind its path.
+fn stale_socket_files_are_removed(program: &[&str]) {
+    let path = socket_path("stale");
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let server = start(program, path.to_str().unwrap());
+    assert!(server.printed("removing stale socket file"));
+}
+
+/// A socket file which a server is still listening on is left alone, and
+/// the second server fails to start.
+fn live_sockets_are_not_replaced(program: &[&str]) {
+    let path = socket_path("live");
+    let _server = start(program, path.to_str().unwrap());
+    let status = command(program, path.to_str().unwrap())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    assert!(path.exists());
+}
+
+macro_rules! datagram_tests {
+    ($module:ident, $($option:expr),+) => {
+        mod $module {
+            const PROGRAM: &[&str] = &[env!("CARGO_BIN_EXE_echo"), $($option),+];
+
+            #[test]
+            fn datagrams_are_echoed() {
+                super::datagrams_are_echoed(PROGRAM);
+            }
+
+            #[test]
+            fn datagrams_are_echoed_on_abstract_names() {
+                super::datagrams_are_echoed_on_abstract_names(PROGRAM);
+            }
+
+            #[test]
+            fn unnamed_senders_get_no_echo() {
+                super::unnamed_senders_get_no_echo(PROGRAM);
+            }
+
+            #[test]
+            fn stale_socket_files_are_removed() {
+                super::stale_socket_files_are_removed(PROGRAM);
+            }
+
+            #[test]
+            fn live_sockets_are_not_replaced() {
+                super::live_sockets_are_not_replaced(PROGRAM);
+            }
+        }
+    };
+}
+
+macro_rules! stream_tests {
+    ($module:ident, $program:expr) => {
+        mod $module {
+            const PROGRAM: &[&str] = &[$program];
+
+            #[test]
+            fn streams_are_echoed() {
+                super::streams_are_echoed(PROGRAM);
+            }
+
+            #[test]
+            fn streams_are_echoed_on_abstract_names() {
+                super::streams_are_echoed_on_abstract_names(PROGRAM);
+            }
+
+            #[test]
+            fn stale_socket_files_are_removed() {
+                super::stale_socket_files_are_removed(PROGRAM);
+            }
+
+            #[test]
+            fn live_sockets_are_not_replaced() {
+                super::live_sockets_are_not_replaced(PROGRAM);
+            }
+        }
+    };
+}
+
+#[cfg(feature = "select")]
+datagram_tests!(select, "--backend=select");
+#[cfg(feature = "poll")]
+datagram_tests!(poll, "--backend=poll");
+#[cfg(feature = "epoll")]
+datagram_tests!(epoll_level, "--backend=epoll-level");
+#[cfg(feature = "epoll")]
+datagram_tests!(epoll_edge, "--backend=epoll-edge");
+#[cfg(feature = "epoll")]
+datagram_tests!(epoll_edge_batched, "--backend=epoll-edge", "--batch=8");
+#[cfg(feature = "io-uring")]
+datagram_tests!(io_uring, "--backend=io-uring");
+datagram_tests!(mio_level, "--backend=mio-level");
+datagram_tests!(mio_edge, "--backend=mio-edge");
+datagram_tests!(tokio, "--backend=tokio");
+
+#[cfg(feature = "select")]
+stream_tests!(tcp_select, env!("CARGO_BIN_EXE_tcp-echo-select"));
+#[cfg(feature = "epoll")]
+stream_tests!(tcp_epoll_level, env!("CARGO_BIN_EXE_tcp-echo-epoll-level"));
+#[cfg(feature = "epoll")]
+stream_tests!(tcp_epoll_edge, env!("CARGO_BIN_EXE_tcp-echo-epoll-edge"));
+stream_tests!(tcp_mio, env!("CARGO_BIN_EXE_tcp-echo-mio"));
+stream_tests!(tcp_tokio, env!("CARGO_BIN_EXE_tcp-echo-tokio"));
+
+/// The tokio-mpsc backend's sockets are UDP only.
+#[test]
+fn tokio_mpsc_refuses_unix_sockets() {
+    let path = socket_path("mpsc");
+    let program = &[env!("CARGO_BIN_EXE_echo"), "--backend=tokio-mpsc"];
+    let output = command(program, path.to_str().unwrap()).output().unwrap();
+    assert!(!output.status.success());
+    assert!(!path.exists());
+}